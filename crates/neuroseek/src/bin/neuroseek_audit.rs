@@ -0,0 +1,45 @@
+//! Verify a compiler audit log.
+//!
+//! Usage: neuroseek_audit <audit.ndjson> [--anchor <entries>:<head hash>]
+//!
+//! Checks sequence numbers, hash links and entry hashes of every line and exits
+//! non-zero at the first sign of tampering or a gap. With `--anchor`, the entry count
+//! and head hash printed by an earlier run, it also detects entries removed from the
+//! end of the log since then.
+
+use clap::Parser;
+use neuroseek::compiler::audit::{self, AuditSummary};
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// Audit log written by the compiler (one JSON entry per line).
+    log_file: PathBuf,
+    /// Summary of an earlier verification, as `<entries>:<head hash>`.
+    #[arg(long, value_parser = parse_anchor)]
+    anchor: Option<AuditSummary>,
+}
+
+fn parse_anchor(s: &str) -> Result<AuditSummary, String> {
+    let (entries, head_hash) = s.split_once(':').ok_or_else(|| format!("expected <entries>:<head hash>, got `{}`", s))?;
+    let entries = entries.parse().map_err(|_| format!("invalid entry count `{}`", entries))?;
+    Ok(AuditSummary { entries, head_hash: head_hash.to_string() })
+}
+
+fn main() {
+    let args = Args::parse();
+    let verified = match &args.anchor {
+        Some(anchor) => audit::verify_anchored(&args.log_file, anchor),
+        None => audit::verify(&args.log_file),
+    };
+    match verified {
+        Ok(summary) => {
+            println!("✅ {} entries verified, head {}", summary.entries, summary.head_hash);
+            println!("   anchor: {}:{}", summary.entries, summary.head_hash);
+        }
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -1,32 +1,68 @@
 //! Run a single audio experiment: compile, generate, log.
 //!
-//! Usage: neuroseek_experiment <protocol.json>
+//! Usage: neuroseek_experiment <protocol.json> [--profile <profile.json>] [--dry-run [--json]]
+//!        [--audit-log <audit.ndjson>] [--play [--player <command>] --event-log <events.ndjson> [--subject <id>]]
+//!
+//! Without `--profile`, the reference policy in `profiles/default.json` is used.
+//! With `--dry-run`, prints how close the protocol comes to every rule's limit
+//! (Markdown, or JSON with `--json`) and exits without generating audio.
+//! With `--audit-log`, the compilation decision is appended to a hash-chained log
+//! (check it with `neuroseek_audit`).
+//! With `--event-log`, the start and stop of playback are appended to a session event
+//! log, which `neuroseek_observe --stimulus-log` joins to the state stream.
+//! With `--play`, the session is played while it is synthesised, through `--player`
+//! (default `aplay -q -f S16_LE -c 2 -r <sample rate>`); Ctrl-C fades it out, stops the
+//! player and logs the stop before exiting.
 
 use clap::Parser;
-use neuroseek::biophysics::{BiophysicalCorridor, BiophysicalState};
-use neuroseek::compiler::{Compiler, CompilerConfig, HardwareCapabilities, Stimulus, AudioStimulus};
-use neuroseek::neurorights::NeurorightsConstraints;
+use neuroseek::compiler::audit::AuditLog;
+use neuroseek::compiler::{Compiler, Stimulus, AudioStimulus};
+use neuroseek::profile::CompilerProfile;
 use neuroseek::stimulus::audio::{AudioOutputConfig, AudioStimulusExecutor, DummyTelemetryProvider};
+use neuroseek::stimulus::event_log::SessionEventLog;
 use neuroseek_audio::config::Protocol as AudioProtocol;
 use std::fs;
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Duration;
 use tokio::sync::Mutex;
 
+/// Reference policy compiled into the binary.
+const DEFAULT_PROFILE: &str = include_str!("../../profiles/default.json");
+
 #[derive(Parser, Debug)]
 struct Args {
     /// Path to JSON file containing an AudioProtocol (fixed or polytope).
     protocol_file: PathBuf,
+    /// Compiler policy profile (JSON). Defaults to the built-in reference profile.
+    #[arg(short, long)]
+    profile: Option<PathBuf>,
     /// Output directory for generated WAV files.
     #[arg(short, long, default_value = "./audio_output")]
     output_dir: PathBuf,
     /// Sample rate for generated audio.
     #[arg(long, default_value_t = 44100)]
     sample_rate: u32,
-    /// Play audio after generation.
+    /// Play audio while it is generated.
     #[arg(long)]
     play: bool,
+    /// Only explain the compilation decision; do not generate audio.
+    #[arg(long)]
+    dry_run: bool,
+    /// Print the dry-run report as JSON instead of Markdown.
+    #[arg(long, requires = "dry_run")]
+    json: bool,
+    /// Append the compilation decision to this audit log.
+    #[arg(long)]
+    audit_log: Option<PathBuf>,
+    /// Command playing raw 16-bit little-endian stereo PCM from stdin (default: aplay).
+    #[arg(long, requires = "play")]
+    player: Option<String>,
+    /// Append session start and stop events to this log.
+    #[arg(long, requires = "play")]
+    event_log: Option<PathBuf>,
+    /// Subject the session is delivered to, recorded in the event log.
+    #[arg(long, requires = "event_log")]
+    subject: Option<String>,
 }
 
 #[tokio::main]
@@ -38,74 +74,94 @@ async fn main() -> anyhow::Result<()> {
     let audio_proto: AudioProtocol = serde_json::from_str(&json)?;
 
     // Create stimulus
-    let stimulus = Stimulus::Audio(AudioStimulus {
+    let stimulus = Stimulus::Audio(Box::new(AudioStimulus {
         protocol: audio_proto,
         name: args.protocol_file.file_stem().unwrap().to_string_lossy().to_string(),
         description: None,
-    });
+    }));
 
-    // Configure compiler
-    let hardware = HardwareCapabilities {
-        max_amplitude: 1.0,
-        min_frequency: 20.0,
-        max_frequency: 20000.0,
-        max_session_duration_sec: 3600.0,
-        max_daily_duty: 0.3,
-    };
-    let corridor = BiophysicalCorridor {
-        e: (0.0, 0.8),
-        m_prot: (0.0, 0.5),
-        s_bio: (0.0, 0.7),
-        theta: (0.0, 1.0),
-        t: (-1.0, 1.0),
+    // Configure compiler from the policy profile
+    let profile = match &args.profile {
+        Some(path) => CompilerProfile::load(path)?,
+        None => CompilerProfile::from_json(DEFAULT_PROFILE)?,
     };
-    let neurorights = NeurorightsConstraints::default();
-    let compiler_config = CompilerConfig {
-        hardware,
-        biophysical_corridor: corridor,
-        neurorights,
-        token_validity_duration: Duration::from_secs(300),
-    };
-    let compiler = Compiler::new(compiler_config);
+    if !args.json {
+        println!("Policy profile: {} v{} (hash {})", profile.name, profile.version, profile.hash());
+    }
+    let mut compiler = Compiler::from_profile(profile);
+    if let Some(path) = &args.audit_log {
+        compiler = compiler.with_audit_log(AuditLog::open(path)?);
+    }
+
+    if args.dry_run {
+        let report = compiler.explain(&stimulus);
+        if args.json {
+            println!("{}", report.to_json());
+        } else {
+            println!("{}", report.to_markdown());
+        }
+        if !report.would_approve {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
     // Compile
     let result = compiler.compile(stimulus);
     match result {
-        neuroseek::compiler::CompilationResult::Approved { token, valid_until, stimulus } => {
-            println!("✅ Approved. Token: {}, valid until {:?}", token, valid_until);
+        neuroseek::compiler::CompilationResult::Approved { token, valid_until, stimulus, profile_hash, corridor_margin, warnings } => {
+            println!("✅ Approved. Token: {}, valid until {:?}, profile {}", token, valid_until, profile_hash);
+            println!("Worst-case corridor margin: {:.4}", corridor_margin.min_margin);
+            for w in &warnings {
+                println!("  ⚠ {}", w);
+            }
 
             // Set up executor
             let audio_config = AudioOutputConfig {
                 sample_rate: args.sample_rate,
                 output_dir: args.output_dir,
                 play_after_generate: args.play,
+                player: args.player.clone(),
             };
             let telemetry = Arc::new(Mutex::new(DummyTelemetryProvider));
-            let executor = AudioStimulusExecutor::new(audio_config)
+            let mut executor = AudioStimulusExecutor::new(audio_config)
                 .with_telemetry_provider(telemetry);
+            if let Some(path) = &args.event_log {
+                executor = executor.with_event_log(SessionEventLog::open(path)?.with_subject(args.subject.clone()));
+            }
 
-            // Execute
-            let (path, log) = executor.execute(stimulus.as_audio().unwrap()).await?;
+            // Execute; Ctrl-C stops the session
+            let executor = Arc::new(executor);
+            let stopper = executor.clone();
+            tokio::spawn(async move {
+                while tokio::signal::ctrl_c().await.is_ok() {
+                    // Outside a session nothing is playing and no stop is left to log.
+                    if !stopper.stop() {
+                        std::process::exit(130);
+                    }
+                    eprintln!("Stopping session...");
+                }
+            });
+            let (path, log) = executor.execute(stimulus.as_audio().unwrap(), &profile_hash).await?;
             println!("Generated audio: {}", path.display());
             println!("Session log: {:#?}", log);
         }
-        neuroseek::compiler::CompilationResult::Rejected { reasons } => {
+        neuroseek::compiler::CompilationResult::Rejected { reasons, suggestion } => {
             eprintln!("❌ Rejected:");
             for r in reasons {
                 eprintln!("  - {}", r);
             }
+            if let Some(s) = suggestion {
+                let p = &s.suggested;
+                eprintln!(
+                    "💡 Nearest safe alternative (distance {:.3}): amplitude {:.3}, carrier {:.1} Hz, beat {:.2} Hz, duty {:.3}, duration {:.0} s",
+                    s.distance, p.amplitude, p.carrier_hz, p.beat_hz, p.duty, p.session_duration_sec
+                );
+                eprintln!("Suggested protocol:\n{}", serde_json::to_string_pretty(&s.stimulus.protocol)?);
+            }
             std::process::exit(1);
         }
     }
 
     Ok(())
 }
-
-// Helper to extract audio stimulus
-impl Stimulus {
-    fn as_audio(&self) -> Option<&AudioStimulus> {
-        match self {
-            Stimulus::Audio(a) => Some(a),
-        }
-    }
-}
@@ -1,19 +1,42 @@
 //! Observation‑only binary for NeuroSeek.
 //!
-//! Reads biophysical states either from a JSON file or by directly querying Prometheus,
-//! feeds them into the clustering engine, and periodically logs or saves the
-//! evolving polytope map. No device control, no neural data access.
+//! Reads biophysical states from any combination of JSON snapshot files, NDJSON
+//! streams, a Prometheus server, recorded sessions, wearable exports and devices pushing
+//! to a local HTTP endpoint, feeds them into the clustering engine, and
+//! periodically logs or saves the evolving polytope map. No device control, no neural
+//! data access.
 //!
 //! Examples:
-//!   neuroseek_observe --source file --state-file /tmp/state.json
-//!   neuroseek_observe --source prometheus --prometheus-url http://localhost:9090
+//!   neuroseek_observe --state-file /tmp/state.json
+//!   neuroseek_observe --stream-file /tmp/a.ndjson --stream-file /tmp/b.ndjson
+//!   neuroseek_observe --prometheus-url http://localhost:9090 --state-file /tmp/state.json
+//!   neuroseek_observe --replay session.csv --replay-speed max --snapshot-dir /tmp/snapshots
+//!   neuroseek_observe --replay session.csv --eeg session.bdf --eeg-channel Fz
+//!   neuroseek_observe --wearable export.xml --replay-speed max --snapshot-dir /tmp/snapshots
+//!   neuroseek_observe --push-addr 127.0.0.1:9470
+//!   neuroseek_observe --stream-file /tmp/a.ndjson --stimulus-log /tmp/sessions.ndjson
+//!   neuroseek_observe --prometheus-url http://localhost:9090 --state-file /tmp/t.json \
+//!       --replay session.csv --eeg session.bdf --fuse e=prometheus --fuse m_prot=prometheus \
+//!       --fuse s_bio=prometheus --fuse theta=eeg:linear:2000 --fuse t=state-file:last:60000
 
-use clap::{Parser, Subcommand};
+use clap::{Args as ClapArgs, Parser};
 use neuroseek::clustering::{ClustererConfig, PolytopeClusterer};
-use neuroseek::ingest::episode_metrics::{IngesterConfig, MetricsIngester};
-use neuroseek::ingest::prometheus::{PrometheusConfig, PrometheusIngester};
-use std::path::PathBuf;
-use std::sync::Arc;
+use neuroseek::ingest::edf::{theta_series, EdfRecording, ThetaConfig};
+use neuroseek::ingest::episode_metrics::{IngesterConfig, MetricsIngester, WatchMode};
+use neuroseek::ingest::filter::FilterConfig;
+use neuroseek::ingest::fusion::{Alignment, FusionConfig, FusionSource};
+use neuroseek::ingest::ndjson_tail::{NdjsonIngester, NdjsonTailConfig};
+use neuroseek::ingest::phase::PhaseConfig;
+use neuroseek::ingest::pipeline::IngestPipeline;
+use neuroseek::ingest::prometheus::{PrometheusConfig, PrometheusIngester, SeriesMapping};
+use neuroseek::ingest::push::{PushConfig, PushSource};
+use neuroseek::ingest::queue::{OverflowPolicy, QueueConfig};
+use neuroseek::ingest::replay::{ReplaySource, ReplaySpeed};
+use neuroseek::ingest::prometheus::DIMENSION_NAMES;
+use neuroseek::ingest::source::{IngestEvent, StateSource};
+use neuroseek::ingest::wearable::{self, WearableMapping};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use tokio::sync::Mutex;
 use tracing::{info, Level};
@@ -22,9 +45,122 @@ use tracing_subscriber::FmtSubscriber;
 #[derive(Parser, Debug)]
 #[clap(author, version, about = "NeuroSeek observation mode")]
 struct Args {
-    /// Source of biophysical state data.
-    #[clap(subcommand)]
-    source: Source,
+    /// JSON file containing the current BiophysicalState (written by the nicotine
+    /// safety stack). May be given several times.
+    #[clap(long)]
+    state_file: Vec<PathBuf>,
+
+    /// Poll state files instead of watching them for changes.
+    #[clap(long)]
+    poll: bool,
+
+    /// Debounce window for change notifications, in milliseconds.
+    #[clap(long, default_value_t = 50)]
+    debounce_ms: u64,
+
+    /// Append-only NDJSON stream of timestamped states. May be given several times.
+    /// The read offset is persisted next to the stream, in `<stream>.offset`.
+    #[clap(long)]
+    stream_file: Vec<PathBuf>,
+
+    /// Polling interval in milliseconds (for every source).
+    #[clap(long, default_value_t = 1000)]
+    poll_ms: u64,
+
+    /// Recorded session (CSV or NDJSON) to replay with its original timestamps.
+    /// May be given several times.
+    #[clap(long)]
+    replay: Vec<PathBuf>,
+
+    /// Replay speed: a factor of real time (1 is real time, 10 ten times faster), or
+    /// `max` for no waiting.
+    #[clap(long, default_value = "1", value_parser = parse_replay_speed)]
+    replay_speed: ReplaySpeed,
+
+    /// EEG recording (EDF, EDF+ or BDF) of the replayed sessions; theta is derived
+    /// from it and replaces the recorded theta wherever it has a value.
+    #[clap(long)]
+    eeg: Option<PathBuf>,
+
+    /// EEG channel to derive theta from; may be given several times (default: all).
+    #[clap(long)]
+    eeg_channel: Vec<String>,
+
+    /// Wearable export to import: Apple Health `export.xml`, a FIT file, or an
+    /// Oura-style CSV. May be given several times. Replayed at `--replay-speed`; use
+    /// `max` to seed the map from months of history.
+    #[clap(long)]
+    wearable: Vec<PathBuf>,
+
+    /// JSON mapping of wearable measurements to the bioscale (see
+    /// `ingest::wearable::WearableMapping`); defaults to one suited to each format.
+    #[clap(long)]
+    wearable_mapping: Option<PathBuf>,
+
+    /// JSON filter configuration (see `ingest::filter::FilterConfig`); enables
+    /// signal-quality filtering before clustering.
+    #[clap(long)]
+    filter_config: Option<PathBuf>,
+
+    /// Address of a local HTTP endpoint accepting pushed JSON batches of states,
+    /// stimulus events and craving reports (see `ingest::push`).
+    #[clap(long)]
+    push_addr: Option<SocketAddr>,
+
+    /// Pushed batches queued for the clusterer before devices are asked to retry.
+    #[clap(long, default_value_t = 64)]
+    push_queue: usize,
+
+    /// Most events accepted in one pushed batch.
+    #[clap(long, default_value_t = 1000)]
+    push_max_batch: usize,
+
+    /// Stimulus session event log written by the executor; states are tagged with
+    /// the session phase (pre, during, post) they fall in.
+    #[clap(long)]
+    stimulus_log: Option<PathBuf>,
+
+    /// How long before a session's start states are tagged `pre`, in seconds. States
+    /// are held back this long before they are clustered.
+    #[clap(long, default_value_t = 300)]
+    pre_window_secs: u64,
+
+    /// How long after a session's stop states are tagged `post`, in seconds.
+    #[clap(long, default_value_t = 300)]
+    post_window_secs: u64,
+
+    /// Events held back for phase tagging at most; beyond, the oldest are tagged early.
+    #[clap(long, default_value_t = 65536)]
+    max_held_events: usize,
+
+    /// Take a dimension from one kind of source and fuse the dimensions on a common
+    /// time grid: `DIM=SOURCE[:ALIGN]`, e.g. `theta=eeg:linear:2000`. DIM is one of e,
+    /// m_prot, s_bio, theta, t; SOURCE one of prometheus, state-file, stream, replay,
+    /// wearable, push, eeg (the first source of that kind); ALIGN is `nearest:<ms>`,
+    /// `linear:<ms>` or `last:<ms>` (default: last value up to three steps old). Every
+    /// dimension must be assigned.
+    #[clap(long, value_parser = parse_fuse)]
+    fuse: Vec<FuseArg>,
+
+    /// Grid step of fused states, in milliseconds.
+    #[clap(long, default_value_t = 1000)]
+    fuse_step_ms: u64,
+
+    /// Longest a fused state waits for a lagging source, in milliseconds.
+    #[clap(long, default_value_t = 10_000)]
+    fuse_max_delay_ms: u64,
+
+    /// Events queued for the clusterer at most.
+    #[clap(long, default_value_t = 4096)]
+    queue_capacity: usize,
+
+    /// What to do when the clusterer falls behind and the queue is full: `block` the
+    /// sources, `drop-oldest` states, or `downsample` the queued states.
+    #[clap(long, default_value = "block", value_parser = parse_overflow_policy)]
+    overflow_policy: OverflowPolicy,
+
+    #[clap(flatten)]
+    prometheus: PrometheusArgs,
 
     /// Optional directory to write polytope snapshots (JSON).
     #[clap(short, long)]
@@ -35,52 +171,161 @@ struct Args {
     snapshot_interval_secs: u64,
 }
 
-#[derive(Subcommand, Debug)]
-enum Source {
-    /// Read from a JSON file (written by the nicotine safety stack).
-    File {
-        /// Path to JSON file containing the current BiophysicalState.
-        #[clap(short, long)]
-        state_file: PathBuf,
-
-        /// Polling interval in milliseconds.
-        #[clap(long, default_value_t = 1000)]
-        poll_ms: u64,
-    },
-    /// Query Prometheus directly.
-    Prometheus {
-        /// Prometheus server URL.
-        #[clap(short, long)]
-        prometheus_url: String,
-
-        /// Prometheus query for energy (E).
-        #[clap(long, default_value = "avg_over_time(heart_rate_variability[1m])")]
-        query_e: String,
-
-        /// Prometheus query for protein burden (M_prot).
-        #[clap(long, default_value = "protein_oxidation_level")]
-        query_m_prot: String,
-
-        /// Prometheus query for biostress (S_bio).
-        #[clap(long, default_value = "stress_index")]
-        query_s_bio: String,
-
-        /// Prometheus query for theta proxy.
-        #[clap(long, default_value = "brain_theta_delta_ratio")]
-        query_theta: String,
-
-        /// Prometheus query for temperature deviation (T).
-        #[clap(long, default_value = "skin_temperature_deviation")]
-        query_t: String,
-
-        /// Prometheus query for active stimulus (returns a metric with labels id, name, etc.)
-        #[clap(long, default_value = "active_stimulus")]
-        query_stimulus: String,
-
-        /// Polling interval in milliseconds.
-        #[clap(long, default_value_t = 1000)]
-        poll_ms: u64,
-    },
+#[derive(ClapArgs, Debug)]
+#[clap(next_help_heading = "Prometheus")]
+struct PrometheusArgs {
+    /// Prometheus server URL; enables the Prometheus source.
+    #[clap(short, long)]
+    prometheus_url: Option<String>,
+
+    /// Prometheus query for energy (E).
+    #[clap(long, default_value = "avg_over_time(heart_rate_variability[1m])")]
+    query_e: String,
+
+    /// Prometheus query for protein burden (M_prot).
+    #[clap(long, default_value = "protein_oxidation_level")]
+    query_m_prot: String,
+
+    /// Prometheus query for biostress (S_bio).
+    #[clap(long, default_value = "stress_index")]
+    query_s_bio: String,
+
+    /// Prometheus query for theta proxy.
+    #[clap(long, default_value = "brain_theta_delta_ratio")]
+    query_theta: String,
+
+    /// Prometheus query for temperature deviation (T).
+    #[clap(long, default_value = "skin_temperature_deviation")]
+    query_t: String,
+
+    /// Prometheus query for active stimulus (returns a metric with labels id, name, etc.)
+    #[clap(long, default_value = "active_stimulus")]
+    query_stimulus: String,
+
+    /// Sample resolution in milliseconds, for instant and range queries alike
+    /// (defaults to the polling interval).
+    #[clap(long)]
+    step_ms: Option<u64>,
+
+    /// History to load on startup, in seconds (0 to disable).
+    #[clap(long, default_value_t = 3600)]
+    backfill_secs: u64,
+
+    /// Timeout of each Prometheus request, in milliseconds.
+    #[clap(long, default_value_t = 5000)]
+    timeout_ms: u64,
+
+    /// Retries after a failed request (with exponential backoff).
+    #[clap(long, default_value_t = 3)]
+    retries: u32,
+
+    /// How long a dimension's last good value may be carried forward, in seconds.
+    #[clap(long, default_value_t = 30)]
+    max_staleness_secs: u64,
+
+    /// Label identifying the subject of each series (one state per subject).
+    #[clap(long)]
+    subject_label: Option<String>,
+
+    /// Label identifying the device of each series.
+    #[clap(long)]
+    device_label: Option<String>,
+
+    /// Single query returning all five dimensions, told apart by `--dimension-label`
+    /// (values e, m_prot, s_bio, theta, t). Replaces the per-dimension queries.
+    #[clap(long)]
+    combined_query: Option<String>,
+
+    /// Label naming the dimension of each series of `--combined-query`.
+    #[clap(long, default_value = "dimension")]
+    dimension_label: String,
+}
+
+fn parse_replay_speed(s: &str) -> Result<ReplaySpeed, String> {
+    if s.eq_ignore_ascii_case("max") {
+        return Ok(ReplaySpeed::Unlimited);
+    }
+    match s.parse::<f64>() {
+        Ok(factor) if factor.is_finite() && factor > 0.0 => Ok(ReplaySpeed::Factor(factor)),
+        _ => Err(format!("expected a positive finite number or `max`, got `{}`", s)),
+    }
+}
+
+/// One `--fuse` assignment.
+#[derive(Debug, Clone)]
+struct FuseArg {
+    dimension: usize,
+    source: String,
+    alignment: Option<Alignment>,
+}
+
+fn parse_fuse(s: &str) -> Result<FuseArg, String> {
+    let (dimension, rest) = s.split_once('=').ok_or_else(|| format!("expected DIM=SOURCE[:ALIGN], got `{}`", s))?;
+    let dimension = DIMENSION_NAMES
+        .iter()
+        .position(|name| *name == dimension)
+        .ok_or_else(|| format!("unknown dimension `{}`", dimension))?;
+    let (source, alignment) = match rest.split_once(':') {
+        Some((source, alignment)) => (source, Some(alignment)),
+        None => (rest, None),
+    };
+    let alignment = match alignment.map(|a| a.split_once(':')) {
+        None => None,
+        Some(Some((method, ms))) => {
+            let window = Duration::from_millis(ms.parse().map_err(|_| format!("invalid milliseconds `{}`", ms))?);
+            Some(match method {
+                "nearest" => Alignment::Nearest { tolerance: window },
+                "linear" => Alignment::Linear { max_gap: window },
+                "last" => Alignment::LastObservation { max_staleness: window },
+                _ => return Err(format!("unknown alignment `{}`", method)),
+            })
+        }
+        Some(None) => return Err(format!("expected ALIGN as METHOD:<ms>, got `{}`", s)),
+    };
+    Ok(FuseArg { dimension, source: source.to_string(), alignment })
+}
+
+fn parse_overflow_policy(s: &str) -> Result<OverflowPolicy, String> {
+    match s {
+        "block" => Ok(OverflowPolicy::Block),
+        "drop-oldest" => Ok(OverflowPolicy::DropOldest),
+        "downsample" => Ok(OverflowPolicy::Downsample),
+        _ => Err(format!("expected `block`, `drop-oldest` or `downsample`, got `{}`", s)),
+    }
+}
+
+/// Write the current polytope map to `dir`.
+async fn write_snapshot(clusterer: &Mutex<PolytopeClusterer>, dir: &Path) {
+    let polytopes = clusterer.lock().await.polytopes().to_vec();
+    let snapshot = serde_json::json!({
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "polytopes": polytopes,
+    });
+    let filename = dir.join(format!("polytopes_{}.json", chrono::Utc::now().timestamp()));
+    let contents = serde_json::to_string_pretty(&snapshot).expect("snapshots are always serializable");
+    if let Err(e) = tokio::fs::write(&filename, contents).await {
+        eprintln!("Failed to write snapshot: {}", e);
+    } else {
+        info!("Wrote snapshot to {}", filename.display());
+    }
+}
+
+/// How often the pipeline's counters are logged.
+const STATS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Log a JSON snapshot of some counters every `interval`, prefixed with `name`.
+fn spawn_stats_logger<S: serde::Serialize>(
+    name: &'static str,
+    interval: Duration,
+    snapshot: impl Fn() -> S + Send + 'static,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            info!("{}: {}", name, serde_json::to_string(&snapshot()).unwrap_or_default());
+        }
+    });
 }
 
 #[tokio::main]
@@ -102,68 +347,184 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         min_weight: 3.0,
     };
     let maintenance_interval = Duration::from_secs(60);
-    let clusterer = Arc::new(Mutex::new(PolytopeClusterer::new(
-        clusterer_config,
-        maintenance_interval,
-    )));
+    let mut pipeline = IngestPipeline::new(PolytopeClusterer::new(clusterer_config, maintenance_interval))
+        .with_queue(QueueConfig {
+            capacity: args.queue_capacity,
+            policy: args.overflow_policy,
+            ..Default::default()
+        });
+    let queue_stats = pipeline.queue_stats();
+    spawn_stats_logger("Clusterer queue", STATS_INTERVAL, move || queue_stats.snapshot());
+    if let Some(path) = &args.filter_config {
+        let config = FilterConfig::from_json(&std::fs::read_to_string(path)?)?;
+        pipeline = pipeline.with_filter(config);
+    }
+    if let Some(event_log) = &args.stimulus_log {
+        pipeline = pipeline.with_phase_tagging(PhaseConfig {
+            event_log: event_log.clone(),
+            pre: Duration::from_secs(args.pre_window_secs),
+            post: Duration::from_secs(args.post_window_secs),
+            max_held: args.max_held_events,
+        });
+    }
+    if let Some(stats) = pipeline.filter_stats() {
+        spawn_stats_logger("Filter", STATS_INTERVAL, move || stats.snapshot());
+    }
+    let poll_interval = Duration::from_millis(args.poll_ms);
+
+    // Every source with its kind, as named by `--fuse`.
+    let mut sources: Vec<(&str, Box<dyn StateSource>)> = Vec::new();
+    for state_file in args.state_file {
+        let watch = if args.poll {
+            WatchMode::Poll
+        } else {
+            WatchMode::Notify { debounce: Duration::from_millis(args.debounce_ms) }
+        };
+        let config = IngesterConfig { state_file, poll_interval, watch };
+        sources.push(("state-file", Box::new(MetricsIngester::new(config))));
+    }
+    for stream_file in args.stream_file {
+        let mut offset_file = stream_file.clone().into_os_string();
+        offset_file.push(".offset");
+        let config = NdjsonTailConfig { stream_file, offset_file: Some(offset_file.into()), poll_interval };
+        sources.push(("stream", Box::new(NdjsonIngester::new(config))));
+    }
+    let theta = match &args.eeg {
+        Some(path) => {
+            let config = ThetaConfig { channels: args.eeg_channel.clone(), ..Default::default() };
+            let series = theta_series(&EdfRecording::read(path)?, &config)?;
+            info!("Derived {} theta values from {}", series.points.len(), path.display());
+            Some(series)
+        }
+        None => None,
+    };
+    for recording in args.replay {
+        let source = match &theta {
+            Some(theta) => ReplaySource::open_with_theta(recording, args.replay_speed, theta)?,
+            None => ReplaySource::open(recording, args.replay_speed)?,
+        };
+        sources.push(("replay", Box::new(source)));
+    }
+    let wearable_mapping = match &args.wearable_mapping {
+        Some(path) => Some(WearableMapping::from_json(&std::fs::read_to_string(path)?)?),
+        None => None,
+    };
+    for export in &args.wearable {
+        let (format, samples) = wearable::import(export)?;
+        let mapping = wearable_mapping.clone().unwrap_or_else(|| WearableMapping::for_format(format));
+        let events = wearable::to_states(&samples, &mapping)?.into_iter().map(IngestEvent::from).collect();
+        let name = format!("wearable {}", export.display());
+        sources.push(("wearable", Box::new(ReplaySource::from_events(name, events, args.replay_speed)?)));
+    }
+    if let Some(bind_addr) = args.push_addr {
+        let source = PushSource::bind(PushConfig {
+            bind_addr,
+            queue_capacity: args.push_queue,
+            max_batch: args.push_max_batch,
+            ..Default::default()
+        })
+        .await?;
+        let stats = source.stats();
+        spawn_stats_logger("Push endpoint", STATS_INTERVAL, move || stats.snapshot());
+        sources.push(("push", Box::new(source)));
+    }
+    let prom = args.prometheus;
+    if let Some(server_url) = prom.prometheus_url {
+        let ingester = PrometheusIngester::new(PrometheusConfig {
+            server_url,
+            query_e: prom.query_e,
+            query_m_prot: prom.query_m_prot,
+            query_s_bio: prom.query_s_bio,
+            query_theta: prom.query_theta,
+            query_t: prom.query_t,
+            query_active_stimulus: prom.query_stimulus,
+            query_stimulus_name: None, // we can extract name from labels
+            mapping: SeriesMapping {
+                subject_label: prom.subject_label,
+                device_label: prom.device_label,
+                combined_query: prom.combined_query,
+                dimension_label: prom.dimension_label,
+            },
+            poll_interval,
+            range_step: Duration::from_millis(prom.step_ms.unwrap_or(args.poll_ms)),
+            backfill: Duration::from_secs(prom.backfill_secs),
+            request_timeout: Duration::from_millis(prom.timeout_ms),
+            max_retries: prom.retries,
+            retry_backoff: Duration::from_millis(200),
+            max_staleness: Duration::from_secs(prom.max_staleness_secs),
+        });
+        let health = ingester.health();
+        spawn_stats_logger("Ingest health", STATS_INTERVAL, move || health.snapshot());
+        sources.push(("prometheus", Box::new(ingester)));
+    }
+
+    if args.fuse.is_empty() {
+        for (_, source) in sources {
+            pipeline.add_source(source);
+        }
+    } else {
+        if let (Some(theta), Some(path)) = (&theta, &args.eeg) {
+            let events = theta.to_states().into_iter().map(IngestEvent::from).collect();
+            let name = format!("eeg {}", path.display());
+            sources.push(("eeg", Box::new(ReplaySource::from_events(name, events, args.replay_speed)?)));
+        }
+        let step = Duration::from_millis(args.fuse_step_ms);
+        let mut alignment = [Alignment::LastObservation { max_staleness: 3 * step }; 5];
+        let mut kinds: Vec<&str> = Vec::new();
+        for fuse in &args.fuse {
+            if !kinds.contains(&fuse.source.as_str()) {
+                kinds.push(&fuse.source);
+            }
+            if let Some(a) = fuse.alignment {
+                alignment[fuse.dimension] = a;
+            }
+        }
+        let mut fusion = FusionSource::new(FusionConfig {
+            step,
+            alignment,
+            max_delay: Duration::from_millis(args.fuse_max_delay_ms),
+            subject_id: None,
+        });
+        for kind in kinds {
+            let index = sources
+                .iter()
+                .position(|(k, _)| *k == kind)
+                .ok_or_else(|| format!("--fuse names source `{}`, which is not configured", kind))?;
+            let dimensions: Vec<usize> = args.fuse.iter().filter(|f| f.source == kind).map(|f| f.dimension).collect();
+            fusion.add_input(&dimensions, sources.remove(index).1);
+        }
+        let missing = fusion.missing_dimensions();
+        if !missing.is_empty() {
+            return Err(format!("--fuse assigns no source to {}", missing.join(", ")).into());
+        }
+        let stats = fusion.stats();
+        spawn_stats_logger("Fusion", STATS_INTERVAL, move || stats.snapshot());
+        pipeline.add_source(Box::new(fusion));
+        for (_, source) in sources {
+            pipeline.add_source(source);
+        }
+    }
 
     // Spawn snapshot writer if requested.
-    if let Some(dir) = args.snapshot_dir {
-        tokio::fs::create_dir_all(&dir).await?;
+    let clusterer = pipeline.clusterer();
+    if let Some(dir) = &args.snapshot_dir {
+        tokio::fs::create_dir_all(dir).await?;
         let snapshot_interval = Duration::from_secs(args.snapshot_interval_secs);
         let clusterer_for_snapshots = clusterer.clone();
+        let dir = dir.clone();
         tokio::spawn(async move {
             let mut interval = tokio::time::interval(snapshot_interval);
             loop {
                 interval.tick().await;
-                let polytopes = clusterer_for_snapshots.lock().await.polytopes().clone();
-                let snapshot = serde_json::json!({
-                    "timestamp": chrono::Utc::now().to_rfc3339(),
-                    "polytopes": polytopes,
-                });
-                let filename = dir.join(format!("polytopes_{}.json", chrono::Utc::now().timestamp()));
-                if let Err(e) = tokio::fs::write(&filename, serde_json::to_string_pretty(&snapshot)?).await {
-                    eprintln!("Failed to write snapshot: {}", e);
-                } else {
-                    info!("Wrote snapshot to {}", filename.display());
-                }
+                write_snapshot(&clusterer_for_snapshots, &dir).await;
             }
         });
     }
 
-    // Run the appropriate ingester.
-    match args.source {
-        Source::File { state_file, poll_ms } => {
-            let ingester_config = IngesterConfig {
-                state_file,
-                poll_interval: Duration::from_millis(poll_ms),
-            };
-            let ingester = MetricsIngester::new(clusterer, ingester_config);
-            ingester.run().await;
-        }
-        Source::Prometheus {
-            prometheus_url,
-            query_e,
-            query_m_prot,
-            query_s_bio,
-            query_theta,
-            query_t,
-            query_stimulus,
-            poll_ms,
-        } => {
-            let prom_config = PrometheusConfig {
-                server_url: prometheus_url,
-                query_e,
-                query_m_prot,
-                query_s_bio,
-                query_theta,
-                query_t,
-                query_active_stimulus: query_stimulus,
-                query_stimulus_name: None, // we can extract name from labels
-                poll_interval: Duration::from_millis(poll_ms),
-            };
-            let ingester = PrometheusIngester::new(clusterer, prom_config);
-            ingester.run().await;
-        }
+    // Returns once every source is exhausted (only replays ever are).
+    pipeline.run().await;
+    if let Some(dir) = &args.snapshot_dir {
+        write_snapshot(&clusterer, dir).await;
     }
+    Ok(())
 }
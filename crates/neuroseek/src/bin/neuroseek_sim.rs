@@ -5,35 +5,34 @@
 //! Run with: cargo run --bin neuroseek_sim
 
 use neuroseek::clustering::{ClustererConfig, PolytopeClusterer};
-use neuroseek::model::BiophysicalState;
+use chrono::Utc;
+use neuroseek::model::{BiophysicalState, TaggedState};
 use rand::Rng;
 use serde_json::json;
 use std::fs::File;
 use std::io::Write;
 use std::path::Path;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 /// Generate a random state within a specified region.
 fn random_state_in_region(rng: &mut impl Rng, center: &BiophysicalState, spread: f64) -> BiophysicalState {
-    BiophysicalState::new(
+    let state = BiophysicalState::new(
         center.e + rng.gen_range(-spread..spread),
         center.m_prot + rng.gen_range(-spread..spread),
         center.s_bio + rng.gen_range(-spread..spread),
         center.theta + rng.gen_range(-spread..spread),
         center.t + rng.gen_range(-spread..spread),
-    )
-    .clamp(0.0, 1.0) // keep within normalized range
+    );
+    clamp(state, 0.0, 1.0) // keep within normalized range
 }
 
-impl BiophysicalState {
-    fn clamp(mut self, min: f64, max: f64) -> Self {
-        self.e = self.e.clamp(min, max);
-        self.m_prot = self.m_prot.clamp(min, max);
-        self.s_bio = self.s_bio.clamp(min, max);
-        self.theta = self.theta.clamp(min, max);
-        self.t = self.t.clamp(min, max);
-        self
-    }
+fn clamp(mut state: BiophysicalState, min: f64, max: f64) -> BiophysicalState {
+    state.e = state.e.clamp(min, max);
+    state.m_prot = state.m_prot.clamp(min, max);
+    state.s_bio = state.s_bio.clamp(min, max);
+    state.theta = state.theta.clamp(min, max);
+    state.t = state.t.clamp(min, max);
+    state
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -49,13 +48,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut clusterer = PolytopeClusterer::new(config, maintenance_interval);
 
     // Simulation parameters
-    let total_steps = 1000;
+    let total_steps: u32 = 1000;
     let step_duration = Duration::from_secs(1); // 1 simulated second per step
     let mut rng = rand::thread_rng();
-    let start_time = Instant::now();
+    let start_time = Utc::now();
 
     // Define a few attractor regions (simulating different biophysical modes)
-    let regions = vec![
+    let regions = [
         (BiophysicalState::new(0.2, 0.3, 0.2, 0.4, 0.3), 0.05), // "calm" region
         (BiophysicalState::new(0.7, 0.6, 0.8, 0.3, 0.2), 0.08), // "stressed" region
         (BiophysicalState::new(0.5, 0.5, 0.5, 0.5, 0.5), 0.1),  // "neutral" region
@@ -66,20 +65,20 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     std::fs::create_dir_all(out_dir)?;
 
     for step in 0..total_steps {
-        let now = start_time + step * step_duration;
+        let now = start_time + chrono::Duration::from_std(step * step_duration)?;
 
         // Generate a point by picking a random region and adding noise
         let (center, spread) = &regions[rng.gen_range(0..regions.len())];
         let point = random_state_in_region(&mut rng, center, *spread);
 
         // Insert into clusterer
-        clusterer.insert_point(point, now);
+        clusterer.insert_point(TaggedState::new(point, now, None));
 
         // Occasionally output a snapshot
         if step % 100 == 0 || step == total_steps - 1 {
             let snapshot = json!({
                 "step": step,
-                "time_sec": step * step_duration.as_secs(),
+                "time_sec": u64::from(step) * step_duration.as_secs(),
                 "polytopes": clusterer.polytopes().iter().map(|p| {
                     json!({
                         "id": p.id.to_string(),
@@ -0,0 +1,270 @@
+//! Bioload band classification (see `NEUROSEEK_BIOLOAD_SPEC.md`).
+//!
+//! The bioload of a subject is measured against their own baseline: each biophysical
+//! dimension is expressed as a deviation in baseline standard deviations, and the load
+//! is the largest deviation. Loads up to `yellow_sd` are GREEN, up to `red_sd` YELLOW,
+//! and above that RED.
+//!
+//! `BioloadClassifier` adds hysteresis: it escalates as soon as the load crosses a
+//! threshold, but de-escalates only once the load has fallen `hysteresis_sd` below the
+//! threshold of the band it is in, so noise around a threshold does not make it flap.
+
+use crate::biophysics::BiophysicalState;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Smallest standard deviation used when normalizing, to avoid division by zero for
+/// dimensions that never moved in the baseline.
+const MIN_SD: f64 = 1e-9;
+
+/// Risk band of the subject's current bioload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum BioloadBand {
+    /// Routine safe operation.
+    Green,
+    /// Biostretched zone: load may be maintained but not increased.
+    Yellow,
+    /// Emergency protective response: only wind-down is allowed.
+    Red,
+}
+
+impl fmt::Display for BioloadBand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BioloadBand::Green => "GREEN",
+            BioloadBand::Yellow => "YELLOW",
+            BioloadBand::Red => "RED",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Thresholds of the band classification, in baseline standard deviations.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BioloadConfig {
+    /// Loads above this are at least YELLOW.
+    pub yellow_sd: f64,
+    /// Loads above this are RED.
+    pub red_sd: f64,
+    /// How far below a band's threshold the load must fall before the classifier
+    /// leaves that band.
+    pub hysteresis_sd: f64,
+}
+
+impl Default for BioloadConfig {
+    fn default() -> Self {
+        Self {
+            yellow_sd: 1.0,
+            red_sd: 2.0,
+            hysteresis_sd: 0.25,
+        }
+    }
+}
+
+impl BioloadConfig {
+    /// Band of a load without hysteresis.
+    pub fn band_for(&self, load: f64) -> BioloadBand {
+        if load > self.red_sd {
+            BioloadBand::Red
+        } else if load > self.yellow_sd {
+            BioloadBand::Yellow
+        } else {
+            BioloadBand::Green
+        }
+    }
+}
+
+/// A subject's personal baseline: mean and standard deviation per dimension.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PersonalBaseline {
+    pub mean: BiophysicalState,
+    pub sd: BiophysicalState,
+}
+
+impl PersonalBaseline {
+    /// Estimate a baseline from historical states. Needs at least two samples.
+    pub fn from_samples(samples: &[BiophysicalState]) -> Option<Self> {
+        if samples.len() < 2 {
+            return None;
+        }
+        let n = samples.len() as f64;
+        let mut mean = [0.0; 5];
+        for s in samples {
+            for (m, v) in mean.iter_mut().zip(s.to_array()) {
+                *m += v / n;
+            }
+        }
+        let mut var = [0.0; 5];
+        for s in samples {
+            for (i, v) in s.to_array().iter().enumerate() {
+                var[i] += (v - mean[i]).powi(2) / (n - 1.0);
+            }
+        }
+        Some(Self {
+            mean: BiophysicalState::from_array(mean),
+            sd: BiophysicalState::from_array(var.map(f64::sqrt)),
+        })
+    }
+
+    /// Absolute deviation of a state from the mean, per dimension, in standard deviations.
+    pub fn deviations(&self, state: &BiophysicalState) -> [f64; 5] {
+        let (x, mean, sd) = (state.to_array(), self.mean.to_array(), self.sd.to_array());
+        let mut out = [0.0; 5];
+        for i in 0..5 {
+            out[i] = (x[i] - mean[i]).abs() / sd[i].max(MIN_SD);
+        }
+        out
+    }
+
+    /// Load of a state: its largest deviation.
+    pub fn load(&self, state: &BiophysicalState) -> f64 {
+        self.deviations(state).into_iter().fold(0.0, f64::max)
+    }
+
+    /// Largest load over a per-dimension `(min, max)` range of states.
+    pub fn load_of_range(&self, range: &[(f64, f64); 5]) -> f64 {
+        let (mean, sd) = (self.mean.to_array(), self.sd.to_array());
+        (0..5)
+            .map(|i| (range[i].0 - mean[i]).abs().max((range[i].1 - mean[i]).abs()) / sd[i].max(MIN_SD))
+            .fold(0.0, f64::max)
+    }
+}
+
+/// Stateful band classifier with hysteresis.
+#[derive(Debug, Clone)]
+pub struct BioloadClassifier {
+    config: BioloadConfig,
+    band: Option<BioloadBand>,
+}
+
+impl BioloadClassifier {
+    pub fn new(config: BioloadConfig) -> Self {
+        Self { config, band: None }
+    }
+
+    /// The current band, if any state has been classified yet.
+    pub fn band(&self) -> Option<BioloadBand> {
+        self.band
+    }
+
+    /// Classify a new state and return the (possibly unchanged) band.
+    pub fn update(&mut self, state: &BiophysicalState, baseline: &PersonalBaseline) -> BioloadBand {
+        let next = self.peek(state, baseline);
+        self.band = Some(next);
+        next
+    }
+
+    /// The band `update` would return for a state, without recording it.
+    pub fn peek(&self, state: &BiophysicalState, baseline: &PersonalBaseline) -> BioloadBand {
+        let load = baseline.load(state);
+        let raw = self.config.band_for(load);
+        match self.band {
+            Some(current) if raw < current => {
+                // Leave a band only once the load is clear of its threshold.
+                current.min(self.config.band_for(load + self.config.hysteresis_sd))
+            }
+            _ => raw,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::biofield::{BiofieldProtocol, Waveform};
+    use crate::compiler::rules::{SubjectContext, RULE_BIOLOAD};
+    use crate::compiler::{CompilationResult, Compiler, Stimulus};
+    use crate::profile::CompilerProfile;
+
+    fn baseline() -> PersonalBaseline {
+        PersonalBaseline {
+            mean: BiophysicalState::new(0.4, 0.1, 0.3, 0.5, 0.0),
+            sd: BiophysicalState::new(0.1, 0.05, 0.1, 0.1, 0.2),
+        }
+    }
+
+    fn with_stress(s_bio: f64) -> BiophysicalState {
+        BiophysicalState::new(0.4, 0.1, s_bio, 0.5, 0.0)
+    }
+
+    #[test]
+    fn test_classifier_hysteresis() {
+        let mut classifier = BioloadClassifier::new(BioloadConfig::default());
+        let b = baseline();
+        assert_eq!(classifier.update(&with_stress(0.35), &b), BioloadBand::Green);
+        // 2.1 SD escalates straight to RED.
+        assert_eq!(classifier.update(&with_stress(0.51), &b), BioloadBand::Red);
+        // 1.9 SD is YELLOW without hysteresis, but still too close to leave RED.
+        assert_eq!(classifier.update(&with_stress(0.49), &b), BioloadBand::Red);
+        assert_eq!(classifier.update(&with_stress(0.47), &b), BioloadBand::Yellow);
+        // 0.9 SD would be GREEN, but YELLOW holds until 0.75 SD.
+        assert_eq!(classifier.update(&with_stress(0.39), &b), BioloadBand::Yellow);
+        assert_eq!(classifier.update(&with_stress(0.37), &b), BioloadBand::Green);
+    }
+
+    fn stress_protocol(stress_delta: f64) -> Stimulus {
+        Stimulus::Biofield(BiofieldProtocol {
+            id: "stress".into(),
+            waveform_type: Waveform::Sine,
+            frequency_hz: 10.0,
+            amplitude_ua: 20.0,
+            duration_sec: 300,
+            energy_delta: 0.0,
+            protein_delta: 0.0,
+            stress_delta,
+            theta_delta: 0.0,
+            delta_temp_delta: 0.0,
+        })
+    }
+
+    #[test]
+    fn test_bands_gate_compilation() {
+        let profile = CompilerProfile::from_json(include_str!("../profiles/default.json")).unwrap();
+        let compiler = Compiler::from_profile(profile);
+        let subject = |s_bio: f64| SubjectContext {
+            current_state: Some(with_stress(s_bio)),
+            baseline: Some(baseline()),
+            ..Default::default()
+        };
+        let approved = |r: CompilationResult| matches!(r, CompilationResult::Approved { .. });
+
+        // YELLOW (1.5 SD): holding or lowering the load is fine, raising it is not.
+        assert!(approved(compiler.compile_for(stress_protocol(0.0), &subject(0.45))));
+        assert!(approved(compiler.compile_for(stress_protocol(-0.1), &subject(0.45))));
+        match compiler.compile_for(stress_protocol(0.05), &subject(0.45)) {
+            CompilationResult::Rejected { reasons, .. } => {
+                assert!(reasons.iter().all(|r| r.rule_id == RULE_BIOLOAD));
+                assert!(reasons[0].evidence.contains("YELLOW"));
+            }
+            CompilationResult::Approved { .. } => panic!("expected rejection"),
+        }
+
+        // RED (2.5 SD): only protocols that bring the load down.
+        assert!(!approved(compiler.compile_for(stress_protocol(0.0), &subject(0.55))));
+        assert!(approved(compiler.compile_for(stress_protocol(-0.15), &subject(0.55))));
+    }
+
+    #[test]
+    fn test_compiler_band_does_not_flap_at_threshold() {
+        let profile = CompilerProfile::from_json(include_str!("../profiles/default.json")).unwrap();
+        let compiler = Compiler::from_profile(profile);
+        let subject = |id: &str, s_bio: f64| SubjectContext {
+            subject_id: Some(id.into()),
+            current_state: Some(with_stress(s_bio)),
+            baseline: Some(baseline()),
+            ..Default::default()
+        };
+        let approved = |r: CompilationResult| matches!(r, CompilationResult::Approved { .. });
+
+        // 1.9 SD alone is YELLOW, where holding the load is allowed.
+        assert!(approved(compiler.compile_for(stress_protocol(0.0), &subject("fresh", 0.49))));
+        // Right after 2.1 SD (RED), the same 1.9 SD stays RED for this subject.
+        assert!(!approved(compiler.compile_for(stress_protocol(0.0), &subject("s1", 0.51))));
+        assert!(!compiler.explain_for(&stress_protocol(0.0), &subject("s1", 0.49)).would_approve);
+        assert!(!approved(compiler.compile_for(stress_protocol(0.0), &subject("s1", 0.49))));
+        // Clear of the threshold, it leaves RED.
+        assert!(approved(compiler.compile_for(stress_protocol(0.0), &subject("s1", 0.47))));
+    }
+}
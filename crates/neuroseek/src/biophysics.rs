@@ -15,9 +15,42 @@ impl BiophysicalState {
     pub fn new(e: f64, m_prot: f64, s_bio: f64, theta: f64, t: f64) -> Self {
         Self { e, m_prot, s_bio, theta, t }
     }
+
+    /// Build a state from `[E, M_prot, S_bio, theta, T]`.
+    pub fn from_array(z: [f64; 5]) -> Self {
+        Self::new(z[0], z[1], z[2], z[3], z[4])
+    }
+
+    /// The state as `[E, M_prot, S_bio, theta, T]`.
+    pub fn to_array(&self) -> [f64; 5] {
+        [self.e, self.m_prot, self.s_bio, self.theta, self.t]
+    }
+}
+
+/// Dimension names in array order, for reports.
+pub const DIMENSION_NAMES: [&str; 5] = ["E", "M_prot", "S_bio", "theta", "T"];
+
+/// How much room a worst-case projection leaves inside the corridor.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CorridorMargin {
+    /// Worst-case `(min, max)` per dimension over the protocol and the transfer uncertainty.
+    pub worst_case: [(f64, f64); 5],
+    /// Per-dimension margin: the smaller gap to the lower or upper bound. Negative means violated.
+    pub per_dimension: [f64; 5],
+    /// Smallest margin across all dimensions.
+    pub min_margin: f64,
 }
 
-#[derive(Debug, Clone)]
+impl CorridorMargin {
+    /// Whether every dimension stays within the corridor.
+    pub fn holds(&self) -> bool {
+        self.min_margin >= 0.0
+    }
+}
+
+/// Allowed `(min, max)` interval per dimension.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct BiophysicalCorridor {
     pub e: (f64, f64),
     pub m_prot: (f64, f64),
@@ -34,4 +67,22 @@ impl BiophysicalCorridor {
         state.theta >= self.theta.0 && state.theta <= self.theta.1 &&
         state.t >= self.t.0 && state.t <= self.t.1
     }
+
+    /// Bounds in array order `[E, M_prot, S_bio, theta, T]`.
+    pub fn as_array(&self) -> [(f64, f64); 5] {
+        [self.e, self.m_prot, self.s_bio, self.theta, self.t]
+    }
+
+    /// Margin of a worst-case interval projection against this corridor.
+    pub fn margin(&self, worst_case: [(f64, f64); 5]) -> CorridorMargin {
+        let bounds = self.as_array();
+        let mut per_dimension = [0.0; 5];
+        for i in 0..5 {
+            let lower_gap = worst_case[i].0 - bounds[i].0;
+            let upper_gap = bounds[i].1 - worst_case[i].1;
+            per_dimension[i] = lower_gap.min(upper_gap);
+        }
+        let min_margin = per_dimension.iter().cloned().fold(f64::INFINITY, f64::min);
+        CorridorMargin { worst_case, per_dimension, min_margin }
+    }
 }
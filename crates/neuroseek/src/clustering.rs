@@ -3,8 +3,9 @@
 //! Implements an adaptive streaming algorithm with decay, merging, and pruning.
 //! Pure Rust, no I/O, no device control—safe for observation-only mode.
 
-use crate::model::{MicroPolytope, TaggedState, BiophysicalState};
-use std::time::{Duration, Instant};
+use crate::model::{seconds_between, MicroPolytope, TaggedState, BiophysicalState};
+use chrono::{DateTime, Utc};
+use std::time::Duration;
 
 /// Configuration for the polytope clusterer.
 #[derive(Debug, Clone, Copy)]
@@ -37,7 +38,9 @@ impl Default for ClustererConfig {
 pub struct PolytopeClusterer {
     polytopes: Vec<MicroPolytope>,
     config: ClustererConfig,
-    last_maintenance: Instant,
+    /// Time of the last maintenance pass; starts at the first inserted point, so that
+    /// maintenance follows the sources' clock rather than the wall clock.
+    last_maintenance: Option<DateTime<Utc>>,
     maintenance_interval: Duration,
 }
 
@@ -47,45 +50,76 @@ impl PolytopeClusterer {
         Self {
             polytopes: Vec::new(),
             config,
-            last_maintenance: Instant::now(),
+            last_maintenance: None,
             maintenance_interval,
         }
     }
 
-    /// Insert a new observation point at time `now`.
+    /// Insert a new observation point, timed by its own timestamp.
     pub fn insert_point(&mut self, point: TaggedState) {
-        let now = point.timestamp;
-        // Find the nearest polytope within its radius * radius_factor.
-        let mut best_idx = None;
-        let mut best_dist = f64::INFINITY;
-
-        for (i, poly) in self.polytopes.iter().enumerate() {
-            let dist = point.state.distance(&poly.centroid());
-            let threshold = poly.radius() * self.config.radius_factor;
-            if dist <= threshold && dist < best_dist {
-                best_idx = Some(i);
-                best_dist = dist;
+        self.insert_batch(std::iter::once(point));
+    }
+
+    /// Insert observation points in order, each timed by its own timestamp.
+    ///
+    /// Each point joins the nearest polytope within its radius * radius_factor, as with
+    /// `insert_point`, and is still compared against every polytope. What a batch saves
+    /// is recomputing each polytope's centroid and threshold for every point: they are
+    /// cached, and only refreshed for the polytope a point updates and after
+    /// maintenance, which runs after each point that completes an interval.
+    pub fn insert_batch(&mut self, points: impl IntoIterator<Item = TaggedState>) {
+        let radius_factor = self.config.radius_factor;
+        let membership = |poly: &MicroPolytope| (poly.centroid(), poly.radius() * radius_factor);
+        let mut cache: Vec<(BiophysicalState, f64)> = self.polytopes.iter().map(membership).collect();
+
+        for point in points {
+            // Find the nearest polytope within its radius * radius_factor.
+            let mut best_idx = None;
+            let mut best_dist = f64::INFINITY;
+            for (i, (centroid, threshold)) in cache.iter().enumerate() {
+                let dist = point.state.distance(centroid);
+                if dist <= *threshold && dist < best_dist {
+                    best_idx = Some(i);
+                    best_dist = dist;
+                }
+            }
+
+            if let Some(idx) = best_idx {
+                // Update existing polytope.
+                self.polytopes[idx].update(&point, self.config.decay_rate);
+                cache[idx] = membership(&self.polytopes[idx]);
+            } else {
+                // Create a new polytope.
+                let poly = MicroPolytope::from_point(&point);
+                cache.push(membership(&poly));
+                self.polytopes.push(poly);
             }
-        }
 
-        if let Some(idx) = best_idx {
-            // Update existing polytope.
-            self.polytopes[idx].update(&point, self.config.decay_rate);
-        } else {
-            // Create a new polytope.
-            self.polytopes.push(MicroPolytope::from_point(&point));
+            // Periodic maintenance, which may merge, prune and reorder polytopes.
+            if self.maintain_if_due(point.timestamp) {
+                cache = self.polytopes.iter().map(membership).collect();
+            }
         }
+    }
 
-        // Periodic maintenance.
-        if now.duration_since(self.last_maintenance) >= self.maintenance_interval {
-            self.maintenance(now);
+    /// Run maintenance at `now` if an interval has passed since the last pass. Returns
+    /// whether it ran.
+    fn maintain_if_due(&mut self, now: DateTime<Utc>) -> bool {
+        match self.last_maintenance {
+            None => self.last_maintenance = Some(now),
+            Some(last) if seconds_between(last, now) >= self.maintenance_interval.as_secs_f64() => {
+                self.maintenance(now);
+                return true;
+            }
+            Some(_) => {}
         }
+        false
     }
 
     /// Apply time decay to all polytopes (called internally, but can be exposed if needed).
-    pub fn tick_decay(&mut self, now: Instant) {
+    pub fn tick_decay(&mut self, now: DateTime<Utc>) {
         for poly in &mut self.polytopes {
-            let dt = now.duration_since(poly.last_update).as_secs_f64();
+            let dt = seconds_between(poly.last_update, now);
             if dt > 0.0 {
                 poly.decay(self.config.decay_rate.powf(dt));
                 poly.last_update = now;
@@ -134,11 +168,11 @@ impl PolytopeClusterer {
     }
 
     /// Perform full maintenance: decay, merge, prune.
-    pub fn maintenance(&mut self, now: Instant) {
+    pub fn maintenance(&mut self, now: DateTime<Utc>) {
         self.tick_decay(now);
         self.merge_close();
         self.prune();
-        self.last_maintenance = now;
+        self.last_maintenance = Some(now);
     }
 
     /// Get a reference to the current polytopes.
@@ -148,7 +182,7 @@ impl PolytopeClusterer {
 
     /// Associate a craving event with the polytope containing the given state.
     /// If no polytope contains the state, do nothing.
-    pub fn associate_craving(&mut self, state: &BiophysicalState, intensity: f64, now: Instant) {
+    pub fn associate_craving(&mut self, state: &BiophysicalState, intensity: f64, now: DateTime<Utc>) {
         if let Some(poly) = self
             .polytopes
             .iter_mut()
@@ -181,11 +215,7 @@ mod tests {
             stimulus_name: "test".into(),
             audio_params: None,
         });
-        TaggedState {
-            state,
-            timestamp: Instant::now(),
-            stimulus,
-        }
+        TaggedState::new(state, Utc::now(), stimulus)
     }
 
     #[test]
@@ -208,4 +238,31 @@ mod tests {
         let poly_b = &clusterer.polytopes()[1];
         assert_eq!(poly_b.stimulus_counts.get("stimB"), Some(&1));
     }
+
+    #[test]
+    fn test_insert_batch_matches_insert_point() {
+        let t0 = Utc::now();
+        // Three recurring states, two of them close enough to merge, and an occasional
+        // outlier that maintenance prunes.
+        let points: Vec<TaggedState> = (0..60)
+            .map(|i| {
+                let x = if i % 13 == 0 { 0.95 } else { [0.0, 0.05, 0.4, 0.8][i as usize % 4] };
+                let mut p = test_point(x, x, 0.1, 0.1, 0.1, None);
+                // Five seconds apart: the batch crosses ten 30 s maintenance intervals.
+                p.timestamp = t0 + chrono::Duration::seconds(5 * i);
+                p
+            })
+            .collect();
+        let mut one_by_one = PolytopeClusterer::new(ClustererConfig::default(), Duration::from_secs(30));
+        for p in points.clone() {
+            one_by_one.insert_point(p);
+        }
+        let mut batched = PolytopeClusterer::new(ClustererConfig::default(), Duration::from_secs(30));
+        batched.insert_batch(points);
+
+        let summary = |c: &PolytopeClusterer| -> Vec<(f64, [f64; 5])> {
+            c.polytopes().iter().map(|p| (p.weight, p.linear_sum)).collect()
+        };
+        assert_eq!(summary(&one_by_one), summary(&batched));
+    }
 }
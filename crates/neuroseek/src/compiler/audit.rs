@@ -0,0 +1,322 @@
+//! Hash-chained audit trail of compiler decisions.
+//!
+//! With an `AuditLog` attached (`Compiler::with_audit_log`), every `compile` call
+//! appends one JSON line to a local append-only file. Each entry carries the canonical
+//! hash of the stimulus, the profile hash, the decision with its typed findings, the
+//! token if one was issued, and the hash of the previous entry. The entry's own hash
+//! covers all of that, so editing, removing or reordering any line breaks the chain
+//! from that point on; `verify` walks the file and reports the first break.
+//!
+//! The log fails closed: if an approval cannot be recorded, the compiler turns it
+//! into a rejection.
+//!
+//! Several processes may append to the same log: each append holds an exclusive
+//! advisory lock on the file while it reads the current head and writes its entry, so
+//! concurrent writers extend one chain instead of forking it.
+//!
+//! The chain alone cannot reveal that entries were cut off its end: a truncated log is
+//! a valid, shorter chain. To detect that, keep the `AuditSummary` of a verified log
+//! somewhere else (it is printed by `neuroseek_audit`) and check the log against it
+//! later with `verify_anchored`.
+
+use super::rules::{Finding, SubjectContext};
+use super::CompilationResult;
+use crate::profile::canonical_hash;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// `prev_hash` of the first entry.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Errors raised while writing or verifying an audit log.
+#[derive(Debug, thiserror::Error)]
+pub enum AuditError {
+    #[error("Audit log I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Audit log line {line} is not a valid entry: {source}")]
+    Parse { line: usize, source: serde_json::Error },
+    #[error("Audit log line {line}: expected sequence number {expected}, found {found}")]
+    Gap { line: usize, expected: u64, found: u64 },
+    #[error("Audit log line {line}: link to the previous entry is broken")]
+    BrokenLink { line: usize },
+    #[error("Audit log line {line}: entry hash does not match its contents")]
+    HashMismatch { line: usize },
+    #[error("Audit log has {found} entries, fewer than the {expected} of its anchor")]
+    Truncated { expected: u64, found: u64 },
+    #[error("Audit log entry {seq} does not match the anchor's head hash")]
+    AnchorMismatch { seq: u64 },
+}
+
+/// Outcome of a compilation, as recorded in the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Decision {
+    Approved,
+    Rejected,
+}
+
+/// One line of the audit log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Position in the log, starting at 0.
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    pub stimulus_name: String,
+    /// Canonical hash of the compiled stimulus (see `Stimulus::canonical_hash`).
+    pub stimulus_hash: String,
+    pub profile_hash: String,
+    pub subject_id: Option<String>,
+    pub decision: Decision,
+    pub reasons: Vec<Finding>,
+    pub warnings: Vec<Finding>,
+    pub token: Option<Uuid>,
+    /// `entry_hash` of the previous entry, or `GENESIS_HASH`.
+    pub prev_hash: String,
+    /// Canonical hash of every other field of this entry.
+    pub entry_hash: String,
+}
+
+impl AuditEntry {
+    /// Hash of the entry's contents, excluding `entry_hash` itself.
+    pub fn compute_hash(&self) -> String {
+        let mut value = serde_json::to_value(self).expect("audit entries are always serializable");
+        if let Some(fields) = value.as_object_mut() {
+            fields.remove("entry_hash");
+        }
+        canonical_hash(&value)
+    }
+}
+
+/// Summary of a verified log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditSummary {
+    pub entries: u64,
+    /// `entry_hash` of the last entry, or `GENESIS_HASH` for an empty log.
+    pub head_hash: String,
+}
+
+/// Head of the log as last seen by this writer.
+struct Head {
+    summary: AuditSummary,
+    /// File length after the last entry this writer saw.
+    len: u64,
+}
+
+/// An append-only, hash-chained log file.
+pub struct AuditLog {
+    path: PathBuf,
+    head: Mutex<Head>,
+}
+
+impl AuditLog {
+    /// Open (or create) a log. An existing log is verified first, so that new entries
+    /// are never chained onto a tampered history.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, AuditError> {
+        let path = path.into();
+        let head = if path.exists() {
+            Head { summary: verify(&path)?, len: std::fs::metadata(&path)?.len() }
+        } else {
+            Head { summary: AuditSummary { entries: 0, head_hash: GENESIS_HASH.to_string() }, len: 0 }
+        };
+        Ok(Self { path, head: Mutex::new(head) })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Record one compilation decision.
+    pub fn record(
+        &self,
+        stimulus_name: &str,
+        stimulus_hash: &str,
+        profile_hash: &str,
+        subject: &SubjectContext,
+        result: &CompilationResult,
+    ) -> Result<AuditEntry, AuditError> {
+        let (decision, reasons, warnings, token) = match result {
+            CompilationResult::Approved { token, warnings, .. } => {
+                (Decision::Approved, Vec::new(), warnings.clone(), Some(*token))
+            }
+            CompilationResult::Rejected { reasons, .. } => (Decision::Rejected, reasons.clone(), Vec::new(), None),
+        };
+
+        let mut head = self.head.lock().unwrap_or_else(|e| e.into_inner());
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        // Held until `file` is dropped; other writers wait here.
+        file.lock()?;
+        let len = file.metadata()?.len();
+        if len != head.len {
+            // Another writer appended since; continue from its head.
+            head.summary = verify(&self.path)?;
+        }
+
+        let mut entry = AuditEntry {
+            seq: head.summary.entries,
+            timestamp: Utc::now(),
+            stimulus_name: stimulus_name.to_string(),
+            stimulus_hash: stimulus_hash.to_string(),
+            profile_hash: profile_hash.to_string(),
+            subject_id: subject.subject_id.clone(),
+            decision,
+            reasons,
+            warnings,
+            token,
+            prev_hash: head.summary.head_hash.clone(),
+            entry_hash: String::new(),
+        };
+        entry.entry_hash = entry.compute_hash();
+
+        let mut line = serde_json::to_string(&entry).expect("audit entries are always serializable");
+        line.push('\n');
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+
+        head.summary.entries += 1;
+        head.summary.head_hash = entry.entry_hash.clone();
+        head.len = file.metadata()?.len();
+        Ok(entry)
+    }
+}
+
+/// Walk a log and check sequence numbers, links and entry hashes.
+///
+/// Returns the first problem found: a line that does not parse, a missing or
+/// repeated sequence number, a `prev_hash` that does not match the previous entry,
+/// or an entry whose contents no longer match its hash.
+pub fn verify(path: &Path) -> Result<AuditSummary, AuditError> {
+    walk(path, |_| {})
+}
+
+/// Verify a log and check that it still contains the head recorded in `anchor`, an
+/// earlier summary of the same log. Detects entries cut off the end since then.
+pub fn verify_anchored(path: &Path, anchor: &AuditSummary) -> Result<AuditSummary, AuditError> {
+    let mut anchored = anchor.entries == 0;
+    let summary = walk(path, |entry| {
+        if entry.seq + 1 == anchor.entries {
+            anchored = entry.entry_hash == anchor.head_hash;
+        }
+    })?;
+    if summary.entries < anchor.entries {
+        return Err(AuditError::Truncated { expected: anchor.entries, found: summary.entries });
+    }
+    if !anchored {
+        return Err(AuditError::AnchorMismatch { seq: anchor.entries - 1 });
+    }
+    Ok(summary)
+}
+
+/// Walk and check a log, handing every valid entry to `visit`.
+fn walk(path: &Path, mut visit: impl FnMut(&AuditEntry)) -> Result<AuditSummary, AuditError> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut summary = AuditSummary { entries: 0, head_hash: GENESIS_HASH.to_string() };
+    for (i, line) in reader.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: AuditEntry =
+            serde_json::from_str(&line).map_err(|source| AuditError::Parse { line: line_no, source })?;
+        if entry.seq != summary.entries {
+            return Err(AuditError::Gap { line: line_no, expected: summary.entries, found: entry.seq });
+        }
+        if entry.prev_hash != summary.head_hash {
+            return Err(AuditError::BrokenLink { line: line_no });
+        }
+        if entry.compute_hash() != entry.entry_hash {
+            return Err(AuditError::HashMismatch { line: line_no });
+        }
+        visit(&entry);
+        summary.entries += 1;
+        summary.head_hash = entry.entry_hash;
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{AudioStimulus, Compiler, Stimulus};
+    use crate::profile::CompilerProfile;
+    use neuroseek_audio::audio_nanopolytope::AudioState;
+    use neuroseek_audio::config::Protocol as AudioProtocol;
+
+    fn stimulus(amplitude: f64) -> Stimulus {
+        Stimulus::Audio(Box::new(AudioStimulus {
+            protocol: AudioProtocol::Fixed(AudioState {
+                amplitude,
+                carrier_hz: 200.0,
+                beat_hz: 10.0,
+                duty: 0.1,
+                session_duration_sec: 600.0,
+            }),
+            name: "fixed".into(),
+            description: None,
+        }))
+    }
+
+    fn compiler(path: &Path) -> Compiler {
+        let profile = CompilerProfile::from_json(include_str!("../../profiles/default.json")).unwrap();
+        Compiler::from_profile(profile).with_audit_log(AuditLog::open(path).unwrap())
+    }
+
+    #[test]
+    fn test_decisions_are_chained_and_tampering_detected() {
+        let path = std::env::temp_dir().join(format!("neuroseek-audit-{}.ndjson", Uuid::new_v4()));
+        let compiler = compiler(&path);
+        assert!(matches!(compiler.compile(stimulus(0.5)), CompilationResult::Approved { .. }));
+        assert!(matches!(compiler.compile(stimulus(1.5)), CompilationResult::Rejected { .. }));
+
+        // Reopening continues the chain.
+        let compiler = self::compiler(&path);
+        compiler.compile(stimulus(0.6));
+        let summary = verify(&path).unwrap();
+        assert_eq!(summary.entries, 3);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        let second: AuditEntry = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(second.decision, Decision::Rejected);
+        assert_eq!(second.reasons[0].rule_id, "hardware");
+
+        // Editing a decision breaks its hash.
+        let tampered = contents.replacen("\"decision\":\"rejected\"", "\"decision\":\"approved\"", 1);
+        std::fs::write(&path, tampered).unwrap();
+        assert!(matches!(verify(&path), Err(AuditError::HashMismatch { line: 2 })));
+
+        // Dropping an entry leaves a gap.
+        std::fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        assert!(matches!(verify(&path), Err(AuditError::Gap { line: 2, expected: 1, found: 2 })));
+        assert!(AuditLog::open(&path).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_writers_and_anchored_truncation() {
+        let path = std::env::temp_dir().join(format!("neuroseek-audit-{}.ndjson", Uuid::new_v4()));
+        // Two writers on one file, as two processes would be.
+        let (first, second) = (compiler(&path), compiler(&path));
+        first.compile(stimulus(0.5));
+        second.compile(stimulus(0.6));
+        first.compile(stimulus(0.7));
+        let anchor = verify(&path).unwrap();
+        assert_eq!(anchor.entries, 3);
+        assert_eq!(verify_anchored(&path, &anchor).unwrap(), anchor);
+
+        // Cutting off the last entry leaves a valid chain, but not the anchored one.
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let kept: Vec<&str> = contents.lines().take(2).collect();
+        std::fs::write(&path, format!("{}\n", kept.join("\n"))).unwrap();
+        assert_eq!(verify(&path).unwrap().entries, 2);
+        assert!(matches!(verify_anchored(&path, &anchor), Err(AuditError::Truncated { expected: 3, found: 2 })));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,207 @@
+//! Biofield stimulus modality (compile and validation only).
+//!
+//! A `BiofieldProtocol` describes an emission pattern by waveform, frequency, current
+//! in microamps and duration, together with its estimated effect on the 5D
+//! biophysical state as per-dimension deltas. There is no transfer model: the
+//! projected trajectory runs linearly from the subject's current state to
+//! `current + deltas`, so its worst case per dimension is the interval between the
+//! two end points. Without a current state there is nothing to project from, and the
+//! corridor check fails.
+//!
+//! Hardware limits live in `HardwareCapabilities::biofield`; a profile without them
+//! rejects every biofield stimulus.
+
+use crate::biophysics::{BiophysicalState, DIMENSION_NAMES};
+use serde::{Deserialize, Serialize};
+
+/// Shape of the emitted signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Waveform {
+    Sine,
+    Pulsed,
+    Noise,
+}
+
+/// A description of a biofield emission pattern.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BiofieldProtocol {
+    pub id: String,
+    pub waveform_type: Waveform,
+    pub frequency_hz: f64,
+    /// Current in microamps; must stay below the emitter's ceiling.
+    pub amplitude_ua: f64,
+    pub duration_sec: u64,
+    /// Estimated effect on the biophysical coordinates.
+    pub energy_delta: f64,
+    pub protein_delta: f64,
+    pub stress_delta: f64,
+    pub theta_delta: f64,
+    pub delta_temp_delta: f64,
+}
+
+impl BiofieldProtocol {
+    /// Declared deltas in array order `[E, M_prot, S_bio, theta, T]`.
+    pub fn deltas(&self) -> [f64; 5] {
+        [self.energy_delta, self.protein_delta, self.stress_delta, self.theta_delta, self.delta_temp_delta]
+    }
+
+    /// Per-dimension `(min, max)` over the linear trajectory from `start` to `start + deltas`.
+    pub fn projected_range(&self, start: &BiophysicalState) -> [(f64, f64); 5] {
+        let start = start.to_array();
+        let deltas = self.deltas();
+        let mut range = [(0.0, 0.0); 5];
+        for i in 0..5 {
+            let end = start[i] + deltas[i];
+            range[i] = (start[i].min(end), start[i].max(end));
+        }
+        range
+    }
+}
+
+/// Limits of the biofield emitter.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BiofieldCapabilities {
+    /// Current ceiling in microamps.
+    pub max_amplitude_ua: f64,
+    pub min_frequency_hz: f64,
+    pub max_frequency_hz: f64,
+    pub max_duration_sec: u64,
+    /// Waveforms the emitter can produce.
+    pub waveforms: Vec<Waveform>,
+}
+
+impl BiofieldCapabilities {
+    /// Violations of these limits by a protocol, one message each.
+    pub fn violations(&self, protocol: &BiofieldProtocol) -> Vec<String> {
+        let mut evidence = Vec::new();
+        if !protocol.amplitude_ua.is_finite() || protocol.amplitude_ua < 0.0 {
+            evidence.push(format!("Biofield amplitude {} uA is not a valid current", protocol.amplitude_ua));
+        } else if protocol.amplitude_ua > self.max_amplitude_ua {
+            evidence.push(format!(
+                "Biofield amplitude {} uA exceeds hardware max {} uA",
+                protocol.amplitude_ua, self.max_amplitude_ua
+            ));
+        }
+        if !protocol.frequency_hz.is_finite() {
+            evidence.push(format!("Biofield frequency {} Hz is not a valid frequency", protocol.frequency_hz));
+        } else if protocol.frequency_hz < self.min_frequency_hz || protocol.frequency_hz > self.max_frequency_hz {
+            evidence.push(format!(
+                "Biofield frequency {} Hz out of hardware range ({}-{} Hz)",
+                protocol.frequency_hz, self.min_frequency_hz, self.max_frequency_hz
+            ));
+        }
+        if protocol.duration_sec > self.max_duration_sec {
+            evidence.push(format!(
+                "Biofield duration {} s exceeds max {} s",
+                protocol.duration_sec, self.max_duration_sec
+            ));
+        }
+        for (name, delta) in DIMENSION_NAMES.iter().zip(protocol.deltas()) {
+            if !delta.is_finite() {
+                evidence.push(format!("Biofield {} delta {} is not a valid effect estimate", name, delta));
+            }
+        }
+        if !self.waveforms.contains(&protocol.waveform_type) {
+            evidence.push(format!("Waveform {:?} is not supported by the emitter", protocol.waveform_type));
+        }
+        evidence
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::rules::{SubjectContext, RULE_CORRIDOR, RULE_HARDWARE};
+    use crate::compiler::{CompilationResult, Compiler, Stimulus};
+    use crate::profile::CompilerProfile;
+
+    fn compiler() -> Compiler {
+        let profile = CompilerProfile::from_json(include_str!("../../profiles/default.json")).unwrap();
+        Compiler::from_profile(profile)
+    }
+
+    fn protocol(amplitude_ua: f64, energy_delta: f64) -> Stimulus {
+        Stimulus::Biofield(BiofieldProtocol {
+            id: "calm-sine".into(),
+            waveform_type: Waveform::Sine,
+            frequency_hz: 10.0,
+            amplitude_ua,
+            duration_sec: 600,
+            energy_delta,
+            protein_delta: 0.0,
+            stress_delta: -0.1,
+            theta_delta: 0.1,
+            delta_temp_delta: 0.0,
+        })
+    }
+
+    #[test]
+    fn test_biofield_within_limits_approved() {
+        let subject = SubjectContext {
+            current_state: Some(BiophysicalState::new(0.3, 0.1, 0.3, 0.4, 0.0)),
+            ..Default::default()
+        };
+        match compiler().compile_for(protocol(50.0, 0.2), &subject) {
+            CompilationResult::Approved { corridor_margin, .. } => {
+                // E runs from 0.3 to 0.5 inside [0, 0.8].
+                assert!((corridor_margin.per_dimension[0] - 0.3).abs() < 1e-9);
+            }
+            CompilationResult::Rejected { reasons, .. } => panic!("unexpected rejection: {:?}", reasons),
+        }
+    }
+
+    #[test]
+    fn test_biofield_current_and_corridor_rejected() {
+        let subject = SubjectContext {
+            current_state: Some(BiophysicalState::new(0.7, 0.1, 0.3, 0.4, 0.0)),
+            ..Default::default()
+        };
+        match compiler().compile_for(protocol(500.0, 0.2), &subject) {
+            CompilationResult::Rejected { reasons, suggestion } => {
+                assert!(suggestion.is_none());
+                assert!(reasons.iter().any(|r| r.rule_id == RULE_HARDWARE && r.evidence.contains("500 uA")));
+                assert!(reasons.iter().any(|r| r.rule_id == RULE_CORRIDOR && r.evidence.contains("E range")));
+            }
+            CompilationResult::Approved { .. } => panic!("expected rejection"),
+        }
+    }
+
+    #[test]
+    fn test_biofield_rejected_without_state_or_with_nan() {
+        match compiler().compile(protocol(50.0, 0.2)) {
+            CompilationResult::Rejected { reasons, .. } => {
+                assert!(reasons.iter().any(|r| r.rule_id == RULE_CORRIDOR && r.evidence.contains("No biophysical projection")));
+            }
+            CompilationResult::Approved { .. } => panic!("expected rejection without a current state"),
+        }
+
+        let subject = SubjectContext {
+            current_state: Some(BiophysicalState::new(0.3, 0.1, 0.3, 0.4, 0.0)),
+            ..Default::default()
+        };
+        let Stimulus::Biofield(mut nan) = protocol(50.0, f64::NAN) else { unreachable!() };
+        nan.frequency_hz = f64::NAN;
+        match compiler().compile_for(Stimulus::Biofield(nan), &subject) {
+            CompilationResult::Rejected { reasons, .. } => {
+                let hardware: Vec<&str> =
+                    reasons.iter().filter(|r| r.rule_id == RULE_HARDWARE).map(|r| r.evidence.as_str()).collect();
+                assert!(hardware.iter().any(|e| e.contains("frequency NaN")));
+                assert!(hardware.iter().any(|e| e.contains("E delta NaN")));
+            }
+            CompilationResult::Approved { .. } => panic!("expected rejection of NaN fields"),
+        }
+    }
+
+    #[test]
+    fn test_biofield_rejected_without_emitter_limits() {
+        let mut config = compiler().config.clone();
+        config.hardware.biofield = None;
+        match Compiler::new(config).compile(protocol(50.0, 0.2)) {
+            CompilationResult::Rejected { reasons, .. } => {
+                assert!(reasons[0].evidence.contains("No biofield emitter"));
+            }
+            CompilationResult::Approved { .. } => panic!("expected rejection"),
+        }
+    }
+}
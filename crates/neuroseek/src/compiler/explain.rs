@@ -0,0 +1,180 @@
+//! Dry-run explanation reports.
+//!
+//! `Compiler::explain` runs the same rules as `compile` but, instead of a token,
+//! returns how close the stimulus came to every limit: the margin, the audio state
+//! where it is tightest, and the biophysical state that point projects to.
+
+use super::rules::{RuleMargin, RuleStatus, SubjectContext};
+use super::{Compiler, Stimulus};
+use crate::biophysics::{BiophysicalState, CorridorMargin, DIMENSION_NAMES};
+use neuroseek_audio::audio_nanopolytope::AudioState;
+use serde::Serialize;
+use std::fmt::Write;
+
+/// Structured explanation of a compilation decision.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExplainReport {
+    pub stimulus_name: String,
+    pub profile_hash: String,
+    /// Whether `compile` would approve the stimulus.
+    pub would_approve: bool,
+    /// Worst-case corridor margin in biophysical units, if the protocol could be projected.
+    pub corridor_margin: Option<CorridorMargin>,
+    pub rules: Vec<RuleExplanation>,
+}
+
+/// Explanation of one rule.
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleExplanation {
+    pub rule_id: String,
+    pub description: String,
+    pub status: RuleStatus,
+    pub evidence: Vec<String>,
+    /// Tightest margin to the rule's limit (see `RuleMargin`).
+    pub margin: Option<RuleMargin>,
+    /// Nominal biophysical projection of `margin.worst_point`.
+    pub projected: Option<BiophysicalState>,
+}
+
+impl Compiler {
+    /// Explain how a stimulus fares against every rule, without issuing a token.
+    pub fn explain(&self, stimulus: &Stimulus) -> ExplainReport {
+        self.explain_for(stimulus, &SubjectContext::default())
+    }
+
+    /// Explain how a stimulus fares against every rule for a given subject.
+    /// The subject's bioload classifier is consulted but not updated.
+    pub fn explain_for(&self, stimulus: &Stimulus, subject: &SubjectContext) -> ExplainReport {
+        let subject = self.classify_bioload(subject, false);
+        let evaluation = self.evaluate(stimulus, &subject);
+        let would_approve = evaluation.corridor_margin.is_some()
+            && evaluation.results.iter().all(|r| r.outcome.status != RuleStatus::Fail);
+        let rules = evaluation
+            .results
+            .into_iter()
+            .map(|r| RuleExplanation {
+                rule_id: r.id,
+                description: r.description,
+                status: r.outcome.status,
+                evidence: r.outcome.evidence,
+                margin: r.outcome.margin,
+                projected: r.projected,
+            })
+            .collect();
+        ExplainReport {
+            stimulus_name: stimulus.name().to_string(),
+            profile_hash: self.profile_hash.clone(),
+            would_approve,
+            corridor_margin: evaluation.corridor_margin,
+            rules,
+        }
+    }
+}
+
+impl ExplainReport {
+    /// Render as pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("explain report is always serializable")
+    }
+
+    /// Render as a Markdown document.
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::new();
+        let verdict = if self.would_approve { "would be approved" } else { "would be rejected" };
+        let _ = writeln!(md, "# Dry run: {}", self.stimulus_name);
+        let _ = writeln!(md);
+        let _ = writeln!(md, "- Decision: **{}**", verdict);
+        let _ = writeln!(md, "- Profile hash: `{}`", self.profile_hash);
+        if let Some(m) = &self.corridor_margin {
+            let _ = writeln!(md, "- Worst-case corridor margin: {:.4}", m.min_margin);
+        }
+        let _ = writeln!(md);
+        let _ = writeln!(md, "| Rule | Status | Margin | Binding limit | Worst-case audio point | Projected state |");
+        let _ = writeln!(md, "|---|---|---|---|---|---|");
+        for r in &self.rules {
+            let (margin, limit, point) = match &r.margin {
+                Some(m) => (
+                    format!("{:.1}%", m.value * 100.0),
+                    m.limit.clone(),
+                    m.worst_point.as_ref().map(format_audio).unwrap_or_else(|| "-".into()),
+                ),
+                None => ("-".into(), "-".into(), "-".into()),
+            };
+            let projected = r.projected.as_ref().map(format_biophysical).unwrap_or_else(|| "-".into());
+            let _ = writeln!(
+                md,
+                "| {} | {:?} | {} | {} | {} | {} |",
+                r.rule_id, r.status, margin, limit, point, projected
+            );
+        }
+
+        let with_evidence: Vec<_> = self.rules.iter().filter(|r| !r.evidence.is_empty()).collect();
+        if !with_evidence.is_empty() {
+            let _ = writeln!(md);
+            let _ = writeln!(md, "## Evidence");
+            for r in with_evidence {
+                let _ = writeln!(md);
+                let _ = writeln!(md, "### {} ({:?})", r.rule_id, r.status);
+                for e in &r.evidence {
+                    let _ = writeln!(md, "- {}", e);
+                }
+            }
+        }
+        md
+    }
+}
+
+fn format_audio(s: &AudioState) -> String {
+    format!(
+        "amp {:.3}, carrier {:.1} Hz, beat {:.2} Hz, duty {:.3}, {:.0} s",
+        s.amplitude, s.carrier_hz, s.beat_hz, s.duty, s.session_duration_sec
+    )
+}
+
+fn format_biophysical(z: &BiophysicalState) -> String {
+    let values = [z.e, z.m_prot, z.s_bio, z.theta, z.t];
+    DIMENSION_NAMES
+        .iter()
+        .zip(values)
+        .map(|(name, v)| format!("{} {:.3}", name, v))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::AudioStimulus;
+    use crate::profile::CompilerProfile;
+    use neuroseek_audio::config::Protocol as AudioProtocol;
+
+    #[test]
+    fn test_explain_fixed_point() {
+        let profile = CompilerProfile::from_json(include_str!("../../profiles/default.json")).unwrap();
+        let compiler = Compiler::from_profile(profile);
+        let stimulus = Stimulus::Audio(Box::new(AudioStimulus {
+            protocol: AudioProtocol::Fixed(AudioState {
+                amplitude: 0.9,
+                carrier_hz: 200.0,
+                beat_hz: 10.0,
+                duty: 0.1,
+                session_duration_sec: 600.0,
+            }),
+            name: "fixed".into(),
+            description: None,
+        }));
+
+        let report = compiler.explain(&stimulus);
+        assert!(report.would_approve);
+        let hardware = report.rules.iter().find(|r| r.rule_id == "hardware").unwrap();
+        let margin = hardware.margin.as_ref().unwrap();
+        // Amplitude 0.9 of 1.0 is the tightest hardware limit.
+        assert!((margin.value - 0.1).abs() < 1e-9);
+        assert!(margin.limit.starts_with("amplitude"));
+        assert!((hardware.projected.unwrap().e - 0.18).abs() < 1e-9);
+
+        let md = report.to_markdown();
+        assert!(md.contains("would be approved"));
+        assert!(report.to_json().contains("\"rule_id\": \"corridor\""));
+    }
+}
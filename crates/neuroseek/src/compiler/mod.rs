@@ -0,0 +1,571 @@
+//! Safety compiler: validates stimulus protocols against biophysical corridors and neurorights.
+//!
+//! The compiler returns a token if the protocol is safe; otherwise returns rejection reasons
+//! and, where possible, the nearest safe alternative (see `suggest`).
+//! Individual checks are `SafetyRule`s (see `rules`). Decisions can be recorded in a
+//! hash-chained audit log (see `audit`).
+//!
+//! The compiler keeps a `BioloadClassifier` per subject id and feeds it the subject's
+//! current state on every `compile_for`, so that the `bioload` rule sees a band with
+//! hysteresis rather than one that flaps around a threshold.
+
+pub mod audit;
+pub mod biofield;
+pub mod explain;
+pub mod plan;
+pub mod rules;
+pub mod suggest;
+
+use crate::bioload::BioloadClassifier;
+use crate::biophysics::{BiophysicalCorridor, BiophysicalState, CorridorMargin};
+use crate::neurorights::NeurorightsConstraints;
+use crate::profile::{self, CompilerProfile};
+use chrono::{DateTime, Utc};
+use neuroseek_audio::audio_nanopolytope::AudioState;
+use neuroseek_audio::config::Protocol as AudioProtocol;
+use neuroseek_audio::transfer::LinearTransfer;
+use audit::AuditLog;
+use biofield::{BiofieldCapabilities, BiofieldProtocol};
+use plan::SessionPlan;
+use rules::{
+    Finding, RuleContext, RuleOutcome, RuleRegistry, RuleScope, RuleStatus, RulesConfig, SafetyRule, SubjectContext,
+};
+use suggest::{Suggestion, SuggestionConfig};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tracing::error;
+use uuid::Uuid;
+
+/// A stimulus can be audio or other modalities (future).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Stimulus {
+    Audio(Box<AudioStimulus>),
+    /// A sequence of timed audio segments with transitions (see `plan`).
+    SessionPlan(SessionPlan),
+    /// A biofield emission pattern (see `biofield`); validated only, never played.
+    Biofield(BiofieldProtocol),
+}
+
+impl Stimulus {
+    /// Human-readable name of the stimulus.
+    pub fn name(&self) -> &str {
+        match self {
+            Stimulus::Audio(a) => &a.name,
+            Stimulus::SessionPlan(p) => &p.name,
+            Stimulus::Biofield(b) => &b.id,
+        }
+    }
+
+    /// Content hash of the stimulus (SHA-256 of its canonical JSON).
+    pub fn canonical_hash(&self) -> String {
+        profile::canonical_hash(self)
+    }
+
+    /// The audio stimulus, if this is one.
+    pub fn as_audio(&self) -> Option<&AudioStimulus> {
+        match self {
+            Stimulus::Audio(a) => Some(a.as_ref()),
+            Stimulus::SessionPlan(_) | Stimulus::Biofield(_) => None,
+        }
+    }
+}
+
+/// Audio stimulus: either a fixed point or a polytope to explore.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioStimulus {
+    pub protocol: AudioProtocol,
+    pub name: String,
+    pub description: Option<String>,
+}
+
+/// Result of compilation.
+#[derive(Debug, Clone)]
+pub enum CompilationResult {
+    Approved {
+        token: Uuid,
+        valid_until: SystemTime,
+        stimulus: Box<Stimulus>,
+        /// Content hash of the policy profile that approved the stimulus.
+        profile_hash: String,
+        /// Room left inside the corridor under the worst case over the protocol and
+        /// the transfer uncertainty.
+        corridor_margin: CorridorMargin,
+        /// Rules that passed only as warnings.
+        warnings: Vec<Finding>,
+    },
+    Rejected {
+        reasons: Vec<Finding>,
+        /// Nearest approvable alternative, if one exists.
+        suggestion: Option<Box<Suggestion>>,
+    },
+}
+
+/// Hardware capabilities (e.g., max amplitude, sample rate).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HardwareCapabilities {
+    pub max_amplitude: f64,
+    pub min_frequency: f64,
+    pub max_frequency: f64,
+    pub max_session_duration_sec: f64,
+    pub max_daily_duty: f64,
+    /// Biofield emitter limits; without them every biofield stimulus is rejected.
+    #[serde(default)]
+    pub biofield: Option<BiofieldCapabilities>,
+}
+
+/// Compiler configuration. Usually loaded from a policy profile (see `crate::profile`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CompilerConfig {
+    pub hardware: HardwareCapabilities,
+    pub biophysical_corridor: BiophysicalCorridor,
+    pub neurorights: NeurorightsConstraints,
+    #[serde(rename = "token_validity_secs", with = "profile::duration_secs")]
+    pub token_validity_duration: Duration,
+    /// Which rules run and how (defaults to all built-in rules).
+    #[serde(default)]
+    pub rules: RulesConfig,
+    /// Distance scales for nearest-safe suggestions on rejection.
+    #[serde(default)]
+    pub suggestion: SuggestionConfig,
+}
+
+/// The safety compiler.
+pub struct Compiler {
+    config: CompilerConfig,
+    profile_hash: String,
+    rules: RuleRegistry,
+    audit: Option<AuditLog>,
+    /// Band classifier with hysteresis per subject id.
+    bioload: Mutex<HashMap<String, BioloadClassifier>>,
+}
+
+impl Compiler {
+    pub fn new(config: CompilerConfig) -> Self {
+        let profile_hash = profile::policy_hash(&config);
+        let rules = RuleRegistry::builtin(&config.rules);
+        Self { config, profile_hash, rules, audit: None, bioload: Mutex::new(HashMap::new()) }
+    }
+
+    /// Register a site-specific rule in addition to the built-in ones.
+    /// The profile's `rules.disabled` / `rules.warn_only` lists apply to it by id.
+    pub fn with_rule(mut self, rule: Box<dyn SafetyRule>) -> Self {
+        self.rules.register(rule);
+        self
+    }
+
+    /// Record every `compile` decision in an audit log.
+    pub fn with_audit_log(mut self, log: AuditLog) -> Self {
+        self.audit = Some(log);
+        self
+    }
+
+    /// The rules this compiler evaluates.
+    pub fn rules(&self) -> &RuleRegistry {
+        &self.rules
+    }
+
+    /// Create a compiler from a validated policy profile.
+    pub fn from_profile(profile: CompilerProfile) -> Self {
+        Self::new(profile.compiler)
+    }
+
+    /// Content hash of the active policy; stamped on every approval.
+    pub fn profile_hash(&self) -> &str {
+        &self.profile_hash
+    }
+
+    /// Compile a stimulus protocol for an anonymous subject with no history.
+    pub fn compile(&self, stimulus: Stimulus) -> CompilationResult {
+        self.compile_for(stimulus, &SubjectContext::default())
+    }
+
+    /// Compile a stimulus protocol for a subject. Returns a token if safe.
+    pub fn compile_for(&self, stimulus: Stimulus, subject: &SubjectContext) -> CompilationResult {
+        let subject = self.classify_bioload(subject, true);
+        let subject = subject.as_ref();
+        let Some(log) = &self.audit else {
+            return self.compile_inner(stimulus, subject, self.config.suggestion.enabled);
+        };
+        let (name, hash) = (stimulus.name().to_string(), stimulus.canonical_hash());
+        let result = self.compile_inner(stimulus, subject, self.config.suggestion.enabled);
+        match log.record(&name, &hash, &self.profile_hash, subject, &result) {
+            Ok(_) => result,
+            Err(e) => {
+                error!("Failed to record compiler decision for {}: {}", name, e);
+                match result {
+                    // Fail closed: an approval that cannot be audited is not issued.
+                    CompilationResult::Approved { .. } => CompilationResult::Rejected {
+                        reasons: vec![Finding {
+                            rule_id: "audit".into(),
+                            status: RuleStatus::Fail,
+                            evidence: format!("Decision could not be recorded in the audit log: {}", e),
+                        }],
+                        suggestion: None,
+                    },
+                    rejected => rejected,
+                }
+            }
+        }
+    }
+
+    /// The subject with the band of its bioload classifier, unless the caller gave a
+    /// band or the subject has no id, state or baseline. With `commit`, the state is
+    /// recorded in the classifier; otherwise the band is only previewed.
+    fn classify_bioload<'a>(&self, subject: &'a SubjectContext, commit: bool) -> Cow<'a, SubjectContext> {
+        let band = match (&subject.bioload_band, &subject.subject_id, &subject.current_state, &subject.baseline) {
+            (None, Some(id), Some(state), Some(baseline)) => {
+                let mut classifiers = self.bioload.lock().unwrap_or_else(|e| e.into_inner());
+                let config = self.config.rules.bioload;
+                if commit {
+                    classifiers.entry(id.clone()).or_insert_with(|| BioloadClassifier::new(config)).update(state, baseline)
+                } else {
+                    match classifiers.get(id) {
+                        Some(classifier) => classifier.peek(state, baseline),
+                        None => config.band_for(baseline.load(state)),
+                    }
+                }
+            }
+            _ => return Cow::Borrowed(subject),
+        };
+        Cow::Owned(SubjectContext { bioload_band: Some(band), ..subject.clone() })
+    }
+
+    /// Compile, optionally searching for a suggestion on rejection. Suggestions are
+    /// themselves validated by compiling them with `suggest` off.
+    fn compile_inner(&self, stimulus: Stimulus, subject: &SubjectContext, suggest: bool) -> CompilationResult {
+        let evaluation = self.evaluate(&stimulus, subject);
+        let mut reasons = Vec::new();
+        let mut warnings = Vec::new();
+        let mut failed = false;
+        for mut result in evaluation.results {
+            let target = match result.outcome.status {
+                RuleStatus::Pass => continue,
+                RuleStatus::Warn => &mut warnings,
+                RuleStatus::Fail => {
+                    failed = true;
+                    &mut reasons
+                }
+            };
+            if result.outcome.evidence.is_empty() {
+                // A status without evidence still counts; report the rule itself.
+                result.outcome.evidence.push(format!("{} (no evidence given)", result.description));
+            }
+            for evidence in result.outcome.evidence {
+                target.push(Finding {
+                    rule_id: result.id.clone(),
+                    status: result.outcome.status,
+                    evidence,
+                });
+            }
+        }
+
+        match evaluation.corridor_margin {
+            Some(corridor_margin) if !failed => {
+                let token = Uuid::new_v4();
+                let valid_until = SystemTime::now() + self.config.token_validity_duration;
+                CompilationResult::Approved {
+                    token,
+                    valid_until,
+                    stimulus: Box::new(stimulus),
+                    profile_hash: self.profile_hash.clone(),
+                    corridor_margin,
+                    warnings,
+                }
+            }
+            _ => {
+                let suggestion = if suggest {
+                    self.suggest_for(&stimulus, subject).map(Box::new)
+                } else {
+                    None
+                };
+                CompilationResult::Rejected { reasons, suggestion }
+            }
+        }
+    }
+
+    /// Run every enabled rule on a stimulus.
+    fn evaluate(&self, stimulus: &Stimulus, subject: &SubjectContext) -> Evaluation {
+        let audio = match stimulus {
+            Stimulus::Audio(audio) => audio,
+            Stimulus::SessionPlan(plan) => return self.evaluate_plan(stimulus, plan, subject, Utc::now()),
+            Stimulus::Biofield(protocol) => {
+                // No audio states and no transfer: the corridor check uses the declared deltas.
+                let mut ctx = self.rule_context(subject, &[], &[], None, Utc::now());
+                // Without a current state there is no projection, and the corridor check fails.
+                ctx.projected_range = subject.current_state.as_ref().map(|state| protocol.projected_range(state));
+                let results = self.run_rules(None, stimulus, &ctx, "");
+                return Evaluation { corridor_margin: ctx.corridor_margin(), results };
+            }
+        };
+        let mut results = Vec::new();
+        let points = protocol_points(&audio.protocol);
+        if points.is_empty() {
+            results.push(RuleResult::protocol_failure(vec!["Polytope is empty or unbounded".into()]));
+        }
+
+        let transfer = self.transfer_for(&audio.protocol);
+        let segment_points = [points];
+        let ctx = self.rule_context(subject, &segment_points[0], &segment_points, Some(&transfer), Utc::now());
+        results.extend(self.run_rules(None, stimulus, &ctx, ""));
+        let corridor_margin = ctx.corridor_margin();
+
+        Evaluation { corridor_margin, results }
+    }
+
+    fn rule_context<'a>(
+        &'a self,
+        subject: &'a SubjectContext,
+        audio_points: &'a [AudioState],
+        segment_points: &'a [Vec<AudioState>],
+        transfer: Option<&'a LinearTransfer>,
+        now: DateTime<Utc>,
+    ) -> RuleContext<'a> {
+        RuleContext {
+            hardware: &self.config.hardware,
+            corridor: &self.config.biophysical_corridor,
+            neurorights: &self.config.neurorights,
+            subject,
+            audio_points,
+            segment_points,
+            transfer,
+            projected_range: transfer.and_then(|t| t.worst_case(audio_points)),
+            now,
+        }
+    }
+
+    /// Run the rules of one scope (or all rules), prefixing evidence with `label`.
+    fn run_rules(&self, scope: Option<RuleScope>, stimulus: &Stimulus, ctx: &RuleContext, label: &str) -> Vec<RuleResult> {
+        let outcomes = match scope {
+            Some(scope) => self.rules.evaluate_scope(scope, stimulus, ctx),
+            None => self.rules.evaluate(stimulus, ctx),
+        };
+        outcomes
+            .into_iter()
+            .map(|(rule, outcome)| Self::rule_result(rule, outcome, ctx, label))
+            .collect()
+    }
+
+    /// Detach one rule's outcome, prefixing its evidence with `label`.
+    fn rule_result(rule: &dyn SafetyRule, mut outcome: RuleOutcome, ctx: &RuleContext, label: &str) -> RuleResult {
+        // Nominal biophysical projection of the point where the rule is tightest.
+        let worst_point = outcome.margin.as_ref().and_then(|m| m.worst_point.as_ref());
+        let projected = match (ctx.transfer, worst_point) {
+            (Some(transfer), Some(point)) => Some(BiophysicalState::from_array(transfer.nominal(point))),
+            _ => None,
+        };
+        if !label.is_empty() {
+            for evidence in outcome.evidence.iter_mut() {
+                evidence.insert_str(0, label);
+            }
+        }
+        RuleResult {
+            id: rule.id().to_string(),
+            description: rule.description().to_string(),
+            outcome,
+            projected,
+        }
+    }
+
+    /// Transfer model used for the corridor check: the polytope's fitted transfer
+    /// (with its uncertainty) if present, otherwise the built-in placeholder.
+    fn transfer_for(&self, protocol: &AudioProtocol) -> LinearTransfer {
+        protocol
+            .as_polytope()
+            .and_then(|poly| poly.transfer())
+            .unwrap_or_else(placeholder_transfer)
+    }
+}
+
+/// Everything the rules concluded about one stimulus.
+struct Evaluation {
+    corridor_margin: Option<CorridorMargin>,
+    results: Vec<RuleResult>,
+}
+
+/// One rule's outcome, detached from the registry.
+struct RuleResult {
+    id: String,
+    description: String,
+    outcome: RuleOutcome,
+    /// Nominal biophysical projection of the outcome's worst point, if any.
+    projected: Option<BiophysicalState>,
+}
+
+impl RuleResult {
+    /// Failure of the implicit check that the stimulus is well-formed.
+    fn protocol_failure(evidence: Vec<String>) -> Self {
+        Self {
+            id: "protocol".into(),
+            description: "Protocol defines a non-empty, bounded set of audio states".into(),
+            outcome: RuleOutcome::fail(evidence),
+            projected: None,
+        }
+    }
+}
+
+/// Audio states at which a protocol is checked: the fixed point, or every vertex of
+/// the polytope. Hardware limits are boxes and the corridor check is convex in the
+/// audio state, so the vertices cover the worst case over the whole polytope.
+fn protocol_points(protocol: &AudioProtocol) -> Vec<AudioState> {
+    match protocol {
+        AudioProtocol::Fixed(state) => vec![*state],
+        AudioProtocol::Polytope(poly) => poly.vertices().unwrap_or_default(),
+    }
+}
+
+/// Placeholder linear transfer used when a protocol carries no fitted model:
+/// amplitude drives energy, beat drives stress, carrier drives the theta proxy.
+fn placeholder_transfer() -> LinearTransfer {
+    let mut matrix = [[0.0; 5]; 5];
+    matrix[0][0] = 0.2; // E from amplitude
+    matrix[2][2] = 0.01; // S_bio from beat_hz
+    matrix[3][1] = 0.001; // theta from carrier_hz
+    LinearTransfer {
+        matrix,
+        offset: [0.0; 5],
+        uncertainty: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use neuroseek_audio::audio_nanopolytope::AudioNanopolytope;
+    use neuroseek_audio::transfer::TransferUncertainty;
+
+    fn compiler() -> Compiler {
+        let profile = CompilerProfile::from_json(include_str!("../../profiles/default.json")).unwrap();
+        Compiler::from_profile(profile)
+    }
+
+    fn box_polytope(amplitude: (f64, f64)) -> AudioNanopolytope {
+        let bounds = [amplitude, (100.0, 400.0), (4.0, 20.0), (0.05, 0.3), (60.0, 600.0)];
+        let mut a = Vec::new();
+        let mut b = Vec::new();
+        for (i, (lo, hi)) in bounds.iter().enumerate() {
+            let mut row = [0.0; 5];
+            row[i] = 1.0;
+            a.push(row);
+            b.push(*hi);
+            row[i] = -1.0;
+            a.push(row);
+            b.push(-lo);
+        }
+        let mut matrix = [[0.0; 5]; 5];
+        matrix[0][0] = 1.0; // E tracks amplitude directly
+        AudioNanopolytope::new("box".into(), a, b, 0.9, 0.2, 0.1).with_transfer(matrix, [0.0; 5])
+    }
+
+    fn stimulus(poly: AudioNanopolytope) -> Stimulus {
+        Stimulus::Audio(Box::new(AudioStimulus {
+            protocol: AudioProtocol::Polytope(Box::new(poly)),
+            name: "test".into(),
+            description: None,
+        }))
+    }
+
+    #[test]
+    fn test_polytope_approved_with_margin() {
+        match compiler().compile(stimulus(box_polytope((0.1, 0.7)))) {
+            CompilationResult::Approved { corridor_margin, .. } => {
+                // E corridor is [0, 0.8]; worst case E = 0.7.
+                assert!((corridor_margin.per_dimension[0] - 0.1).abs() < 1e-9);
+            }
+            CompilationResult::Rejected { reasons, .. } => panic!("unexpected rejection: {:?}", reasons),
+        }
+    }
+
+    #[test]
+    fn test_uncertainty_can_reject_nominally_safe_polytope() {
+        let mut delta = [[0.0; 5]; 5];
+        delta[0][0] = 0.2;
+        let poly = box_polytope((0.1, 0.7)).with_transfer_uncertainty(TransferUncertainty {
+            matrix: delta,
+            offset: [0.0; 5],
+        });
+        // Worst case E = (1.0 + 0.2) * 0.7 = 0.84 > 0.8.
+        match compiler().compile(stimulus(poly)) {
+            CompilationResult::Rejected { reasons, .. } => {
+                assert!(reasons
+                    .iter()
+                    .any(|r| r.rule_id == rules::RULE_CORRIDOR && r.evidence.contains("Worst-case projected E")));
+            }
+            CompilationResult::Approved { .. } => panic!("expected rejection"),
+        }
+    }
+
+    struct MaxCarrierRule;
+
+    impl SafetyRule for MaxCarrierRule {
+        fn id(&self) -> &str {
+            "site_max_carrier"
+        }
+
+        fn description(&self) -> &str {
+            "Site limit: carrier at most 300 Hz"
+        }
+
+        fn evaluate(&self, _stimulus: &Stimulus, ctx: &RuleContext) -> RuleOutcome {
+            if ctx.audio_points.iter().any(|p| p.carrier_hz > 300.0) {
+                RuleOutcome::fail(vec!["carrier above 300 Hz".into()])
+            } else {
+                RuleOutcome::pass()
+            }
+        }
+    }
+
+    struct SilentFailRule;
+
+    impl SafetyRule for SilentFailRule {
+        fn id(&self) -> &str {
+            "site_silent"
+        }
+
+        fn description(&self) -> &str {
+            "Site rule failing without evidence"
+        }
+
+        fn evaluate(&self, _stimulus: &Stimulus, _ctx: &RuleContext) -> RuleOutcome {
+            RuleOutcome::fail(Vec::new())
+        }
+    }
+
+    #[test]
+    fn test_failure_without_evidence_rejects() {
+        let compiler = compiler().with_rule(Box::new(SilentFailRule));
+        let stimulus = stimulus(box_polytope((0.1, 0.7)));
+        assert!(!compiler.explain(&stimulus).would_approve);
+        match compiler.compile(stimulus) {
+            CompilationResult::Rejected { reasons, .. } => {
+                assert_eq!(reasons.len(), 1);
+                assert_eq!(reasons[0].rule_id, "site_silent");
+            }
+            CompilationResult::Approved { .. } => panic!("expected rejection"),
+        }
+    }
+
+    #[test]
+    fn test_site_rule_and_warn_only_profile() {
+        let compiler = compiler().with_rule(Box::new(MaxCarrierRule));
+        assert!(matches!(
+            compiler.compile(stimulus(box_polytope((0.1, 0.7)))),
+            CompilationResult::Rejected { .. }
+        ));
+
+        let mut config = compiler.config.clone();
+        config.rules.warn_only.push("site_max_carrier".into());
+        let compiler = Compiler::new(config).with_rule(Box::new(MaxCarrierRule));
+        match compiler.compile(stimulus(box_polytope((0.1, 0.7)))) {
+            CompilationResult::Approved { warnings, .. } => {
+                assert_eq!(warnings.len(), 1);
+                assert_eq!(warnings[0].rule_id, "site_max_carrier");
+            }
+            CompilationResult::Rejected { reasons, .. } => panic!("unexpected rejection: {:?}", reasons),
+        }
+    }
+}
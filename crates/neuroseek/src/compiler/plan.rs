@@ -0,0 +1,525 @@
+//! Multi-segment session plans.
+//!
+//! A `SessionPlan` is a sequence of timed audio segments (e.g., warm-up, main block,
+//! wind-down), each with its own fixed point or polytope and transfer model. Between
+//! consecutive segments the output ramps linearly over `transition_sec`.
+//!
+//! The compiler checks a plan at three levels:
+//!
+//! - every segment against the segment-scope rules (hardware, beat range, corridor);
+//! - every transition against the slew limits in `PlanLimits`, and against the
+//!   corridor: the ramp's states are convex combinations of the two segments' states,
+//!   but adjacent segments may use different transfer models, so the points of both
+//!   segments are checked under each of the two models;
+//! - the whole plan against cumulative dose limits: amplitude-time, total duration
+//!   and daily duty including the subject's sessions of the last 24 hours.
+
+use super::rules::{
+    CorridorRule, RuleContext, RuleMargin, RuleOutcome, RuleScope, SafetyRule, SubjectContext, RULE_PLAN_DOSE,
+    RULE_PLAN_TRANSITIONS,
+};
+use super::{protocol_points, AudioStimulus, Compiler, Evaluation, RuleResult, Stimulus};
+use chrono::{DateTime, Utc};
+use neuroseek_audio::audio_nanopolytope::AudioState;
+use neuroseek_audio::config::Protocol as AudioProtocol;
+use serde::{Deserialize, Serialize};
+
+const SECONDS_PER_DAY: f64 = 86_400.0;
+
+/// A session made of timed segments played back to back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionPlan {
+    pub name: String,
+    pub description: Option<String>,
+    pub segments: Vec<PlanSegment>,
+}
+
+/// One segment of a session plan.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlanSegment {
+    /// Short label, e.g. "warm-up".
+    pub label: String,
+    pub protocol: AudioProtocol,
+    /// Playing time of the segment in seconds.
+    pub duration_sec: f64,
+    /// Length of the linear ramp into the next segment in seconds (ignored for the
+    /// last segment). Zero means an abrupt switch.
+    #[serde(default)]
+    pub transition_sec: f64,
+}
+
+impl SessionPlan {
+    /// Total playing time: all segments plus the transitions between them.
+    pub fn total_duration_sec(&self) -> f64 {
+        let n = self.segments.len();
+        self.segments
+            .iter()
+            .enumerate()
+            .map(|(i, s)| s.duration_sec + if i + 1 < n { s.transition_sec } else { 0.0 })
+            .sum()
+    }
+
+    /// The i-th segment as a standalone audio stimulus.
+    pub fn segment_stimulus(&self, index: usize) -> AudioStimulus {
+        let segment = &self.segments[index];
+        AudioStimulus {
+            protocol: segment.protocol.clone(),
+            name: format!("{} / {}", self.name, segment.label),
+            description: None,
+        }
+    }
+
+    /// Structural problems: no segments, or non-positive or non-finite timings.
+    fn structure_problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.segments.is_empty() {
+            problems.push("Session plan has no segments".to_string());
+        }
+        for (i, s) in self.segments.iter().enumerate() {
+            if !s.duration_sec.is_finite() || s.duration_sec <= 0.0 {
+                problems.push(format!("Segment {} ({}): duration {} must be positive", i, s.label, s.duration_sec));
+            }
+            if !s.transition_sec.is_finite() || s.transition_sec < 0.0 {
+                problems.push(format!("Segment {} ({}): transition {} must not be negative", i, s.label, s.transition_sec));
+            }
+        }
+        problems
+    }
+}
+
+/// Transition and cumulative dose limits for session plans.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlanLimits {
+    /// Largest amplitude change per second during a transition.
+    pub max_amplitude_slew_per_sec: f64,
+    /// Largest carrier change (Hz) per second during a transition.
+    pub max_carrier_slew_hz_per_sec: f64,
+    /// Largest beat change (Hz) per second during a transition.
+    pub max_beat_slew_hz_per_sec: f64,
+    /// Ceiling on amplitude-time (amplitude × seconds) over one session.
+    pub max_amplitude_seconds: f64,
+}
+
+impl Default for PlanLimits {
+    fn default() -> Self {
+        Self {
+            max_amplitude_slew_per_sec: 0.05,
+            max_carrier_slew_hz_per_sec: 20.0,
+            max_beat_slew_hz_per_sec: 0.5,
+            max_amplitude_seconds: 3600.0,
+        }
+    }
+}
+
+/// Cumulative dose of a stimulus, worst case over each segment's protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct SessionDose {
+    /// Integral of amplitude over the session, using each segment's largest amplitude
+    /// and linear ramps across transitions.
+    pub amplitude_seconds: f64,
+    pub total_duration_sec: f64,
+    /// Fraction of the last 24 hours spent in sessions, including this one.
+    pub daily_duty: f64,
+}
+
+impl SessionDose {
+    /// Dose of the stimulus being evaluated in `ctx`. A single protocol counts as one
+    /// segment lasting its longest session duration. Amplitude-time counts audio
+    /// amplitude only, so it is zero for biofield stimuli.
+    pub fn of(stimulus: &Stimulus, ctx: &RuleContext) -> Self {
+        let timings: Vec<(f64, f64)> = match stimulus {
+            Stimulus::Audio(_) => vec![(max_of(ctx.audio_points, |s| s.session_duration_sec), 0.0)],
+            Stimulus::SessionPlan(plan) => plan.segments.iter().map(|s| (s.duration_sec, s.transition_sec)).collect(),
+            Stimulus::Biofield(protocol) => vec![(protocol.duration_sec as f64, 0.0)],
+        };
+        let amplitudes: Vec<f64> = ctx.segment_points.iter().map(|p| max_of(p, |s| s.amplitude)).collect();
+
+        let mut amplitude_seconds = 0.0;
+        let mut total_duration_sec = 0.0;
+        for (i, (duration, transition)) in timings.iter().enumerate() {
+            let amplitude = amplitudes.get(i).copied().unwrap_or(0.0);
+            amplitude_seconds += amplitude * duration;
+            total_duration_sec += duration;
+            if i + 1 < timings.len() {
+                let next = amplitudes.get(i + 1).copied().unwrap_or(0.0);
+                amplitude_seconds += 0.5 * (amplitude + next) * transition;
+                total_duration_sec += transition;
+            }
+        }
+        let history_sec: f64 = ctx.subject.sessions_in_last_day(ctx.now).map(|s| s.duration_sec).sum();
+        Self {
+            amplitude_seconds,
+            total_duration_sec,
+            daily_duty: (history_sec + total_duration_sec) / SECONDS_PER_DAY,
+        }
+    }
+}
+
+fn max_of(points: &[AudioState], f: impl Fn(&AudioState) -> f64) -> f64 {
+    points.iter().map(f).fold(0.0, f64::max)
+}
+
+impl Compiler {
+    /// Evaluate a session plan: segment-scope rules per segment, session-scope rules once.
+    pub(super) fn evaluate_plan(
+        &self,
+        stimulus: &Stimulus,
+        plan: &SessionPlan,
+        subject: &SubjectContext,
+        now: DateTime<Utc>,
+    ) -> Evaluation {
+        let mut problems = plan.structure_problems();
+        let segment_points: Vec<Vec<AudioState>> = plan.segments.iter().map(|s| protocol_points(&s.protocol)).collect();
+        for (i, points) in segment_points.iter().enumerate() {
+            if points.is_empty() {
+                problems.push(format!("Segment {} ({}): Polytope is empty or unbounded", i, plan.segments[i].label));
+            }
+        }
+        let mut results = Vec::new();
+        let well_formed = problems.is_empty();
+        if !well_formed {
+            results.push(RuleResult::protocol_failure(problems));
+        }
+
+        let mut worst_case: Option<[(f64, f64); 5]> = None;
+        let mut widen = |worst: Option<[(f64, f64); 5]>| {
+            if let Some(worst) = worst {
+                let acc = worst_case.get_or_insert(worst);
+                for d in 0..5 {
+                    acc[d].0 = acc[d].0.min(worst[d].0);
+                    acc[d].1 = acc[d].1.max(worst[d].1);
+                }
+            }
+        };
+        let transfers: Vec<_> = plan.segments.iter().map(|s| self.transfer_for(&s.protocol)).collect();
+        let mut merged: Vec<RuleResult> = Vec::new();
+        for (i, segment) in plan.segments.iter().enumerate() {
+            let segment_stimulus = Stimulus::Audio(Box::new(plan.segment_stimulus(i)));
+            let points = &segment_points[i];
+            let ctx = self.rule_context(subject, points, std::slice::from_ref(points), Some(&transfers[i]), now);
+            let label = format!("Segment {} ({}): ", i, segment.label);
+            for result in self.run_rules(Some(RuleScope::Segment), &segment_stimulus, &ctx, &label) {
+                merge_result(&mut merged, result);
+            }
+            widen(transfers[i].worst_case(points));
+        }
+        for i in 1..segment_points.len() {
+            if segment_points[i - 1].is_empty() || segment_points[i].is_empty() {
+                continue;
+            }
+            let ramp: Vec<AudioState> = segment_points[i - 1].iter().chain(&segment_points[i]).copied().collect();
+            for model in [i - 1, i] {
+                let ctx = self.rule_context(subject, &ramp, std::slice::from_ref(&ramp), Some(&transfers[model]), now);
+                let label = format!("Transition {} -> {} under the model of segment {}: ", i - 1, i, model);
+                let outcome = CorridorRule.evaluate(stimulus, &ctx);
+                merge_result(&mut merged, Self::rule_result(&CorridorRule, outcome, &ctx, &label));
+                widen(ctx.projected_range);
+            }
+        }
+        results.extend(merged);
+
+        let all_points: Vec<AudioState> = segment_points.iter().flatten().copied().collect();
+        let mut ctx = self.rule_context(subject, &all_points, &segment_points, None, now);
+        ctx.projected_range = worst_case.filter(|_| well_formed);
+        results.extend(self.run_rules(Some(RuleScope::Session), stimulus, &ctx, ""));
+
+        Evaluation { corridor_margin: ctx.corridor_margin(), results }
+    }
+}
+
+/// Fold one segment's result into the per-rule results: worst status, all evidence,
+/// tightest margin.
+fn merge_result(merged: &mut Vec<RuleResult>, result: RuleResult) {
+    let Some(existing) = merged.iter_mut().find(|r| r.id == result.id) else {
+        merged.push(result);
+        return;
+    };
+    existing.outcome.status = existing.outcome.status.max(result.outcome.status);
+    existing.outcome.evidence.extend(result.outcome.evidence);
+    let tighter = match (&existing.outcome.margin, &result.outcome.margin) {
+        (Some(a), Some(b)) => b.value < a.value,
+        (None, Some(_)) => true,
+        _ => false,
+    };
+    if tighter {
+        existing.outcome.margin = result.outcome.margin;
+        existing.projected = result.projected;
+    }
+}
+
+/// Accessor for one audio coordinate.
+type Coordinate = fn(&AudioState) -> f64;
+
+/// Transitions between consecutive segments respect the slew limits.
+pub struct PlanTransitionRule {
+    pub limits: PlanLimits,
+}
+
+impl SafetyRule for PlanTransitionRule {
+    fn id(&self) -> &str {
+        RULE_PLAN_TRANSITIONS
+    }
+
+    fn description(&self) -> &str {
+        "Amplitude, carrier and beat ramps between plan segments within slew limits"
+    }
+
+    fn scope(&self) -> RuleScope {
+        RuleScope::Session
+    }
+
+    fn evaluate(&self, stimulus: &Stimulus, ctx: &RuleContext) -> RuleOutcome {
+        let Stimulus::SessionPlan(plan) = stimulus else {
+            return RuleOutcome::pass();
+        };
+        let mut evidence = Vec::new();
+        let mut margins = Vec::new();
+        for (i, pair) in plan.segments.windows(2).enumerate() {
+            let (Some(from), Some(to)) = (ctx.segment_points.get(i), ctx.segment_points.get(i + 1)) else {
+                continue;
+            };
+            let t = pair[0].transition_sec;
+            let dimensions: [(&str, &str, f64, Coordinate); 3] = [
+                ("amplitude", "", self.limits.max_amplitude_slew_per_sec, |s| s.amplitude),
+                ("carrier", " Hz", self.limits.max_carrier_slew_hz_per_sec, |s| s.carrier_hz),
+                ("beat", " Hz", self.limits.max_beat_slew_hz_per_sec, |s| s.beat_hz),
+            ];
+            for (name, unit, limit, value) in dimensions {
+                // Largest change over any pair of points of the two protocols.
+                let delta = from
+                    .iter()
+                    .flat_map(|a| to.iter().map(move |b| (value(a) - value(b)).abs()))
+                    .fold(0.0, f64::max);
+                let rate = if t > 0.0 { delta / t } else if delta > 0.0 { f64::INFINITY } else { 0.0 };
+                if rate > limit {
+                    evidence.push(format!(
+                        "Transition {} -> {} ({} -> {}): {} changes by {:.3}{} over {} s, above {}{}/s",
+                        i,
+                        i + 1,
+                        pair[0].label,
+                        pair[1].label,
+                        name,
+                        delta,
+                        unit,
+                        t,
+                        limit,
+                        unit
+                    ));
+                }
+                margins.push(RuleMargin {
+                    // An abrupt change counts as fully violated.
+                    value: if rate.is_finite() { (limit - rate) / limit } else { -1.0 },
+                    limit: format!("{} slew <= {}{}/s ({} -> {})", name, limit, unit, i, i + 1),
+                    worst_point: None,
+                });
+            }
+        }
+        RuleOutcome::fail_if_any(evidence).with_margin(RuleMargin::tightest(margins))
+    }
+}
+
+/// Cumulative dose over the whole session: amplitude-time, duration and daily duty.
+pub struct PlanDoseRule {
+    pub max_amplitude_seconds: f64,
+}
+
+impl SafetyRule for PlanDoseRule {
+    fn id(&self) -> &str {
+        RULE_PLAN_DOSE
+    }
+
+    fn description(&self) -> &str {
+        "Cumulative amplitude-time, session duration and daily duty across the whole session"
+    }
+
+    fn scope(&self) -> RuleScope {
+        RuleScope::Session
+    }
+
+    fn evaluate(&self, stimulus: &Stimulus, ctx: &RuleContext) -> RuleOutcome {
+        let dose = SessionDose::of(stimulus, ctx);
+        let hw = ctx.hardware;
+        let mut evidence = Vec::new();
+        if dose.amplitude_seconds > self.max_amplitude_seconds {
+            evidence.push(format!(
+                "Cumulative amplitude-time {:.1} exceeds max {}",
+                dose.amplitude_seconds, self.max_amplitude_seconds
+            ));
+        }
+        if dose.total_duration_sec > hw.max_session_duration_sec {
+            evidence.push(format!(
+                "Total session duration {:.0} s exceeds max {} s",
+                dose.total_duration_sec, hw.max_session_duration_sec
+            ));
+        }
+        if dose.daily_duty > hw.max_daily_duty {
+            evidence.push(format!(
+                "Daily duty {:.3} including the last 24 h exceeds max {}",
+                dose.daily_duty, hw.max_daily_duty
+            ));
+        }
+        let margin = RuleMargin::tightest([
+            RuleMargin {
+                value: (self.max_amplitude_seconds - dose.amplitude_seconds) / self.max_amplitude_seconds,
+                limit: format!("amplitude-time <= {}", self.max_amplitude_seconds),
+                worst_point: None,
+            },
+            RuleMargin {
+                value: (hw.max_session_duration_sec - dose.total_duration_sec) / hw.max_session_duration_sec,
+                limit: format!("total duration <= {} s", hw.max_session_duration_sec),
+                worst_point: None,
+            },
+            RuleMargin {
+                value: (hw.max_daily_duty - dose.daily_duty) / hw.max_daily_duty,
+                limit: format!("daily duty <= {}", hw.max_daily_duty),
+                worst_point: None,
+            },
+        ]);
+        RuleOutcome::fail_if_any(evidence).with_margin(margin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::rules::{SessionRecord, RULE_CORRIDOR};
+    use crate::compiler::CompilationResult;
+    use crate::profile::CompilerProfile;
+    use neuroseek_audio::audio_nanopolytope::AudioNanopolytope;
+
+    fn compiler() -> Compiler {
+        let profile = CompilerProfile::from_json(include_str!("../../profiles/default.json")).unwrap();
+        Compiler::from_profile(profile)
+    }
+
+    fn segment(label: &str, amplitude: f64, beat_hz: f64, duration_sec: f64, transition_sec: f64) -> PlanSegment {
+        PlanSegment {
+            label: label.into(),
+            protocol: AudioProtocol::Fixed(AudioState {
+                amplitude,
+                carrier_hz: 200.0,
+                beat_hz,
+                duty: 0.1,
+                session_duration_sec: duration_sec,
+            }),
+            duration_sec,
+            transition_sec,
+        }
+    }
+
+    fn plan(segments: Vec<PlanSegment>) -> Stimulus {
+        Stimulus::SessionPlan(SessionPlan { name: "plan".into(), description: None, segments })
+    }
+
+    fn warm_main_down() -> Vec<PlanSegment> {
+        vec![
+            segment("warm-up", 0.2, 10.0, 300.0, 20.0),
+            segment("main", 0.6, 6.0, 1200.0, 20.0),
+            segment("wind-down", 0.2, 10.0, 300.0, 0.0),
+        ]
+    }
+
+    #[test]
+    fn test_plan_approved_and_dose_counts_whole_plan() {
+        match compiler().compile(plan(warm_main_down())) {
+            CompilationResult::Approved { .. } => {}
+            CompilationResult::Rejected { reasons, .. } => panic!("unexpected rejection: {:?}", reasons),
+        }
+
+        // Each segment alone is far below the amplitude-time cap; together they exceed it.
+        let mut config = compiler().config.clone();
+        config.rules.plan.max_amplitude_seconds = 800.0;
+        let compiler = Compiler::new(config);
+        let report = compiler.explain(&plan(warm_main_down()));
+        assert!(!report.would_approve);
+        let dose = report.rules.iter().find(|r| r.rule_id == RULE_PLAN_DOSE).unwrap();
+        // 0.2*300 + 0.4*20 + 0.6*1200 + 0.4*20 + 0.2*300 = 856.
+        assert!(dose.evidence[0].contains("856.0"));
+    }
+
+    #[test]
+    fn test_abrupt_transition_and_history_duty_rejected() {
+        let mut segments = warm_main_down();
+        segments[0].transition_sec = 0.0;
+        match compiler().compile(plan(segments)) {
+            CompilationResult::Rejected { reasons, .. } => {
+                assert!(reasons.iter().all(|r| r.rule_id == RULE_PLAN_TRANSITIONS));
+                assert!(reasons.iter().any(|r| r.evidence.contains("amplitude changes by 0.400")));
+            }
+            CompilationResult::Approved { .. } => panic!("expected rejection"),
+        }
+
+        // 7 h of sessions today plus a 30 min plan exceeds 30 % daily duty.
+        let subject = SubjectContext {
+            history: vec![SessionRecord { started_at: Utc::now(), duration_sec: 7.0 * 3600.0, max_amplitude: 0.5 }],
+            ..Default::default()
+        };
+        match compiler().compile_for(plan(warm_main_down()), &subject) {
+            CompilationResult::Rejected { reasons, .. } => {
+                assert!(reasons.iter().any(|r| r.rule_id == RULE_PLAN_DOSE && r.evidence.starts_with("Daily duty")));
+            }
+            CompilationResult::Approved { .. } => panic!("expected rejection"),
+        }
+    }
+
+    /// A segment on a small box around `amplitude`, whose model maps amplitude to E
+    /// with `e_gain`.
+    fn box_segment(label: &str, amplitude: f64, e_gain: f64) -> PlanSegment {
+        let bounds = [(amplitude, amplitude + 0.05), (200.0, 201.0), (10.0, 10.5), (0.1, 0.2), (60.0, 300.0)];
+        let (mut a, mut b) = (Vec::new(), Vec::new());
+        for (i, (lo, hi)) in bounds.iter().enumerate() {
+            let mut row = [0.0; 5];
+            row[i] = 1.0;
+            a.push(row);
+            b.push(*hi);
+            row[i] = -1.0;
+            a.push(row);
+            b.push(-lo);
+        }
+        let mut matrix = [[0.0; 5]; 5];
+        matrix[0][0] = e_gain;
+        let polytope = AudioNanopolytope::new(label.into(), a, b, 0.9, 0.2, 0.1).with_transfer(matrix, [0.0; 5]);
+        PlanSegment {
+            label: label.into(),
+            protocol: AudioProtocol::Polytope(Box::new(polytope)),
+            duration_sec: 300.0,
+            transition_sec: 20.0,
+        }
+    }
+
+    #[test]
+    fn test_transition_checked_under_both_models() {
+        // Each segment stays inside the E corridor [0, 0.8] under its own model.
+        let same = plan(vec![box_segment("low", 0.1, 0.5), box_segment("high", 0.7, 0.5)]);
+        assert!(compiler().explain(&same).would_approve);
+        let segments = vec![box_segment("low", 0.1, 1.2), box_segment("high", 0.7, 0.5)];
+        let report = compiler().explain(&plan(segments.clone()));
+        let corridor = report.rules.iter().find(|r| r.rule_id == RULE_CORRIDOR).unwrap();
+        assert!(corridor.evidence.iter().all(|e| !e.starts_with("Segment")));
+
+        // The ramp reaches the amplitudes of "high" under the model of "low": 1.2 * 0.75 > 0.8.
+        match compiler().compile(plan(segments)) {
+            CompilationResult::Rejected { reasons, .. } => {
+                assert!(reasons.iter().all(|r| r.rule_id == RULE_CORRIDOR));
+                assert!(reasons[0].evidence.starts_with("Transition 0 -> 1 under the model of segment 0: "));
+            }
+            CompilationResult::Approved { .. } => panic!("expected rejection"),
+        }
+    }
+
+    #[test]
+    fn test_segment_failures_are_labelled() {
+        let mut segments = warm_main_down();
+        segments[1] = segment("main", 0.6, 40.0, 1200.0, 20.0);
+        segments[0].transition_sec = 100.0;
+        segments[1].transition_sec = 100.0;
+        match compiler().compile(plan(segments)) {
+            CompilationResult::Rejected { reasons, .. } => {
+                assert_eq!(reasons.len(), 1);
+                assert!(reasons[0].evidence.starts_with("Segment 1 (main): Beat frequency 40"));
+            }
+            CompilationResult::Approved { .. } => panic!("expected rejection"),
+        }
+    }
+}
@@ -0,0 +1,672 @@
+//! Pluggable safety rules evaluated by the compiler.
+//!
+//! Every check the compiler performs is a `SafetyRule`: it looks at a stimulus in a
+//! `RuleContext` (hardware, corridor, neurorights, subject and history) and returns a
+//! pass, warn or fail outcome with evidence. Rules live in a `RuleRegistry`; the
+//! built-in rules are always registered, site-specific rules can be added with
+//! `Compiler::with_rule`, and the policy profile decides which rules are disabled or
+//! downgraded to warnings (see `RulesConfig`).
+//!
+//! For a multi-segment `SessionPlan`, segment-scope rules run once per segment with
+//! that segment's points and transfer model, and session-scope rules run once on the
+//! whole plan (see `RuleScope`).
+
+use super::biofield::BiofieldProtocol;
+use super::plan::{PlanDoseRule, PlanLimits, PlanTransitionRule};
+use super::{HardwareCapabilities, Stimulus};
+use crate::bioload::{BioloadBand, BioloadConfig, PersonalBaseline};
+use crate::biophysics::{BiophysicalCorridor, BiophysicalState, CorridorMargin, DIMENSION_NAMES};
+use crate::neurorights::NeurorightsConstraints;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use neuroseek_audio::audio_nanopolytope::AudioState;
+use neuroseek_audio::config::Protocol as AudioProtocol;
+use neuroseek_audio::transfer::LinearTransfer;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use tracing::warn;
+
+/// Identifiers of the built-in rules.
+pub const RULE_HARDWARE: &str = "hardware";
+pub const RULE_BEAT_RANGE: &str = "beat_range";
+pub const RULE_CORRIDOR: &str = "corridor";
+pub const RULE_NEURORIGHTS: &str = "neurorights";
+pub const RULE_PLAN_TRANSITIONS: &str = "plan_transitions";
+pub const RULE_PLAN_DOSE: &str = "plan_dose";
+pub const RULE_BIOLOAD: &str = "bioload";
+
+/// Built-in rules that a profile may neither disable nor downgrade.
+pub const MANDATORY_RULES: [&str; 5] = [RULE_HARDWARE, RULE_CORRIDOR, RULE_NEURORIGHTS, RULE_PLAN_DOSE, RULE_BIOLOAD];
+
+/// Outcome class of a rule evaluation, ordered from best to worst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// How close a stimulus came to a rule's binding limit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleMargin {
+    /// Distance to the limit, normalized by the limit's scale: the limit value for
+    /// hardware floors and ceilings, the allowed range width for beat and corridor
+    /// ranges. Zero is at the limit; negative is violated.
+    pub value: f64,
+    /// The binding limit, e.g. "amplitude <= 1".
+    pub limit: String,
+    /// Audio state at which the margin is smallest, if the rule is point-wise.
+    pub worst_point: Option<AudioState>,
+}
+
+impl RuleMargin {
+    /// The smallest of a set of candidate margins.
+    pub fn tightest(candidates: impl IntoIterator<Item = RuleMargin>) -> Option<RuleMargin> {
+        candidates
+            .into_iter()
+            .min_by(|a, b| a.value.partial_cmp(&b.value).unwrap_or(std::cmp::Ordering::Equal))
+    }
+}
+
+/// Result of evaluating one rule.
+#[derive(Debug, Clone)]
+pub struct RuleOutcome {
+    pub status: RuleStatus,
+    /// Human-readable evidence supporting the status (empty for a plain pass).
+    pub evidence: Vec<String>,
+    /// Tightest margin to the rule's limit, if the rule can quantify one.
+    pub margin: Option<RuleMargin>,
+}
+
+impl RuleOutcome {
+    pub fn pass() -> Self {
+        Self { status: RuleStatus::Pass, evidence: Vec::new(), margin: None }
+    }
+
+    pub fn warn(evidence: Vec<String>) -> Self {
+        Self { status: RuleStatus::Warn, evidence, margin: None }
+    }
+
+    pub fn fail(evidence: Vec<String>) -> Self {
+        Self { status: RuleStatus::Fail, evidence, margin: None }
+    }
+
+    /// Attach the tightest margin found by the rule.
+    pub fn with_margin(mut self, margin: Option<RuleMargin>) -> Self {
+        self.margin = margin;
+        self
+    }
+
+    /// Fail with the given evidence, or pass if there is none.
+    pub fn fail_if_any(evidence: Vec<String>) -> Self {
+        if evidence.is_empty() {
+            Self::pass()
+        } else {
+            Self::fail(evidence)
+        }
+    }
+}
+
+/// A non-passing rule result attached to a compilation result.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Finding {
+    pub rule_id: String,
+    pub status: RuleStatus,
+    pub evidence: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}", self.rule_id, self.evidence)
+    }
+}
+
+/// A past session of the subject, used by history-aware rules.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionRecord {
+    pub started_at: DateTime<Utc>,
+    pub duration_sec: f64,
+    pub max_amplitude: f64,
+}
+
+/// Who the stimulus is compiled for, and what they have already received.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SubjectContext {
+    pub subject_id: Option<String>,
+    #[serde(default)]
+    pub history: Vec<SessionRecord>,
+    /// Latest estimate of the subject's biophysical state, used as the starting point
+    /// of biofield projections.
+    #[serde(default)]
+    pub current_state: Option<BiophysicalState>,
+    /// Personal baseline for bioload classification.
+    #[serde(default)]
+    pub baseline: Option<PersonalBaseline>,
+    /// Bioload band from an upstream (hysteresis-aware) classifier. When absent, the
+    /// compiler fills it in from its own classifier for the subject (see
+    /// `Compiler::compile_for`); rules evaluated outside a compiler classify
+    /// `current_state` against `baseline` without hysteresis.
+    #[serde(default)]
+    pub bioload_band: Option<BioloadBand>,
+}
+
+impl SubjectContext {
+    /// Sessions that started within the 24 hours before `now`.
+    pub fn sessions_in_last_day(&self, now: DateTime<Utc>) -> impl Iterator<Item = &SessionRecord> {
+        let since = now - ChronoDuration::hours(24);
+        self.history.iter().filter(move |s| s.started_at > since && s.started_at <= now)
+    }
+
+    /// The subject's bioload band, if it is given or can be classified.
+    pub fn bioload_band(&self, config: &BioloadConfig) -> Option<BioloadBand> {
+        self.bioload_band.or_else(|| {
+            let (state, baseline) = (self.current_state.as_ref()?, self.baseline.as_ref()?);
+            Some(config.band_for(baseline.load(state)))
+        })
+    }
+}
+
+/// Everything a rule may look at besides the stimulus itself.
+pub struct RuleContext<'a> {
+    pub hardware: &'a HardwareCapabilities,
+    pub corridor: &'a BiophysicalCorridor,
+    pub neurorights: &'a NeurorightsConstraints,
+    pub subject: &'a SubjectContext,
+    /// Audio states covering the protocol: the fixed point or all polytope vertices.
+    /// In the session-wide context of a plan, the points of every segment.
+    pub audio_points: &'a [AudioState],
+    /// Points of each plan segment, in order (a single entry outside plans).
+    pub segment_points: &'a [Vec<AudioState>],
+    /// Transfer model used to project `audio_points` into biophysical space. `None`
+    /// in the session-wide context of a plan, whose segments may use different models.
+    pub transfer: Option<&'a LinearTransfer>,
+    /// Worst-case projected `(min, max)` per biophysical dimension over the whole
+    /// stimulus: from `transfer` over `audio_points`, the union over plan segments,
+    /// or the declared deltas of a biofield protocol.
+    pub projected_range: Option<[(f64, f64); 5]>,
+    /// Evaluation time (for history windows).
+    pub now: DateTime<Utc>,
+}
+
+impl RuleContext<'_> {
+    /// Worst-case corridor margin of `projected_range`.
+    pub fn corridor_margin(&self) -> Option<CorridorMargin> {
+        self.projected_range.map(|worst| self.corridor.margin(worst))
+    }
+}
+
+/// Granularity at which a rule is evaluated on a multi-segment plan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleScope {
+    /// Once per segment, on that segment's protocol alone.
+    Segment,
+    /// Once per stimulus, on the whole plan.
+    Session,
+}
+
+/// A safety check the compiler runs on every stimulus.
+pub trait SafetyRule: Send + Sync {
+    /// Stable identifier used in profiles and findings.
+    fn id(&self) -> &str;
+
+    /// One-line description for reports.
+    fn description(&self) -> &str;
+
+    /// Evaluate the stimulus.
+    fn evaluate(&self, stimulus: &Stimulus, ctx: &RuleContext) -> RuleOutcome;
+
+    /// Whether the rule looks at single protocols or whole sessions.
+    fn scope(&self) -> RuleScope {
+        RuleScope::Segment
+    }
+}
+
+/// Per-profile rule configuration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RulesConfig {
+    /// Rules that are not evaluated at all.
+    #[serde(default)]
+    pub disabled: Vec<String>,
+    /// Rules whose failures are reported as warnings only.
+    #[serde(default)]
+    pub warn_only: Vec<String>,
+    /// Allowed binaural beat range for the `beat_range` rule.
+    #[serde(default = "default_beat_range_hz")]
+    pub beat_range_hz: (f64, f64),
+    /// Transition and cumulative dose limits for session plans.
+    #[serde(default)]
+    pub plan: PlanLimits,
+    /// Band thresholds for the `bioload` rule.
+    #[serde(default)]
+    pub bioload: BioloadConfig,
+}
+
+fn default_beat_range_hz() -> (f64, f64) {
+    (0.0, 30.0) // typical safe range
+}
+
+impl Default for RulesConfig {
+    fn default() -> Self {
+        Self {
+            disabled: Vec::new(),
+            warn_only: Vec::new(),
+            beat_range_hz: default_beat_range_hz(),
+            plan: PlanLimits::default(),
+            bioload: BioloadConfig::default(),
+        }
+    }
+}
+
+/// The set of rules a compiler evaluates, in registration order.
+pub struct RuleRegistry {
+    rules: Vec<Box<dyn SafetyRule>>,
+    disabled: Vec<String>,
+    warn_only: Vec<String>,
+}
+
+impl RuleRegistry {
+    /// Registry with the built-in rules, configured from the profile.
+    pub fn builtin(config: &RulesConfig) -> Self {
+        let mut registry = Self {
+            rules: Vec::new(),
+            disabled: config.disabled.clone(),
+            warn_only: config.warn_only.clone(),
+        };
+        registry.register(Box::new(HardwareRule));
+        registry.register(Box::new(BeatRangeRule { range_hz: config.beat_range_hz }));
+        registry.register(Box::new(CorridorRule));
+        registry.register(Box::new(NeurorightsRule));
+        registry.register(Box::new(PlanTransitionRule { limits: config.plan }));
+        registry.register(Box::new(PlanDoseRule { max_amplitude_seconds: config.plan.max_amplitude_seconds }));
+        registry.register(Box::new(BioloadRule { config: config.bioload }));
+        registry
+    }
+
+    /// Add a rule. A rule with the same id replaces the existing one, unless that
+    /// rule is mandatory.
+    pub fn register(&mut self, rule: Box<dyn SafetyRule>) {
+        if let Some(pos) = self.rules.iter().position(|r| r.id() == rule.id()) {
+            if MANDATORY_RULES.contains(&rule.id()) {
+                warn!("Refusing to replace mandatory rule {}", rule.id());
+                return;
+            }
+            self.rules[pos] = rule;
+        } else {
+            self.rules.push(rule);
+        }
+    }
+
+    /// Ids of all registered rules.
+    pub fn ids(&self) -> Vec<&str> {
+        self.rules.iter().map(|r| r.id()).collect()
+    }
+
+    /// Evaluate all enabled rules, applying warn-only downgrades.
+    pub fn evaluate(&self, stimulus: &Stimulus, ctx: &RuleContext) -> Vec<(&dyn SafetyRule, RuleOutcome)> {
+        self.evaluate_matching(None, stimulus, ctx)
+    }
+
+    /// Evaluate the enabled rules of one scope, applying warn-only downgrades.
+    pub fn evaluate_scope(
+        &self,
+        scope: RuleScope,
+        stimulus: &Stimulus,
+        ctx: &RuleContext,
+    ) -> Vec<(&dyn SafetyRule, RuleOutcome)> {
+        self.evaluate_matching(Some(scope), stimulus, ctx)
+    }
+
+    fn evaluate_matching(
+        &self,
+        scope: Option<RuleScope>,
+        stimulus: &Stimulus,
+        ctx: &RuleContext,
+    ) -> Vec<(&dyn SafetyRule, RuleOutcome)> {
+        self.rules
+            .iter()
+            .filter(|r| !self.disabled.iter().any(|d| d == r.id()))
+            .filter(|r| scope.is_none_or(|s| r.scope() == s))
+            .map(|r| {
+                let mut outcome = r.evaluate(stimulus, ctx);
+                if outcome.status == RuleStatus::Fail && self.warn_only.iter().any(|w| w == r.id()) {
+                    outcome.status = RuleStatus::Warn;
+                }
+                (r.as_ref(), outcome)
+            })
+            .collect()
+    }
+}
+
+/// Label for the i-th evaluated point in evidence strings.
+fn point_label(stimulus: &Stimulus, index: usize) -> String {
+    match stimulus {
+        Stimulus::Audio(a) => match &a.protocol {
+            AudioProtocol::Fixed(_) => String::new(),
+            AudioProtocol::Polytope(_) => format!("Polytope vertex {}: ", index),
+        },
+        Stimulus::SessionPlan(_) => format!("Point {}: ", index),
+        Stimulus::Biofield(_) => String::new(),
+    }
+}
+
+/// Amplitude, carrier frequency, session duration and duty within hardware limits;
+/// current, frequency, duration and waveform within the emitter's limits for biofield
+/// stimuli.
+pub struct HardwareRule;
+
+impl SafetyRule for HardwareRule {
+    fn id(&self) -> &str {
+        RULE_HARDWARE
+    }
+
+    fn description(&self) -> &str {
+        "Stimulus parameters within hardware capabilities"
+    }
+
+    fn evaluate(&self, stimulus: &Stimulus, ctx: &RuleContext) -> RuleOutcome {
+        if let Stimulus::Biofield(protocol) = stimulus {
+            return biofield_hardware(protocol, ctx);
+        }
+        let hw = ctx.hardware;
+        let mut evidence = Vec::new();
+        for (i, state) in ctx.audio_points.iter().enumerate() {
+            let label = point_label(stimulus, i);
+            if state.amplitude > hw.max_amplitude {
+                evidence.push(format!("{}Amplitude {} exceeds hardware max {}", label, state.amplitude, hw.max_amplitude));
+            }
+            if state.carrier_hz < hw.min_frequency || state.carrier_hz > hw.max_frequency {
+                evidence.push(format!("{}Carrier frequency {} out of hardware range", label, state.carrier_hz));
+            }
+            if state.session_duration_sec > hw.max_session_duration_sec {
+                evidence.push(format!("{}Session duration {} exceeds max {}", label, state.session_duration_sec, hw.max_session_duration_sec));
+            }
+            if state.duty > hw.max_daily_duty {
+                evidence.push(format!("{}Duty {} exceeds max daily duty {}", label, state.duty, hw.max_daily_duty));
+            }
+        }
+        let margin = RuleMargin::tightest(ctx.audio_points.iter().flat_map(|s| {
+            [
+                ((hw.max_amplitude - s.amplitude) / hw.max_amplitude, format!("amplitude <= {}", hw.max_amplitude)),
+                ((s.carrier_hz - hw.min_frequency) / hw.min_frequency, format!("carrier >= {} Hz", hw.min_frequency)),
+                ((hw.max_frequency - s.carrier_hz) / hw.max_frequency, format!("carrier <= {} Hz", hw.max_frequency)),
+                (
+                    (hw.max_session_duration_sec - s.session_duration_sec) / hw.max_session_duration_sec,
+                    format!("session duration <= {} s", hw.max_session_duration_sec),
+                ),
+                ((hw.max_daily_duty - s.duty) / hw.max_daily_duty, format!("duty <= {}", hw.max_daily_duty)),
+            ]
+            .into_iter()
+            .map(|(value, limit)| RuleMargin { value, limit, worst_point: Some(*s) })
+        }));
+        RuleOutcome::fail_if_any(evidence).with_margin(margin)
+    }
+}
+
+/// Hardware check for biofield stimuli against the emitter's limits.
+fn biofield_hardware(protocol: &BiofieldProtocol, ctx: &RuleContext) -> RuleOutcome {
+    let Some(caps) = &ctx.hardware.biofield else {
+        return RuleOutcome::fail(vec!["No biofield emitter limits configured in the profile".into()]);
+    };
+    let margin = RuleMargin::tightest([
+        RuleMargin {
+            value: (caps.max_amplitude_ua - protocol.amplitude_ua) / caps.max_amplitude_ua,
+            limit: format!("amplitude <= {} uA", caps.max_amplitude_ua),
+            worst_point: None,
+        },
+        RuleMargin {
+            value: (protocol.frequency_hz - caps.min_frequency_hz) / caps.min_frequency_hz,
+            limit: format!("frequency >= {} Hz", caps.min_frequency_hz),
+            worst_point: None,
+        },
+        RuleMargin {
+            value: (caps.max_frequency_hz - protocol.frequency_hz) / caps.max_frequency_hz,
+            limit: format!("frequency <= {} Hz", caps.max_frequency_hz),
+            worst_point: None,
+        },
+        RuleMargin {
+            value: (caps.max_duration_sec as f64 - protocol.duration_sec as f64) / caps.max_duration_sec as f64,
+            limit: format!("duration <= {} s", caps.max_duration_sec),
+            worst_point: None,
+        },
+    ]);
+    RuleOutcome::fail_if_any(caps.violations(protocol)).with_margin(margin)
+}
+
+/// Binaural beat frequency within the profile's allowed range.
+pub struct BeatRangeRule {
+    pub range_hz: (f64, f64),
+}
+
+impl SafetyRule for BeatRangeRule {
+    fn id(&self) -> &str {
+        RULE_BEAT_RANGE
+    }
+
+    fn description(&self) -> &str {
+        "Beat frequency within the allowed binaural range"
+    }
+
+    fn evaluate(&self, stimulus: &Stimulus, ctx: &RuleContext) -> RuleOutcome {
+        let (min, max) = self.range_hz;
+        let evidence = ctx
+            .audio_points
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.beat_hz < min || s.beat_hz > max)
+            .map(|(i, s)| format!("{}Beat frequency {} out of safe range ({}-{} Hz)", point_label(stimulus, i), s.beat_hz, min, max))
+            .collect();
+        let span = max - min;
+        let margin = RuleMargin::tightest(ctx.audio_points.iter().flat_map(|s| {
+            [
+                RuleMargin { value: (s.beat_hz - min) / span, limit: format!("beat >= {} Hz", min), worst_point: Some(*s) },
+                RuleMargin { value: (max - s.beat_hz) / span, limit: format!("beat <= {} Hz", max), worst_point: Some(*s) },
+            ]
+        }));
+        RuleOutcome::fail_if_any(evidence).with_margin(margin)
+    }
+}
+
+/// Worst-case projected biophysical state stays inside the corridor.
+pub struct CorridorRule;
+
+impl SafetyRule for CorridorRule {
+    fn id(&self) -> &str {
+        RULE_CORRIDOR
+    }
+
+    fn description(&self) -> &str {
+        "Worst-case biophysical projection inside the safety corridor"
+    }
+
+    fn evaluate(&self, _stimulus: &Stimulus, ctx: &RuleContext) -> RuleOutcome {
+        let Some(margin) = ctx.corridor_margin() else {
+            return RuleOutcome::fail(vec!["No biophysical projection available".into()]);
+        };
+        let bounds = ctx.corridor.as_array();
+        let evidence = (0..5)
+            .filter(|&i| margin.per_dimension[i] < 0.0)
+            .map(|i| {
+                format!(
+                    "Worst-case projected {} range [{:.4}, {:.4}] leaves corridor [{}, {}] (margin {:.4})",
+                    DIMENSION_NAMES[i],
+                    margin.worst_case[i].0,
+                    margin.worst_case[i].1,
+                    bounds[i].0,
+                    bounds[i].1,
+                    margin.per_dimension[i],
+                )
+            })
+            .collect();
+        // Per point when a transfer model is available, so that the tightest margin
+        // names the audio state responsible; otherwise over the projected range.
+        let tightest = match ctx.transfer {
+            Some(transfer) => RuleMargin::tightest(
+                ctx.audio_points
+                    .iter()
+                    .flat_map(|s| corridor_margins(&bounds, &transfer.interval(s), Some(*s))),
+            ),
+            None => RuleMargin::tightest(corridor_margins(&bounds, &margin.worst_case, None)),
+        };
+        RuleOutcome::fail_if_any(evidence).with_margin(tightest)
+    }
+}
+
+/// Normalized margins of a projected interval against each corridor bound.
+fn corridor_margins(
+    bounds: &[(f64, f64); 5],
+    interval: &[(f64, f64); 5],
+    worst_point: Option<AudioState>,
+) -> Vec<RuleMargin> {
+    (0..5)
+        .flat_map(|i| {
+            let (lo, hi) = bounds[i];
+            let width = hi - lo;
+            [
+                RuleMargin {
+                    value: (interval[i].0 - lo) / width,
+                    limit: format!("{} >= {}", DIMENSION_NAMES[i], lo),
+                    worst_point,
+                },
+                RuleMargin {
+                    value: (hi - interval[i].1) / width,
+                    limit: format!("{} <= {}", DIMENSION_NAMES[i], hi),
+                    worst_point,
+                },
+            ]
+        })
+        .collect()
+}
+
+/// Neurorights constraints: no thought decoding, bounded sessions per day.
+pub struct NeurorightsRule;
+
+impl SafetyRule for NeurorightsRule {
+    fn id(&self) -> &str {
+        RULE_NEURORIGHTS
+    }
+
+    fn description(&self) -> &str {
+        "Neurorights constraints (thought decoding prohibited, daily session cap)"
+    }
+
+    fn scope(&self) -> RuleScope {
+        RuleScope::Session
+    }
+
+    fn evaluate(&self, _stimulus: &Stimulus, ctx: &RuleContext) -> RuleOutcome {
+        let mut evidence = Vec::new();
+        if !ctx.neurorights.prohibit_thought_decoding {
+            evidence.push("Thought decoding must be prohibited".into());
+        }
+        let sessions_today = ctx.subject.sessions_in_last_day(ctx.now).count();
+        if sessions_today >= ctx.neurorights.max_sessions_per_day as usize {
+            evidence.push(format!(
+                "{} sessions in the last 24 h reach the limit of {} per day",
+                sessions_today, ctx.neurorights.max_sessions_per_day
+            ));
+        }
+        let max = ctx.neurorights.max_sessions_per_day as f64;
+        let margin = if ctx.neurorights.prohibit_thought_decoding {
+            // Sessions left after this one, relative to the daily cap.
+            RuleMargin {
+                value: (max - sessions_today as f64 - 1.0) / max,
+                limit: format!("at most {} sessions per 24 h", ctx.neurorights.max_sessions_per_day),
+                worst_point: None,
+            }
+        } else {
+            RuleMargin { value: -1.0, limit: "thought decoding prohibited".into(), worst_point: None }
+        };
+        RuleOutcome::fail_if_any(evidence).with_margin(Some(margin))
+    }
+}
+
+/// Bioload gating: RED allows only wind-down, YELLOW only protocols that do not
+/// increase load (see `crate::bioload`).
+///
+/// The load a stimulus leads to is the largest baseline deviation over its worst-case
+/// projection; for biofield stimuli, which start from the current state, it is the
+/// deviation of the end state. A protocol "does not increase load" if that is at most
+/// the current load; it is a wind-down if it is strictly lower and, for session plans,
+/// the amplitude never rises from one segment to the next. GREEN or unknown bands are
+/// not gated.
+pub struct BioloadRule {
+    pub config: BioloadConfig,
+}
+
+impl SafetyRule for BioloadRule {
+    fn id(&self) -> &str {
+        RULE_BIOLOAD
+    }
+
+    fn description(&self) -> &str {
+        "Bioload band gating (RED: wind-down only, YELLOW: no load increase)"
+    }
+
+    fn scope(&self) -> RuleScope {
+        RuleScope::Session
+    }
+
+    fn evaluate(&self, stimulus: &Stimulus, ctx: &RuleContext) -> RuleOutcome {
+        let band = match ctx.subject.bioload_band(&self.config) {
+            None | Some(BioloadBand::Green) => return RuleOutcome::pass(),
+            Some(band) => band,
+        };
+        let (Some(state), Some(baseline)) = (&ctx.subject.current_state, &ctx.subject.baseline) else {
+            return RuleOutcome::fail(vec![format!(
+                "Bioload band is {} but the current state or baseline is missing to assess load",
+                band
+            )]);
+        };
+        let current = baseline.load(state);
+        let projected = match stimulus {
+            Stimulus::Biofield(protocol) => {
+                let mut end = state.to_array();
+                for (z, d) in end.iter_mut().zip(protocol.deltas()) {
+                    *z += d;
+                }
+                Some(baseline.load(&BiophysicalState::from_array(end)))
+            }
+            _ => ctx.projected_range.as_ref().map(|range| baseline.load_of_range(range)),
+        };
+        let Some(projected) = projected else {
+            return RuleOutcome::fail(vec!["No biophysical projection to assess load".into()]);
+        };
+
+        let mut evidence = Vec::new();
+        match band {
+            BioloadBand::Red => {
+                if projected >= current {
+                    evidence.push(format!(
+                        "Bioload band {} allows only wind-down: projected load {:.2} SD does not fall below current {:.2} SD",
+                        band, projected, current
+                    ));
+                }
+                if let Stimulus::SessionPlan(_) = stimulus {
+                    let amplitudes: Vec<f64> = ctx
+                        .segment_points
+                        .iter()
+                        .map(|p| p.iter().map(|s| s.amplitude).fold(0.0, f64::max))
+                        .collect();
+                    if amplitudes.windows(2).any(|w| w[1] > w[0]) {
+                        evidence.push(format!("Bioload band {} allows only wind-down: plan amplitude rises between segments", band));
+                    }
+                }
+            }
+            BioloadBand::Yellow if projected > current => evidence.push(format!(
+                "Bioload band {} forbids increasing load: projected {:.2} SD exceeds current {:.2} SD",
+                band, projected, current
+            )),
+            _ => {}
+        }
+        let margin = RuleMargin {
+            value: (current - projected) / current.max(f64::EPSILON),
+            limit: match band {
+                BioloadBand::Red => format!("load < {:.2} SD ({})", current, band),
+                _ => format!("load <= {:.2} SD ({})", current, band),
+            },
+            worst_point: None,
+        };
+        RuleOutcome::fail_if_any(evidence).with_margin(Some(margin))
+    }
+}
@@ -0,0 +1,365 @@
+//! Nearest-safe-protocol suggestions.
+//!
+//! When a stimulus is rejected, the compiler looks for the closest audio state that
+//! would have been approved: the projection of the requested state onto the
+//! intersection of
+//!
+//! - the hardware limits (and the beat range, if that rule is enabled),
+//! - the requested polytope, if the protocol is one, and
+//! - the preimage of the biophysical corridor under the transfer model.
+//!
+//! Every set is a finite intersection of halfspaces. The corridor preimage is linear
+//! even with transfer uncertainty because all audio dimensions are non-negative:
+//! the worst-case upper bound of output i is Σ_j (m_ij + Δm_ij) x_j + offset_i + Δoffset_i,
+//! which is linear on x ≥ 0. The projection is computed with Dykstra's alternating
+//! projections, which converges to the exact nearest point of a convex intersection.
+//!
+//! Distance is measured in a weighted norm: each dimension is divided by a scale from
+//! `SuggestionConfig`, so that "one unit" of amplitude, carrier, beat, duty and
+//! duration are comparable steps rather than raw numbers in mixed units.
+
+use super::rules::{SubjectContext, RULE_BEAT_RANGE};
+use super::{AudioStimulus, CompilationResult, Compiler, Stimulus};
+use neuroseek_audio::audio_nanopolytope::AudioState;
+use neuroseek_audio::config::Protocol as AudioProtocol;
+use neuroseek_audio::transfer::LinearTransfer;
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of Dykstra sweeps over all halfspaces.
+const MAX_SWEEPS: usize = 20_000;
+/// Convergence tolerance, in scaled units.
+const TOLERANCE: f64 = 1e-12;
+/// Safe halfspaces are tightened by this much (in scaled units) so that the
+/// suggestion passes the compiler's strict checks despite rounding.
+const SLACK: f64 = 1e-9;
+
+/// Scales of the weighted distance used to pick the nearest safe protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SuggestionConfig {
+    /// Whether rejections carry a suggestion at all.
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Amplitude change counted as one unit of distance.
+    pub amplitude: f64,
+    /// Carrier change (Hz) counted as one unit of distance.
+    pub carrier_hz: f64,
+    /// Beat change (Hz) counted as one unit of distance.
+    pub beat_hz: f64,
+    /// Duty change counted as one unit of distance.
+    pub duty: f64,
+    /// Session duration change (s) counted as one unit of distance.
+    pub session_duration_sec: f64,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl Default for SuggestionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            amplitude: 0.1,
+            carrier_hz: 50.0,
+            beat_hz: 1.0,
+            duty: 0.05,
+            session_duration_sec: 60.0,
+        }
+    }
+}
+
+impl SuggestionConfig {
+    fn scales(&self) -> [f64; 5] {
+        [self.amplitude, self.carrier_hz, self.beat_hz, self.duty, self.session_duration_sec]
+    }
+
+    /// Weighted distance between two audio states.
+    pub fn distance(&self, a: &AudioState, b: &AudioState) -> f64 {
+        let (a, b) = (to_array(a), to_array(b));
+        self.scales()
+            .iter()
+            .enumerate()
+            .map(|(j, s)| ((a[j] - b[j]) / s).powi(2))
+            .sum::<f64>()
+            .sqrt()
+    }
+}
+
+/// The closest approvable alternative to a rejected stimulus.
+#[derive(Debug, Clone, Serialize)]
+pub struct Suggestion {
+    /// An alternative stimulus that `compile` approves for the same subject.
+    ///
+    /// For a fixed-point request this is the projected point. For a polytope request
+    /// it is the requested polytope restricted to the safe set, which keeps the
+    /// polytope's fitted transfer model and contains `suggested`.
+    pub stimulus: AudioStimulus,
+    /// The requested audio state (the polytope center for polytope requests).
+    pub requested: AudioState,
+    /// Nearest safe audio state.
+    pub suggested: AudioState,
+    /// Weighted distance between `requested` and `suggested`.
+    pub distance: f64,
+}
+
+/// One halfspace g · x <= h in audio space.
+#[derive(Debug, Clone, Copy)]
+struct Halfspace {
+    g: [f64; 5],
+    h: f64,
+}
+
+impl Compiler {
+    /// Compute the nearest approvable alternative to a stimulus, if there is one.
+    ///
+    /// Returns `None` for session plans, if the safe set is empty, the request has no
+    /// well-defined state (empty or unbounded polytope), or the projected alternative still fails
+    /// a rule that is not geometric (e.g., the daily session limit or a site rule).
+    pub fn suggest_for(&self, stimulus: &Stimulus, subject: &SubjectContext) -> Option<Suggestion> {
+        let audio = stimulus.as_audio()?;
+        let requested = match &audio.protocol {
+            AudioProtocol::Fixed(state) => *state,
+            AudioProtocol::Polytope(poly) => poly.center()?,
+        };
+        let transfer = self.transfer_for(&audio.protocol);
+        let mut safe = self.safe_halfspaces(&transfer)?;
+
+        let protocol = match &audio.protocol {
+            AudioProtocol::Fixed(_) => None,
+            AudioProtocol::Polytope(poly) => Some(poly),
+        };
+        let mut constraints = safe.clone();
+        if let Some(poly) = protocol {
+            constraints.extend(poly.a.iter().zip(&poly.b).map(|(g, h)| Halfspace { g: *g, h: *h }));
+        }
+        let scales = self.config.suggestion.scales();
+        let suggested = from_array(project(&to_array(&requested), &constraints, &scales)?);
+
+        let protocol = match protocol {
+            None => AudioProtocol::Fixed(suggested),
+            Some(poly) => {
+                let mut restricted = poly.clone();
+                restricted.name = format!("{} (safe)", poly.name);
+                for halfspace in safe.drain(..) {
+                    restricted.a.push(halfspace.g);
+                    restricted.b.push(halfspace.h - SLACK * (1.0 + halfspace.h.abs()));
+                }
+                AudioProtocol::Polytope(restricted)
+            }
+        };
+        let candidate = AudioStimulus {
+            protocol,
+            name: format!("{} (suggested)", audio.name),
+            description: Some(format!("Nearest safe alternative to {}", audio.name)),
+        };
+        match self.compile_inner(Stimulus::Audio(Box::new(candidate.clone())), subject, false) {
+            CompilationResult::Approved { .. } => Some(Suggestion {
+                distance: self.config.suggestion.distance(&requested, &suggested),
+                stimulus: candidate,
+                requested,
+                suggested,
+            }),
+            CompilationResult::Rejected { .. } => None,
+        }
+    }
+
+    /// Halfspaces describing every audio state that passes the geometric rules under
+    /// the given transfer. `None` if some constraint can never be met.
+    fn safe_halfspaces(&self, transfer: &LinearTransfer) -> Option<Vec<Halfspace>> {
+        let hw = &self.config.hardware;
+        let mut out = Vec::new();
+        let mut bound = |dim: usize, lo: f64, hi: f64| {
+            let mut g = [0.0; 5];
+            g[dim] = 1.0;
+            out.push(Halfspace { g, h: hi });
+            g[dim] = -1.0;
+            out.push(Halfspace { g, h: -lo });
+        };
+        bound(0, 0.0, hw.max_amplitude);
+        bound(1, hw.min_frequency, hw.max_frequency);
+        let (beat_lo, beat_hi) = if self.config.rules.disabled.iter().any(|r| r == RULE_BEAT_RANGE) {
+            (0.0, f64::INFINITY)
+        } else {
+            (self.config.rules.beat_range_hz.0.max(0.0), self.config.rules.beat_range_hz.1)
+        };
+        bound(2, beat_lo, beat_hi);
+        bound(3, 0.0, hw.max_daily_duty);
+        bound(4, 0.0, hw.max_session_duration_sec);
+        out.retain(|c| c.h.is_finite());
+
+        let corridor = self.config.biophysical_corridor.as_array();
+        let (delta, delta_offset) = match &transfer.uncertainty {
+            Some(u) => (u.matrix, u.offset),
+            None => ([[0.0; 5]; 5], [0.0; 5]),
+        };
+        for i in 0..5 {
+            let (lo, hi) = corridor[i];
+            let mut upper = [0.0; 5];
+            let mut lower = [0.0; 5];
+            for j in 0..5 {
+                upper[j] = transfer.matrix[i][j] + delta[i][j].abs();
+                lower[j] = -(transfer.matrix[i][j] - delta[i][j].abs());
+            }
+            let spread = delta_offset[i].abs();
+            out.push(Halfspace { g: upper, h: hi - transfer.offset[i] - spread });
+            out.push(Halfspace { g: lower, h: -(lo - transfer.offset[i] + spread) });
+        }
+
+        // Rows without coefficients are either always or never satisfied.
+        let mut safe = Vec::with_capacity(out.len());
+        for c in out {
+            if c.g.iter().all(|v| *v == 0.0) {
+                if c.h < 0.0 {
+                    return None;
+                }
+            } else {
+                safe.push(c);
+            }
+        }
+        Some(safe)
+    }
+}
+
+/// Weighted projection of `x` onto the intersection of `constraints`.
+///
+/// Works in scaled coordinates y = (x' - x) / s, where the problem becomes the
+/// Euclidean projection of the origin. Returns `None` if the intersection is empty
+/// (detected as non-convergence) or the constraints are degenerate.
+fn project(x: &[f64; 5], constraints: &[Halfspace], scales: &[f64; 5]) -> Option<[f64; 5]> {
+    // Rewrite g · x' <= h as (g ∘ s) · y <= h - g · x, normalized and tightened.
+    let mut scaled = Vec::with_capacity(constraints.len());
+    for c in constraints {
+        let mut a = [0.0; 5];
+        for j in 0..5 {
+            a[j] = c.g[j] * scales[j];
+        }
+        let norm = dot(&a, &a).sqrt();
+        if norm == 0.0 || !norm.is_finite() {
+            continue;
+        }
+        let rhs = c.h - dot(&c.g, x);
+        for v in a.iter_mut() {
+            *v /= norm;
+        }
+        scaled.push((a, rhs / norm - SLACK));
+    }
+
+    let mut y = [0.0; 5];
+    let mut corrections = vec![[0.0; 5]; scaled.len()];
+    for _ in 0..MAX_SWEEPS {
+        let previous = y;
+        for ((a, b), p) in scaled.iter().zip(corrections.iter_mut()) {
+            let mut z = [0.0; 5];
+            for j in 0..5 {
+                z[j] = y[j] + p[j];
+            }
+            let excess = dot(a, &z) - b;
+            let mut projected = z;
+            if excess > 0.0 {
+                for j in 0..5 {
+                    projected[j] -= excess * a[j];
+                }
+            }
+            for j in 0..5 {
+                p[j] = z[j] - projected[j];
+            }
+            y = projected;
+        }
+        let step = (0..5).map(|j| (y[j] - previous[j]).powi(2)).sum::<f64>().sqrt();
+        let feasible = scaled.iter().all(|(a, b)| dot(a, &y) <= b + SLACK);
+        if step < TOLERANCE && feasible {
+            let mut out = [0.0; 5];
+            for j in 0..5 {
+                out[j] = x[j] + scales[j] * y[j];
+            }
+            return Some(out);
+        }
+    }
+    None
+}
+
+fn dot(a: &[f64; 5], b: &[f64; 5]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+fn to_array(s: &AudioState) -> [f64; 5] {
+    [s.amplitude, s.carrier_hz, s.beat_hz, s.duty, s.session_duration_sec]
+}
+
+fn from_array(v: [f64; 5]) -> AudioState {
+    AudioState {
+        amplitude: v[0],
+        carrier_hz: v[1],
+        beat_hz: v[2],
+        duty: v[3],
+        session_duration_sec: v[4],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::CompilerProfile;
+
+    fn compiler() -> Compiler {
+        let profile = CompilerProfile::from_json(include_str!("../../profiles/default.json")).unwrap();
+        Compiler::from_profile(profile)
+    }
+
+    fn fixed(state: AudioState) -> Stimulus {
+        Stimulus::Audio(Box::new(AudioStimulus {
+            protocol: AudioProtocol::Fixed(state),
+            name: "loud".into(),
+            description: None,
+        }))
+    }
+
+    #[test]
+    fn test_rejection_carries_nearest_safe_point() {
+        let requested = AudioState {
+            amplitude: 1.5,
+            carrier_hz: 200.0,
+            beat_hz: 10.0,
+            duty: 0.1,
+            session_duration_sec: 600.0,
+        };
+        match compiler().compile(fixed(requested)) {
+            CompilationResult::Rejected { suggestion: Some(s), .. } => {
+                let s = *s;
+                // Only amplitude violates the hardware limit of 1.0; the nearest safe
+                // point lowers it to the limit and leaves everything else alone.
+                assert!((s.suggested.amplitude - 1.0).abs() < 1e-6);
+                assert!(s.suggested.amplitude <= 1.0);
+                assert!((s.suggested.carrier_hz - 200.0).abs() < 1e-6);
+                assert!((s.distance - 5.0).abs() < 1e-4);
+                assert!(matches!(
+                    compiler().compile(Stimulus::Audio(Box::new(s.stimulus))),
+                    CompilationResult::Approved { .. }
+                ));
+            }
+            other => panic!("expected rejection with suggestion, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_no_suggestion_when_limit_is_not_geometric() {
+        let state = AudioState {
+            amplitude: 1.5,
+            carrier_hz: 200.0,
+            beat_hz: 10.0,
+            duty: 0.1,
+            session_duration_sec: 600.0,
+        };
+        let compiler = compiler();
+        let history = (0..compiler.config.neurorights.max_sessions_per_day)
+            .map(|_| super::super::rules::SessionRecord {
+                started_at: chrono::Utc::now(),
+                duration_sec: 60.0,
+                max_amplitude: 0.5,
+            })
+            .collect();
+        let subject = SubjectContext { history, ..Default::default() };
+        assert!(compiler.suggest_for(&fixed(state), &subject).is_none());
+    }
+}
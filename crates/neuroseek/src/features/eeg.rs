@@ -0,0 +1,37 @@
+//! EEG band powers.
+
+use super::spectrum::band_powers;
+
+/// Delta band, in Hz.
+pub const DELTA_BAND: (f64, f64) = (0.5, 4.0);
+/// Theta band, in Hz.
+pub const THETA_BAND: (f64, f64) = (4.0, 8.0);
+
+/// Ratio of theta to delta power of one EEG channel.
+///
+/// Returns `None` if the window has no delta power (too short or flat).
+pub fn theta_delta_ratio(samples: &[f64], sample_rate_hz: f64) -> Option<f64> {
+    let powers = band_powers(samples, sample_rate_hz, &[THETA_BAND, DELTA_BAND]);
+    (powers[1] > 0.0).then(|| powers[0] / powers[1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn test_theta_delta_ratio_of_mixed_tones() {
+        // A 6 Hz tone at twice the amplitude of a 2 Hz tone: four times the power.
+        let fs = 256.0;
+        let samples: Vec<f64> = (0..1024)
+            .map(|i| {
+                let t = i as f64 / fs;
+                2.0 * (2.0 * PI * 6.0 * t).sin() + (2.0 * PI * 2.0 * t).sin()
+            })
+            .collect();
+        let ratio = theta_delta_ratio(&samples, fs).unwrap();
+        assert!((ratio - 4.0).abs() < 0.2, "theta/delta {}", ratio);
+        assert!(theta_delta_ratio(&[1.0; 64], fs).is_none());
+    }
+}
@@ -0,0 +1,106 @@
+//! Heart-rate variability from RR intervals.
+
+use super::spectrum::band_powers;
+
+/// Low-frequency band of HRV, in Hz.
+pub const LF_BAND: (f64, f64) = (0.04, 0.15);
+/// High-frequency (respiratory) band of HRV, in Hz.
+pub const HF_BAND: (f64, f64) = (0.15, 0.4);
+
+/// Shortest recording for which LF power is meaningful: two periods of the lowest
+/// LF frequency.
+pub const MIN_LF_HF_DURATION_SEC: f64 = 2.0 / LF_BAND.0;
+
+/// Mean heart rate, in beats per minute, of RR intervals in milliseconds.
+pub fn heart_rate_bpm(rr_ms: &[f64]) -> Option<f64> {
+    if rr_ms.is_empty() {
+        return None;
+    }
+    let mean = rr_ms.iter().sum::<f64>() / rr_ms.len() as f64;
+    (mean > 0.0).then(|| 60_000.0 / mean)
+}
+
+/// Root mean square of successive differences, in milliseconds.
+pub fn rmssd(rr_ms: &[f64]) -> Option<f64> {
+    if rr_ms.len() < 2 {
+        return None;
+    }
+    let sum: f64 = rr_ms.windows(2).map(|w| (w[1] - w[0]).powi(2)).sum();
+    Some((sum / (rr_ms.len() - 1) as f64).sqrt())
+}
+
+/// Standard deviation of the RR intervals (sample, n - 1), in milliseconds.
+pub fn sdnn(rr_ms: &[f64]) -> Option<f64> {
+    if rr_ms.len() < 2 {
+        return None;
+    }
+    let mean = rr_ms.iter().sum::<f64>() / rr_ms.len() as f64;
+    let sum: f64 = rr_ms.iter().map(|rr| (rr - mean).powi(2)).sum();
+    Some((sum / (rr_ms.len() - 1) as f64).sqrt())
+}
+
+/// Ratio of LF to HF power of the RR tachogram.
+///
+/// The RR series is placed on its beat times, resampled evenly at `resample_hz` by
+/// linear interpolation, and its spectrum split into the LF and HF bands. Returns
+/// `None` for recordings shorter than `MIN_LF_HF_DURATION_SEC` or without HF power.
+pub fn lf_hf_ratio(rr_ms: &[f64], resample_hz: f64) -> Option<f64> {
+    let tachogram = resample(rr_ms, resample_hz)?;
+    let powers = band_powers(&tachogram, resample_hz, &[LF_BAND, HF_BAND]);
+    (powers[1] > 0.0).then(|| powers[0] / powers[1])
+}
+
+/// Evenly resampled tachogram, or `None` if the recording is too short.
+fn resample(rr_ms: &[f64], resample_hz: f64) -> Option<Vec<f64>> {
+    let mut times = Vec::with_capacity(rr_ms.len());
+    let mut t = 0.0;
+    for rr in rr_ms {
+        t += rr / 1000.0;
+        times.push(t);
+    }
+    let duration = times.last()? - times[0];
+    if duration < MIN_LF_HF_DURATION_SEC || resample_hz <= 0.0 {
+        return None;
+    }
+
+    let count = (duration * resample_hz) as usize + 1;
+    let mut out = Vec::with_capacity(count);
+    let mut j = 0;
+    for i in 0..count {
+        let at = times[0] + i as f64 / resample_hz;
+        while j + 2 < times.len() && times[j + 1] < at {
+            j += 1;
+        }
+        let span = times[j + 1] - times[j];
+        let frac = if span > 0.0 { ((at - times[j]) / span).clamp(0.0, 1.0) } else { 0.0 };
+        out.push(rr_ms[j] + frac * (rr_ms[j + 1] - rr_ms[j]));
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn test_time_and_frequency_domain_metrics() {
+        assert_eq!(rmssd(&[800.0, 820.0, 800.0, 820.0]), Some(20.0));
+        assert_eq!(heart_rate_bpm(&[1000.0, 1000.0]), Some(60.0));
+        assert!((sdnn(&[800.0, 820.0, 800.0, 820.0]).unwrap() - 11.547).abs() < 1e-3);
+        assert!(sdnn(&[800.0]).is_none());
+        assert!(lf_hf_ratio(&[800.0; 20], 4.0).is_none());
+
+        // Three minutes of beats modulated strongly at 0.1 Hz (LF) and weakly at
+        // 0.25 Hz (HF): power ratio about (50 / 20)^2.
+        let mut rr = Vec::new();
+        let mut t = 0.0;
+        while t < 180.0 {
+            let interval = 800.0 + 50.0 * (2.0 * PI * 0.1 * t).sin() + 20.0 * (2.0 * PI * 0.25 * t).sin();
+            rr.push(interval);
+            t += interval / 1000.0;
+        }
+        let ratio = lf_hf_ratio(&rr, 4.0).unwrap();
+        assert!(ratio > 4.0 && ratio < 9.0, "LF/HF {}", ratio);
+    }
+}
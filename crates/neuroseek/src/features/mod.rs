@@ -0,0 +1,331 @@
+//! Derivation of the 5D bioscale coordinates from raw physiological signals.
+//!
+//! Upstream stacks that only expose raw windows (RR intervals, electrodermal activity,
+//! EEG, skin temperature) can still feed the clusterer: `FeatureExtractor` computes
+//! the individual features of each window (see `hrv`, `eda`, `eeg`, `temperature`)
+//! and combines them into a `BiophysicalState`.
+//!
+//! The combination is configurable (`Combination`): each coordinate is an offset plus
+//! a weighted sum of features, each feature first normalized to 0..1 over a given
+//! range and clamped. The result is clamped to 0..1 as well. Coordinates left out of a
+//! configuration keep their default mapping. For example:
+//!
+//! ```json
+//! { "s_bio": { "offset": 0.3, "terms": [
+//!     { "feature": "eda_tonic_us", "weight": 0.4, "range": [1.0, 20.0] },
+//!     { "feature": "rmssd_ms", "weight": -0.3, "range": [10.0, 100.0] } ] } }
+//! ```
+//!
+//! Protein burden (`M_prot`) has no correlate among these signals; the default maps it
+//! to a constant 0 and leaves it to configurations with a suitable source.
+
+pub mod eda;
+pub mod eeg;
+pub mod hrv;
+mod spectrum;
+pub mod temperature;
+
+use crate::model::BiophysicalState;
+use chrono::{DateTime, Utc};
+use eda::{EdaConfig, EdaFeatures};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use temperature::TemperatureBaseline;
+
+/// Errors raised while combining features into a state.
+#[derive(Debug, thiserror::Error)]
+pub enum FeatureError {
+    #[error("Feature {0} is needed for the state but was not computed from this window")]
+    Missing(Feature),
+}
+
+/// A feature that can enter the combination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    HeartRateBpm,
+    RmssdMs,
+    SdnnMs,
+    LfHf,
+    EdaTonicUs,
+    EdaPhasicRmsUs,
+    ScrPerMin,
+    ThetaDelta,
+    SkinTempDeviationC,
+    /// Share of the window spent in deep sleep (0..1).
+    SleepDeepShare,
+    /// Share of the window spent in REM sleep (0..1).
+    SleepRemShare,
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Feature::HeartRateBpm => "heart_rate_bpm",
+            Feature::RmssdMs => "rmssd_ms",
+            Feature::SdnnMs => "sdnn_ms",
+            Feature::LfHf => "lf_hf",
+            Feature::EdaTonicUs => "eda_tonic_us",
+            Feature::EdaPhasicRmsUs => "eda_phasic_rms_us",
+            Feature::ScrPerMin => "scr_per_min",
+            Feature::ThetaDelta => "theta_delta",
+            Feature::SkinTempDeviationC => "skin_temp_deviation_c",
+            Feature::SleepDeepShare => "sleep_deep_share",
+            Feature::SleepRemShare => "sleep_rem_share",
+        };
+        f.write_str(name)
+    }
+}
+
+/// An evenly sampled signal.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SampledSignal {
+    pub samples: Vec<f64>,
+    pub sample_rate_hz: f64,
+}
+
+/// Raw signals of one analysis window. Any of them may be absent.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RawWindow {
+    /// End of the window.
+    pub timestamp: DateTime<Utc>,
+    /// RR intervals, in milliseconds.
+    #[serde(default)]
+    pub rr_intervals_ms: Vec<f64>,
+    /// Skin conductance, in microsiemens.
+    #[serde(default)]
+    pub eda: Option<SampledSignal>,
+    /// One EEG channel, in microvolts.
+    #[serde(default)]
+    pub eeg: Option<SampledSignal>,
+    /// Skin temperature at the end of the window, in °C.
+    #[serde(default)]
+    pub skin_temp_c: Option<f64>,
+}
+
+/// Features computed from one window; `None` where the signal was absent or too short.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct FeatureVector {
+    pub heart_rate_bpm: Option<f64>,
+    pub rmssd_ms: Option<f64>,
+    pub sdnn_ms: Option<f64>,
+    pub lf_hf: Option<f64>,
+    pub eda: Option<EdaFeatures>,
+    pub theta_delta: Option<f64>,
+    pub skin_temp_deviation_c: Option<f64>,
+    pub sleep_deep_share: Option<f64>,
+    pub sleep_rem_share: Option<f64>,
+}
+
+impl FeatureVector {
+    pub fn get(&self, feature: Feature) -> Option<f64> {
+        match feature {
+            Feature::HeartRateBpm => self.heart_rate_bpm,
+            Feature::RmssdMs => self.rmssd_ms,
+            Feature::SdnnMs => self.sdnn_ms,
+            Feature::LfHf => self.lf_hf,
+            Feature::EdaTonicUs => self.eda.map(|e| e.tonic_mean_us),
+            Feature::EdaPhasicRmsUs => self.eda.map(|e| e.phasic_rms_us),
+            Feature::ScrPerMin => self.eda.map(|e| e.scr_per_min),
+            Feature::ThetaDelta => self.theta_delta,
+            Feature::SkinTempDeviationC => self.skin_temp_deviation_c,
+            Feature::SleepDeepShare => self.sleep_deep_share,
+            Feature::SleepRemShare => self.sleep_rem_share,
+        }
+    }
+}
+
+/// One weighted feature of a coordinate.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Term {
+    pub feature: Feature,
+    pub weight: f64,
+    /// Feature values mapped to 0 and 1; values outside are clamped.
+    pub range: (f64, f64),
+}
+
+impl Term {
+    pub fn new(feature: Feature, weight: f64, range: (f64, f64)) -> Self {
+        Self { feature, weight, range }
+    }
+}
+
+/// How one coordinate is computed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DimensionMapping {
+    pub offset: f64,
+    pub terms: Vec<Term>,
+}
+
+impl DimensionMapping {
+    /// Value of the coordinate for these features.
+    pub fn evaluate(&self, features: &FeatureVector) -> Result<f64, FeatureError> {
+        let mut value = self.offset;
+        for term in &self.terms {
+            let x = features.get(term.feature).ok_or(FeatureError::Missing(term.feature))?;
+            let (low, high) = term.range;
+            let normalized = if high > low { ((x - low) / (high - low)).clamp(0.0, 1.0) } else { 0.0 };
+            value += term.weight * normalized;
+        }
+        Ok(value.clamp(0.0, 1.0))
+    }
+}
+
+/// How the five coordinates are computed from features.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Combination {
+    pub e: DimensionMapping,
+    pub m_prot: DimensionMapping,
+    pub s_bio: DimensionMapping,
+    pub theta: DimensionMapping,
+    pub t: DimensionMapping,
+}
+
+impl Default for Combination {
+    fn default() -> Self {
+        Self {
+            e: DimensionMapping { offset: 0.0, terms: vec![Term::new(Feature::HeartRateBpm, 1.0, (50.0, 150.0))] },
+            m_prot: DimensionMapping::default(),
+            s_bio: DimensionMapping {
+                offset: 0.3,
+                terms: vec![
+                    Term::new(Feature::EdaTonicUs, 0.4, (1.0, 20.0)),
+                    Term::new(Feature::ScrPerMin, 0.3, (0.0, 10.0)),
+                    Term::new(Feature::RmssdMs, -0.3, (10.0, 100.0)),
+                ],
+            },
+            theta: DimensionMapping { offset: 0.0, terms: vec![Term::new(Feature::ThetaDelta, 1.0, (0.0, 2.0))] },
+            t: DimensionMapping { offset: 0.0, terms: vec![Term::new(Feature::SkinTempDeviationC, 1.0, (-2.0, 2.0))] },
+        }
+    }
+}
+
+impl Combination {
+    /// Combine features into a state. Every feature used by a term must be present.
+    pub fn combine(&self, features: &FeatureVector) -> Result<BiophysicalState, FeatureError> {
+        Ok(BiophysicalState::new(
+            self.e.evaluate(features)?,
+            self.m_prot.evaluate(features)?,
+            self.s_bio.evaluate(features)?,
+            self.theta.evaluate(features)?,
+            self.t.evaluate(features)?,
+        ))
+    }
+}
+
+/// Configuration of a `FeatureExtractor`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeatureConfig {
+    pub combination: Combination,
+    pub eda: EdaConfig,
+    /// Resampling rate of the RR tachogram for LF/HF, in Hz.
+    pub tachogram_hz: f64,
+    /// Length of the skin temperature baseline, in seconds.
+    pub temperature_baseline_sec: i64,
+}
+
+impl Default for FeatureConfig {
+    fn default() -> Self {
+        Self {
+            combination: Combination::default(),
+            eda: EdaConfig::default(),
+            tachogram_hz: 4.0,
+            temperature_baseline_sec: 3600,
+        }
+    }
+}
+
+impl FeatureConfig {
+    /// Load a configuration from JSON.
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+/// Computes features window by window, keeping the state that spans windows (the
+/// temperature baseline).
+#[derive(Debug, Clone)]
+pub struct FeatureExtractor {
+    config: FeatureConfig,
+    temperature: TemperatureBaseline,
+}
+
+impl FeatureExtractor {
+    pub fn new(config: FeatureConfig) -> Self {
+        let temperature = TemperatureBaseline::new(chrono::Duration::seconds(config.temperature_baseline_sec));
+        Self { config, temperature }
+    }
+
+    /// Compute the features of a window. Windows must be passed in time order.
+    pub fn extract(&mut self, window: &RawWindow) -> FeatureVector {
+        let rr = &window.rr_intervals_ms;
+        FeatureVector {
+            heart_rate_bpm: hrv::heart_rate_bpm(rr),
+            rmssd_ms: hrv::rmssd(rr),
+            sdnn_ms: hrv::sdnn(rr),
+            lf_hf: hrv::lf_hf_ratio(rr, self.config.tachogram_hz),
+            eda: window.eda.as_ref().and_then(|s| eda::features(&s.samples, s.sample_rate_hz, &self.config.eda)),
+            theta_delta: window.eeg.as_ref().and_then(|s| eeg::theta_delta_ratio(&s.samples, s.sample_rate_hz)),
+            skin_temp_deviation_c: window.skin_temp_c.and_then(|c| self.temperature.deviation(window.timestamp, c)),
+            ..Default::default()
+        }
+    }
+
+    /// Compute the features of a window and combine them into a state.
+    pub fn state(&mut self, window: &RawWindow) -> Result<BiophysicalState, FeatureError> {
+        let features = self.extract(window);
+        self.config.combination.combine(&features)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_configurable_combination() {
+        let features = FeatureVector {
+            heart_rate_bpm: Some(100.0),
+            rmssd_ms: Some(55.0),
+            theta_delta: Some(3.0),
+            ..Default::default()
+        };
+        let combination: Combination = serde_json::from_str(
+            r#"{ "e": { "terms": [{ "feature": "heart_rate_bpm", "weight": 1.0, "range": [50.0, 150.0] }] },
+                 "s_bio": { "offset": 0.5, "terms": [{ "feature": "rmssd_ms", "weight": -0.5, "range": [10.0, 100.0] }] },
+                 "theta": { "terms": [{ "feature": "theta_delta", "weight": 1.0, "range": [0.0, 2.0] }] },
+                 "t": {} }"#,
+        )
+        .unwrap();
+        let state = combination.combine(&features).unwrap();
+        assert!((state.e - 0.5).abs() < 1e-9);
+        assert!((state.s_bio - 0.25).abs() < 1e-9);
+        // Clamped at the top of its range.
+        assert_eq!(state.theta, 1.0);
+        assert_eq!(state.t, 0.0);
+
+        // The default needs EDA and temperature, which these features lack.
+        assert!(matches!(Combination::default().combine(&features), Err(FeatureError::Missing(Feature::EdaTonicUs))));
+    }
+
+    #[test]
+    fn test_temperature_deviation_across_windows() {
+        let mut extractor = FeatureExtractor::new(FeatureConfig::default());
+        let start = Utc::now();
+        let window = |secs: i64, celsius: f64| RawWindow {
+            timestamp: start + chrono::Duration::seconds(secs),
+            rr_intervals_ms: Vec::new(),
+            eda: None,
+            eeg: None,
+            skin_temp_c: Some(celsius),
+        };
+        assert_eq!(extractor.extract(&window(0, 33.0)).skin_temp_deviation_c, None);
+        extractor.extract(&window(60, 33.2));
+        let deviation = extractor.extract(&window(120, 34.1)).skin_temp_deviation_c.unwrap();
+        assert!((deviation - 1.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,40 @@
+//! Band powers of evenly sampled signals.
+
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+
+/// Power of `samples` in each `[low, high)` frequency band, in Hz.
+///
+/// The mean is removed and a Hann window applied before the FFT. Powers are not
+/// normalized to physical units, so they are only meaningful relative to each other
+/// (ratios, shares), which is all the features need.
+pub(crate) fn band_powers(samples: &[f64], sample_rate_hz: f64, bands: &[(f64, f64)]) -> Vec<f64> {
+    let n = samples.len();
+    if n < 2 {
+        return vec![0.0; bands.len()];
+    }
+    let mean = samples.iter().sum::<f64>() / n as f64;
+    let mut buffer: Vec<Complex<f64>> = samples
+        .iter()
+        .enumerate()
+        .map(|(i, &x)| {
+            let hann = 0.5 - 0.5 * (2.0 * std::f64::consts::PI * i as f64 / (n - 1) as f64).cos();
+            Complex::new((x - mean) * hann, 0.0)
+        })
+        .collect();
+    FftPlanner::new().plan_fft_forward(n).process(&mut buffer);
+
+    let resolution = sample_rate_hz / n as f64;
+    bands
+        .iter()
+        .map(|&(low, high)| {
+            (1..=n / 2)
+                .filter(|&k| {
+                    let f = k as f64 * resolution;
+                    f >= low && f < high
+                })
+                .map(|k| buffer[k].norm_sqr())
+                .sum()
+        })
+        .collect()
+}
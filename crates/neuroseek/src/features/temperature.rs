@@ -0,0 +1,41 @@
+//! Skin temperature deviation from a rolling baseline.
+
+use chrono::{DateTime, Duration, Utc};
+use std::collections::VecDeque;
+
+/// Rolling mean of recent skin temperature readings.
+#[derive(Debug, Clone)]
+pub struct TemperatureBaseline {
+    window: Duration,
+    readings: VecDeque<(DateTime<Utc>, f64)>,
+}
+
+impl TemperatureBaseline {
+    /// A baseline over the readings of the last `window`.
+    pub fn new(window: Duration) -> Self {
+        Self { window, readings: VecDeque::new() }
+    }
+
+    /// Current baseline, if there are readings in the window.
+    pub fn mean(&self) -> Option<f64> {
+        if self.readings.is_empty() {
+            return None;
+        }
+        Some(self.readings.iter().map(|(_, v)| v).sum::<f64>() / self.readings.len() as f64)
+    }
+
+    /// Deviation of a reading (°C) from the baseline of the readings before it, then
+    /// add it to the baseline. The first reading has no baseline to deviate from.
+    pub fn deviation(&mut self, timestamp: DateTime<Utc>, celsius: f64) -> Option<f64> {
+        while let Some(&(at, _)) = self.readings.front() {
+            if timestamp - at > self.window {
+                self.readings.pop_front();
+            } else {
+                break;
+            }
+        }
+        let deviation = self.mean().map(|mean| celsius - mean);
+        self.readings.push_back((timestamp, celsius));
+        deviation
+    }
+}
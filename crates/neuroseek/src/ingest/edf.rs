@@ -0,0 +1,531 @@
+//! EEG recordings in EDF, EDF+ and BDF format, as a source of the theta dimension.
+//!
+//! `EdfRecording::read` parses the file: the fixed 256-byte header, the per-signal
+//! headers, and the data records (16-bit samples for EDF, 24-bit for BDF), scaled to
+//! physical units. Annotation signals of EDF+ are not returned as signals; in
+//! discontinuous EDF+ files (`EDF+D`) their first time-keeping annotation gives the
+//! onset of each record, so gaps are not bridged.
+//!
+//! `theta_series` slides a window over the chosen channels, computes the theta/delta
+//! power ratio of each window (averaged across channels) and maps it to the theta
+//! coordinate. The resulting `ThetaSeries` is fused with the other four dimensions of
+//! a recorded session by `ReplaySource::open_with_theta`.
+//!
+//! The start date and time in EDF headers carry no time zone; they are taken as UTC.
+
+use crate::features::{eeg, DimensionMapping, FeatureError, FeatureVector};
+use crate::model::{BiophysicalState, QualityFlags, TaggedState};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use std::path::Path;
+
+/// Shortest step between analysis windows, in seconds.
+const MIN_STEP_SEC: f64 = 1e-3;
+
+/// Size of the fixed part of the header.
+const HEADER_BYTES: usize = 256;
+
+/// Widths of the per-signal header fields: label, transducer, physical dimension,
+/// physical min/max, digital min/max, prefiltering, samples per record, reserved.
+const SIGNAL_FIELD_WIDTHS: [usize; 10] = [16, 80, 8, 8, 8, 8, 8, 80, 8, 32];
+
+/// Errors raised while reading a recording or deriving theta from it.
+#[derive(Debug, thiserror::Error)]
+pub enum EdfError {
+    #[error("Failed to read EEG recording: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid EDF header: {0}")]
+    InvalidHeader(String),
+    #[error("EDF data ends inside record {0}")]
+    Truncated(usize),
+    #[error("No signal labelled '{0}' in the recording")]
+    UnknownChannel(String),
+    #[error("Invalid theta configuration: {0}")]
+    InvalidConfig(String),
+    #[error(transparent)]
+    Feature(#[from] FeatureError),
+}
+
+/// Flavour of the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdfFormat {
+    Edf,
+    /// EDF+, continuous (`EDF+C`) or not (`EDF+D`).
+    EdfPlus { continuous: bool },
+    Bdf,
+}
+
+impl EdfFormat {
+    fn bytes_per_sample(self) -> usize {
+        match self {
+            EdfFormat::Bdf => 3,
+            _ => 2,
+        }
+    }
+}
+
+/// One ordinary signal, in physical units, with the samples of all records concatenated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EdfSignal {
+    pub label: String,
+    pub physical_dimension: String,
+    pub sample_rate_hz: f64,
+    pub samples_per_record: usize,
+    pub samples: Vec<f64>,
+}
+
+/// A parsed recording.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EdfRecording {
+    pub format: EdfFormat,
+    pub start: DateTime<Utc>,
+    /// Duration of one data record, in seconds.
+    pub record_duration: f64,
+    /// Onset of each data record, in seconds from `start`.
+    pub record_onsets: Vec<f64>,
+    pub signals: Vec<EdfSignal>,
+}
+
+/// Header of one signal, before its data is read.
+struct SignalHeader {
+    label: String,
+    physical_dimension: String,
+    gain: f64,
+    offset: f64,
+    samples_per_record: usize,
+}
+
+fn text(bytes: &[u8], start: usize, len: usize) -> String {
+    String::from_utf8_lossy(&bytes[start..start + len]).trim().to_string()
+}
+
+fn number<T: std::str::FromStr>(bytes: &[u8], start: usize, len: usize, what: &str) -> Result<T, EdfError> {
+    let field = text(bytes, start, len);
+    field
+        .parse()
+        .map_err(|_| EdfError::InvalidHeader(format!("{} is not a number: '{}'", what, field)))
+}
+
+fn is_annotation(label: &str) -> bool {
+    label == "EDF Annotations" || label == "BDF Annotations"
+}
+
+/// Start of the recording from the `dd.mm.yy` and `hh.mm.ss` header fields. Years
+/// 85–99 are 19xx, the rest 20xx, as the specification requires.
+fn parse_start(date: &str, time: &str) -> Result<DateTime<Utc>, EdfError> {
+    let parts = |s: &str| -> Option<Vec<u32>> { s.split('.').map(|p| p.parse().ok()).collect() };
+    let invalid = || EdfError::InvalidHeader(format!("invalid start date/time '{} {}'", date, time));
+    let (d, t) = (parts(date).ok_or_else(invalid)?, parts(time).ok_or_else(invalid)?);
+    if d.len() != 3 || t.len() != 3 {
+        return Err(invalid());
+    }
+    let year = if d[2] >= 85 { 1900 + d[2] } else { 2000 + d[2] };
+    let date = NaiveDate::from_ymd_opt(year as i32, d[1], d[0]).ok_or_else(invalid)?;
+    let time = NaiveTime::from_hms_opt(t[0], t[1], t[2]).ok_or_else(invalid)?;
+    Ok(NaiveDateTime::new(date, time).and_utc())
+}
+
+/// Onset of a record from its first time-keeping annotation (`+<seconds>\x14\x14`).
+fn record_onset(tal: &[u8]) -> Option<f64> {
+    let end = tal.iter().position(|&b| b == 0x14 || b == 0x15)?;
+    std::str::from_utf8(&tal[..end]).ok()?.parse().ok()
+}
+
+impl EdfRecording {
+    /// Read and parse a file.
+    pub fn read(path: &Path) -> Result<Self, EdfError> {
+        Self::parse(&std::fs::read(path)?)
+    }
+
+    /// Parse the contents of a file.
+    pub fn parse(bytes: &[u8]) -> Result<Self, EdfError> {
+        if bytes.len() < HEADER_BYTES {
+            return Err(EdfError::InvalidHeader("file shorter than the fixed header".into()));
+        }
+        let reserved = text(bytes, 192, 44);
+        let format = if bytes[0] == 0xFF && &bytes[1..8] == b"BIOSEMI" {
+            EdfFormat::Bdf
+        } else if text(bytes, 0, 8) == "0" {
+            match reserved.as_str() {
+                "EDF+C" => EdfFormat::EdfPlus { continuous: true },
+                "EDF+D" => EdfFormat::EdfPlus { continuous: false },
+                _ => EdfFormat::Edf,
+            }
+        } else {
+            return Err(EdfError::InvalidHeader("unknown version field".into()));
+        };
+        let start = parse_start(&text(bytes, 168, 8), &text(bytes, 176, 8))?;
+        let header_bytes: usize = number(bytes, 184, 8, "header size")?;
+        let declared_records: i64 = number(bytes, 236, 8, "number of data records")?;
+        let record_duration: f64 = number(bytes, 244, 8, "record duration")?;
+        let ns: usize = number(bytes, 252, 4, "number of signals")?;
+        if header_bytes != HEADER_BYTES * (ns + 1) || bytes.len() < header_bytes {
+            return Err(EdfError::InvalidHeader(format!("header size {} does not fit {} signals", header_bytes, ns)));
+        }
+
+        // Field `f` of signal `i`.
+        let field_start = |f: usize, i: usize| {
+            HEADER_BYTES + SIGNAL_FIELD_WIDTHS[..f].iter().sum::<usize>() * ns + i * SIGNAL_FIELD_WIDTHS[f]
+        };
+        let mut headers = Vec::with_capacity(ns);
+        for i in 0..ns {
+            let w = SIGNAL_FIELD_WIDTHS;
+            let physical_min: f64 = number(bytes, field_start(3, i), w[3], "physical minimum")?;
+            let physical_max: f64 = number(bytes, field_start(4, i), w[4], "physical maximum")?;
+            let digital_min: f64 = number(bytes, field_start(5, i), w[5], "digital minimum")?;
+            let digital_max: f64 = number(bytes, field_start(6, i), w[6], "digital maximum")?;
+            if digital_max <= digital_min {
+                return Err(EdfError::InvalidHeader(format!("signal {} has an empty digital range", i)));
+            }
+            let gain = (physical_max - physical_min) / (digital_max - digital_min);
+            headers.push(SignalHeader {
+                label: text(bytes, field_start(0, i), w[0]),
+                physical_dimension: text(bytes, field_start(2, i), w[2]),
+                gain,
+                offset: physical_min - digital_min * gain,
+                samples_per_record: number(bytes, field_start(8, i), w[8], "samples per record")?,
+            });
+        }
+
+        let sample_bytes = format.bytes_per_sample();
+        let record_bytes = headers
+            .iter()
+            .try_fold(0usize, |sum, h| sum.checked_add(h.samples_per_record.checked_mul(sample_bytes)?))
+            .ok_or_else(|| EdfError::InvalidHeader("data records are too large".into()))?;
+        if record_bytes == 0 {
+            return Err(EdfError::InvalidHeader("data records are empty".into()));
+        }
+        let data = &bytes[header_bytes..];
+        // -1 (unknown, e.g. an interrupted recording) means as many as the data holds.
+        // A declared count is checked against the data before anything is allocated.
+        let available = data.len() / record_bytes;
+        let records = match usize::try_from(declared_records) {
+            Err(_) => available,
+            Ok(declared) if declared > available => return Err(EdfError::Truncated(available)),
+            Ok(declared) => declared,
+        };
+
+        let mut signals: Vec<EdfSignal> = headers
+            .iter()
+            .filter(|h| !is_annotation(&h.label))
+            .map(|h| EdfSignal {
+                label: h.label.clone(),
+                physical_dimension: h.physical_dimension.clone(),
+                sample_rate_hz: h.samples_per_record as f64 / record_duration,
+                samples_per_record: h.samples_per_record,
+                samples: Vec::with_capacity(h.samples_per_record * records),
+            })
+            .collect();
+        let mut record_onsets = Vec::with_capacity(records);
+        for r in 0..records {
+            let record = &data[r * record_bytes..(r + 1) * record_bytes];
+            let mut onset = r as f64 * record_duration;
+            let (mut pos, mut out) = (0, 0);
+            for h in &headers {
+                let len = h.samples_per_record * sample_bytes;
+                let chunk = &record[pos..pos + len];
+                pos += len;
+                if is_annotation(&h.label) {
+                    if format == (EdfFormat::EdfPlus { continuous: false }) {
+                        onset = record_onset(chunk).unwrap_or(onset);
+                    }
+                    continue;
+                }
+                let samples = &mut signals[out].samples;
+                out += 1;
+                for s in chunk.chunks_exact(sample_bytes) {
+                    let digital = match sample_bytes {
+                        3 => (i32::from_le_bytes([0, s[0], s[1], s[2]]) >> 8) as f64,
+                        _ => i16::from_le_bytes([s[0], s[1]]) as f64,
+                    };
+                    samples.push(digital * h.gain + h.offset);
+                }
+            }
+            record_onsets.push(onset);
+        }
+
+        Ok(Self { format, start, record_duration, record_onsets, signals })
+    }
+
+    /// Runs of contiguous records, as (onset in seconds, first record, record count).
+    fn segments(&self) -> Vec<(f64, usize, usize)> {
+        let mut segments: Vec<(f64, usize, usize)> = Vec::new();
+        for (r, &onset) in self.record_onsets.iter().enumerate() {
+            match segments.last_mut() {
+                Some((first, _, count)) if (onset - (*first + *count as f64 * self.record_duration)).abs() < 1e-6 => {
+                    *count += 1;
+                }
+                _ => segments.push((onset, r, 1)),
+            }
+        }
+        segments
+    }
+}
+
+/// Parameters of the theta derivation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThetaConfig {
+    /// Labels of the channels to use; empty for every ordinary signal.
+    pub channels: Vec<String>,
+    /// Length of each analysis window, in seconds.
+    pub window_sec: f64,
+    /// Distance between successive windows, in seconds; at least a millisecond.
+    pub step_sec: f64,
+    /// Maps the theta/delta ratio (feature `theta_delta`) to the theta coordinate.
+    pub mapping: DimensionMapping,
+    /// How long a theta value may be used for states after its window ends, in seconds.
+    pub max_age_sec: f64,
+}
+
+impl Default for ThetaConfig {
+    fn default() -> Self {
+        Self {
+            channels: Vec::new(),
+            window_sec: 4.0,
+            step_sec: 1.0,
+            mapping: crate::features::Combination::default().theta,
+            max_age_sec: 2.0,
+        }
+    }
+}
+
+/// Timestamped theta coordinates, ordered by time.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ThetaSeries {
+    /// Window end and theta value.
+    pub points: Vec<(DateTime<Utc>, f64)>,
+    pub max_age: Duration,
+}
+
+impl ThetaSeries {
+    /// The latest theta at or before `timestamp`, unless it is older than `max_age`.
+    pub fn at(&self, timestamp: DateTime<Utc>) -> Option<f64> {
+        let i = self.points.partition_point(|(t, _)| *t <= timestamp).checked_sub(1)?;
+        let (t, theta) = self.points[i];
+        (timestamp - t <= self.max_age).then_some(theta)
+    }
+
+    /// The series as states, e.g. for a fusion input: theta as derived, every other
+    /// coordinate zero and flagged stale.
+    pub fn to_states(&self) -> Vec<TaggedState> {
+        let unmeasured = QualityFlags::STALE_E | QualityFlags::STALE_M_PROT | QualityFlags::STALE_S_BIO | QualityFlags::STALE_T;
+        self.points
+            .iter()
+            .map(|&(t, theta)| {
+                TaggedState::new(BiophysicalState::new(0.0, 0.0, 0.0, theta, 0.0), t, None).with_quality(unmeasured)
+            })
+            .collect()
+    }
+}
+
+/// Slide a window over the recording and derive the theta coordinate of each window.
+///
+/// Windows never span a gap between records of a discontinuous recording. Each value
+/// is stamped with the end of its window.
+pub fn theta_series(recording: &EdfRecording, config: &ThetaConfig) -> Result<ThetaSeries, EdfError> {
+    if !(config.window_sec.is_finite() && config.window_sec > 0.0) {
+        return Err(EdfError::InvalidConfig(format!("window of {} s", config.window_sec)));
+    }
+    if !(config.step_sec.is_finite() && config.step_sec >= MIN_STEP_SEC) {
+        return Err(EdfError::InvalidConfig(format!("step of {} s", config.step_sec)));
+    }
+    let channels: Vec<&EdfSignal> = if config.channels.is_empty() {
+        recording.signals.iter().collect()
+    } else {
+        config
+            .channels
+            .iter()
+            .map(|label| {
+                recording
+                    .signals
+                    .iter()
+                    .find(|s| &s.label == label)
+                    .ok_or_else(|| EdfError::UnknownChannel(label.clone()))
+            })
+            .collect::<Result<_, _>>()?
+    };
+
+    let mut points = Vec::new();
+    let step = config.step_sec;
+    for (onset, first, count) in recording.segments() {
+        let length = count as f64 * recording.record_duration;
+        let mut end = config.window_sec;
+        while end <= length + 1e-9 {
+            let ratios: Vec<f64> = channels
+                .iter()
+                .filter_map(|s| {
+                    let base = first * s.samples_per_record;
+                    let from = base + ((end - config.window_sec) * s.sample_rate_hz).round() as usize;
+                    let to = base + (end * s.sample_rate_hz).round() as usize;
+                    eeg::theta_delta_ratio(s.samples.get(from..to)?, s.sample_rate_hz)
+                })
+                .collect();
+            if !ratios.is_empty() {
+                let features = FeatureVector {
+                    theta_delta: Some(ratios.iter().sum::<f64>() / ratios.len() as f64),
+                    ..Default::default()
+                };
+                let at = recording.start + Duration::microseconds(((onset + end) * 1e6) as i64);
+                points.push((at, config.mapping.evaluate(&features)?));
+            }
+            end += step;
+        }
+    }
+    Ok(ThetaSeries {
+        points,
+        max_age: Duration::microseconds((config.max_age_sec * 1e6) as i64),
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::features::{Feature, Term};
+    use std::f64::consts::PI;
+
+    /// A one-channel recording of `seconds` one-second records at 256 Hz: a 6 Hz tone
+    /// at twice the amplitude of a 2 Hz tone (theta/delta ratio 4).
+    pub(crate) fn synthetic(bdf: bool, seconds: usize) -> Vec<u8> {
+        let fs = 256;
+        let pad = |s: &str, width: usize| format!("{:<width$}", s, width = width);
+        let mut header = String::new();
+        header += &pad(if bdf { "BIOSEMI" } else { "0" }, 8);
+        header += &pad("X X X X", 80);
+        header += &pad("Startdate 01-MAY-2024 X X X", 80);
+        header += "01.05.2412.00.00";
+        header += &pad("512", 8);
+        header += &pad(if bdf { "24BIT" } else { "EDF+C" }, 44);
+        header += &pad(&seconds.to_string(), 8);
+        header += &pad("1", 8);
+        header += &pad("1", 4);
+        for (value, width) in ["EEG Fz", "AgAgCl", "uV", "-100", "100", "-8388608", "8388607", "", "256", ""]
+            .iter()
+            .zip(SIGNAL_FIELD_WIDTHS)
+        {
+            header += &pad(value, width);
+        }
+        let mut bytes = header.into_bytes();
+        if bdf {
+            bytes[0] = 0xFF;
+            bytes[1..8].copy_from_slice(b"BIOSEMI");
+        } else {
+            // Digital range of 16-bit EDF.
+            let range = format!("{:<8}{:<8}", "-32768", "32767");
+            let at = HEADER_BYTES + (16 + 80 + 8 + 8 + 8);
+            bytes[at..at + 16].copy_from_slice(range.as_bytes());
+        }
+        let full_scale = if bdf { 8_388_607.0 } else { 32_767.0 };
+        for i in 0..seconds * fs {
+            let t = i as f64 / fs as f64;
+            let uv = 40.0 * (2.0 * PI * 6.0 * t).sin() + 20.0 * (2.0 * PI * 2.0 * t).sin();
+            let digital = (uv / 100.0 * full_scale).round() as i32;
+            if bdf {
+                bytes.extend_from_slice(&digital.to_le_bytes()[..3]);
+            } else {
+                bytes.extend_from_slice(&(digital as i16).to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    pub(crate) fn config() -> ThetaConfig {
+        ThetaConfig {
+            mapping: DimensionMapping { offset: 0.0, terms: vec![Term::new(Feature::ThetaDelta, 1.0, (0.0, 8.0))] },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_edf_and_bdf_yield_the_same_theta() {
+        for bdf in [false, true] {
+            let recording = EdfRecording::parse(&synthetic(bdf, 8)).unwrap();
+            assert_eq!(recording.format, if bdf { EdfFormat::Bdf } else { EdfFormat::EdfPlus { continuous: true } });
+            assert_eq!(recording.signals[0].sample_rate_hz, 256.0);
+            let t = 10.0 / 256.0;
+            let expected = 40.0 * (2.0 * PI * 6.0 * t).sin() + 20.0 * (2.0 * PI * 2.0 * t).sin();
+            assert!((recording.signals[0].samples[10] - expected).abs() < 0.01);
+
+            let series = theta_series(&recording, &config()).unwrap();
+            // Windows ending at 4, 5, ..., 8 seconds.
+            assert_eq!(series.points.len(), 5);
+            assert_eq!(series.points[0].0.to_rfc3339(), "2024-05-01T12:00:04+00:00");
+            assert!(series.points.iter().all(|(_, theta)| (theta - 0.5).abs() < 0.03));
+
+            let late = series.points[4].0 + Duration::seconds(3);
+            assert!(series.at(late).is_none());
+        }
+        assert!(matches!(
+            theta_series(&EdfRecording::parse(&synthetic(false, 8)).unwrap(), &ThetaConfig {
+                channels: vec!["EEG Cz".into()],
+                ..config()
+            }),
+            Err(EdfError::UnknownChannel(_))
+        ));
+        let recording = EdfRecording::parse(&synthetic(false, 8)).unwrap();
+        let invalid = [(4.0, 0.0), (4.0, f64::NAN), (4.0, -1.0), (4.0, 1e-300), (0.0, 1.0), (f64::NAN, 1.0)];
+        for (window_sec, step_sec) in invalid {
+            assert!(matches!(
+                theta_series(&recording, &ThetaConfig { window_sec, step_sec, ..config() }),
+                Err(EdfError::InvalidConfig(_))
+            ));
+        }
+    }
+
+    /// A discontinuous EDF+ recording of one-second records with the given onsets: the
+    /// 256 Hz tone of `synthetic` plus an annotation signal keeping the time.
+    fn discontinuous(onsets: &[f64]) -> Vec<u8> {
+        let pad = |s: &str, width: usize| format!("{:<width$}", s, width = width);
+        let mut header = String::new();
+        header += &pad("0", 8);
+        header += &pad("X X X X", 80);
+        header += &pad("Startdate 01-MAY-2024 X X X", 80);
+        header += "01.05.2412.00.00";
+        header += &pad("768", 8);
+        header += &pad("EDF+D", 44);
+        header += &pad(&onsets.len().to_string(), 8);
+        header += &pad("1", 8);
+        header += &pad("2", 4);
+        let signals = [
+            ["EEG Fz", "AgAgCl", "uV", "-100", "100", "-32768", "32767", "", "256", ""],
+            ["EDF Annotations", "", "", "-1", "1", "-32768", "32767", "", "32", ""],
+        ];
+        for (f, width) in SIGNAL_FIELD_WIDTHS.iter().enumerate() {
+            for signal in &signals {
+                header += &pad(signal[f], *width);
+            }
+        }
+        let mut bytes = header.into_bytes();
+        for (r, onset) in onsets.iter().enumerate() {
+            for i in 0..256 {
+                let t = (r * 256 + i) as f64 / 256.0;
+                let uv = 40.0 * (2.0 * PI * 6.0 * t).sin() + 20.0 * (2.0 * PI * 2.0 * t).sin();
+                bytes.extend_from_slice(&((uv / 100.0 * 32_767.0).round() as i16).to_le_bytes());
+            }
+            let mut tal = format!("+{}\x14\x14\0", onset).into_bytes();
+            tal.resize(64, 0);
+            bytes.extend_from_slice(&tal);
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_discontinuous_and_truncated_recordings() {
+        // Two runs of four seconds, ten seconds apart: one window each, never across the gap.
+        let onsets = [0.0, 1.0, 2.0, 3.0, 10.0, 11.0, 12.0, 13.0];
+        let recording = EdfRecording::parse(&discontinuous(&onsets)).unwrap();
+        assert_eq!(recording.format, EdfFormat::EdfPlus { continuous: false });
+        assert_eq!(recording.record_onsets, onsets);
+        assert_eq!(recording.signals.len(), 1);
+        assert_eq!(recording.signals[0].samples.len(), 8 * 256);
+        let series = theta_series(&recording, &config()).unwrap();
+        let ends: Vec<String> = series.points.iter().map(|(t, _)| t.to_rfc3339()).collect();
+        assert_eq!(ends, ["2024-05-01T12:00:04+00:00", "2024-05-01T12:00:14+00:00"]);
+
+        // Data cut inside the last record.
+        let bytes = synthetic(false, 8);
+        assert!(matches!(EdfRecording::parse(&bytes[..bytes.len() - 100]), Err(EdfError::Truncated(7))));
+        // A record count far beyond the data is refused before allocating for it.
+        let mut bytes = synthetic(false, 8);
+        bytes[236..244].copy_from_slice(b"99999999");
+        assert!(matches!(EdfRecording::parse(&bytes), Err(EdfError::Truncated(8))));
+    }
+}
@@ -1,27 +1,53 @@
-//! Ingests biophysical state from a metrics source and feeds it into the clusterer.
+//! Ingests biophysical state from a metrics source.
 //!
-//! The `MetricsIngester` periodically reads the current state (e.g., from a JSON file
-//! written by the nicotine safety stack) and inserts it into the `PolytopeClusterer`.
+//! The `MetricsIngester` is a `StateSource` that reads the current state (e.g., from a
+//! JSON file written by the nicotine safety stack), either every `poll_interval` or,
+//! in `WatchMode::Notify`, whenever the file system reports that the file changed.
 //! All operations are read‑only and use only outer‑domain signals.
+//!
+//! The watcher observes the file's directory rather than the file itself, so the
+//! atomic write-then-rename pattern (which replaces the file's inode) keeps being
+//! seen. Bursts of events are coalesced by the debounce window before the file is
+//! read, so a write in progress is not read half-way. While the watcher is down the
+//! file is polled, and starting the watcher is retried every `WATCH_RETRY`.
 
-use crate::clustering::PolytopeClusterer;
-use crate::model::BiophysicalState;
+use super::source::{IngestEvent, StateSource};
+use crate::model::{BiophysicalState, TaggedState};
+use async_trait::async_trait;
+use chrono::Utc;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use std::time::{Duration, Instant};
-use tokio::sync::Mutex;
-use tokio::time;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{self, Instant};
 use tracing::{error, info, warn};
 
+/// How long to poll before trying again to start a watcher that could not be started
+/// or failed.
+const WATCH_RETRY: Duration = Duration::from_secs(60);
+
+/// How the ingester learns that the state file changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchMode {
+    /// Read the file every `poll_interval`.
+    Poll,
+    /// Read the file when it changes, once no further change has been reported for
+    /// `debounce`. Falls back to polling while the watcher cannot be started or has
+    /// failed, and retries starting it every `WATCH_RETRY`.
+    Notify { debounce: Duration },
+}
+
 /// Configuration for the metrics ingester.
 #[derive(Debug, Clone)]
 pub struct IngesterConfig {
     /// Path to a JSON file containing the current `BiophysicalState`.
     /// The file should be updated atomically (e.g., write to temp then rename).
     pub state_file: PathBuf,
-    /// Polling interval.
+    /// Polling interval (in `WatchMode::Poll`, or after falling back to it).
     pub poll_interval: Duration,
+    /// How to detect changes to `state_file`.
+    pub watch: WatchMode,
 }
 
 /// Reads the current biophysical state from a JSON file.
@@ -44,30 +70,225 @@ async fn read_state_from_file(path: &Path) -> Option<BiophysicalState> {
     }
 }
 
-/// Ingests metrics by polling a file and updating the clusterer.
+/// A running file-system watcher and the events it delivers.
+struct FileWatch {
+    // Dropping the watcher stops the events.
+    _watcher: RecommendedWatcher,
+    events: mpsc::UnboundedReceiver<notify::Result<Event>>,
+}
+
+/// Reads states from a snapshot file.
 pub struct MetricsIngester {
-    clusterer: Arc<Mutex<PolytopeClusterer>>,
     config: IngesterConfig,
+    watch: Option<FileWatch>,
+    /// When to next try to start the watcher; `None` until the first attempt.
+    next_watch_attempt: Option<Instant>,
+    watch_retry: Duration,
+    interval: Option<time::Interval>,
 }
 
 impl MetricsIngester {
-    /// Create a new ingester that will update the given clusterer.
-    pub fn new(clusterer: Arc<Mutex<PolytopeClusterer>>, config: IngesterConfig) -> Self {
-        Self { clusterer, config }
+    /// Create a new ingester for the configured file.
+    pub fn new(config: IngesterConfig) -> Self {
+        Self {
+            config,
+            watch: None,
+            next_watch_attempt: None,
+            watch_retry: WATCH_RETRY,
+            interval: None,
+        }
+    }
+
+    /// Read the state file once.
+    async fn read(&self) -> Option<TaggedState> {
+        let state = read_state_from_file(&self.config.state_file).await?;
+        info!("Read state: {:?}", state);
+        Some(TaggedState::new(state, Utc::now(), None))
+    }
+
+    /// Start watching the directory of the state file.
+    fn start_watch(&self) -> notify::Result<FileWatch> {
+        let (tx, events) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |res| {
+            let _ = tx.send(res);
+        })?;
+        let dir = match self.config.state_file.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        info!("Watching {} for changes", self.config.state_file.display());
+        Ok(FileWatch { _watcher: watcher, events })
+    }
+
+    /// Try to start the watcher if an attempt is due, and return whether it started.
+    /// Until it does, the file is polled.
+    fn rearm_watch(&mut self) -> bool {
+        if self.next_watch_attempt.is_some_and(|at| Instant::now() < at) {
+            return false;
+        }
+        match self.start_watch() {
+            Ok(watch) => {
+                self.watch = Some(watch);
+                self.interval = None;
+                true
+            }
+            Err(e) => {
+                warn!("File watcher unavailable ({}); polling, retrying in {:?}", e, self.watch_retry);
+                self.next_watch_attempt = Some(Instant::now() + self.watch_retry);
+                false
+            }
+        }
+    }
+
+    /// Wait for a change to the state file and for the burst of events to end.
+    /// Only returns an error when the watcher fails.
+    async fn changed(&mut self, debounce: Duration) -> notify::Result<()> {
+        let name = self.config.state_file.file_name().map(|n| n.to_os_string());
+        let Some(watch) = self.watch.as_mut() else {
+            return Err(notify::Error::generic("no watcher"));
+        };
+        loop {
+            match watch.events.recv().await {
+                Some(Ok(event)) if concerns(&event, name.as_deref()) => break,
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Err(e),
+                None => return Err(notify::Error::generic("watcher channel closed")),
+            }
+        }
+        loop {
+            match time::timeout(debounce, watch.events.recv()).await {
+                Ok(Some(Ok(_))) => continue,
+                Ok(Some(Err(e))) => return Err(e),
+                Ok(None) => return Err(notify::Error::generic("watcher channel closed")),
+                Err(_) => return Ok(()),
+            }
+        }
+    }
+}
+
+/// Whether an event may have changed the contents of the file named `name`: a
+/// creation or modification (including a rename onto it) of a path with that name.
+fn concerns(event: &Event, name: Option<&std::ffi::OsStr>) -> bool {
+    if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+        return false;
+    }
+    event.paths.iter().any(|p| p.file_name() == name)
+}
+
+#[async_trait]
+impl StateSource for MetricsIngester {
+    fn name(&self) -> String {
+        format!("file {}", self.config.state_file.display())
     }
 
-    /// Run the ingestion loop (blocks forever).
-    pub async fn run(&self) -> ! {
-        let mut interval = time::interval(self.config.poll_interval);
+    async fn next_batch(&mut self) -> Option<Vec<IngestEvent>> {
         loop {
-            interval.tick().await;
-            if let Some(state) = read_state_from_file(&self.config.state_file).await {
-                let mut clusterer = self.clusterer.lock().await;
-                clusterer.insert_point(state, Instant::now());
-                info!("Inserted state: {:?}", state);
-            } else {
-                warn!("No valid state available");
+            if let WatchMode::Notify { debounce } = self.config.watch {
+                // Pick up whatever state is already there, or was missed while polling.
+                if self.watch.is_none() && self.rearm_watch() {
+                    if let Some(state) = self.read().await {
+                        return Some(vec![state.into()]);
+                    }
+                }
+                if self.watch.is_some() {
+                    match self.changed(debounce).await {
+                        Ok(()) => {
+                            if let Some(state) = self.read().await {
+                                return Some(vec![state.into()]);
+                            }
+                        }
+                        Err(e) => {
+                            warn!("File watcher failed ({}); polling, retrying in {:?}", e, self.watch_retry);
+                            self.watch = None;
+                            self.next_watch_attempt = Some(Instant::now() + self.watch_retry);
+                        }
+                    }
+                    continue;
+                }
+            }
+
+            let poll_interval = self.config.poll_interval;
+            self.interval.get_or_insert_with(|| time::interval(poll_interval)).tick().await;
+            match self.read().await {
+                Some(state) => return Some(vec![state.into()]),
+                None => warn!("No valid state available"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_watch_picks_up_atomic_replace() {
+        let dir = std::env::temp_dir().join(format!("neuroseek-watch-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let state_file = dir.join("state.json");
+        let mut ingester = MetricsIngester::new(IngesterConfig {
+            state_file: state_file.clone(),
+            // Long enough that only a notification can explain a timely read.
+            poll_interval: Duration::from_secs(3600),
+            watch: WatchMode::Notify { debounce: Duration::from_millis(20) },
+        });
+        let next = tokio::spawn(async move { ingester.next_batch().await });
+        time::sleep(Duration::from_millis(200)).await;
+
+        let state = BiophysicalState::new(0.4, 0.1, 0.2, 0.3, 0.0);
+        let tmp = dir.join("state.json.tmp");
+        fs::write(&tmp, serde_json::to_string(&state).unwrap()).unwrap();
+        fs::rename(&tmp, &state_file).unwrap();
+
+        let batch = time::timeout(Duration::from_secs(2), next)
+            .await
+            .expect("state was not read after the rename")
+            .unwrap()
+            .unwrap();
+        match &batch[0] {
+            IngestEvent::State(tagged) => assert_eq!(tagged.state, state),
+            other => panic!("unexpected event {:?}", other),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[tokio::test]
+    async fn test_watch_is_rearmed_after_falling_back_to_polling() {
+        let dir = std::env::temp_dir().join(format!("neuroseek-rewatch-{}", uuid::Uuid::new_v4()));
+        let state_file = dir.join("state.json");
+        let mut ingester = MetricsIngester::new(IngesterConfig {
+            state_file: state_file.clone(),
+            poll_interval: Duration::from_millis(20),
+            watch: WatchMode::Notify { debounce: Duration::from_millis(20) },
+        });
+        ingester.watch_retry = Duration::from_millis(50);
+
+        // The directory does not exist yet, so the watcher cannot start.
+        assert!(time::timeout(Duration::from_millis(100), ingester.next_batch()).await.is_err());
+        assert!(ingester.watch.is_none());
+
+        fs::create_dir_all(&dir).unwrap();
+        let state = BiophysicalState::new(0.4, 0.1, 0.2, 0.3, 0.0);
+        fs::write(&state_file, serde_json::to_string(&state).unwrap()).unwrap();
+        for _ in 0..20 {
+            if ingester.watch.is_some() {
+                break;
             }
+            time::timeout(Duration::from_secs(2), ingester.next_batch()).await.unwrap().unwrap();
+        }
+        assert!(ingester.watch.is_some(), "watcher was not re-armed");
+
+        // Back on notifications: a slow poll could not explain a timely read.
+        ingester.config.poll_interval = Duration::from_secs(3600);
+        let next = tokio::spawn(async move { ingester.next_batch().await });
+        time::sleep(Duration::from_millis(200)).await;
+        let state = BiophysicalState::new(0.5, 0.1, 0.2, 0.3, 0.0);
+        fs::write(&state_file, serde_json::to_string(&state).unwrap()).unwrap();
+        let batch = time::timeout(Duration::from_secs(2), next).await.unwrap().unwrap().unwrap();
+        match &batch[0] {
+            IngestEvent::State(tagged) => assert_eq!(tagged.state, state),
+            other => panic!("unexpected event {:?}", other),
         }
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
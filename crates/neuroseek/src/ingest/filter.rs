@@ -0,0 +1,336 @@
+//! Signal-quality filtering of states before they reach the clusterer.
+//!
+//! Sensor dropouts and motion produce NaNs, all-zero samples and spikes that would
+//! otherwise each seed or drag a polytope. The `SignalFilter` runs every state through
+//! a fixed chain, each stage optional:
+//!
+//! 1. validation: non-finite coordinates, all-zero dropouts and values outside the
+//!    configured physiological limits reject the sample;
+//! 2. motion gating: a sample whose device-reported `signal_quality` is below the gate
+//!    is rejected as a motion artefact;
+//! 3. spike rejection: a coordinate too far from the median of the recent window is
+//!    replaced by that median and flagged (`QualityFlags::spike`);
+//! 4. exponential smoothing, flagged `QualityFlags::SMOOTHED`.
+//!
+//! Windows and smoothing state are kept per subject, so interleaved subjects do not
+//! contaminate each other. Rejected samples leave them untouched and are counted in
+//! `FilterStats` by reason.
+
+use crate::model::{BiophysicalState, QualityFlags, TaggedState};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Scale factor turning the median absolute deviation into a standard deviation
+/// estimate for normally distributed data.
+const MAD_SCALE: f64 = 1.4826;
+
+/// Samples needed in the window before spike rejection starts.
+const MIN_SPIKE_WINDOW: usize = 3;
+
+/// How spikes are detected.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "lowercase", deny_unknown_fields)]
+pub enum SpikeFilter {
+    /// Reject values more than `threshold` (absolute) from the window median.
+    Median { window: usize, threshold: f64 },
+    /// Hampel identifier: reject values more than `n_sigmas` robust standard
+    /// deviations (scaled MAD) from the window median.
+    Hampel { window: usize, n_sigmas: f64 },
+}
+
+/// Configuration of the filter chain. Every stage is off unless configured, except
+/// the non-finite and dropout checks.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterConfig {
+    /// Physiological `(min, max)` per dimension, in `as_array` order.
+    pub limits: Option<[(f64, f64); 5]>,
+    /// Reject samples whose five coordinates are all exactly zero (sensor dropout).
+    pub reject_all_zero: bool,
+    pub spike: Option<SpikeFilter>,
+    /// Smoothing factor of the exponential moving average, in (0, 1]; 1 is no smoothing.
+    pub ema_alpha: Option<f64>,
+    /// Reject samples whose `signal_quality` is below this. Samples without a
+    /// reported quality pass.
+    pub min_signal_quality: Option<f64>,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self {
+            limits: None,
+            reject_all_zero: true,
+            spike: None,
+            ema_alpha: None,
+            min_signal_quality: None,
+        }
+    }
+}
+
+impl FilterConfig {
+    /// Load a configuration from JSON.
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+/// Why a sample was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    NonFinite,
+    Dropout,
+    /// Dimension index (0 = E, ..., 4 = T) outside its limits.
+    OutOfRange(usize),
+    MotionArtefact,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::NonFinite => f.write_str("non-finite coordinate"),
+            Rejection::Dropout => f.write_str("all-zero dropout"),
+            Rejection::OutOfRange(i) => write!(f, "dimension {} outside physiological limits", i),
+            Rejection::MotionArtefact => f.write_str("signal quality below motion gate"),
+        }
+    }
+}
+
+/// Running counters of a filter, readable from any thread.
+#[derive(Debug, Default)]
+pub struct FilterStats {
+    passed: AtomicU64,
+    spikes_replaced: AtomicU64,
+    rejected_non_finite: AtomicU64,
+    rejected_dropout: AtomicU64,
+    rejected_out_of_range: AtomicU64,
+    rejected_motion: AtomicU64,
+}
+
+/// Point-in-time copy of `FilterStats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct FilterSnapshot {
+    /// Samples handed on to the clusterer.
+    pub passed: u64,
+    /// Coordinates replaced by their window median.
+    pub spikes_replaced: u64,
+    pub rejected_non_finite: u64,
+    pub rejected_dropout: u64,
+    pub rejected_out_of_range: u64,
+    pub rejected_motion: u64,
+}
+
+impl FilterSnapshot {
+    /// Samples rejected for any reason.
+    pub fn rejected(&self) -> u64 {
+        self.rejected_non_finite + self.rejected_dropout + self.rejected_out_of_range + self.rejected_motion
+    }
+}
+
+impl FilterStats {
+    fn rejected(&self, reason: Rejection) {
+        let counter = match reason {
+            Rejection::NonFinite => &self.rejected_non_finite,
+            Rejection::Dropout => &self.rejected_dropout,
+            Rejection::OutOfRange(_) => &self.rejected_out_of_range,
+            Rejection::MotionArtefact => &self.rejected_motion,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> FilterSnapshot {
+        FilterSnapshot {
+            passed: self.passed.load(Ordering::Relaxed),
+            spikes_replaced: self.spikes_replaced.load(Ordering::Relaxed),
+            rejected_non_finite: self.rejected_non_finite.load(Ordering::Relaxed),
+            rejected_dropout: self.rejected_dropout.load(Ordering::Relaxed),
+            rejected_out_of_range: self.rejected_out_of_range.load(Ordering::Relaxed),
+            rejected_motion: self.rejected_motion.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Per-subject filter state.
+#[derive(Debug, Default)]
+struct Channel {
+    /// Recent raw (accepted) values, for spike detection.
+    window: VecDeque<[f64; 5]>,
+    ema: Option<[f64; 5]>,
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// The filter chain.
+#[derive(Debug)]
+pub struct SignalFilter {
+    config: FilterConfig,
+    channels: HashMap<Option<String>, Channel>,
+    stats: Arc<FilterStats>,
+}
+
+impl SignalFilter {
+    pub fn new(config: FilterConfig) -> Self {
+        Self {
+            config,
+            channels: HashMap::new(),
+            stats: Arc::new(FilterStats::default()),
+        }
+    }
+
+    /// Shared handle to the counters, for monitoring.
+    pub fn stats(&self) -> Arc<FilterStats> {
+        self.stats.clone()
+    }
+
+    /// Run one state through the chain: the (possibly repaired and flagged) state, or
+    /// the reason it was rejected.
+    pub fn apply(&mut self, mut state: TaggedState) -> Result<TaggedState, Rejection> {
+        let values = state.state.as_array();
+        if let Err(reason) = self.validate(&state, &values) {
+            self.stats.rejected(reason);
+            return Err(reason);
+        }
+
+        let channel = self.channels.entry(state.subject_id.clone()).or_default();
+        let mut out = values;
+        if let Some(spike) = self.config.spike {
+            let (window, replaced) = spike_step(channel, spike, &mut out);
+            for (i, &replaced) in replaced.iter().enumerate() {
+                if replaced {
+                    state.quality.insert(QualityFlags::spike(i));
+                    self.stats.spikes_replaced.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            channel.window.push_back(values);
+            while channel.window.len() > window {
+                channel.window.pop_front();
+            }
+        }
+        if let Some(alpha) = self.config.ema_alpha {
+            let alpha = alpha.clamp(f64::MIN_POSITIVE, 1.0);
+            let smoothed = match channel.ema {
+                Some(prev) => std::array::from_fn(|i| alpha * out[i] + (1.0 - alpha) * prev[i]),
+                None => out,
+            };
+            channel.ema = Some(smoothed);
+            out = smoothed;
+            state.quality.insert(QualityFlags::SMOOTHED);
+        }
+
+        state.state = BiophysicalState::new(out[0], out[1], out[2], out[3], out[4]);
+        self.stats.passed.fetch_add(1, Ordering::Relaxed);
+        Ok(state)
+    }
+
+    fn validate(&self, state: &TaggedState, values: &[f64; 5]) -> Result<(), Rejection> {
+        if values.iter().any(|v| !v.is_finite()) {
+            return Err(Rejection::NonFinite);
+        }
+        if self.config.reject_all_zero && values.iter().all(|&v| v == 0.0) {
+            return Err(Rejection::Dropout);
+        }
+        if let Some(limits) = &self.config.limits {
+            if let Some(i) = (0..5).find(|&i| values[i] < limits[i].0 || values[i] > limits[i].1) {
+                return Err(Rejection::OutOfRange(i));
+            }
+        }
+        match (self.config.min_signal_quality, state.signal_quality) {
+            (Some(gate), Some(quality)) if quality < gate => Err(Rejection::MotionArtefact),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Replace spiking coordinates of `values` by the window median. Returns the window
+/// length and which coordinates were replaced.
+fn spike_step(channel: &Channel, spike: SpikeFilter, values: &mut [f64; 5]) -> (usize, [bool; 5]) {
+    let window = match spike {
+        SpikeFilter::Median { window, .. } | SpikeFilter::Hampel { window, .. } => window.max(1),
+    };
+    let mut replaced = [false; 5];
+    if channel.window.len() < MIN_SPIKE_WINDOW.min(window) {
+        return (window, replaced);
+    }
+    for i in 0..5 {
+        let mut recent: Vec<f64> = channel.window.iter().map(|v| v[i]).collect();
+        let med = median(&mut recent);
+        let threshold = match spike {
+            SpikeFilter::Median { threshold, .. } => threshold,
+            SpikeFilter::Hampel { n_sigmas, .. } => {
+                let mut deviations: Vec<f64> = recent.iter().map(|v| (v - med).abs()).collect();
+                n_sigmas * MAD_SCALE * median(&mut deviations)
+            }
+        };
+        // A flat window (zero MAD) would otherwise flag every change as a spike.
+        if threshold > 0.0 && (values[i] - med).abs() > threshold {
+            values[i] = med;
+            replaced[i] = true;
+        }
+    }
+    (window, replaced)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn sample(e: f64) -> TaggedState {
+        TaggedState::new(BiophysicalState::new(e, 0.2, 0.3, 0.4, 0.1), Utc::now(), None)
+    }
+
+    #[test]
+    fn test_chain_rejects_repairs_and_counts() {
+        let mut filter = SignalFilter::new(FilterConfig {
+            limits: Some([(0.0, 1.0), (0.0, 1.0), (0.0, 1.0), (0.0, 1.0), (-1.0, 1.0)]),
+            spike: Some(SpikeFilter::Hampel { window: 5, n_sigmas: 3.0 }),
+            min_signal_quality: Some(0.5),
+            ..Default::default()
+        });
+
+        assert_eq!(filter.apply(sample(f64::NAN)).unwrap_err(), Rejection::NonFinite);
+        let dropout = TaggedState::new(BiophysicalState::new(0.0, 0.0, 0.0, 0.0, 0.0), Utc::now(), None);
+        assert_eq!(filter.apply(dropout).unwrap_err(), Rejection::Dropout);
+        assert_eq!(filter.apply(sample(1.5)).unwrap_err(), Rejection::OutOfRange(0));
+        let noisy = sample(0.5).with_signal_quality(Some(0.2));
+        assert_eq!(filter.apply(noisy).unwrap_err(), Rejection::MotionArtefact);
+
+        for e in [0.50, 0.52, 0.49, 0.51] {
+            assert!(filter.apply(sample(e)).unwrap().quality.is_clean());
+        }
+        // A jump to 0.95 is a spike against a window around 0.5.
+        let repaired = filter.apply(sample(0.95)).unwrap();
+        assert!(repaired.quality.contains(QualityFlags::SPIKE_E));
+        assert!((repaired.state.e - 0.505).abs() < 1e-9);
+        assert_eq!(repaired.state.m_prot, 0.2);
+
+        let stats = filter.stats().snapshot();
+        assert_eq!(stats.rejected(), 4);
+        assert_eq!(stats.passed, 5);
+        assert_eq!(stats.spikes_replaced, 1);
+    }
+
+    #[test]
+    fn test_ema_is_per_subject() {
+        let mut filter = SignalFilter::new(FilterConfig { ema_alpha: Some(0.5), ..Default::default() });
+        filter.apply(sample(0.2).with_subject(Some("a".into()), None)).unwrap();
+        filter.apply(sample(0.8).with_subject(Some("b".into()), None)).unwrap();
+        let a = filter.apply(sample(0.4).with_subject(Some("a".into()), None)).unwrap();
+        assert!((a.state.e - 0.3).abs() < 1e-9);
+        assert!(a.quality.contains(QualityFlags::SMOOTHED));
+
+        let config = FilterConfig::from_json(r#"{"spike":{"method":"median","window":5,"threshold":0.2}}"#).unwrap();
+        assert!(config.reject_all_zero);
+        assert!(FilterConfig::from_json(r#"{"smoothing":0.5}"#).is_err());
+    }
+}
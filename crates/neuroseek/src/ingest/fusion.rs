@@ -0,0 +1,475 @@
+//! Per-dimension fusion of several sources into one state stream.
+//!
+//! Often no single source measures all five dimensions: E and S_bio may come from
+//! Prometheus, theta from an EEG recording and T from a snapshot file. A
+//! `FusionSource` runs such inputs, each assigned the dimensions it is trusted for, and
+//! takes every other coordinate of their states as unmeasured.
+//!
+//! Fused states are emitted on a grid of `step` (aligned to the Unix epoch). The value
+//! of a dimension at a grid point is found by its `Alignment`:
+//!
+//! - `Nearest`: the sample closest in time, within `tolerance`;
+//! - `Linear`: interpolated between the samples around the point, if they are at most
+//!   `max_gap` apart;
+//! - `LastObservation`: the latest sample at or before the point, if it is at most
+//!   `max_staleness` old (flagged stale when older than one step).
+//!
+//! A grid point is only emitted if every dimension has a value; otherwise it is
+//! counted as incomplete in `FusionStats` by the dimensions that lacked one. A point is
+//! decided once every dimension has been observed past it, or once any dimension is
+//! `max_delay` past it, so a silent input delays the stream by at most `max_delay`.
+//! Time is the sources' own, which makes fusion work the same live and in replays.
+//!
+//! Fusion is for one subject: subject tags of input states are ignored, and fused
+//! states carry `FusionConfig::subject_id`. The stimulus and signal quality of a fused
+//! state come from the samples its values were taken from. Craving reports of the
+//! inputs are passed through.
+
+use super::prometheus::DIMENSION_NAMES;
+use super::source::{IngestEvent, StateSource};
+use crate::model::{BiophysicalState, QualityFlags, StimulusMetadata, TaggedState};
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+/// Batches of all inputs waiting to be fused.
+const CHANNEL_CAPACITY: usize = 64;
+
+/// How the value of a dimension at a grid point is found.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Alignment {
+    Nearest { tolerance: Duration },
+    Linear { max_gap: Duration },
+    LastObservation { max_staleness: Duration },
+}
+
+impl Alignment {
+    fn window(&self) -> chrono::Duration {
+        let d = match *self {
+            Alignment::Nearest { tolerance } => tolerance,
+            Alignment::Linear { max_gap } => max_gap,
+            Alignment::LastObservation { max_staleness } => max_staleness,
+        };
+        chrono_duration(d)
+    }
+}
+
+fn chrono_duration(d: Duration) -> chrono::Duration {
+    chrono::Duration::from_std(d).unwrap_or_else(|_| chrono::Duration::days(36_500))
+}
+
+/// Configuration of the fusion stage.
+#[derive(Debug, Clone)]
+pub struct FusionConfig {
+    /// Spacing of the output grid.
+    pub step: Duration,
+    /// Alignment of each dimension, in `as_array` order.
+    pub alignment: [Alignment; 5],
+    /// Longest a grid point waits for a lagging dimension.
+    pub max_delay: Duration,
+    /// Subject of the fused states.
+    pub subject_id: Option<String>,
+}
+
+/// Running counters of the fusion stage.
+#[derive(Debug, Default)]
+pub struct FusionStats {
+    emitted: AtomicU64,
+    incomplete: AtomicU64,
+    missing: [AtomicU64; 5],
+}
+
+/// Point-in-time copy of `FusionStats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct FusionSnapshot {
+    pub emitted: u64,
+    /// Grid points dropped because a dimension had no fresh enough value.
+    pub incomplete: u64,
+    /// Of those, how often each dimension lacked a value, in `as_array` order.
+    pub missing: [u64; 5],
+}
+
+impl FusionStats {
+    pub fn snapshot(&self) -> FusionSnapshot {
+        FusionSnapshot {
+            emitted: self.emitted.load(Ordering::Relaxed),
+            incomplete: self.incomplete.load(Ordering::Relaxed),
+            missing: std::array::from_fn(|d| self.missing[d].load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// One observation of one dimension.
+#[derive(Debug, Clone)]
+struct Sample {
+    timestamp: DateTime<Utc>,
+    value: f64,
+    /// The input had carried the value forward.
+    stale: bool,
+    stimulus: Option<StimulusMetadata>,
+    signal_quality: Option<f64>,
+}
+
+/// A resolved value and the sample it mainly came from.
+struct Resolved<'a> {
+    value: f64,
+    stale: bool,
+    sample: &'a Sample,
+}
+
+impl<'a> Resolved<'a> {
+    fn exact(sample: &'a Sample) -> Self {
+        Self { value: sample.value, stale: sample.stale, sample }
+    }
+}
+
+/// Aligns per-dimension samples on the grid. Pure; fed by `FusionSource`.
+#[derive(Debug)]
+struct Aligner {
+    config: FusionConfig,
+    step: chrono::Duration,
+    /// Samples per dimension, by timestamp.
+    samples: [Vec<Sample>; 5],
+    /// Latest timestamp observed per dimension.
+    watermarks: [Option<DateTime<Utc>>; 5],
+    /// Next grid point to decide.
+    next: Option<DateTime<Utc>>,
+    /// Whether any grid point was decided; until then the grid starts at the
+    /// earliest sample seen.
+    started: bool,
+    stats: Arc<FusionStats>,
+}
+
+impl Aligner {
+    fn new(config: FusionConfig) -> Self {
+        let step = chrono_duration(config.step).max(chrono::Duration::milliseconds(1));
+        Self {
+            config,
+            step,
+            samples: Default::default(),
+            watermarks: [None; 5],
+            next: None,
+            started: false,
+            stats: Arc::new(FusionStats::default()),
+        }
+    }
+
+    /// First grid point at or after `t`.
+    fn grid_ceil(&self, t: DateTime<Utc>) -> DateTime<Utc> {
+        let step = self.step.num_milliseconds();
+        let ms = t.timestamp_millis();
+        let ceil = ms.div_euclid(step) * step + if ms.rem_euclid(step) == 0 { 0 } else { step };
+        Utc.timestamp_millis_opt(ceil).single().unwrap_or(t)
+    }
+
+    fn observe(&mut self, dimension: usize, state: &TaggedState) {
+        let sample = Sample {
+            timestamp: state.timestamp,
+            value: state.state.as_array()[dimension],
+            stale: state.quality.contains(QualityFlags::stale(dimension)),
+            stimulus: state.stimulus.clone(),
+            signal_quality: state.signal_quality,
+        };
+        let samples = &mut self.samples[dimension];
+        let at = samples.partition_point(|s| s.timestamp <= sample.timestamp);
+        samples.insert(at, sample);
+        self.watermarks[dimension] = self.watermarks[dimension].max(Some(state.timestamp));
+        let first = self.grid_ceil(state.timestamp);
+        if !self.started && self.next.is_none_or(|next| first < next) {
+            self.next = Some(first);
+        }
+    }
+
+    fn resolve(&self, dimension: usize, t: DateTime<Utc>) -> Option<Resolved<'_>> {
+        let samples = &self.samples[dimension];
+        let after = samples.partition_point(|s| s.timestamp <= t);
+        let before = after.checked_sub(1).map(|i| &samples[i]);
+        let next = samples.get(after);
+        let alignment = self.config.alignment[dimension];
+        let window = alignment.window();
+        match alignment {
+            Alignment::Nearest { .. } => {
+                let nearest = match (before, next) {
+                    (Some(b), Some(n)) if n.timestamp - t < t - b.timestamp => n,
+                    (Some(b), _) => b,
+                    (None, Some(n)) => n,
+                    (None, None) => return None,
+                };
+                ((nearest.timestamp - t).abs() <= window).then(|| Resolved::exact(nearest))
+            }
+            Alignment::Linear { .. } => {
+                let b = before?;
+                if b.timestamp == t {
+                    return Some(Resolved::exact(b));
+                }
+                let n = next?;
+                if n.timestamp - b.timestamp > window {
+                    return None;
+                }
+                let span = (n.timestamp - b.timestamp).num_milliseconds() as f64;
+                let w = (t - b.timestamp).num_milliseconds() as f64 / span;
+                Some(Resolved { value: b.value + w * (n.value - b.value), stale: b.stale || n.stale, sample: b })
+            }
+            Alignment::LastObservation { .. } => {
+                let b = before?;
+                let age = t - b.timestamp;
+                (age <= window).then(|| Resolved { value: b.value, stale: b.stale || age > self.step, sample: b })
+            }
+        }
+    }
+
+    /// Whether grid point `t` can be decided; `finished` once no input has more.
+    fn decidable(&self, t: DateTime<Utc>, finished: bool) -> bool {
+        let Some(latest) = self.watermarks.iter().flatten().max() else { return false };
+        if finished {
+            return t <= *latest;
+        }
+        self.watermarks.iter().all(|w| w.is_some_and(|w| w >= t)) || *latest - t >= chrono_duration(self.config.max_delay)
+    }
+
+    /// Decide every grid point that can be, returning the fused states.
+    fn fuse(&mut self, finished: bool) -> Vec<TaggedState> {
+        let mut fused = Vec::new();
+        while let Some(t) = self.next.filter(|&t| self.decidable(t, finished)) {
+            self.started = true;
+            let resolved: Vec<Option<Resolved>> = (0..5).map(|d| self.resolve(d, t)).collect();
+            if resolved.iter().all(Option::is_some) {
+                let resolved: Vec<Resolved> = resolved.into_iter().flatten().collect();
+                let v: Vec<f64> = resolved.iter().map(|r| r.value).collect();
+                let mut quality = QualityFlags::default();
+                for (d, r) in resolved.iter().enumerate() {
+                    if r.stale {
+                        quality.insert(QualityFlags::stale(d));
+                    }
+                }
+                let stimulus = resolved.iter().find_map(|r| r.sample.stimulus.clone());
+                let signal_quality = resolved.iter().filter_map(|r| r.sample.signal_quality).reduce(f64::min);
+                fused.push(
+                    TaggedState::new(BiophysicalState::new(v[0], v[1], v[2], v[3], v[4]), t, stimulus)
+                        .with_subject(self.config.subject_id.clone(), None)
+                        .with_quality(quality)
+                        .with_signal_quality(signal_quality),
+                );
+                self.stats.emitted.fetch_add(1, Ordering::Relaxed);
+                self.next = Some(t + self.step);
+            } else {
+                self.stats.incomplete.fetch_add(1, Ordering::Relaxed);
+                for (d, r) in resolved.iter().enumerate() {
+                    if r.is_none() {
+                        self.stats.missing[d].fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                self.next = Some(self.skip_gap(t));
+            }
+            self.prune();
+        }
+        fused
+    }
+
+    /// The grid point after `t`, jumping over stretches no sample can reach.
+    fn skip_gap(&self, t: DateTime<Utc>) -> DateTime<Utc> {
+        let reachable = (0..5)
+            .filter_map(|d| {
+                let samples = &self.samples[d];
+                let next = samples.get(samples.partition_point(|s| s.timestamp <= t))?;
+                Some(next.timestamp - self.config.alignment[d].window())
+            })
+            .max();
+        match reachable {
+            Some(r) if r > t + self.step => self.grid_ceil(r),
+            _ => t + self.step,
+        }
+    }
+
+    /// Drop samples no later grid point can use: all but the last before `next`.
+    fn prune(&mut self) {
+        let Some(next) = self.next else { return };
+        for samples in &mut self.samples {
+            let keep_from = samples.partition_point(|s| s.timestamp <= next).saturating_sub(1);
+            samples.drain(..keep_from);
+        }
+    }
+}
+
+/// A `StateSource` fusing the dimensions of several inputs.
+pub struct FusionSource {
+    aligner: Aligner,
+    inputs: Vec<(Vec<usize>, Box<dyn StateSource>)>,
+    receiver: Option<mpsc::Receiver<(usize, Option<Vec<IngestEvent>>)>>,
+    /// Indices of the inputs that have not finished.
+    running: Vec<bool>,
+    /// Which input provides each dimension.
+    owners: [Option<usize>; 5],
+}
+
+impl FusionSource {
+    pub fn new(config: FusionConfig) -> Self {
+        Self { aligner: Aligner::new(config), inputs: Vec::new(), receiver: None, running: Vec::new(), owners: [None; 5] }
+    }
+
+    /// Take `dimensions` (indices in `as_array` order) from `source`. A dimension
+    /// assigned twice is taken from the last input given.
+    pub fn with_input(mut self, dimensions: &[usize], source: impl StateSource + 'static) -> Self {
+        self.add_input(dimensions, Box::new(source));
+        self
+    }
+
+    /// Add an already boxed input.
+    pub fn add_input(&mut self, dimensions: &[usize], source: Box<dyn StateSource>) {
+        let index = self.inputs.len();
+        for &d in dimensions.iter().filter(|&&d| d < 5) {
+            self.owners[d] = Some(index);
+        }
+        self.inputs.push((dimensions.to_vec(), source));
+    }
+
+    /// Names of the dimensions no input provides; fusion needs all five.
+    pub fn missing_dimensions(&self) -> Vec<&'static str> {
+        (0..5).filter(|&d| self.owners[d].is_none()).map(|d| DIMENSION_NAMES[d]).collect()
+    }
+
+    /// Shared handle to the counters.
+    pub fn stats(&self) -> Arc<FusionStats> {
+        self.aligner.stats.clone()
+    }
+
+    fn start(&mut self) -> mpsc::Receiver<(usize, Option<Vec<IngestEvent>>)> {
+        let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+        self.running = vec![true; self.inputs.len()];
+        for (index, (_, mut source)) in self.inputs.drain(..).enumerate() {
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                while let Some(batch) = source.next_batch().await {
+                    if tx.send((index, Some(batch))).await.is_err() {
+                        return;
+                    }
+                }
+                let _ = tx.send((index, None)).await;
+            });
+        }
+        rx
+    }
+}
+
+#[async_trait]
+impl StateSource for FusionSource {
+    fn name(&self) -> String {
+        let inputs: Vec<String> = self
+            .inputs
+            .iter()
+            .map(|(dims, source)| {
+                let dims: Vec<&str> = dims.iter().filter_map(|&d| DIMENSION_NAMES.get(d).copied()).collect();
+                format!("{} from {}", dims.join("+"), source.name())
+            })
+            .collect();
+        format!("fusion of {}", inputs.join(", "))
+    }
+
+    async fn next_batch(&mut self) -> Option<Vec<IngestEvent>> {
+        if self.receiver.is_none() {
+            let missing = self.missing_dimensions();
+            if !missing.is_empty() {
+                error!("No fusion input provides {}; fusion disabled", missing.join(", "));
+                return None;
+            }
+            self.receiver = Some(self.start());
+        }
+        loop {
+            let finished = !self.running.contains(&true);
+            let received = if finished { None } else { self.receiver.as_mut()?.recv().await };
+            let mut events: Vec<IngestEvent> = Vec::new();
+            match received {
+                Some((index, Some(batch))) => {
+                    for event in batch {
+                        match event {
+                            IngestEvent::State(state) => {
+                                for d in (0..5).filter(|&d| self.owners[d] == Some(index)) {
+                                    self.aligner.observe(d, &state);
+                                }
+                            }
+                            craving => events.push(craving),
+                        }
+                    }
+                }
+                Some((index, None)) => {
+                    self.running[index] = false;
+                    if !self.running.contains(&true) {
+                        info!("All fusion inputs finished");
+                    }
+                    continue;
+                }
+                None => {
+                    let fused = self.aligner.fuse(true);
+                    self.aligner.next = None;
+                    self.aligner.started = true;
+                    return (!fused.is_empty()).then(|| fused.into_iter().map(IngestEvent::from).collect());
+                }
+            }
+            events.extend(self.aligner.fuse(false).into_iter().map(IngestEvent::from));
+            if !events.is_empty() {
+                return Some(events);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(ms: i64) -> DateTime<Utc> {
+        Utc.timestamp_millis_opt(1_714_560_000_000 + ms).unwrap()
+    }
+
+    /// A state whose every coordinate is `value`.
+    fn state(ms: i64, value: f64) -> TaggedState {
+        TaggedState::new(BiophysicalState::new(value, value, value, value, value), at(ms), None)
+    }
+
+    #[test]
+    fn test_alignment_methods_and_freshness() {
+        let second = Duration::from_secs(1);
+        let mut aligner = Aligner::new(FusionConfig {
+            step: second,
+            alignment: [
+                Alignment::Nearest { tolerance: Duration::from_millis(300) },
+                Alignment::Linear { max_gap: Duration::from_secs(3) },
+                Alignment::Linear { max_gap: Duration::from_secs(3) },
+                Alignment::LastObservation { max_staleness: Duration::from_secs(2) },
+                Alignment::LastObservation { max_staleness: Duration::from_secs(2) },
+            ],
+            max_delay: second,
+            subject_id: Some("s1".into()),
+        });
+        // E every second, 200 ms late; S_bio and m_prot every two seconds; theta and T once.
+        for i in 0..6 {
+            aligner.observe(0, &state(i * 1000 + 200, i as f64));
+        }
+        for i in 0..3 {
+            aligner.observe(1, &state(i * 2000, (i * 2) as f64));
+            aligner.observe(2, &state(i * 2000, (i * 2) as f64));
+        }
+        aligner.observe(3, &state(0, 0.5));
+        aligner.observe(4, &state(0, 0.25));
+        // Theta and T fell silent; E reached 5.2 s, so points up to 4.2 s are decided.
+        let fused = aligner.fuse(false);
+        assert_eq!(fused.iter().map(|s| s.timestamp).collect::<Vec<_>>(), vec![at(0), at(1000), at(2000)]);
+        let arrays: Vec<[f64; 5]> = fused.iter().map(|s| s.state.as_array()).collect();
+        assert_eq!(arrays[0], [0.0, 0.0, 0.0, 0.5, 0.25]);
+        // Interpolated halfway between 0 and 2; theta carried forward, and flagged
+        // once older than a step.
+        assert_eq!(arrays[1], [1.0, 1.0, 1.0, 0.5, 0.25]);
+        assert!(!fused[1].quality.contains(QualityFlags::stale(3)));
+        assert!(fused[2].quality.contains(QualityFlags::stale(3)));
+        assert_eq!(fused[0].subject_id.as_deref(), Some("s1"));
+        // Theta and T go stale after 2 s, so 3 s and 4 s are dropped.
+        let stats = aligner.stats.snapshot();
+        assert_eq!((stats.emitted, stats.incomplete), (3, 2));
+        assert_eq!(stats.missing, [0, 0, 0, 2, 2]);
+    }
+}
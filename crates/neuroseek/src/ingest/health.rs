@@ -0,0 +1,64 @@
+//! Ingestion health counters.
+//!
+//! Ingesters count failures as they happen; monitoring reads a consistent-enough
+//! `HealthSnapshot` at any time, from any thread, without taking a lock.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Running counters of one ingester.
+#[derive(Debug, Default)]
+pub struct IngestHealth {
+    queries_failed: AtomicU64,
+    retries: AtomicU64,
+    samples_ingested: AtomicU64,
+    samples_partial: AtomicU64,
+    samples_dropped: AtomicU64,
+}
+
+/// Point-in-time copy of `IngestHealth`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct HealthSnapshot {
+    /// Requests that failed after all retries.
+    pub queries_failed: u64,
+    /// Retries issued after transient failures.
+    pub retries: u64,
+    /// Samples handed to the pipeline, partial ones included.
+    pub samples_ingested: u64,
+    /// Samples handed on with at least one carried-forward dimension.
+    pub samples_partial: u64,
+    /// Samples that could not be assembled and were not delivered.
+    pub samples_dropped: u64,
+}
+
+impl IngestHealth {
+    pub fn query_failed(&self) {
+        self.queries_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn retried(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a delivered sample; `partial` if any dimension was carried forward.
+    pub fn sample_ingested(&self, partial: bool) {
+        self.samples_ingested.fetch_add(1, Ordering::Relaxed);
+        if partial {
+            self.samples_partial.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn samples_dropped(&self, count: u64) {
+        self.samples_dropped.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> HealthSnapshot {
+        HealthSnapshot {
+            queries_failed: self.queries_failed.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+            samples_ingested: self.samples_ingested.load(Ordering::Relaxed),
+            samples_partial: self.samples_partial.load(Ordering::Relaxed),
+            samples_dropped: self.samples_dropped.load(Ordering::Relaxed),
+        }
+    }
+}
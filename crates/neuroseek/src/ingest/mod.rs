@@ -1,5 +1,22 @@
 //! Data ingestion from external metrics sources.
-//! Supports file‑based polling and direct Prometheus queries.
+//! Supports file‑based polling, tailing an NDJSON stream, direct Prometheus queries,
+//! replay of recorded sessions, import of wearable exports and a local push endpoint.
+//! Every source implements `StateSource`; a `FusionSource` can combine dimensions of
+//! several sources on a common time grid. The `IngestPipeline` runs them, optionally
+//! filters their states for signal quality and tags them with stimulus session
+//! phases, and queues them for the clusterer.
 
+pub mod edf;
 pub mod episode_metrics;
+pub mod filter;
+pub mod fusion;
+pub mod health;
+pub mod ndjson_tail;
+pub mod phase;
+pub mod pipeline;
 pub mod prometheus;
+pub mod push;
+pub mod queue;
+pub mod replay;
+pub mod source;
+pub mod wearable;
@@ -0,0 +1,287 @@
+//! Ingests biophysical states from an append-only NDJSON stream.
+//!
+//! Unlike the snapshot file read by `MetricsIngester`, the stream keeps every update:
+//! the producer appends one record per line,
+//!
+//! ```text
+//! {"timestamp":"2024-05-01T12:00:00Z","state":{"e":0.4,...},"stimulus":{...}}
+//! ```
+//!
+//! and the `NdjsonTailer` reads whatever was appended since its last read. Samples are
+//! stamped with the record's own timestamp, and the stimulus metadata (if any) is
+//! carried through to the clusterer.
+//!
+//! New lines are read one at a time. The byte offset past the last complete line is
+//! persisted to an offset file once the records read up to it have been handed on
+//! (`NdjsonTailer::commit`), so a restarted tailer resumes where it stopped instead of
+//! re-reading the stream, and a crash in between re-reads those records rather than
+//! losing them. A trailing line without a newline is left for the next read, since the
+//! producer may still be writing it. If the stream shrinks below the read offset it
+//! was truncated or replaced, and reading restarts from the beginning.
+
+use super::source::{IngestEvent, StateSource};
+use crate::model::{BiophysicalState, StimulusMetadata, TaggedState};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::time;
+use tracing::{error, info, warn};
+
+/// Configuration for the NDJSON stream ingester.
+#[derive(Debug, Clone)]
+pub struct NdjsonTailConfig {
+    /// Path to the append-only NDJSON stream.
+    pub stream_file: PathBuf,
+    /// Where to persist the read offset. Without one, every start reads the whole stream.
+    pub offset_file: Option<PathBuf>,
+    /// How often to check the stream for new records.
+    pub poll_interval: Duration,
+}
+
+/// One line of the stream.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamRecord {
+    /// When the state was measured.
+    pub timestamp: DateTime<Utc>,
+    pub state: BiophysicalState,
+    /// Stimulus active at the time, if any.
+    #[serde(default)]
+    pub stimulus: Option<StimulusMetadata>,
+    /// Subject the state belongs to, for streams that carry several subjects.
+    #[serde(default)]
+    pub subject_id: Option<String>,
+    /// Device-reported signal quality (1 is clean, 0 unusable).
+    #[serde(default)]
+    pub signal_quality: Option<f64>,
+}
+
+impl From<StreamRecord> for TaggedState {
+    fn from(record: StreamRecord) -> Self {
+        TaggedState::new(record.state, record.timestamp, record.stimulus)
+            .with_subject(record.subject_id, None)
+            .with_signal_quality(record.signal_quality)
+    }
+}
+
+/// Incremental reader of an NDJSON stream with a persisted offset.
+#[derive(Debug)]
+pub struct NdjsonTailer {
+    stream_file: PathBuf,
+    offset_file: Option<PathBuf>,
+    offset: u64,
+    /// Offset last written to `offset_file`.
+    saved: u64,
+}
+
+impl NdjsonTailer {
+    /// Create a tailer, resuming from the saved offset if there is one.
+    pub fn new(stream_file: impl Into<PathBuf>, offset_file: Option<PathBuf>) -> Self {
+        let offset = offset_file.as_deref().and_then(load_offset).unwrap_or(0);
+        Self {
+            stream_file: stream_file.into(),
+            offset_file,
+            offset,
+            saved: offset,
+        }
+    }
+
+    /// Byte offset of the next unread line.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Read the complete records appended since the last call. The offset is not saved
+    /// until `commit`.
+    ///
+    /// Lines that do not parse are logged and skipped, so a single bad record cannot
+    /// stall the stream. A missing stream file yields no records.
+    pub fn read_new(&mut self) -> io::Result<Vec<TaggedState>> {
+        Ok(self.read_new_as::<StreamRecord>()?.into_iter().map(TaggedState::from).collect())
+    }
+
+    /// Like `read_new`, for streams of other records.
+    pub fn read_new_as<T: DeserializeOwned>(&mut self) -> io::Result<Vec<T>> {
+        let mut file = match File::open(&self.stream_file) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let len = file.metadata()?.len();
+        if len < self.offset {
+            warn!(
+                "Stream {} shrank below offset {} (now {} bytes); reading from the start",
+                self.stream_file.display(),
+                self.offset,
+                len
+            );
+            self.offset = 0;
+        }
+        if len == self.offset {
+            return Ok(Vec::new());
+        }
+
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut reader = BufReader::new(file.take(len - self.offset));
+        let mut records = Vec::new();
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line)?;
+            // Stop at the end, or before a last line that may still be being written.
+            if read == 0 || line.last() != Some(&b'\n') {
+                break;
+            }
+            let text = String::from_utf8_lossy(&line);
+            let text = text.trim();
+            if !text.is_empty() {
+                match serde_json::from_str::<T>(text) {
+                    Ok(record) => records.push(record),
+                    Err(e) => warn!(
+                        "Skipping malformed record at byte {} of {}: {}",
+                        self.offset,
+                        self.stream_file.display(),
+                        e
+                    ),
+                }
+            }
+            self.offset += read as u64;
+        }
+        Ok(records)
+    }
+
+    /// Persist the offset reached so far; call once the records read have been handed
+    /// on. Without an offset file this does nothing.
+    pub fn commit(&mut self) {
+        let Some(path) = &self.offset_file else { return };
+        if self.saved == self.offset {
+            return;
+        }
+        match store_offset(path, self.offset) {
+            Ok(()) => self.saved = self.offset,
+            Err(e) => error!("Failed to save stream offset to {}: {}", path.display(), e),
+        }
+    }
+}
+
+fn load_offset(path: &Path) -> Option<u64> {
+    let contents = fs::read_to_string(path).ok()?;
+    match contents.trim().parse() {
+        Ok(offset) => Some(offset),
+        Err(e) => {
+            warn!("Ignoring unreadable offset file {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Write the offset atomically (temp file then rename).
+fn store_offset(path: &Path, offset: u64) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, offset.to_string())?;
+    fs::rename(&tmp, path)
+}
+
+/// A `StateSource` tailing an NDJSON stream.
+pub struct NdjsonIngester {
+    config: NdjsonTailConfig,
+    tailer: NdjsonTailer,
+    interval: Option<time::Interval>,
+}
+
+impl NdjsonIngester {
+    /// Create a new source, resuming from the saved offset if there is one.
+    pub fn new(config: NdjsonTailConfig) -> Self {
+        let tailer = NdjsonTailer::new(&config.stream_file, config.offset_file.clone());
+        Self { config, tailer, interval: None }
+    }
+}
+
+#[async_trait]
+impl StateSource for NdjsonIngester {
+    fn name(&self) -> String {
+        format!("stream {}", self.config.stream_file.display())
+    }
+
+    async fn next_batch(&mut self) -> Option<Vec<IngestEvent>> {
+        // Being asked for the next batch means the previous one was handed on.
+        self.tailer.commit();
+        loop {
+            let poll_interval = self.config.poll_interval;
+            self.interval.get_or_insert_with(|| time::interval(poll_interval)).tick().await;
+            match self.tailer.read_new() {
+                Ok(states) if !states.is_empty() => {
+                    info!("Read {} states from stream (offset {})", states.len(), self.tailer.offset());
+                    return Some(states.into_iter().map(IngestEvent::from).collect());
+                }
+                Ok(_) => {}
+                Err(e) => error!("Failed to read stream {}: {}", self.config.stream_file.display(), e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn record(e: f64, second: u32, stimulus: bool) -> String {
+        let stimulus = if stimulus {
+            r#","stimulus":{"stimulus_id":"s1","stimulus_name":"calm","audio_params":null}"#
+        } else {
+            ""
+        };
+        format!(
+            r#"{{"timestamp":"2024-05-01T12:00:{:02}Z","state":{{"e":{},"m_prot":0.1,"s_bio":0.2,"theta":0.3,"t":0.0}}{}}}"#,
+            second, e, stimulus
+        )
+    }
+
+    fn append(path: &Path, text: &str) {
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(path).unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_tail_resumes_and_handles_partial_lines() {
+        let dir = std::env::temp_dir().join(format!("neuroseek-tail-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let stream = dir.join("states.ndjson");
+        let offsets = dir.join("states.offset");
+
+        append(&stream, &format!("{}\nnot json\n{}\n{}", record(0.1, 0, false), record(0.2, 1, true), record(0.3, 2, false)));
+        let mut tailer = NdjsonTailer::new(&stream, Some(offsets.clone()));
+        let states = tailer.read_new().unwrap();
+        assert_eq!(states.len(), 2);
+        assert_eq!(states[1].timestamp.to_rfc3339(), "2024-05-01T12:00:01+00:00");
+        assert_eq!(states[1].stimulus.as_ref().unwrap().stimulus_id, "s1");
+
+        // Until the records are committed, a restart reads them again.
+        assert!(!offsets.exists());
+        let mut tailer = NdjsonTailer::new(&stream, Some(offsets.clone()));
+        assert_eq!(tailer.read_new().unwrap().len(), 2);
+        tailer.commit();
+
+        // The unterminated third record is picked up once its newline arrives,
+        // by a new tailer that resumes from the saved offset.
+        append(&stream, "\n");
+        let mut tailer = NdjsonTailer::new(&stream, Some(offsets.clone()));
+        let states = tailer.read_new().unwrap();
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].state.e, 0.3);
+        assert!(tailer.read_new().unwrap().is_empty());
+
+        // Truncation restarts from the beginning.
+        fs::write(&stream, format!("{}\n", record(0.4, 3, false))).unwrap();
+        let states = tailer.read_new().unwrap();
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].state.e, 0.4);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,274 @@
+//! Joins the stimulus event log to the state stream.
+//!
+//! The executor logs the start and stop of every session (see
+//! `stimulus::event_log`). The `PhaseTagger` reads that log as it grows and tags each
+//! state with the session it falls around (`TaggedState::session_phase`):
+//!
+//! - `during` from the start to the stop (or, while no stop is logged, up to now);
+//! - `post` within `post` after the stop;
+//! - `pre` within `pre` before the start.
+//!
+//! A session without a subject applies to every subject. If several sessions match,
+//! `during` wins over `post` over `pre`, and the latest start among equals. States
+//! during a session that carry no stimulus of their own are attributed to it.
+//!
+//! A state can only be tagged `pre` once its session's start is logged, which live is
+//! after the state arrived. The tagger therefore holds events back until `pre` has
+//! passed since their timestamp; cravings are held with the states so that they keep
+//! their order. Recorded sessions, whose timestamps are long past, are not delayed.
+//! At most `PhaseConfig::max_held` events are held; beyond that the oldest are tagged
+//! early (possibly missing a `pre` tag) and handed on, so the ingest queue's overflow
+//! policy applies to them rather than the tagger growing without bound.
+//!
+//! A session is forgotten once `post` has passed since its stop, as measured by the
+//! newest state tagged; states of an ended session arriving later still are not tagged.
+
+use super::ndjson_tail::NdjsonTailer;
+use super::replay::event_timestamp;
+use super::source::IngestEvent;
+use crate::model::{Phase, SessionPhase, StimulusMetadata, TaggedState};
+use crate::stimulus::event_log::{SessionEvent, SessionEventKind};
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::warn;
+
+/// Configuration of the join.
+#[derive(Debug, Clone)]
+pub struct PhaseConfig {
+    /// The executor's session event log.
+    pub event_log: PathBuf,
+    /// How long before a session's start states are tagged `pre`.
+    pub pre: Duration,
+    /// How long after a session's stop states are tagged `post`.
+    pub post: Duration,
+    /// Most events held back at once.
+    pub max_held: usize,
+}
+
+#[derive(Debug)]
+struct Session {
+    stimulus: StimulusMetadata,
+    subject_id: Option<String>,
+    start: DateTime<Utc>,
+    stop: Option<DateTime<Utc>>,
+}
+
+/// Tags states with the stimulus session they fall around.
+#[derive(Debug)]
+pub struct PhaseTagger {
+    log: NdjsonTailer,
+    pre: chrono::Duration,
+    post: chrono::Duration,
+    sessions: HashMap<String, Session>,
+    held: VecDeque<IngestEvent>,
+    max_held: usize,
+    /// Timestamp of the newest state tagged so far.
+    newest: Option<DateTime<Utc>>,
+}
+
+impl PhaseTagger {
+    pub fn new(config: PhaseConfig) -> Self {
+        let window = |d: Duration| chrono::Duration::from_std(d).unwrap_or_else(|_| chrono::Duration::days(36_500));
+        Self {
+            log: NdjsonTailer::new(config.event_log, None),
+            pre: window(config.pre),
+            post: window(config.post),
+            sessions: HashMap::new(),
+            held: VecDeque::new(),
+            max_held: config.max_held.max(1),
+            newest: None,
+        }
+    }
+
+    /// Hold events until they can be tagged. Returns, tagged, the oldest events that
+    /// no longer fit.
+    pub fn hold(&mut self, events: impl IntoIterator<Item = IngestEvent>) -> Vec<IngestEvent> {
+        self.held.extend(events);
+        let excess = self.held.len().saturating_sub(self.max_held);
+        if excess > 0 {
+            warn!("Phase tagger holds more than {} events; tagging {} early", self.max_held, excess);
+        }
+        self.tag_front(excess)
+    }
+
+    /// Events currently held.
+    pub fn held(&self) -> usize {
+        self.held.len()
+    }
+
+    /// Sessions currently known, ended or not.
+    pub fn sessions(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Tag and hand back, in order, the held events at least `pre` older than `now`.
+    pub fn release(&mut self, now: DateTime<Utc>) -> Vec<IngestEvent> {
+        let ready = self
+            .held
+            .iter()
+            .take_while(|event| event_timestamp(event) + self.pre <= now)
+            .count();
+        self.tag_front(ready)
+    }
+
+    /// Tag and hand back every held event, e.g. once the sources are exhausted.
+    pub fn flush(&mut self) -> Vec<IngestEvent> {
+        self.tag_front(self.held.len())
+    }
+
+    fn tag_front(&mut self, count: usize) -> Vec<IngestEvent> {
+        if count == 0 {
+            return Vec::new();
+        }
+        self.refresh();
+        let events: Vec<IngestEvent> = self.held.drain(..count).collect();
+        let tagged = events
+            .into_iter()
+            .map(|event| match event {
+                IngestEvent::State(state) => {
+                    self.newest = self.newest.max(Some(state.timestamp));
+                    IngestEvent::State(self.tag(state))
+                }
+                craving => craving,
+            })
+            .collect();
+        self.prune();
+        tagged
+    }
+
+    /// Forget sessions that ended more than `post` before the newest state tagged.
+    fn prune(&mut self) {
+        let (Some(newest), post) = (self.newest, self.post) else { return };
+        self.sessions.retain(|_, s| s.stop.is_none_or(|stop| stop + post > newest));
+    }
+
+    /// Read the events logged since the last call.
+    fn refresh(&mut self) {
+        let events = match self.log.read_new_as::<SessionEvent>() {
+            Ok(events) => events,
+            Err(e) => {
+                warn!("Failed to read stimulus event log: {}", e);
+                return;
+            }
+        };
+        for event in events {
+            match event.kind {
+                SessionEventKind::Start => {
+                    self.sessions.insert(
+                        event.session_id,
+                        Session {
+                            stimulus: event.stimulus,
+                            subject_id: event.subject_id,
+                            start: event.timestamp,
+                            stop: None,
+                        },
+                    );
+                }
+                SessionEventKind::Stop => match self.sessions.get_mut(&event.session_id) {
+                    Some(session) => session.stop = Some(event.timestamp),
+                    None => warn!("Stop of unknown stimulus session {} ignored", event.session_id),
+                },
+            }
+        }
+    }
+
+    fn tag(&self, mut state: TaggedState) -> TaggedState {
+        let at = state.timestamp;
+        let best = self
+            .sessions
+            .iter()
+            .filter(|(_, s)| s.subject_id.is_none() || s.subject_id == state.subject_id)
+            .filter_map(|(id, s)| {
+                let phase = if at < s.start {
+                    (at >= s.start - self.pre).then_some(Phase::Pre)
+                } else {
+                    match s.stop {
+                        Some(stop) if at >= stop => (at < stop + self.post).then_some(Phase::Post),
+                        _ => Some(Phase::During),
+                    }
+                }?;
+                let rank = match phase {
+                    Phase::During => 2,
+                    Phase::Post => 1,
+                    Phase::Pre => 0,
+                };
+                Some(((rank, s.start), id, s, phase))
+            })
+            .max_by_key(|(key, ..)| *key);
+        if let Some((_, id, session, phase)) = best {
+            if phase == Phase::During && state.stimulus.is_none() {
+                state.stimulus = Some(session.stimulus.clone());
+            }
+            state.session_phase = Some(SessionPhase { session_id: id.clone(), phase });
+        }
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::BiophysicalState;
+    use std::io::Write;
+
+    #[test]
+    fn test_states_are_tagged_pre_during_and_post() {
+        let path = std::env::temp_dir().join(format!("neuroseek_phase_{}.ndjson", uuid::Uuid::new_v4()));
+        let mut tagger = PhaseTagger::new(PhaseConfig {
+            event_log: path.clone(),
+            pre: Duration::from_secs(60),
+            post: Duration::from_secs(60),
+            max_held: 4,
+        });
+        let t0: DateTime<Utc> = "2024-05-01T12:00:00Z".parse().unwrap();
+        let at = |sec: i64| t0 + chrono::Duration::seconds(sec);
+        let state = |sec: i64| -> IngestEvent {
+            TaggedState::new(BiophysicalState::new(0.1, 0.2, 0.3, 0.4, 0.0), at(sec), None).into()
+        };
+
+        // Held states are not released until `pre` has passed since them.
+        assert!(tagger.hold([state(-90), state(-30), state(10)]).is_empty());
+        assert_eq!(tagger.release(at(0)).len(), 1);
+
+        // The start is logged only now, yet still tags the state 30 s before it.
+        let stimulus = StimulusMetadata { stimulus_id: "calm".into(), stimulus_name: "Calm".into(), audio_params: None };
+        let mut log = std::fs::File::create(&path).unwrap();
+        for (kind, sec) in [(SessionEventKind::Start, 0), (SessionEventKind::Stop, 120)] {
+            let event = SessionEvent {
+                timestamp: at(sec),
+                session_id: "s-1".into(),
+                kind,
+                stimulus: stimulus.clone(),
+                subject_id: None,
+            };
+            writeln!(log, "{}", serde_json::to_string(&event).unwrap()).unwrap();
+        }
+        // Two held, three more: the oldest one no longer fits and is tagged now.
+        let mut released = tagger.hold([
+            IngestEvent::Craving { timestamp: at(20), intensity: 0.6, state: None, subject_id: None },
+            state(150),
+            state(200),
+        ]);
+        assert_eq!((released.len(), tagger.held()), (1, 4));
+        assert_eq!(tagger.sessions(), 1);
+        released.extend(tagger.flush());
+        let phases: Vec<Option<Phase>> = released
+            .iter()
+            .filter_map(|event| match event {
+                IngestEvent::State(s) => Some(s.session_phase.as_ref().map(|p| p.phase)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(phases, vec![Some(Phase::Pre), Some(Phase::During), Some(Phase::Post), None]);
+        assert!(matches!(released[2], IngestEvent::Craving { .. }));
+        match &released[1] {
+            IngestEvent::State(s) => assert_eq!(s.stimulus.as_ref().unwrap().stimulus_id, "calm"),
+            _ => unreachable!(),
+        }
+        // The state at 200 s is past stop + post, so the session is forgotten.
+        assert_eq!(tagger.sessions(), 0);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,264 @@
+//! Ingest pipeline: drives state sources and feeds the clusterer.
+//!
+//! Each source runs in its own task and hands its batches to the pipeline over a
+//! channel. The pipeline queues the events it accepts on a bounded `IngestQueue`, from
+//! which a clustering worker, the only writer of the clusterer, inserts them in
+//! batches under a single lock. Readers (snapshot writers, monitoring) share the
+//! clusterer through `IngestPipeline::clusterer`.
+//!
+//! With a `SignalFilter` attached (`IngestPipeline::with_filter`), states go through
+//! it first; rejected samples never reach the clusterer. With a `PhaseTagger`
+//! attached (`IngestPipeline::with_phase_tagging`), accepted events are then held
+//! until they can be tagged with their stimulus session phase.
+
+use super::filter::{FilterConfig, FilterStats, SignalFilter};
+use super::phase::{PhaseConfig, PhaseTagger};
+use super::queue::{IngestQueue, QueueConfig, QueueStats};
+use super::source::{IngestEvent, StateSource};
+use crate::clustering::PolytopeClusterer;
+use crate::model::{BiophysicalState, TaggedState};
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time;
+use tracing::{debug, info, warn};
+
+/// Batches waiting to be inserted before sources are made to wait.
+const CHANNEL_CAPACITY: usize = 64;
+
+/// How often held events are checked for release while no batch arrives.
+const PHASE_RELEASE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Owns the clusterer and the sources feeding it.
+pub struct IngestPipeline {
+    clusterer: Arc<Mutex<PolytopeClusterer>>,
+    sources: Vec<Box<dyn StateSource>>,
+    filter: Option<SignalFilter>,
+    phases: Option<PhaseTagger>,
+    queue: Arc<IngestQueue>,
+}
+
+impl IngestPipeline {
+    /// Create a pipeline around a clusterer, with no sources yet.
+    pub fn new(clusterer: PolytopeClusterer) -> Self {
+        Self {
+            clusterer: Arc::new(Mutex::new(clusterer)),
+            sources: Vec::new(),
+            filter: None,
+            phases: None,
+            queue: Arc::new(IngestQueue::new(QueueConfig::default())),
+        }
+    }
+
+    /// Filter states before they are clustered.
+    pub fn with_filter(mut self, config: FilterConfig) -> Self {
+        self.filter = Some(SignalFilter::new(config));
+        self
+    }
+
+    /// Tag states with the stimulus session phase they fall in.
+    pub fn with_phase_tagging(mut self, config: PhaseConfig) -> Self {
+        self.phases = Some(PhaseTagger::new(config));
+        self
+    }
+
+    /// Configure the queue between the pipeline and the clusterer.
+    pub fn with_queue(mut self, config: QueueConfig) -> Self {
+        self.queue = Arc::new(IngestQueue::new(config));
+        self
+    }
+
+    /// Add a source.
+    pub fn with_source(mut self, source: impl StateSource + 'static) -> Self {
+        self.sources.push(Box::new(source));
+        self
+    }
+
+    /// Add an already boxed source.
+    pub fn add_source(&mut self, source: Box<dyn StateSource>) {
+        self.sources.push(source);
+    }
+
+    /// Shared handle to the clusterer, for readers.
+    pub fn clusterer(&self) -> Arc<Mutex<PolytopeClusterer>> {
+        self.clusterer.clone()
+    }
+
+    /// Counters of the filter, if one is attached.
+    pub fn filter_stats(&self) -> Option<Arc<FilterStats>> {
+        self.filter.as_ref().map(SignalFilter::stats)
+    }
+
+    /// Counters of the queue to the clusterer.
+    pub fn queue_stats(&self) -> Arc<QueueStats> {
+        self.queue.stats()
+    }
+
+    /// Run all sources until every one of them is exhausted (live sources never are).
+    pub async fn run(mut self) {
+        if self.sources.is_empty() {
+            warn!("Ingest pipeline has no sources");
+            return;
+        }
+        let (tx, mut rx) = mpsc::channel::<(String, Vec<IngestEvent>)>(CHANNEL_CAPACITY);
+        for mut source in self.sources {
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let name = source.name();
+                info!("Source {} started", name);
+                while let Some(batch) = source.next_batch().await {
+                    if tx.send((name.clone(), batch)).await.is_err() {
+                        break;
+                    }
+                }
+                info!("Source {} finished", name);
+            });
+        }
+        drop(tx);
+
+        let queue = self.queue.clone();
+        let clusterer = self.clusterer.clone();
+        let worker = tokio::spawn(async move {
+            // Most recent state per subject, for craving reports that carry none.
+            let mut latest: HashMap<Option<String>, BiophysicalState> = HashMap::new();
+            while let Some(events) = queue.pop_batch().await {
+                apply(&mut *clusterer.lock().await, &mut latest, events);
+            }
+        });
+
+        let mut release = time::interval(PHASE_RELEASE_INTERVAL);
+        loop {
+            let (name, batch) = tokio::select! {
+                received = rx.recv() => match received {
+                    Some(received) => received,
+                    None => break,
+                },
+                _ = release.tick(), if self.phases.is_some() => {
+                    let released = self.phases.as_mut().map(|p| p.release(Utc::now())).unwrap_or_default();
+                    self.queue.push(released).await;
+                    continue;
+                }
+            };
+            if batch.is_empty() {
+                continue;
+            }
+            let count = batch.len();
+            let mut accepted = Vec::with_capacity(count);
+            for event in batch {
+                let event = match (event, self.filter.as_mut()) {
+                    (IngestEvent::State(state), Some(filter)) => match filter.apply(state) {
+                        Ok(state) => IngestEvent::State(state),
+                        Err(reason) => {
+                            debug!("Rejected sample from {}: {}", name, reason);
+                            continue;
+                        }
+                    },
+                    (event, _) => event,
+                };
+                accepted.push(event);
+            }
+            if accepted.len() < count {
+                warn!("Filter rejected {} of {} events from {}", count - accepted.len(), count, name);
+            }
+            let ready = match self.phases.as_mut() {
+                Some(phases) => {
+                    let mut ready = phases.hold(accepted);
+                    ready.extend(phases.release(Utc::now()));
+                    ready
+                }
+                None => accepted,
+            };
+            info!("Queued {} events from {}", ready.len(), name);
+            self.queue.push(ready).await;
+        }
+        if let Some(phases) = self.phases.as_mut() {
+            self.queue.push(phases.flush()).await;
+        }
+        self.queue.close();
+        if let Err(e) = worker.await {
+            warn!("Clustering worker failed: {}", e);
+        }
+    }
+}
+
+/// Insert states and associate cravings, in order; runs of states go in one batch.
+fn apply(clusterer: &mut PolytopeClusterer, latest: &mut HashMap<Option<String>, BiophysicalState>, events: Vec<IngestEvent>) {
+    let count = events.len();
+    let mut states: Vec<TaggedState> = Vec::new();
+    for event in events {
+        match event {
+            IngestEvent::State(state) => {
+                latest.insert(state.subject_id.clone(), state.state);
+                states.push(state);
+            }
+            IngestEvent::Craving { timestamp, intensity, state, subject_id } => {
+                clusterer.insert_batch(states.drain(..));
+                match state.or_else(|| latest.get(&subject_id).copied()) {
+                    Some(state) => clusterer.associate_craving(&state, intensity, timestamp),
+                    None => warn!("Craving report precedes any state of its subject; ignored"),
+                }
+            }
+        }
+    }
+    clusterer.insert_batch(states);
+    debug!("Applied {} events", count);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clustering::ClustererConfig;
+    use crate::model::TaggedState;
+    use async_trait::async_trait;
+    use chrono::Utc;
+    use std::time::Duration;
+
+    /// Yields fixed batches, then ends.
+    struct Batches(Vec<Vec<IngestEvent>>);
+
+    #[async_trait]
+    impl StateSource for Batches {
+        fn name(&self) -> String {
+            "batches".into()
+        }
+
+        async fn next_batch(&mut self) -> Option<Vec<IngestEvent>> {
+            if self.0.is_empty() {
+                None
+            } else {
+                Some(self.0.remove(0))
+            }
+        }
+    }
+
+    fn at(e: f64) -> IngestEvent {
+        TaggedState::new(BiophysicalState::new(e, 0.1, 0.1, 0.1, 0.1), Utc::now(), None).into()
+    }
+
+    fn craving(intensity: f64) -> IngestEvent {
+        IngestEvent::Craving { timestamp: Utc::now(), intensity, state: None, subject_id: None }
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_drains_all_sources() {
+        let clusterer = PolytopeClusterer::new(ClustererConfig::default(), Duration::from_secs(3600));
+        let pipeline = IngestPipeline::new(clusterer)
+            .with_source(Batches(vec![vec![at(0.1)], vec![at(0.5), craving(0.8), at(0.9)]]))
+            .with_source(Batches(vec![vec![at(0.3), at(f64::NAN)]]))
+            .with_filter(FilterConfig::default());
+        let clusterer = pipeline.clusterer();
+        let stats = pipeline.filter_stats().unwrap();
+        pipeline.run().await;
+        assert_eq!(stats.snapshot().rejected_non_finite, 1);
+
+        // Four distant points (the NaN one filtered out), each its own polytope of weight 1.
+        let clusterer = clusterer.lock().await;
+        let weight: f64 = clusterer.polytopes().iter().map(|p| p.weight).sum();
+        assert_eq!(weight, 4.0);
+        // The craving went to the polytope of the state just before it.
+        let craved = clusterer.polytopes().iter().find(|p| p.avg_craving().is_some()).unwrap();
+        assert!((craved.centroid().e - 0.5).abs() < 1e-9);
+    }
+}
@@ -1,20 +1,77 @@
 //! Ingests biophysical state by directly querying a Prometheus server.
 //!
-//! The `PrometheusIngester` periodically queries Prometheus instant queries
-//! for each of the five biophysical dimensions, constructs a `BiophysicalState`,
-//! and feeds it into the clusterer. All operations are read‑only and use only
-//! outer‑domain signals.
+//! The `PrometheusIngester` periodically queries Prometheus for each of the five
+//! biophysical dimensions, constructs a `BiophysicalState`, and yields it as a
+//! `StateSource`. Each poll is an instant query (`/api/v1/query`) for the latest
+//! aligned timestamp; history is read with range queries (`/api/v1/query_range`), as
+//! described below. All operations are read‑only and use only outer‑domain signals.
+//!
+//! All queries are evaluated at timestamps aligned to `range_step`, so the five
+//! dimensions are sampled at the same instants and points carry the sample timestamps
+//! Prometheus returns. On startup the last `backfill` of history is loaded with
+//! `/api/v1/query_range`, and whenever samples were missed (Prometheus unreachable,
+//! observer paused) the gap since the last ingested sample is filled the same way.
+//!
+//! Every request has a timeout and transient failures are retried with exponential
+//! backoff. If a dimension still cannot be read, its last good value is carried
+//! forward for up to `max_staleness` and the point is flagged in `TaggedState::quality`;
+//! only beyond that is the sample dropped. Failures are counted in an `IngestHealth`
+//! that monitoring can read through `PrometheusIngester::health`.
+//!
+//! A query may return several series, e.g. one per subject or device. The
+//! `SeriesMapping` names the labels that identify them; every series is then ingested
+//! as its own `TaggedState`, tagged with its subject and device. All five dimensions
+//! can also come from a single query whose series carry a dimension label.
 
-use crate::clustering::PolytopeClusterer;
-use crate::model::{BiophysicalState, StimulusMetadata, TaggedState, AudioParams};
+use super::health::IngestHealth;
+use super::source::{IngestEvent, StateSource};
+use crate::model::{seconds_between, BiophysicalState, StimulusMetadata, TaggedState, AudioParams, QualityFlags};
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
 use reqwest::Client;
 use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 use tokio::sync::Mutex;
 use tokio::time;
 use tracing::{error, info, warn};
 
+/// Values of the dimension label in a combined query, in `as_array` order.
+pub const DIMENSION_NAMES: [&str; 5] = ["e", "m_prot", "s_bio", "theta", "t"];
+
+/// How the labels of returned series map to subjects and dimensions.
+#[derive(Debug, Clone)]
+pub struct SeriesMapping {
+    /// Label identifying the subject. Without one, every query is expected to return a
+    /// single series, and extra series are ignored with a warning.
+    pub subject_label: Option<String>,
+    /// Label identifying the device, for subjects measured by several devices.
+    pub device_label: Option<String>,
+    /// A single query returning all five dimensions; replaces the per-dimension queries.
+    pub combined_query: Option<String>,
+    /// Label of `combined_query` series naming their dimension (see `DIMENSION_NAMES`).
+    pub dimension_label: String,
+}
+
+impl Default for SeriesMapping {
+    fn default() -> Self {
+        Self {
+            subject_label: None,
+            device_label: None,
+            combined_query: None,
+            dimension_label: "dimension".into(),
+        }
+    }
+}
+
+/// Whose series a sample belongs to.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SeriesKey {
+    pub subject: Option<String>,
+    pub device: Option<String>,
+}
+
 /// Configuration for the Prometheus ingester.
 #[derive(Debug, Clone)]
 pub struct PrometheusConfig {
@@ -31,148 +88,744 @@ pub struct PrometheusConfig {
     /// Prometheus instant query for the temperature deviation (T).
     pub query_t: String,
     /// Prometheus query for active stimulus ID (returns a string or empty).
-    /// Should return a metric like `active_stimulus{id="..."}`.
+    /// Should return a metric like `active_stimulus{id="..."}`. A series without the
+    /// subject label applies to every subject.
     pub query_active_stimulus: String,
     /// Optional: query for stimulus name (if separate).
     pub query_stimulus_name: Option<String>,
+    /// Which labels identify subjects, devices and dimensions.
+    pub mapping: SeriesMapping,
     /// Polling interval.
     pub poll_interval: Duration,
+    /// Sample resolution: query timestamps are aligned to multiples of it, so at most
+    /// one sample is ingested per step. Usually equal to `poll_interval`.
+    pub range_step: Duration,
+    /// How much history to load on startup (zero disables the backfill).
+    pub backfill: Duration,
+    /// Timeout of each HTTP request.
+    pub request_timeout: Duration,
+    /// Retries after a transient request failure.
+    pub max_retries: u32,
+    /// Delay before the first retry; doubled for each further one.
+    pub retry_backoff: Duration,
+    /// How long the last good value of a dimension may stand in for a failed query.
+    pub max_staleness: Duration,
+}
+
+/// Prometheus refuses range queries returning more than 11,000 points per series.
+const MAX_POINTS_PER_QUERY: i64 = 10_000;
+
+/// One series of a query result: its labels and `(timestamp in ms, value)` samples.
+/// An instant query yields series with a single sample.
+type Series = (Value, Vec<(i64, f64)>);
+
+/// The five dimensions of every subject, by sample timestamp (ms).
+type Grid = BTreeMap<(i64, SeriesKey), [Option<f64>; 5]>;
+
+/// A dimension's last good value and when it was sampled.
+type LastGood = Option<(DateTime<Utc>, f64)>;
+
+/// When to evaluate a query.
+#[derive(Debug, Clone, Copy)]
+enum Window {
+    At(DateTime<Utc>),
+    Range(DateTime<Utc>, DateTime<Utc>),
+}
+
+/// Round a timestamp down to a multiple of `step`.
+fn align_down(t: DateTime<Utc>, step: Duration) -> DateTime<Utc> {
+    let step_ms = (step.as_millis() as i64).max(1);
+    let ms = t.timestamp_millis();
+    Utc.timestamp_millis_opt(ms - ms.rem_euclid(step_ms)).unwrap()
+}
+
+/// Prometheus timestamps are float seconds.
+fn prometheus_time(t: DateTime<Utc>) -> String {
+    format!("{:.3}", t.timestamp_millis() as f64 / 1000.0)
+}
+
+/// Parse a `[timestamp, "value"]` pair.
+fn parse_sample(v: &Value) -> Option<(i64, f64)> {
+    let t = v.get(0)?.as_f64()?;
+    let value = v.get(1)?.as_str()?.parse::<f64>().ok()?;
+    Some(((t * 1000.0).round() as i64, value))
+}
+
+/// Extract stimulus metadata from the labels of an `active_stimulus` series.
+/// Returns None if the series has no `id`.
+fn stimulus_from_labels(metric: &Value) -> Option<StimulusMetadata> {
+    let id = metric.get("id").and_then(|v| v.as_str()).unwrap_or("").to_string();
+    if id.is_empty() {
+        return None;
+    }
+    let name = metric.get("name").and_then(|v| v.as_str()).unwrap_or("unknown").to_string();
+    // Optionally extract audio params from labels (e.g., carrier, beat)
+    let carrier = metric.get("carrier").and_then(|v| v.as_str()).and_then(|s| s.parse().ok());
+    let beat = metric.get("beat").and_then(|v| v.as_str()).and_then(|s| s.parse().ok());
+    let amp = metric.get("amplitude").and_then(|v| v.as_str()).and_then(|s| s.parse().ok());
+    let audio_params = match (carrier, beat, amp) {
+        (Some(c), Some(b), Some(a)) => Some(AudioParams { carrier_hz: c, beat_hz: b, amplitude: a }),
+        _ => None,
+    };
+    Some(StimulusMetadata {
+        stimulus_id: id,
+        stimulus_name: name,
+        audio_params,
+    })
 }
 
-/// Ingests metrics by querying Prometheus and updating the clusterer.
+/// Active stimuli by sample timestamp, per subject and for all subjects.
+#[derive(Debug, Default)]
+struct Stimuli {
+    by_key: HashMap<(i64, SeriesKey), StimulusMetadata>,
+    everyone: HashMap<i64, StimulusMetadata>,
+}
+
+impl Stimuli {
+    fn get(&self, t: i64, key: &SeriesKey) -> Option<StimulusMetadata> {
+        self.by_key.get(&(t, key.clone())).or_else(|| self.everyone.get(&t)).cloned()
+    }
+}
+
+/// A `StateSource` querying Prometheus.
 pub struct PrometheusIngester {
-    clusterer: Arc<Mutex<PolytopeClusterer>>,
     config: PrometheusConfig,
     client: Client,
+    health: Arc<IngestHealth>,
+    /// Last good value and its timestamp, per subject and dimension.
+    last_good: Mutex<HashMap<SeriesKey, [LastGood; 5]>>,
+    started: bool,
+    /// Timestamp of the last sample yielded (or of the end of the last filled range).
+    last: Option<DateTime<Utc>>,
+    interval: Option<time::Interval>,
 }
 
 impl PrometheusIngester {
     /// Create a new Prometheus ingester.
-    pub fn new(clusterer: Arc<Mutex<PolytopeClusterer>>, config: PrometheusConfig) -> Self {
+    pub fn new(config: PrometheusConfig) -> Self {
+        let client = Client::builder()
+            .timeout(config.request_timeout)
+            .build()
+            .unwrap_or_else(|e| {
+                warn!("Failed to build HTTP client with timeout ({}); using defaults", e);
+                Client::new()
+            });
         Self {
-            clusterer,
             config,
-            client: Client::new(),
+            client,
+            health: Arc::new(IngestHealth::default()),
+            last_good: Mutex::new(HashMap::new()),
+            started: false,
+            last: None,
+            interval: None,
         }
     }
 
-    /// Perform a single instant query and return the value as f64.
-    async fn query_metric(&self, query: &str) -> Option<f64> {
-        let url = format!(
-            "{}/api/v1/query?query={}",
-            self.config.server_url,
-            urlencoding::encode(query)
-        );
-        match self.client.get(&url).send().await {
-            Ok(resp) => {
-                if !resp.status().is_success() {
+    /// Health counters of this ingester.
+    pub fn health(&self) -> Arc<IngestHealth> {
+        self.health.clone()
+    }
+
+    /// GET a Prometheus API URL and return the JSON body.
+    ///
+    /// Transport errors, timeouts and server errors are retried up to `max_retries`
+    /// times with exponential backoff starting at `retry_backoff`; client errors and
+    /// malformed bodies are not. Every request that ultimately fails is counted.
+    async fn get_json(&self, url: &str) -> Option<Value> {
+        let mut backoff = self.config.retry_backoff;
+        for attempt in 0..=self.config.max_retries {
+            if attempt > 0 {
+                self.health.retried();
+                time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            match self.client.get(url).send().await {
+                Ok(resp) if resp.status().is_success() => match resp.json::<Value>().await {
+                    Ok(json) => return Some(json),
+                    Err(e) => {
+                        error!("Failed to parse Prometheus JSON response: {}", e);
+                        break;
+                    }
+                },
+                Ok(resp) => {
                     error!("Prometheus query failed with status: {}", resp.status());
-                    return None;
+                    if resp.status().is_client_error() {
+                        break;
+                    }
                 }
-                match resp.json::<Value>().await {
-                    Ok(json) => {
-                        // Expected Prometheus response structure:
-                        // {"status":"success","data":{"resultType":"vector","result":[{"metric":{},"value":[timestamp,"value"]}]}}
-                        if let Some(result) = json["data"]["result"].as_array() {
-                            if let Some(first) = result.first() {
-                                if let Some(value_str) = first["value"].as_array().and_then(|v| v.get(1)).and_then(|v| v.as_str()) {
-                                    if let Ok(val) = value_str.parse::<f64>() {
-                                        return Some(val);
-                                    }
-                                }
-                            }
-                        }
-                        error!("Unexpected Prometheus response format: {}", json);
-                        None
+                Err(e) => error!("Failed to query Prometheus: {}", e),
+            }
+        }
+        self.health.query_failed();
+        None
+    }
+
+    /// Evaluate a query over a window and return its series. Returns None if the
+    /// query failed.
+    async fn query(&self, query: &str, window: Window) -> Option<Vec<Series>> {
+        let url = match window {
+            Window::At(at) => format!(
+                "{}/api/v1/query?query={}&time={}",
+                self.config.server_url,
+                urlencoding::encode(query),
+                prometheus_time(at)
+            ),
+            Window::Range(start, end) => format!(
+                "{}/api/v1/query_range?query={}&start={}&end={}&step={}",
+                self.config.server_url,
+                urlencoding::encode(query),
+                prometheus_time(start),
+                prometheus_time(end),
+                self.config.range_step.as_secs_f64()
+            ),
+        };
+        let json = self.get_json(&url).await?;
+        // Expected structure, with "value" for instant and "values" for range queries:
+        // {"status":"success","data":{"resultType":"vector","result":[{"metric":{},"value":[timestamp,"value"]}]}}
+        // {"status":"success","data":{"resultType":"matrix","result":[{"metric":{},"values":[[timestamp,"value"],...]}]}}
+        let Some(result) = json["data"]["result"].as_array() else {
+            error!("Unexpected Prometheus response format: {}", json);
+            self.health.query_failed();
+            return None;
+        };
+        let series = result
+            .iter()
+            .map(|s| {
+                let samples = match s.get("values").and_then(|v| v.as_array()) {
+                    Some(values) => values.iter().filter_map(parse_sample).collect(),
+                    None => s.get("value").and_then(parse_sample).into_iter().collect(),
+                };
+                (s["metric"].clone(), samples)
+            })
+            .collect();
+        Some(series)
+    }
+
+    /// The subject and device a series belongs to, by its labels.
+    fn series_key(&self, labels: &Value) -> SeriesKey {
+        let label = |name: &Option<String>| {
+            name.as_ref()
+                .and_then(|n| labels.get(n))
+                .and_then(|v| v.as_str())
+                .map(str::to_string)
+        };
+        SeriesKey {
+            subject: label(&self.config.mapping.subject_label),
+            device: label(&self.config.mapping.device_label),
+        }
+    }
+
+    /// Enter the series of dimension `d` into the grid. Without a subject label only
+    /// the first series is used.
+    fn add_to_grid(&self, grid: &mut Grid, d: usize, series: &[Series]) {
+        if self.config.mapping.subject_label.is_none() && series.len() > 1 {
+            warn!(
+                "Query for {} returned {} series but no subject label is configured; using the first",
+                DIMENSION_NAMES[d],
+                series.len()
+            );
+        }
+        let take = if self.config.mapping.subject_label.is_none() { 1 } else { series.len() };
+        for (labels, samples) in series.iter().take(take) {
+            let key = self.series_key(labels);
+            for (t, v) in samples {
+                grid.entry((*t, key.clone())).or_insert([None; 5])[d] = Some(*v);
+            }
+        }
+    }
+
+    /// Query all five dimensions and the active stimulus over a window.
+    ///
+    /// The grid holds whatever was returned; the flags tell which dimensions' queries
+    /// failed outright (all of them, if a combined query failed).
+    async fn query_dimensions(&self, window: Window) -> (Grid, [bool; 5], Stimuli) {
+        let mut grid = Grid::new();
+        let mut failed = [false; 5];
+        let stim = if let Some(combined) = &self.config.mapping.combined_query {
+            let (all, stim) = tokio::join!(
+                self.query(combined, window),
+                self.query(&self.config.query_active_stimulus, window),
+            );
+            match all {
+                Some(series) => {
+                    let dimension_label = &self.config.mapping.dimension_label;
+                    for (d, name) in DIMENSION_NAMES.iter().enumerate() {
+                        let of_dimension: Vec<Series> = series
+                            .iter()
+                            .filter(|(labels, _)| labels.get(dimension_label).and_then(|v| v.as_str()) == Some(name))
+                            .cloned()
+                            .collect();
+                        self.add_to_grid(&mut grid, d, &of_dimension);
                     }
-                    Err(e) => {
-                        error!("Failed to parse Prometheus JSON response: {}", e);
-                        None
+                }
+                None => failed = [true; 5],
+            }
+            stim
+        } else {
+            let (e, m_prot, s_bio, theta, t, stim) = tokio::join!(
+                self.query(&self.config.query_e, window),
+                self.query(&self.config.query_m_prot, window),
+                self.query(&self.config.query_s_bio, window),
+                self.query(&self.config.query_theta, window),
+                self.query(&self.config.query_t, window),
+                self.query(&self.config.query_active_stimulus, window),
+            );
+            for (d, result) in [e, m_prot, s_bio, theta, t].into_iter().enumerate() {
+                match result {
+                    Some(series) => self.add_to_grid(&mut grid, d, &series),
+                    None => failed[d] = true,
+                }
+            }
+            stim
+        };
+
+        let mut stimuli = Stimuli::default();
+        for (labels, samples) in stim.unwrap_or_default() {
+            let Some(metadata) = stimulus_from_labels(&labels) else { continue };
+            let key = self.series_key(&labels);
+            for (t, _) in samples {
+                if key.subject.is_none() {
+                    stimuli.everyone.insert(t, metadata.clone());
+                } else {
+                    stimuli.by_key.insert((t, key.clone()), metadata.clone());
+                }
+            }
+        }
+        (grid, failed, stimuli)
+    }
+
+    /// Fetch the states sampled at the step-aligned timestamps in `[start, end]`.
+    ///
+    /// A state is produced only for timestamps at which all five dimensions of a
+    /// subject have a sample. Returns None if any dimension query failed, so that the
+    /// caller can retry the same window later instead of skipping it.
+    pub async fn fetch_range(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Option<Vec<TaggedState>> {
+        let step = self.config.range_step;
+        let step_ms = (step.as_millis() as i64).max(1);
+        let mut states = Vec::new();
+        let mut chunk_start = align_down(start, step);
+        if chunk_start < start {
+            chunk_start += chrono::Duration::milliseconds(step_ms);
+        }
+        while chunk_start <= end {
+            let chunk_end = end.min(chunk_start + chrono::Duration::milliseconds(step_ms * (MAX_POINTS_PER_QUERY - 1)));
+            let (grid, failed, stimuli) = self.query_dimensions(Window::Range(chunk_start, chunk_end)).await;
+            if failed.contains(&true) {
+                return None;
+            }
+            for ((t, key), v) in grid {
+                if let [Some(e), Some(m_prot), Some(s_bio), Some(theta), Some(temp)] = v {
+                    let state = BiophysicalState::new(e, m_prot, s_bio, theta, temp);
+                    let timestamp = Utc.timestamp_millis_opt(t).unwrap();
+                    let stimulus = stimuli.get(t, &key);
+                    states.push(TaggedState::new(state, timestamp, stimulus).with_subject(key.subject, key.device));
+                } else {
+                    self.health.samples_dropped(1);
+                }
+            }
+            chunk_start = chunk_end + chrono::Duration::milliseconds(step_ms);
+        }
+        Some(states)
+    }
+
+    /// Sample all five dimensions of every subject at time `at` with instant queries.
+    ///
+    /// A dimension that failed or is missing for a subject takes the subject's last
+    /// good value if that is at most `max_staleness` old, and is flagged as stale. A
+    /// subject with a dimension that has no usable value is dropped (and counted).
+    pub async fn sample_at(&self, at: DateTime<Utc>) -> Vec<TaggedState> {
+        let (grid, _, stimuli) = self.query_dimensions(Window::At(at)).await;
+        // Instant queries report the evaluation time, so re-key by subject alone.
+        let mut measured: BTreeMap<SeriesKey, [Option<f64>; 5]> = BTreeMap::new();
+        for ((_, key), v) in grid {
+            let entry = measured.entry(key).or_insert([None; 5]);
+            for d in 0..5 {
+                entry[d] = entry[d].or(v[d]);
+            }
+        }
+
+        let mut last_good = self.last_good.lock().await;
+        // Subjects seen before but absent now may still be carried forward.
+        for key in last_good.keys() {
+            measured.entry(key.clone()).or_insert([None; 5]);
+        }
+        let t = at.timestamp_millis();
+        let mut states = Vec::new();
+        let mut gone = Vec::new();
+        'subjects: for (key, measured) in measured {
+            let last = last_good.entry(key.clone()).or_insert([None; 5]);
+            let mut values = [0.0; 5];
+            let mut quality = QualityFlags::default();
+            for (d, value) in measured.into_iter().enumerate() {
+                match (value, last[d]) {
+                    (Some(v), _) => {
+                        last[d] = Some((at, v));
+                        values[d] = v;
+                    }
+                    (None, Some((when, v))) if seconds_between(when, at) <= self.config.max_staleness.as_secs_f64() => {
+                        values[d] = v;
+                        quality.insert(QualityFlags::stale(d));
+                    }
+                    (None, _) => {
+                        self.health.samples_dropped(1);
+                        if measured.iter().all(Option::is_none) {
+                            // The subject's series are gone; stop expecting them.
+                            gone.push(key);
+                        }
+                        continue 'subjects;
                     }
                 }
             }
-            Err(e) => {
-                error!("Failed to query Prometheus: {}", e);
-                None
+            let state = BiophysicalState::new(values[0], values[1], values[2], values[3], values[4]);
+            let stimulus = stimuli.get(t, &key);
+            states.push(
+                TaggedState::new(state, at, stimulus)
+                    .with_quality(quality)
+                    .with_subject(key.subject, key.device),
+            );
+        }
+        for key in gone {
+            last_good.remove(&key);
+        }
+        states
+    }
+
+    /// Remember the newest values of each subject in a batch of states.
+    async fn remember(&self, states: &[TaggedState]) {
+        let mut last_good = self.last_good.lock().await;
+        for state in states {
+            let key = SeriesKey { subject: state.subject_id.clone(), device: state.device_id.clone() };
+            let last = last_good.entry(key).or_insert([None; 5]);
+            for (d, v) in state.state.as_array().into_iter().enumerate() {
+                last[d] = Some((state.timestamp, v));
             }
         }
     }
 
-    /// Query for active stimulus metadata. Returns None if no stimulus active.
-    async fn query_active_stimulus(&self) -> Option<StimulusMetadata> {
-        let url = format!(
-            "{}/api/v1/query?query={}",
-            self.config.server_url,
-            urlencoding::encode(&self.config.query_active_stimulus)
-        );
-        match self.client.get(&url).send().await {
-            Ok(resp) => {
-                if !resp.status().is_success() {
-                    return None;
+    /// Fetch the states in `[start, end]` and remember the newest values. Returns None
+    /// if the range could not be fetched.
+    async fn fill(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Option<Vec<TaggedState>> {
+        let states = self.fetch_range(start, end).await?;
+        self.remember(&states).await;
+        for _ in &states {
+            self.health.sample_ingested(false);
+        }
+        info!("Backfilled {} states from Prometheus ({} to {})", states.len(), start, end);
+        Some(states)
+    }
+}
+
+#[async_trait]
+impl StateSource for PrometheusIngester {
+    fn name(&self) -> String {
+        format!("prometheus {}", self.config.server_url)
+    }
+
+    async fn next_batch(&mut self) -> Option<Vec<IngestEvent>> {
+        let step = chrono::Duration::from_std(self.config.range_step).unwrap_or(chrono::Duration::seconds(1));
+        if !self.started {
+            self.started = true;
+            if !self.config.backfill.is_zero() {
+                let now = align_down(Utc::now(), self.config.range_step);
+                let backfill = chrono::Duration::from_std(self.config.backfill).unwrap_or(chrono::Duration::zero());
+                match self.fill(now - backfill, now).await {
+                    Some(states) => {
+                        self.last = Some(now);
+                        if !states.is_empty() {
+                            return Some(states.into_iter().map(IngestEvent::from).collect());
+                        }
+                    }
+                    // Leave the window to the gap fill below, which retries until it succeeds.
+                    None => {
+                        warn!("Failed to backfill the last {:?}; will retry", self.config.backfill);
+                        self.last = Some(now - backfill - step);
+                    }
                 }
-                match resp.json::<Value>().await {
-                    Ok(json) => {
-                        if let Some(result) = json["data"]["result"].as_array() {
-                            if let Some(first) = result.first() {
-                                // The metric might have labels: we want the "id" label.
-                                let metric = &first["metric"];
-                                let id = metric.get("id").and_then(|v| v.as_str()).unwrap_or("").to_string();
-                                if id.is_empty() {
-                                    return None;
-                                }
-                                let name = metric.get("name").and_then(|v| v.as_str()).unwrap_or("unknown").to_string();
-                                // Optionally extract audio params from labels (e.g., carrier, beat)
-                                let carrier = metric.get("carrier").and_then(|v| v.as_str()).and_then(|s| s.parse().ok());
-                                let beat = metric.get("beat").and_then(|v| v.as_str()).and_then(|s| s.parse().ok());
-                                let amp = metric.get("amplitude").and_then(|v| v.as_str()).and_then(|s| s.parse().ok());
-                                let audio_params = match (carrier, beat, amp) {
-                                    (Some(c), Some(b), Some(a)) => Some(AudioParams { carrier_hz: c, beat_hz: b, amplitude: a }),
-                                    _ => None,
-                                };
-                                return Some(StimulusMetadata {
-                                    stimulus_id: id,
-                                    stimulus_name: name,
-                                    audio_params,
-                                });
+            }
+        }
+
+        loop {
+            let poll_interval = self.config.poll_interval;
+            self.interval.get_or_insert_with(|| time::interval(poll_interval)).tick().await;
+            let at = align_down(Utc::now(), self.config.range_step);
+            match self.last {
+                // Already ingested this step.
+                Some(l) if at <= l => continue,
+                // One or more steps were missed: fill the gap.
+                Some(l) if at - l > step => {
+                    match self.fill(l + step, at).await {
+                        Some(states) => {
+                            self.last = Some(at);
+                            if !states.is_empty() {
+                                return Some(states.into_iter().map(IngestEvent::from).collect());
                             }
                         }
-                        None
+                        None => warn!("Failed to backfill gap since {}; will retry", l),
                     }
-                    Err(_) => None,
+                    continue;
+                }
+                _ => {}
+            }
+
+            let states = self.sample_at(at).await;
+            if states.is_empty() {
+                warn!("Failed to retrieve all metrics from Prometheus: {:?}", self.health.snapshot());
+                continue;
+            }
+            for tagged in &states {
+                let partial = !tagged.quality.is_clean();
+                if partial {
+                    warn!(
+                        "Carrying forward stale dimensions for {:?} (quality {:#07b})",
+                        tagged.subject_id, tagged.quality.0
+                    );
                 }
+                self.health.sample_ingested(partial);
             }
-            Err(_) => None,
+            self.last = Some(at);
+            return Some(states.into_iter().map(IngestEvent::from).collect());
         }
     }
+}
 
-    /// Run the ingestion loop (blocks forever).
-    pub async fn run(&self) -> ! {
-        let mut interval = time::interval(self.config.poll_interval);
-        loop {
-            interval.tick().await;
-            // Query all five dimensions concurrently.
-            let (e, m_prot, s_bio, theta, t, stim) = tokio::join!(
-                self.query_metric(&self.config.query_e),
-                self.query_metric(&self.config.query_m_prot),
-                self.query_metric(&self.config.query_s_bio),
-                self.query_metric(&self.config.query_theta),
-                self.query_metric(&self.config.query_t),
-                self.query_active_stimulus(),
-            );
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
 
-            if let (Some(e), Some(m_prot), Some(s_bio), Some(theta), Some(t)) = (e, m_prot, s_bio, theta, t) {
-                let state = BiophysicalState::new(e, m_prot, s_bio, theta, t);
-                let tagged = TaggedState {
-                    state,
-                    timestamp: Instant::now(),
-                    stimulus: stim,
-                };
-                let mut clusterer = self.clusterer.lock().await;
-                clusterer.insert_point(tagged);
-                info!("Inserted state from Prometheus with stimulus: {:?}", stim.is_some());
-            } else {
-                warn!("Failed to retrieve all metrics from Prometheus");
+    /// Query parameters of a request line such as `GET /api/v1/query_range?a=b HTTP/1.1`.
+    fn query_params(request: &str) -> (String, HashMap<String, String>) {
+        let target = request.split_whitespace().nth(1).unwrap_or("");
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let params = query
+            .split('&')
+            .filter_map(|kv| kv.split_once('='))
+            .map(|(k, v)| (k.to_string(), urlencoding::decode(v).unwrap().into_owned()))
+            .collect();
+        (path.to_string(), params)
+    }
+
+    /// Answer range queries for series named `e`, `m`, `s`, `th`, `t` and `stim` with
+    /// one sample per step; `th` has no sample at 1000 s.
+    fn range_response(params: &HashMap<String, String>) -> Value {
+        let start: f64 = params["start"].parse().unwrap();
+        let end: f64 = params["end"].parse().unwrap();
+        let step: f64 = params["step"].parse().unwrap();
+        let query = params["query"].as_str();
+        let mut values = Vec::new();
+        let mut t = start;
+        while t <= end {
+            if !(query == "th" && t == 1000.0) {
+                values.push(serde_json::json!([t, format!("{}", t / 10_000.0)]));
             }
+            t += step;
+        }
+        let metric = match query {
+            "stim" => serde_json::json!({"id": "calm", "name": "Calm"}),
+            _ => serde_json::json!({}),
+        };
+        serde_json::json!({
+            "status": "success",
+            "data": {"resultType": "matrix", "result": [{"metric": metric, "values": values}]}
+        })
+    }
+
+    /// Answer instant queries with the value `time / 10000`; `stim` has no series.
+    ///
+    /// `all` returns every dimension of subjects `a` and `b` (whose values are 0.5
+    /// higher), except `theta` of `b`; `multi_*` returns one series per subject, and
+    /// `multi_stim` a stimulus for `b` only.
+    fn instant_response(params: &HashMap<String, String>) -> Value {
+        let t: f64 = params["time"].parse().unwrap();
+        let sample = |subject: &str| {
+            let offset = if subject == "b" { 0.5 } else { 0.0 };
+            serde_json::json!([t, format!("{}", t / 10_000.0 + offset)])
+        };
+        let result = match params["query"].as_str() {
+            "stim" => serde_json::json!([]),
+            "all" => DIMENSION_NAMES
+                .iter()
+                .flat_map(|d| ["a", "b"].map(|subject| (d, subject)))
+                .filter(|(d, subject)| !(**d == "theta" && *subject == "b"))
+                .map(|(d, subject)| serde_json::json!({"metric": {"subject": subject, "dimension": d}, "value": sample(subject)}))
+                .collect(),
+            "multi_stim" => serde_json::json!([{"metric": {"subject": "b", "id": "calm"}, "value": [t, "1"]}]),
+            q if q.starts_with("multi_") => ["a", "b"]
+                .iter()
+                .map(|subject| serde_json::json!({"metric": {"subject": subject}, "value": sample(subject)}))
+                .collect(),
+            _ => serde_json::json!([{"metric": {}, "value": [t, format!("{}", t / 10_000.0)]}]),
+        };
+        serde_json::json!({"status": "success", "data": {"resultType": "vector", "result": result}})
+    }
+
+    /// A mock Prometheus server. While `fail_s` is set, queries for `s` return 503.
+    async fn mock_prometheus(fail_s: Arc<AtomicBool>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let fail_s = fail_s.clone();
+                tokio::spawn(async move {
+                    let mut buf = vec![0u8; 8192];
+                    let n = socket.read(&mut buf).await.unwrap();
+                    let request = String::from_utf8_lossy(&buf[..n]).to_string();
+                    let (path, params) = query_params(&request);
+                    let (status, body) = match path.as_str() {
+                        _ if params["query"] == "s" && fail_s.load(Ordering::SeqCst) => {
+                            ("503 Service Unavailable", String::new())
+                        }
+                        "/api/v1/query_range" => ("200 OK", range_response(&params).to_string()),
+                        "/api/v1/query" => ("200 OK", instant_response(&params).to_string()),
+                        _ => ("404 Not Found", String::new()),
+                    };
+                    let response = format!(
+                        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        status,
+                        body.len(),
+                        body
+                    );
+                    socket.write_all(response.as_bytes()).await.unwrap();
+                });
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    fn config(server_url: String) -> PrometheusConfig {
+        PrometheusConfig {
+            server_url,
+            query_e: "e".into(),
+            query_m_prot: "m".into(),
+            query_s_bio: "s".into(),
+            query_theta: "th".into(),
+            query_t: "t".into(),
+            query_active_stimulus: "stim".into(),
+            query_stimulus_name: None,
+            mapping: SeriesMapping::default(),
+            poll_interval: Duration::from_secs(15),
+            range_step: Duration::from_secs(15),
+            backfill: Duration::from_secs(3600),
+            request_timeout: Duration::from_secs(2),
+            max_retries: 2,
+            retry_backoff: Duration::from_millis(1),
+            max_staleness: Duration::from_secs(60),
         }
     }
+
+    fn ingester(config: PrometheusConfig) -> PrometheusIngester {
+        PrometheusIngester::new(config)
+    }
+
+    #[tokio::test]
+    async fn test_backfill_aligns_dimensions_on_sample_timestamps() {
+        let ingester = ingester(config(mock_prometheus(Arc::default()).await));
+
+        // On a 15 s grid every dimension has every sample.
+        let start = Utc.timestamp_opt(960, 0).unwrap();
+        let end = Utc.timestamp_opt(1035, 0).unwrap();
+        let states = ingester.fetch_range(start, end).await.unwrap();
+        let times: Vec<i64> = states.iter().map(|s| s.timestamp.timestamp()).collect();
+        assert_eq!(times, vec![960, 975, 990, 1005, 1020, 1035]);
+        assert!((states[1].state.e - 0.0975).abs() < 1e-12);
+        assert_eq!(states[0].stimulus.as_ref().unwrap().stimulus_id, "calm");
+
+        // An unaligned start is moved to the next step, and a timestamp missing from one
+        // dimension yields no point.
+        let ingester_step_20 =
+            self::ingester(PrometheusConfig { range_step: Duration::from_secs(20), ..ingester.config.clone() });
+        let states = ingester_step_20
+            .fetch_range(Utc.timestamp_opt(965, 0).unwrap(), Utc.timestamp_opt(1040, 0).unwrap())
+            .await
+            .unwrap();
+        let times: Vec<i64> = states.iter().map(|s| s.timestamp.timestamp()).collect();
+        assert_eq!(times, vec![980, 1020, 1040]);
+    }
+
+    #[tokio::test]
+    async fn test_failed_startup_backfill_is_retried() {
+        let fail_s = Arc::new(AtomicBool::new(true));
+        let mut ingester = ingester(PrometheusConfig {
+            poll_interval: Duration::from_millis(20),
+            max_retries: 0,
+            ..config(mock_prometheus(fail_s.clone()).await)
+        });
+        let started = Utc::now();
+        let batch = tokio::spawn(async move { ingester.next_batch().await.unwrap() });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!batch.is_finished());
+
+        // Once Prometheus recovers, the whole backfill window arrives.
+        fail_s.store(false, Ordering::SeqCst);
+        let batch = batch.await.unwrap();
+        let first = match &batch[0] {
+            IngestEvent::State(s) => s.timestamp,
+            _ => unreachable!(),
+        };
+        assert!(first <= started - chrono::Duration::seconds(3600 - 15));
+        assert!(batch.len() >= 240);
+    }
+
+    #[tokio::test]
+    async fn test_failed_dimension_is_carried_forward_until_stale() {
+        let fail_s = Arc::new(AtomicBool::new(false));
+        let ingester = ingester(config(mock_prometheus(fail_s.clone()).await));
+        let at = |secs| Utc.timestamp_opt(secs, 0).unwrap();
+
+        let fresh = ingester.sample_at(at(1000)).await.remove(0);
+        assert!(fresh.quality.is_clean());
+
+        // S_bio keeps failing: its last value stands in, flagged, for up to a minute.
+        fail_s.store(true, Ordering::SeqCst);
+        let partial = ingester.sample_at(at(1030)).await.remove(0);
+        assert_eq!(partial.quality, QualityFlags::STALE_S_BIO);
+        assert_eq!(partial.state.s_bio, fresh.state.s_bio);
+        assert!((partial.state.e - 0.103).abs() < 1e-12);
+        assert!(ingester.sample_at(at(1070)).await.is_empty());
+
+        let health = ingester.health().snapshot();
+        assert_eq!(health.queries_failed, 2);
+        assert_eq!(health.retries, 4);
+        assert_eq!(health.samples_dropped, 1);
+    }
+
+    #[tokio::test]
+    async fn test_series_fan_out_per_subject() {
+        let server = mock_prometheus(Arc::default()).await;
+        let mapping = SeriesMapping { subject_label: Some("subject".into()), ..Default::default() };
+        let at = Utc.timestamp_opt(1000, 0).unwrap();
+
+        let per_dimension = ingester(PrometheusConfig {
+            query_e: "multi_e".into(),
+            query_m_prot: "multi_m".into(),
+            query_s_bio: "multi_s".into(),
+            query_theta: "multi_th".into(),
+            query_t: "multi_t".into(),
+            query_active_stimulus: "multi_stim".into(),
+            mapping: mapping.clone(),
+            ..config(server.clone())
+        });
+        let states = per_dimension.sample_at(at).await;
+        assert_eq!(states.len(), 2);
+        assert_eq!(states[0].subject_id.as_deref(), Some("a"));
+        assert!(states[0].stimulus.is_none());
+        assert_eq!(states[1].subject_id.as_deref(), Some("b"));
+        assert!((states[1].state.theta - 0.6).abs() < 1e-12);
+        assert_eq!(states[1].stimulus.as_ref().unwrap().stimulus_id, "calm");
+
+        // One combined query; subject b lacks theta and has no earlier value to carry.
+        let combined = ingester(PrometheusConfig {
+            mapping: SeriesMapping { combined_query: Some("all".into()), ..mapping },
+            ..config(server)
+        });
+        let states = combined.sample_at(at).await;
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].subject_id.as_deref(), Some("a"));
+        assert!((states[0].state.t - 0.1).abs() < 1e-12);
+        assert_eq!(combined.health().snapshot().samples_dropped, 1);
+    }
 }
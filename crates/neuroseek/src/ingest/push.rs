@@ -0,0 +1,424 @@
+//! Local HTTP endpoint for devices that push their samples instead of being scraped.
+//!
+//! A `PushSource` listens on `bind_addr` and accepts JSON batches at `POST /v1/samples`:
+//!
+//! ```text
+//! {
+//!   "states":   [{"timestamp":"2024-05-01T12:00:00Z","state":{"e":0.4,...},"subject_id":"s1"}],
+//!   "stimuli":  [{"timestamp":"2024-05-01T11:59:30Z","stimulus":{"stimulus_id":"calm",...},"subject_id":"s1"}],
+//!   "cravings": [{"timestamp":"2024-05-01T12:00:03Z","craving":0.7,"subject_id":"s1"}]
+//! }
+//! ```
+//!
+//! Any of the three lists may be omitted. States use the stream format of
+//! `ndjson_tail`. A stimulus event starts a stimulus for its subject, or stops it when
+//! `stimulus` is null; states of that subject that do not name a stimulus themselves
+//! are tagged with the one active at their timestamp. Events only apply to states
+//! pushed with or after them.
+//!
+//! A batch is validated as a whole before it is queued: coordinates must be finite and
+//! within `limits`, craving intensities and signal qualities within 0..1, and no
+//! timestamp more than `max_clock_skew` ahead of the observer's clock. The response is
+//!
+//! - `202 Accepted` with the number of events queued;
+//! - `400 Bad Request` if the body is not a valid batch;
+//! - `413 Payload Too Large` beyond `max_body_bytes` or `max_batch` events;
+//! - `422 Unprocessable Entity` naming the first out-of-range value;
+//! - `503 Service Unavailable` with `Retry-After` while the queue is full.
+//!
+//! Accepted batches wait in a queue of `queue_capacity` batches until the pipeline
+//! takes them. The queue is never grown: a full queue is pushed back to the device,
+//! which is expected to retry, rather than buffered without bound. Outcomes are counted
+//! in `PushStats`.
+
+use super::ndjson_tail::StreamRecord;
+use super::replay::event_timestamp;
+use super::source::{IngestEvent, StateSource};
+use crate::model::{StimulusMetadata, TaggedState};
+use async_trait::async_trait;
+use axum::body::Bytes;
+use axum::extract::rejection::BytesRejection;
+use axum::extract::{DefaultBodyLimit, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info};
+
+/// Path batches are posted to.
+pub const PUSH_PATH: &str = "/v1/samples";
+
+/// Seconds a device is asked to wait before retrying when the queue is full.
+const RETRY_AFTER_SEC: &str = "1";
+
+/// Configuration of the push endpoint.
+#[derive(Debug, Clone)]
+pub struct PushConfig {
+    pub bind_addr: SocketAddr,
+    /// Batches accepted but not yet taken by the pipeline.
+    pub queue_capacity: usize,
+    /// Most events (states, stimulus events and cravings together) in one batch.
+    pub max_batch: usize,
+    /// Largest accepted request body.
+    pub max_body_bytes: usize,
+    /// Accepted `(min, max)` per dimension, in `as_array` order.
+    pub limits: [(f64, f64); 5],
+    /// How far ahead of the local clock a timestamp may be.
+    pub max_clock_skew: Duration,
+}
+
+impl Default for PushConfig {
+    fn default() -> Self {
+        Self {
+            bind_addr: SocketAddr::from(([127, 0, 0, 1], 9470)),
+            queue_capacity: 64,
+            max_batch: 1000,
+            max_body_bytes: 1 << 20,
+            limits: [(0.0, 1.0), (0.0, 1.0), (0.0, 1.0), (0.0, 1.0), (-1.0, 1.0)],
+            max_clock_skew: Duration::from_secs(60),
+        }
+    }
+}
+
+/// A stimulus starting, or stopping if `stimulus` is None.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StimulusEvent {
+    pub timestamp: DateTime<Utc>,
+    pub stimulus: Option<StimulusMetadata>,
+    #[serde(default)]
+    pub subject_id: Option<String>,
+}
+
+/// A craving report.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CravingReport {
+    pub timestamp: DateTime<Utc>,
+    /// Intensity, 0 (none) to 1.
+    pub craving: f64,
+    #[serde(default)]
+    pub subject_id: Option<String>,
+}
+
+/// One request body.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PushBatch {
+    pub states: Vec<StreamRecord>,
+    pub stimuli: Vec<StimulusEvent>,
+    pub cravings: Vec<CravingReport>,
+}
+
+impl PushBatch {
+    fn len(&self) -> usize {
+        self.states.len() + self.stimuli.len() + self.cravings.len()
+    }
+
+    /// The first value outside what `config` accepts, described.
+    fn validate(&self, config: &PushConfig, now: DateTime<Utc>) -> Result<(), String> {
+        let latest = now + chrono::Duration::from_std(config.max_clock_skew).unwrap_or(chrono::Duration::zero());
+        let check_time = |what: String, timestamp: DateTime<Utc>| {
+            if timestamp > latest {
+                Err(format!("{}: timestamp {} is in the future", what, timestamp.to_rfc3339()))
+            } else {
+                Ok(())
+            }
+        };
+        let check_unit = |what: String, value: f64| {
+            if (0.0..=1.0).contains(&value) {
+                Ok(())
+            } else {
+                Err(format!("{}: {} outside [0, 1]", what, value))
+            }
+        };
+        const NAMES: [&str; 5] = ["e", "m_prot", "s_bio", "theta", "t"];
+        for (i, record) in self.states.iter().enumerate() {
+            check_time(format!("states[{}]", i), record.timestamp)?;
+            for ((value, (min, max)), name) in record.state.as_array().into_iter().zip(config.limits).zip(NAMES) {
+                if !value.is_finite() || value < min || value > max {
+                    return Err(format!("states[{}]: {} = {} outside [{}, {}]", i, name, value, min, max));
+                }
+            }
+            if let Some(quality) = record.signal_quality {
+                check_unit(format!("states[{}].signal_quality", i), quality)?;
+            }
+        }
+        for (i, event) in self.stimuli.iter().enumerate() {
+            check_time(format!("stimuli[{}]", i), event.timestamp)?;
+            if event.stimulus.as_ref().is_some_and(|s| s.stimulus_id.is_empty()) {
+                return Err(format!("stimuli[{}]: empty stimulus_id", i));
+            }
+        }
+        for (i, report) in self.cravings.iter().enumerate() {
+            check_time(format!("cravings[{}]", i), report.timestamp)?;
+            check_unit(format!("cravings[{}].craving", i), report.craving)?;
+        }
+        Ok(())
+    }
+}
+
+/// Running counters of the endpoint.
+#[derive(Debug, Default)]
+pub struct PushStats {
+    batches_accepted: AtomicU64,
+    events_accepted: AtomicU64,
+    malformed: AtomicU64,
+    too_large: AtomicU64,
+    invalid: AtomicU64,
+    busy: AtomicU64,
+}
+
+/// Point-in-time copy of `PushStats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct PushSnapshot {
+    pub batches_accepted: u64,
+    pub events_accepted: u64,
+    /// Bodies that were not a valid batch.
+    pub malformed: u64,
+    /// Batches over the body size or event limit.
+    pub too_large: u64,
+    /// Batches with an out-of-range value.
+    pub invalid: u64,
+    /// Batches turned away because the queue was full.
+    pub busy: u64,
+}
+
+impl PushStats {
+    pub fn snapshot(&self) -> PushSnapshot {
+        PushSnapshot {
+            batches_accepted: self.batches_accepted.load(Ordering::Relaxed),
+            events_accepted: self.events_accepted.load(Ordering::Relaxed),
+            malformed: self.malformed.load(Ordering::Relaxed),
+            too_large: self.too_large.load(Ordering::Relaxed),
+            invalid: self.invalid.load(Ordering::Relaxed),
+            busy: self.busy.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Shared state of the request handler.
+struct Endpoint {
+    config: PushConfig,
+    sender: mpsc::Sender<PushBatch>,
+    stats: Arc<PushStats>,
+}
+
+fn reject(status: StatusCode, counter: &AtomicU64, message: String) -> Response {
+    counter.fetch_add(1, Ordering::Relaxed);
+    debug!("Rejected pushed batch ({}): {}", status, message);
+    (status, Json(serde_json::json!({ "error": message }))).into_response()
+}
+
+async fn push(State(endpoint): State<Arc<Endpoint>>, body: Result<Bytes, BytesRejection>) -> Response {
+    let stats = &endpoint.stats;
+    let body = match body {
+        Ok(body) => body,
+        Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+            return reject(e.status(), &stats.too_large, e.body_text())
+        }
+        Err(e) => return reject(e.status(), &stats.malformed, e.body_text()),
+    };
+    let batch: PushBatch = match serde_json::from_slice(&body) {
+        Ok(batch) => batch,
+        Err(e) => return reject(StatusCode::BAD_REQUEST, &stats.malformed, e.to_string()),
+    };
+    let events = batch.len();
+    if events > endpoint.config.max_batch {
+        let message = format!("{} events, at most {} per batch", events, endpoint.config.max_batch);
+        return reject(StatusCode::PAYLOAD_TOO_LARGE, &stats.too_large, message);
+    }
+    if let Err(message) = batch.validate(&endpoint.config, Utc::now()) {
+        return reject(StatusCode::UNPROCESSABLE_ENTITY, &stats.invalid, message);
+    }
+    match endpoint.sender.try_send(batch) {
+        Ok(()) => {
+            stats.batches_accepted.fetch_add(1, Ordering::Relaxed);
+            stats.events_accepted.fetch_add(events as u64, Ordering::Relaxed);
+            (StatusCode::ACCEPTED, Json(serde_json::json!({ "accepted": events }))).into_response()
+        }
+        Err(TrySendError::Full(_) | TrySendError::Closed(_)) => {
+            let mut response = reject(StatusCode::SERVICE_UNAVAILABLE, &stats.busy, "queue full".into());
+            response.headers_mut().insert(header::RETRY_AFTER, RETRY_AFTER_SEC.parse().unwrap());
+            response
+        }
+    }
+}
+
+/// Source of the batches pushed to the local endpoint.
+pub struct PushSource {
+    addr: SocketAddr,
+    receiver: mpsc::Receiver<PushBatch>,
+    stats: Arc<PushStats>,
+    /// Stimulus active per subject, as set by the latest stimulus event.
+    active: HashMap<Option<String>, StimulusMetadata>,
+    server: JoinHandle<()>,
+}
+
+impl PushSource {
+    /// Bind the endpoint and start serving it.
+    pub async fn bind(config: PushConfig) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(config.bind_addr).await?;
+        let addr = listener.local_addr()?;
+        let (sender, receiver) = mpsc::channel(config.queue_capacity.max(1));
+        let stats = Arc::new(PushStats::default());
+        let body_limit = config.max_body_bytes;
+        let endpoint = Arc::new(Endpoint { config, sender, stats: stats.clone() });
+        let app = Router::new()
+            .route(PUSH_PATH, post(push))
+            .layer(DefaultBodyLimit::max(body_limit))
+            .with_state(endpoint);
+        let server = tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, app).await {
+                error!("Push endpoint stopped: {}", e);
+            }
+        });
+        info!("Accepting pushed samples at http://{}{}", addr, PUSH_PATH);
+        Ok(Self { addr, receiver, stats, active: HashMap::new(), server })
+    }
+
+    /// Address actually bound (useful with port 0).
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Shared handle to the endpoint's counters.
+    pub fn stats(&self) -> Arc<PushStats> {
+        self.stats.clone()
+    }
+
+    /// Turn a batch into events in timestamp order, tagging states with the stimulus
+    /// active at their timestamp.
+    fn events(&mut self, batch: PushBatch) -> Vec<IngestEvent> {
+        let PushBatch { mut states, mut stimuli, cravings } = batch;
+        states.sort_by_key(|record| record.timestamp);
+        stimuli.sort_by_key(|event| event.timestamp);
+        let mut stimuli = stimuli.into_iter().peekable();
+        let mut events = Vec::with_capacity(states.len() + cravings.len());
+        for mut record in states {
+            while let Some(event) = stimuli.next_if(|event| event.timestamp <= record.timestamp) {
+                self.apply(event);
+            }
+            if record.stimulus.is_none() {
+                record.stimulus = self.active.get(&record.subject_id).cloned();
+            }
+            events.push(TaggedState::from(record).into());
+        }
+        stimuli.for_each(|event| self.apply(event));
+        events.extend(cravings.into_iter().map(|report| IngestEvent::Craving {
+            timestamp: report.timestamp,
+            intensity: report.craving,
+            state: None,
+            subject_id: report.subject_id,
+        }));
+        // Stable, so a craving reported at a state's instant follows the state.
+        events.sort_by_key(event_timestamp);
+        events
+    }
+
+    fn apply(&mut self, event: StimulusEvent) {
+        match event.stimulus {
+            Some(stimulus) => self.active.insert(event.subject_id, stimulus),
+            None => self.active.remove(&event.subject_id),
+        };
+    }
+}
+
+impl Drop for PushSource {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+#[async_trait]
+impl StateSource for PushSource {
+    fn name(&self) -> String {
+        format!("push endpoint {}", self.addr)
+    }
+
+    async fn next_batch(&mut self) -> Option<Vec<IngestEvent>> {
+        // The sender lives in the server task, so the queue only closes if it stopped.
+        let batch = self.receiver.recv().await?;
+        Some(self.events(batch))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_accepts_validates_and_pushes_back() {
+        let config = PushConfig {
+            bind_addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+            queue_capacity: 1,
+            max_batch: 5,
+            ..Default::default()
+        };
+        let mut source = PushSource::bind(config).await.unwrap();
+        let url = format!("http://{}{}", source.local_addr(), PUSH_PATH);
+        let client = reqwest::Client::new();
+        let state = json!({"e": 0.4, "m_prot": 0.2, "s_bio": 0.3, "theta": 0.5, "t": 0.0});
+        let post = |body: String| client.post(&url).header("content-type", "application/json").body(body).send();
+
+        let batch = json!({
+            "states": [
+                {"timestamp": "2024-05-01T12:00:10Z", "state": state, "subject_id": "s1"},
+                {"timestamp": "2024-05-01T11:59:50Z", "state": state, "subject_id": "s1"},
+                {"timestamp": "2024-05-01T12:30:00Z", "state": state, "subject_id": "s1"},
+            ],
+            "stimuli": [{
+                "timestamp": "2024-05-01T12:00:00Z",
+                "stimulus": {"stimulus_id": "calm", "stimulus_name": "Calm", "audio_params": null},
+                "subject_id": "s1",
+            }],
+            "cravings": [{"timestamp": "2024-05-01T12:00:12Z", "craving": 0.7, "subject_id": "s1"}],
+        });
+        let response = post(batch.to_string()).await.unwrap();
+        assert_eq!(response.status(), 202);
+        // The queue holds one batch; the next is pushed back until it is drained.
+        let response = post(json!({"cravings": []}).to_string()).await.unwrap();
+        assert_eq!(response.status(), 503);
+        assert_eq!(response.headers()["retry-after"], "1");
+
+        assert_eq!(post("{\"states\": [".into()).await.unwrap().status(), 400);
+        let mut out_of_range = state.clone();
+        out_of_range["e"] = json!(1.5);
+        let invalid = json!({"states": [{"timestamp": "2024-05-01T12:00:00Z", "state": out_of_range}]});
+        let response = post(invalid.to_string()).await.unwrap();
+        assert_eq!(response.status(), 422);
+        assert!(response.text().await.unwrap().contains("states[0]: e = 1.5"));
+        let record = json!({"timestamp": "2024-05-01T12:00:00Z", "state": state});
+        let oversized = json!({"states": vec![record; 6]});
+        assert_eq!(post(oversized.to_string()).await.unwrap().status(), 413);
+
+        let events = source.next_batch().await.unwrap();
+        assert_eq!(events.len(), 4);
+        let stimuli: Vec<Option<String>> = events
+            .iter()
+            .filter_map(|event| match event {
+                IngestEvent::State(s) => Some(s.stimulus.as_ref().map(|s| s.stimulus_id.clone())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(stimuli, vec![None, Some("calm".to_string()), Some("calm".to_string())]);
+        // The craving lands between the states around it, not after the batch.
+        assert!(matches!(events[2], IngestEvent::Craving { intensity, .. } if intensity == 0.7));
+        assert!(matches!(&events[3], IngestEvent::State(s) if s.timestamp.to_rfc3339() == "2024-05-01T12:30:00+00:00"));
+
+        let stats = source.stats().snapshot();
+        assert_eq!((stats.batches_accepted, stats.events_accepted), (1, 5));
+        assert_eq!((stats.busy, stats.malformed, stats.invalid, stats.too_large), (1, 1, 1, 1));
+    }
+}
@@ -0,0 +1,304 @@
+//! Bounded queue between the ingest pipeline and the clusterer.
+//!
+//! The pipeline pushes filtered events; a clustering worker pops them in batches and
+//! inserts each batch under a single lock of the clusterer (`insert_batch`). The queue
+//! holds at most `capacity` events. When it is full, the `OverflowPolicy` decides:
+//!
+//! - `block`: the pipeline waits, which in turn makes the sources wait;
+//! - `drop_oldest`: the oldest queued state makes room;
+//! - `downsample`: every second queued state is dropped, halving the resolution of
+//!   the backlog rather than losing its oldest part.
+//!
+//! Craving reports are never dropped: with no state left to drop, a push waits as under
+//! `block` until the consumer makes room. Depth, drops and the time
+//! events spend queued (lag) are counted in `QueueStats`.
+
+use super::source::IngestEvent;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::Notify;
+
+/// What to do with a new event when the queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    Block,
+    DropOldest,
+    Downsample,
+}
+
+/// Configuration of the queue.
+#[derive(Debug, Clone, Copy)]
+pub struct QueueConfig {
+    /// Most events queued at once.
+    pub capacity: usize,
+    pub policy: OverflowPolicy,
+    /// Most events inserted into the clusterer under one lock.
+    pub max_batch: usize,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 4096,
+            policy: OverflowPolicy::Block,
+            max_batch: 512,
+        }
+    }
+}
+
+/// Running counters of the queue.
+#[derive(Debug, Default)]
+pub struct QueueStats {
+    depth: AtomicU64,
+    max_depth: AtomicU64,
+    enqueued: AtomicU64,
+    dropped: AtomicU64,
+    downsampled: AtomicU64,
+    blocked: AtomicU64,
+    lag_ms: AtomicU64,
+    max_lag_ms: AtomicU64,
+}
+
+/// Point-in-time copy of `QueueStats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct QueueSnapshot {
+    /// Events currently queued.
+    pub depth: u64,
+    pub max_depth: u64,
+    pub enqueued: u64,
+    /// States dropped by `drop_oldest`.
+    pub dropped: u64,
+    /// States dropped by `downsample`.
+    pub downsampled: u64,
+    /// Pushes that had to wait under `block`.
+    pub blocked: u64,
+    /// Time the oldest event of the latest popped batch spent queued.
+    pub lag_ms: u64,
+    pub max_lag_ms: u64,
+}
+
+impl QueueStats {
+    pub fn snapshot(&self) -> QueueSnapshot {
+        QueueSnapshot {
+            depth: self.depth.load(Ordering::Relaxed),
+            max_depth: self.max_depth.load(Ordering::Relaxed),
+            enqueued: self.enqueued.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            downsampled: self.downsampled.load(Ordering::Relaxed),
+            blocked: self.blocked.load(Ordering::Relaxed),
+            lag_ms: self.lag_ms.load(Ordering::Relaxed),
+            max_lag_ms: self.max_lag_ms.load(Ordering::Relaxed),
+        }
+    }
+
+    fn set_depth(&self, depth: usize) {
+        self.depth.store(depth as u64, Ordering::Relaxed);
+        self.max_depth.fetch_max(depth as u64, Ordering::Relaxed);
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    events: VecDeque<(Instant, IngestEvent)>,
+    closed: bool,
+}
+
+/// Bounded multi-policy queue with one producer and one consumer.
+pub struct IngestQueue {
+    config: QueueConfig,
+    inner: Mutex<Inner>,
+    not_empty: Notify,
+    not_full: Notify,
+    stats: Arc<QueueStats>,
+}
+
+impl IngestQueue {
+    pub fn new(config: QueueConfig) -> Self {
+        Self {
+            config: QueueConfig { capacity: config.capacity.max(1), max_batch: config.max_batch.max(1), ..config },
+            inner: Mutex::new(Inner::default()),
+            not_empty: Notify::new(),
+            not_full: Notify::new(),
+            stats: Arc::new(QueueStats::default()),
+        }
+    }
+
+    /// Shared handle to the counters.
+    pub fn stats(&self) -> Arc<QueueStats> {
+        self.stats.clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Queue events in order, applying the overflow policy when full.
+    pub async fn push(&self, events: Vec<IngestEvent>) {
+        for event in events {
+            let mut event = Some(event);
+            while let Some(pending) = event.take() {
+                {
+                    let mut inner = self.lock();
+                    if inner.events.len() >= self.config.capacity && !self.make_room(&mut inner.events) {
+                        event = Some(pending);
+                    } else {
+                        inner.events.push_back((Instant::now(), pending));
+                        self.stats.enqueued.fetch_add(1, Ordering::Relaxed);
+                        self.stats.set_depth(inner.events.len());
+                    }
+                }
+                if event.is_some() {
+                    self.stats.blocked.fetch_add(1, Ordering::Relaxed);
+                    self.not_full.notified().await;
+                }
+            }
+            self.not_empty.notify_one();
+        }
+    }
+
+    /// Free at least one slot according to the policy by dropping states; false if the
+    /// push must wait.
+    fn make_room(&self, events: &mut VecDeque<(Instant, IngestEvent)>) -> bool {
+        let is_state = |(_, event): &(Instant, IngestEvent)| matches!(event, IngestEvent::State(_));
+        match self.config.policy {
+            OverflowPolicy::Block => false,
+            OverflowPolicy::DropOldest => {
+                let Some(oldest) = events.iter().position(is_state) else { return false };
+                events.remove(oldest);
+                self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            OverflowPolicy::Downsample => {
+                let before = events.len();
+                let mut states = 0;
+                events.retain(|queued| {
+                    if !is_state(queued) {
+                        return true;
+                    }
+                    states += 1;
+                    states % 2 == 1
+                });
+                if events.len() == before {
+                    // Too few states to thin; fall back to the oldest state.
+                    let Some(oldest) = events.iter().position(is_state) else { return false };
+                    events.remove(oldest);
+                }
+                self.stats.downsampled.fetch_add((before - events.len()) as u64, Ordering::Relaxed);
+                true
+            }
+        }
+    }
+
+    /// Wait for events and take up to `max_batch` of them, in order. Returns None once
+    /// the queue is closed and drained.
+    pub async fn pop_batch(&self) -> Option<Vec<IngestEvent>> {
+        loop {
+            {
+                let mut inner = self.lock();
+                if !inner.events.is_empty() {
+                    let n = inner.events.len().min(self.config.max_batch);
+                    let batch: Vec<(Instant, IngestEvent)> = inner.events.drain(..n).collect();
+                    self.stats.set_depth(inner.events.len());
+                    drop(inner);
+                    let lag_ms = batch[0].0.elapsed().as_millis() as u64;
+                    self.stats.lag_ms.store(lag_ms, Ordering::Relaxed);
+                    self.stats.max_lag_ms.fetch_max(lag_ms, Ordering::Relaxed);
+                    self.not_full.notify_one();
+                    return Some(batch.into_iter().map(|(_, event)| event).collect());
+                }
+                if inner.closed {
+                    return None;
+                }
+            }
+            self.not_empty.notified().await;
+        }
+    }
+
+    /// No more events will be pushed; `pop_batch` drains what is left, then ends.
+    pub fn close(&self) {
+        self.lock().closed = true;
+        self.not_empty.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{BiophysicalState, TaggedState};
+    use chrono::Utc;
+    use std::time::Duration;
+
+    fn states(values: std::ops::Range<usize>) -> Vec<IngestEvent> {
+        values
+            .map(|i| TaggedState::new(BiophysicalState::new(i as f64, 0.0, 0.0, 0.0, 0.0), Utc::now(), None).into())
+            .collect()
+    }
+
+    fn values(events: &[IngestEvent]) -> Vec<f64> {
+        events
+            .iter()
+            .map(|event| match event {
+                IngestEvent::State(s) => s.state.e,
+                IngestEvent::Craving { intensity, .. } => -intensity,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_overflow_policies() {
+        let config = QueueConfig { capacity: 4, policy: OverflowPolicy::DropOldest, max_batch: 10 };
+        let queue = IngestQueue::new(config);
+        let craving = IngestEvent::Craving { timestamp: Utc::now(), intensity: 0.5, state: None, subject_id: None };
+        queue.push(vec![craving]).await;
+        queue.push(states(1..5)).await;
+        queue.close();
+        // The craving survives; state 1 made room for state 4.
+        assert_eq!(values(&queue.pop_batch().await.unwrap()), vec![-0.5, 2.0, 3.0, 4.0]);
+        assert!(queue.pop_batch().await.is_none());
+        assert_eq!(queue.stats().snapshot().dropped, 1);
+
+        let queue = IngestQueue::new(QueueConfig { policy: OverflowPolicy::Downsample, ..config });
+        queue.push(states(0..5)).await;
+        queue.close();
+        assert_eq!(values(&queue.pop_batch().await.unwrap()), vec![0.0, 2.0, 4.0]);
+        assert_eq!(queue.stats().snapshot().downsampled, 2);
+
+        let queue = Arc::new(IngestQueue::new(QueueConfig { policy: OverflowPolicy::Block, max_batch: 3, ..config }));
+        let producer = {
+            let queue = queue.clone();
+            tokio::spawn(async move { queue.push(states(0..6)).await })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!producer.is_finished());
+        assert_eq!(queue.stats().snapshot().depth, 4);
+        assert_eq!(values(&queue.pop_batch().await.unwrap()), vec![0.0, 1.0, 2.0]);
+        producer.await.unwrap();
+        queue.close();
+        assert_eq!(values(&queue.pop_batch().await.unwrap()), vec![3.0, 4.0, 5.0]);
+        let stats = queue.stats().snapshot();
+        assert_eq!((stats.enqueued, stats.max_depth, stats.depth), (6, 4, 0));
+
+        // A queue full of cravings makes every policy wait rather than drop one.
+        for policy in [OverflowPolicy::DropOldest, OverflowPolicy::Downsample] {
+            let queue = Arc::new(IngestQueue::new(QueueConfig { capacity: 2, policy, max_batch: 10 }));
+            let craving =
+                |intensity| IngestEvent::Craving { timestamp: Utc::now(), intensity, state: None, subject_id: None };
+            let producer = {
+                let queue = queue.clone();
+                tokio::spawn(async move { queue.push(vec![craving(0.1), craving(0.2), craving(0.3)]).await })
+            };
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            assert!(!producer.is_finished());
+            assert_eq!(values(&queue.pop_batch().await.unwrap()), vec![-0.1, -0.2]);
+            producer.await.unwrap();
+            queue.close();
+            assert_eq!(values(&queue.pop_batch().await.unwrap()), vec![-0.3]);
+            let stats = queue.stats().snapshot();
+            assert_eq!((stats.dropped, stats.downsampled), (0, 0));
+        }
+    }
+}
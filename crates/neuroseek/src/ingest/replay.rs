@@ -0,0 +1,377 @@
+//! Replay of recorded sessions, for tuning `ClustererConfig` on real data.
+//!
+//! A `ReplaySource` reads a recording of timestamped states, craving reports and
+//! stimulus tags and yields them through the normal ingest path. Events keep their
+//! recorded timestamps, so maintenance, decay and craving association behave as they
+//! did live; only the pacing is chosen: real time, N times faster, or as fast as
+//! possible.
+//!
+//! Two formats are read. NDJSON (any extension but `.csv`) has one record per line,
+//! either a state in the stream format of `ndjson_tail` or a craving report:
+//!
+//! ```text
+//! {"timestamp":"2024-05-01T12:00:00Z","state":{"e":0.4,...},"stimulus":{...},"subject_id":"s1"}
+//! {"timestamp":"2024-05-01T12:00:03Z","craving":0.7,"subject_id":"s1"}
+//! ```
+//!
+//! CSV has a header with `timestamp` and the columns `e,m_prot,s_bio,theta,t`,
+//! `craving`, `stimulus_id`, `stimulus_name`, `subject_id` and `signal_quality`, any
+//! of which may be empty. A row with all five coordinates is a state; a row with a
+//! `craving` value is also (or only) a craving report.
+//!
+//! A recording can be fused with theta derived from an EEG recording of the same
+//! session (see `edf`): `ReplaySource::open_with_theta` takes each state's theta from
+//! the EEG wherever it covers the state's timestamp, so CSV rows may leave the `theta`
+//! column empty. Rows without theta from either source carry no state.
+
+use super::edf::ThetaSeries;
+use super::ndjson_tail::StreamRecord;
+use super::source::{IngestEvent, StateSource};
+use crate::model::{BiophysicalState, StimulusMetadata, TaggedState};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::time::{self, Instant};
+use tracing::info;
+
+/// Largest batch yielded at once when replaying as fast as possible.
+const MAX_BATCH: usize = 1024;
+
+/// Errors raised while loading a recording.
+#[derive(Debug, thiserror::Error)]
+pub enum ReplayError {
+    #[error("Failed to read recording: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Recording line {line} is not a valid record: {source}")]
+    Json { line: usize, source: serde_json::Error },
+    #[error("Recording is not valid CSV: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("Replay speed factor must be positive and finite, got {0}")]
+    Speed(f64),
+}
+
+/// How fast to replay.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// Recorded time divided by this factor (1.0 is real time); positive and finite.
+    Factor(f64),
+    /// No waiting between events.
+    Unlimited,
+}
+
+/// A craving report in an NDJSON recording.
+#[derive(Debug, Deserialize)]
+struct CravingRecord {
+    timestamp: DateTime<Utc>,
+    craving: f64,
+    #[serde(default)]
+    subject_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum JsonRecord {
+    State(StreamRecord),
+    Craving(CravingRecord),
+}
+
+/// A row of a CSV recording.
+#[derive(Debug, Deserialize)]
+struct CsvRecord {
+    timestamp: DateTime<Utc>,
+    e: Option<f64>,
+    m_prot: Option<f64>,
+    s_bio: Option<f64>,
+    theta: Option<f64>,
+    t: Option<f64>,
+    craving: Option<f64>,
+    stimulus_id: Option<String>,
+    stimulus_name: Option<String>,
+    subject_id: Option<String>,
+    signal_quality: Option<f64>,
+}
+
+impl CsvRecord {
+    fn into_events(self, eeg: Option<&ThetaSeries>) -> Vec<IngestEvent> {
+        let mut events = Vec::new();
+        let theta = eeg.and_then(|series| series.at(self.timestamp)).or(self.theta);
+        let state = match (self.e, self.m_prot, self.s_bio, theta, self.t) {
+            (Some(e), Some(m_prot), Some(s_bio), Some(theta), Some(t)) => {
+                Some(BiophysicalState::new(e, m_prot, s_bio, theta, t))
+            }
+            _ => None,
+        };
+        if let Some(state) = state {
+            let stimulus = self.stimulus_id.filter(|id| !id.is_empty()).map(|id| StimulusMetadata {
+                stimulus_name: self.stimulus_name.clone().unwrap_or_else(|| id.clone()),
+                stimulus_id: id,
+                audio_params: None,
+            });
+            events.push(
+                TaggedState::new(state, self.timestamp, stimulus)
+                    .with_subject(self.subject_id.clone(), None)
+                    .with_signal_quality(self.signal_quality)
+                    .into(),
+            );
+        }
+        if let Some(intensity) = self.craving {
+            events.push(IngestEvent::Craving {
+                timestamp: self.timestamp,
+                intensity,
+                state,
+                subject_id: self.subject_id,
+            });
+        }
+        events
+    }
+}
+
+pub(super) fn event_timestamp(event: &IngestEvent) -> DateTime<Utc> {
+    match event {
+        IngestEvent::State(state) => state.timestamp,
+        IngestEvent::Craving { timestamp, .. } => *timestamp,
+    }
+}
+
+fn load_ndjson(path: &Path, eeg: Option<&ThetaSeries>) -> Result<Vec<IngestEvent>, ReplayError> {
+    let mut events = Vec::new();
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line).map_err(|source| ReplayError::Json { line: i + 1, source })?;
+        events.push(match record {
+            JsonRecord::State(mut record) => {
+                if let Some(theta) = eeg.and_then(|series| series.at(record.timestamp)) {
+                    record.state.theta = theta;
+                }
+                TaggedState::from(record).into()
+            }
+            JsonRecord::Craving(c) => IngestEvent::Craving {
+                timestamp: c.timestamp,
+                intensity: c.craving,
+                state: None,
+                subject_id: c.subject_id,
+            },
+        });
+    }
+    Ok(events)
+}
+
+fn load_csv(path: &Path, eeg: Option<&ThetaSeries>) -> Result<Vec<IngestEvent>, ReplayError> {
+    let mut reader = csv::Reader::from_path(path)?;
+    let mut events = Vec::new();
+    for row in reader.deserialize::<CsvRecord>() {
+        events.extend(row?.into_events(eeg));
+    }
+    Ok(events)
+}
+
+/// A `StateSource` replaying a recording.
+pub struct ReplaySource {
+    name: String,
+    events: std::vec::IntoIter<IngestEvent>,
+    pending: Option<IngestEvent>,
+    speed: ReplaySpeed,
+    /// Wall-clock instant and recorded time of the first event.
+    anchor: Option<(Instant, DateTime<Utc>)>,
+}
+
+impl ReplaySource {
+    /// Load a recording. Events are ordered by timestamp (stably, so events recorded
+    /// at the same instant keep their order).
+    pub fn open(path: impl Into<PathBuf>, speed: ReplaySpeed) -> Result<Self, ReplayError> {
+        Self::load(path.into(), speed, None)
+    }
+
+    /// Load a recording, taking theta from an EEG-derived series where it has a value.
+    pub fn open_with_theta(
+        path: impl Into<PathBuf>,
+        speed: ReplaySpeed,
+        theta: &ThetaSeries,
+    ) -> Result<Self, ReplayError> {
+        Self::load(path.into(), speed, Some(theta))
+    }
+
+    fn load(path: PathBuf, speed: ReplaySpeed, eeg: Option<&ThetaSeries>) -> Result<Self, ReplayError> {
+        let events = match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => load_csv(&path, eeg)?,
+            _ => load_ndjson(&path, eeg)?,
+        };
+        info!("Loaded {} events from {}", events.len(), path.display());
+        Self::from_events(format!("replay {}", path.display()), events, speed)
+    }
+
+    /// Replay events obtained elsewhere (e.g. imported from a wearable export).
+    pub fn from_events(
+        name: impl Into<String>,
+        mut events: Vec<IngestEvent>,
+        speed: ReplaySpeed,
+    ) -> Result<Self, ReplayError> {
+        if let ReplaySpeed::Factor(factor) = speed {
+            if !(factor.is_finite() && factor > 0.0) {
+                return Err(ReplayError::Speed(factor));
+            }
+        }
+        events.sort_by_key(event_timestamp);
+        Ok(Self {
+            name: name.into(),
+            events: events.into_iter(),
+            pending: None,
+            speed,
+            anchor: None,
+        })
+    }
+
+    /// Wait until an event recorded at `timestamp` is due.
+    async fn pace(&mut self, timestamp: DateTime<Utc>) {
+        let ReplaySpeed::Factor(factor) = self.speed else { return };
+        let (start, first) = *self.anchor.get_or_insert((Instant::now(), timestamp));
+        let recorded = (timestamp - first).to_std().unwrap_or_default();
+        // A very slow factor can put an event beyond any representable instant.
+        let due = Duration::try_from_secs_f64(recorded.as_secs_f64() / factor)
+            .ok()
+            .and_then(|delay| start.checked_add(delay));
+        match due {
+            Some(due) => time::sleep_until(due).await,
+            None => std::future::pending().await,
+        }
+    }
+}
+
+#[async_trait]
+impl StateSource for ReplaySource {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    async fn next_batch(&mut self) -> Option<Vec<IngestEvent>> {
+        let first = self.pending.take().or_else(|| self.events.next())?;
+        let timestamp = event_timestamp(&first);
+        self.pace(timestamp).await;
+
+        // Everything due at the same time goes together; without pacing, everything
+        // up to the batch limit.
+        let mut batch = vec![first];
+        while batch.len() < MAX_BATCH {
+            let Some(event) = self.events.next() else { break };
+            if self.speed != ReplaySpeed::Unlimited && event_timestamp(&event) != timestamp {
+                self.pending = Some(event);
+                break;
+            }
+            batch.push(event);
+        }
+        Some(batch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recording(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("neuroseek-replay-{}-{}", uuid::Uuid::new_v4(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    async fn drain(mut source: ReplaySource) -> Vec<IngestEvent> {
+        let mut events = Vec::new();
+        while let Some(batch) = source.next_batch().await {
+            events.extend(batch);
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn test_replays_both_formats_in_timestamp_order() {
+        let csv = recording(
+            "session.csv",
+            "timestamp,e,m_prot,s_bio,theta,t,craving,stimulus_id,stimulus_name,subject_id\n\
+             2024-05-01T12:00:02Z,0.5,0.1,0.2,0.3,0.0,0.7,calm,Calm,s1\n\
+             2024-05-01T12:00:00Z,0.4,0.1,0.2,0.3,0.0,,,,s1\n",
+        );
+        let events = drain(ReplaySource::open(&csv, ReplaySpeed::Unlimited).unwrap()).await;
+        assert_eq!(events.len(), 3);
+        match (&events[0], &events[1], &events[2]) {
+            (IngestEvent::State(a), IngestEvent::State(b), IngestEvent::Craving { intensity, state, .. }) => {
+                assert_eq!(a.state.e, 0.4);
+                assert!(a.stimulus.is_none());
+                assert_eq!(b.stimulus.as_ref().unwrap().stimulus_id, "calm");
+                assert_eq!(b.subject_id.as_deref(), Some("s1"));
+                assert_eq!(*intensity, 0.7);
+                assert_eq!(state.unwrap().e, 0.5);
+            }
+            other => panic!("unexpected events {:?}", other),
+        }
+
+        let ndjson = recording(
+            "session.ndjson",
+            r#"{"timestamp":"2024-05-01T12:00:00Z","state":{"e":0.4,"m_prot":0.1,"s_bio":0.2,"theta":0.3,"t":0.0}}
+{"timestamp":"2024-05-01T12:00:01Z","craving":0.6}
+"#,
+        );
+        // One recorded second at 20x takes 50 ms.
+        let started = Instant::now();
+        let events = drain(ReplaySource::open(&ndjson, ReplaySpeed::Factor(20.0)).unwrap()).await;
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert!(matches!(events[1], IngestEvent::Craving { intensity, state: None, .. } if intensity == 0.6));
+
+        std::fs::remove_file(csv).unwrap();
+        std::fs::remove_file(ndjson).unwrap();
+
+        for factor in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                ReplaySource::from_events("bad", Vec::new(), ReplaySpeed::Factor(factor)),
+                Err(ReplayError::Speed(_))
+            ));
+        }
+        // A tiny factor is valid; its second event is simply never due.
+        let events = vec![
+            IngestEvent::Craving { timestamp: Utc::now(), intensity: 0.1, state: None, subject_id: None },
+            IngestEvent::Craving {
+                timestamp: Utc::now() + chrono::Duration::seconds(1),
+                intensity: 0.2,
+                state: None,
+                subject_id: None,
+            },
+        ];
+        let mut source = ReplaySource::from_events("slow", events, ReplaySpeed::Factor(1e-300)).unwrap();
+        assert_eq!(source.next_batch().await.unwrap().len(), 1);
+        assert!(time::timeout(Duration::from_millis(20), source.next_batch()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_theta_fused_from_eeg() {
+        use crate::ingest::edf::{tests as edf_tests, theta_series, EdfRecording};
+
+        // The EEG covers 12:00:00-12:00:08 and yields theta 0.5 from 12:00:04 on.
+        let eeg = EdfRecording::parse(&edf_tests::synthetic(false, 8)).unwrap();
+        let series = theta_series(&eeg, &edf_tests::config()).unwrap();
+        let csv = recording(
+            "fused.csv",
+            "timestamp,e,m_prot,s_bio,theta,t,craving,stimulus_id,stimulus_name,subject_id\n\
+             2024-05-01T12:00:05Z,0.5,0.1,0.2,,0.0,,,,\n\
+             2024-05-01T12:00:06Z,0.5,0.1,0.2,0.9,0.0,,,,\n\
+             2024-05-01T12:01:00Z,0.5,0.1,0.2,,0.0,,,,\n",
+        );
+        let events = drain(ReplaySource::open_with_theta(&csv, ReplaySpeed::Unlimited, &series).unwrap()).await;
+        let thetas: Vec<f64> = events
+            .iter()
+            .map(|e| match e {
+                IngestEvent::State(s) => s.state.theta,
+                other => panic!("unexpected event {:?}", other),
+            })
+            .collect();
+        // The EEG overrides the recorded theta; the last row is past its coverage.
+        assert_eq!(thetas.len(), 2);
+        assert!(thetas.iter().all(|theta| (theta - 0.5).abs() < 0.03));
+
+        std::fs::remove_file(csv).unwrap();
+    }
+}
@@ -0,0 +1,43 @@
+//! The interface between data sources and the ingest pipeline.
+//!
+//! A `StateSource` only produces `IngestEvent`s; it never touches the clusterer. The
+//! `IngestPipeline` drives any number of sources concurrently and applies what they
+//! yield, so a new source only has to implement this trait.
+
+use crate::model::{BiophysicalState, TaggedState};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+/// Something a source observed.
+#[derive(Debug, Clone)]
+pub enum IngestEvent {
+    /// A measured state, inserted into the clusterer.
+    State(TaggedState),
+    /// A craving report, associated with the polytope of `state`, or of the subject's
+    /// most recent state if the report carries none.
+    Craving {
+        timestamp: DateTime<Utc>,
+        intensity: f64,
+        state: Option<BiophysicalState>,
+        subject_id: Option<String>,
+    },
+}
+
+impl From<TaggedState> for IngestEvent {
+    fn from(state: TaggedState) -> Self {
+        IngestEvent::State(state)
+    }
+}
+
+/// An asynchronous producer of tagged states and other ingest events.
+#[async_trait]
+pub trait StateSource: Send {
+    /// Short name used in logs (e.g. the file or server it reads).
+    fn name(&self) -> String;
+
+    /// Wait for the next batch of events.
+    ///
+    /// Returns None once the source is exhausted; live sources never are. Transient
+    /// failures are handled (and logged) inside the source, which keeps waiting.
+    async fn next_batch(&mut self) -> Option<Vec<IngestEvent>>;
+}
@@ -0,0 +1,123 @@
+//! Apple Health `export.xml`.
+//!
+//! The export is one large XML document of `<Record>` elements; it is streamed rather
+//! than loaded, since years of history run to gigabytes. The records used are heart
+//! rate, HRV (SDNN), sleeping wrist temperature and sleep analysis; everything else is
+//! skipped, as are records whose value or dates do not parse.
+
+use super::{parse_timestamp, Measurement, SleepStage, WearableError, WearableSample};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use tracing::warn;
+
+const HEART_RATE: &str = "HKQuantityTypeIdentifierHeartRate";
+const HRV_SDNN: &str = "HKQuantityTypeIdentifierHeartRateVariabilitySDNN";
+const WRIST_TEMPERATURE: &str = "HKQuantityTypeIdentifierAppleSleepingWristTemperature";
+const SLEEP_ANALYSIS: &str = "HKCategoryTypeIdentifierSleepAnalysis";
+
+/// Read an export file.
+pub fn read(path: &Path) -> Result<Vec<WearableSample>, WearableError> {
+    parse(BufReader::new(File::open(path)?))
+}
+
+/// Parse an export.
+pub fn parse<R: BufRead>(input: R) -> Result<Vec<WearableSample>, WearableError> {
+    let mut reader = Reader::from_reader(input);
+    let mut buf = Vec::new();
+    let mut samples = Vec::new();
+    let mut unreadable = 0;
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"Record" => match record(&e)? {
+                Some(sample) => samples.push(sample),
+                None => unreadable += 1,
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    if unreadable > 0 {
+        warn!("Skipped {} Apple Health records that are not used or do not parse", unreadable);
+    }
+    Ok(samples)
+}
+
+/// The sample of one `<Record>`, or `None` if it is of no interest or malformed.
+fn record(element: &BytesStart) -> Result<Option<WearableSample>, WearableError> {
+    let mut attributes = HashMap::new();
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(quick_xml::Error::from)?;
+        let key = String::from_utf8_lossy(attribute.key.as_ref()).into_owned();
+        attributes.insert(key, attribute.unescape_value()?.into_owned());
+    }
+    let get = |name: &str| attributes.get(name).map(String::as_str);
+    let (Some(kind), Some(start)) = (get("type"), get("startDate").and_then(parse_timestamp)) else {
+        return Ok(None);
+    };
+    let value = get("value").unwrap_or_default();
+    let number = value.parse::<f64>().ok();
+
+    let measurement = match kind {
+        HEART_RATE => number.map(Measurement::HeartRateBpm),
+        HRV_SDNN => number.map(Measurement::SdnnMs),
+        WRIST_TEMPERATURE => number.map(|v| match get("unit") {
+            Some("degF") => Measurement::SkinTempC((v - 32.0) * 5.0 / 9.0),
+            _ => Measurement::SkinTempC(v),
+        }),
+        SLEEP_ANALYSIS => {
+            let stage = match value.strip_prefix("HKCategoryValueSleepAnalysis") {
+                Some("Awake") => Some(SleepStage::Awake),
+                Some("AsleepCore" | "AsleepUnspecified" | "Asleep") => Some(SleepStage::Light),
+                Some("AsleepDeep") => Some(SleepStage::Deep),
+                Some("AsleepREM") => Some(SleepStage::Rem),
+                // `InBed` says nothing about the stage.
+                _ => None,
+            };
+            match (stage, get("endDate").and_then(parse_timestamp)) {
+                (Some(stage), Some(end)) => Some(Measurement::Sleep { stage, end }),
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+    Ok(measurement.map(|m| WearableSample::new(start, m)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_of_interest_are_extracted() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<HealthData locale="en_US">
+ <ExportDate value="2024-05-02 08:00:00 +0200"/>
+ <Record type="HKQuantityTypeIdentifierHeartRate" unit="count/min" value="62" startDate="2024-05-01 23:00:00 +0200" endDate="2024-05-01 23:00:00 +0200">
+  <MetadataEntry key="HKMetadataKeyHeartRateMotionContext" value="0"/>
+ </Record>
+ <Record type="HKQuantityTypeIdentifierHeartRateVariabilitySDNN" unit="ms" value="48.5" startDate="2024-05-01 23:01:00 +0200" endDate="2024-05-01 23:02:00 +0200"/>
+ <Record type="HKQuantityTypeIdentifierAppleSleepingWristTemperature" unit="degF" value="95" startDate="2024-05-02 03:00:00 +0200" endDate="2024-05-02 06:00:00 +0200"/>
+ <Record type="HKCategoryTypeIdentifierSleepAnalysis" value="HKCategoryValueSleepAnalysisAsleepREM" startDate="2024-05-02 01:00:00 +0200" endDate="2024-05-02 01:20:00 +0200"/>
+ <Record type="HKCategoryTypeIdentifierSleepAnalysis" value="HKCategoryValueSleepAnalysisInBed" startDate="2024-05-01 22:30:00 +0200" endDate="2024-05-02 07:00:00 +0200"/>
+ <Record type="HKQuantityTypeIdentifierStepCount" unit="count" value="12" startDate="2024-05-01 12:00:00 +0200" endDate="2024-05-01 12:01:00 +0200"/>
+</HealthData>"#;
+        let samples = parse(xml.as_bytes()).unwrap();
+        assert_eq!(samples.len(), 4);
+        assert_eq!(samples[0].timestamp.to_rfc3339(), "2024-05-01T21:00:00+00:00");
+        assert_eq!(samples[0].measurement, Measurement::HeartRateBpm(62.0));
+        assert_eq!(samples[1].measurement, Measurement::SdnnMs(48.5));
+        assert_eq!(samples[2].measurement, Measurement::SkinTempC(35.0));
+        match samples[3].measurement {
+            Measurement::Sleep { stage, end } => {
+                assert_eq!(stage, SleepStage::Rem);
+                assert_eq!((end - samples[3].timestamp).num_minutes(), 20);
+            }
+            other => panic!("unexpected measurement {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,247 @@
+//! Garmin/Fitbit FIT files.
+//!
+//! A FIT file is a header followed by a stream of records: definition messages
+//! describe the layout of a local message type, and data messages carry the values.
+//! Only the messages used here are decoded:
+//!
+//! - `record` (20) and `monitoring` (55): heart rate, with their timestamps;
+//! - `hrv` (78): beat-to-beat intervals, stamped with the latest timestamp seen;
+//! - `sleep_level` (275): sleep stages, each lasting until the next one.
+//!
+//! Compressed timestamp headers are expanded against the latest full timestamp.
+//! Developer fields are skipped. The trailing CRC is not checked.
+
+use super::{Measurement, SleepStage, WearableError, WearableSample};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Seconds from the Unix epoch to the FIT epoch, 1989-12-31T00:00:00Z.
+const FIT_EPOCH: i64 = 631_065_600;
+
+const MESG_RECORD: u16 = 20;
+const MESG_MONITORING: u16 = 55;
+const MESG_HRV: u16 = 78;
+const MESG_SLEEP_LEVEL: u16 = 275;
+
+const FIELD_TIMESTAMP: u8 = 253;
+const FIELD_RECORD_HEART_RATE: u8 = 3;
+const FIELD_MONITORING_HEART_RATE: u8 = 27;
+const FIELD_HRV_TIME: u8 = 0;
+const FIELD_SLEEP_LEVEL: u8 = 0;
+
+/// How long the last sleep level of a file lasts, for lack of a next one.
+const LAST_SLEEP_LEVEL_SEC: i64 = 60;
+
+struct FieldDefinition {
+    number: u8,
+    size: usize,
+}
+
+struct Definition {
+    global: u16,
+    big_endian: bool,
+    fields: Vec<FieldDefinition>,
+    developer_bytes: usize,
+}
+
+fn invalid(message: impl Into<String>) -> WearableError {
+    WearableError::Fit(message.into())
+}
+
+/// Unsigned integer of 1 to 8 bytes.
+fn uint(bytes: &[u8], big_endian: bool) -> u64 {
+    let fold = |acc: u64, &b: &u8| (acc << 8) | b as u64;
+    if big_endian {
+        bytes.iter().fold(0, fold)
+    } else {
+        bytes.iter().rev().fold(0, fold)
+    }
+}
+
+/// The next `n` bytes of `data`.
+fn take<'a>(data: &'a [u8], pos: &mut usize, n: usize) -> Result<&'a [u8], WearableError> {
+    let slice = data.get(*pos..*pos + n).ok_or_else(|| invalid("record runs past the end of the data"))?;
+    *pos += n;
+    Ok(slice)
+}
+
+fn fit_time(seconds: u32) -> Option<DateTime<Utc>> {
+    Utc.timestamp_opt(FIT_EPOCH + seconds as i64, 0).single()
+}
+
+/// Read a FIT file.
+pub fn read(path: &Path) -> Result<Vec<WearableSample>, WearableError> {
+    parse(&std::fs::read(path)?)
+}
+
+/// Parse the contents of a FIT file.
+pub fn parse(bytes: &[u8]) -> Result<Vec<WearableSample>, WearableError> {
+    let header_size = *bytes.first().ok_or_else(|| invalid("empty file"))? as usize;
+    if header_size < 12 || bytes.len() < header_size || &bytes[8..12] != b".FIT" {
+        return Err(invalid("missing .FIT header"));
+    }
+    let data_size = uint(&bytes[4..8], false) as usize;
+    let data = bytes
+        .get(header_size..header_size + data_size)
+        .ok_or_else(|| invalid("file shorter than its declared data size"))?;
+
+    let mut definitions: HashMap<u8, Definition> = HashMap::new();
+    let mut samples = Vec::new();
+    let mut sleep_levels: Vec<(DateTime<Utc>, Option<SleepStage>)> = Vec::new();
+    let mut last_timestamp: Option<u32> = None;
+    let mut pos = 0;
+    while pos < data.len() {
+        let header = take(data, &mut pos, 1)?[0];
+        let (local, compressed_offset) = if header & 0x80 != 0 {
+            ((header >> 5) & 0x03, Some((header & 0x1F) as u32))
+        } else {
+            (header & 0x0F, None)
+        };
+
+        if compressed_offset.is_none() && header & 0x40 != 0 {
+            let fixed = take(data, &mut pos, 5)?;
+            let big_endian = fixed[1] == 1;
+            let global = uint(&fixed[2..4], big_endian) as u16;
+            let count = fixed[4] as usize;
+            let fields = take(data, &mut pos, 3 * count)?
+                .chunks_exact(3)
+                .map(|f| FieldDefinition { number: f[0], size: f[1] as usize })
+                .collect();
+            let mut developer_bytes = 0;
+            if header & 0x20 != 0 {
+                let count = take(data, &mut pos, 1)?[0] as usize;
+                developer_bytes = take(data, &mut pos, 3 * count)?.chunks_exact(3).map(|f| f[1] as usize).sum();
+            }
+            definitions.insert(local, Definition { global, big_endian, fields, developer_bytes });
+            continue;
+        }
+
+        let definition = definitions
+            .get(&local)
+            .ok_or_else(|| invalid(format!("data message of undefined local type {}", local)))?;
+        let mut timestamp = compressed_offset.and_then(|offset| {
+            let last = last_timestamp?;
+            let mut t = (last & !0x1F) + offset;
+            if offset < (last & 0x1F) {
+                t += 0x20;
+            }
+            Some(t)
+        });
+        let mut heart_rate = None;
+        let mut rr = Vec::new();
+        let mut sleep_level = None;
+        for field in &definition.fields {
+            let value = take(data, &mut pos, field.size)?;
+            let be = definition.big_endian;
+            match (definition.global, field.number) {
+                (_, FIELD_TIMESTAMP) if field.size == 4 => timestamp = Some(uint(value, be) as u32),
+                (MESG_RECORD, FIELD_RECORD_HEART_RATE) | (MESG_MONITORING, FIELD_MONITORING_HEART_RATE)
+                    if field.size == 1 && value[0] != 0xFF =>
+                {
+                    heart_rate = Some(value[0] as f64);
+                }
+                (MESG_HRV, FIELD_HRV_TIME) => {
+                    // Seconds with scale 1000, i.e. milliseconds.
+                    rr.extend(
+                        value
+                            .chunks_exact(2)
+                            .map(|v| uint(v, be))
+                            .filter(|&v| v != 0xFFFF)
+                            .map(|v| v as f64),
+                    );
+                }
+                (MESG_SLEEP_LEVEL, FIELD_SLEEP_LEVEL) if field.size == 1 => {
+                    sleep_level = Some(match value[0] {
+                        1 => Some(SleepStage::Awake),
+                        2 => Some(SleepStage::Light),
+                        3 => Some(SleepStage::Deep),
+                        4 => Some(SleepStage::Rem),
+                        _ => None,
+                    });
+                }
+                _ => {}
+            }
+        }
+        take(data, &mut pos, definition.developer_bytes)?;
+
+        if timestamp.is_some() {
+            last_timestamp = timestamp;
+        }
+        let Some(at) = last_timestamp.and_then(fit_time) else { continue };
+        if let Some(bpm) = heart_rate {
+            samples.push(WearableSample::new(at, Measurement::HeartRateBpm(bpm)));
+        }
+        samples.extend(rr.into_iter().map(|ms| WearableSample::new(at, Measurement::RrIntervalMs(ms))));
+        if let Some(stage) = sleep_level {
+            sleep_levels.push((at, stage));
+        }
+    }
+
+    // Each sleep level lasts until the next one; unmeasurable periods only end the
+    // previous level.
+    for (i, &(start, stage)) in sleep_levels.iter().enumerate() {
+        let end = sleep_levels
+            .get(i + 1)
+            .map_or(start + Duration::seconds(LAST_SLEEP_LEVEL_SEC), |&(next, _)| next);
+        if let Some(stage) = stage {
+            samples.push(WearableSample::new(start, Measurement::Sleep { stage, end }));
+        }
+    }
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(data: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![14, 0x20, 0x08, 0x08];
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(b".FIT");
+        bytes.extend_from_slice(&[0, 0]);
+        bytes.extend_from_slice(data);
+        bytes.extend_from_slice(&[0, 0]);
+        bytes
+    }
+
+    #[test]
+    fn test_heart_rate_intervals_and_sleep_levels() {
+        let t0: u32 = 1_083_000_000;
+        let mut data = Vec::new();
+        // Local 0: record {timestamp u32, heart_rate u8}, little-endian.
+        data.extend_from_slice(&[0x40, 0, 0, 20, 0, 2, 253, 4, 0x86, 3, 1, 0x02]);
+        data.push(0x00);
+        data.extend_from_slice(&t0.to_le_bytes());
+        data.push(61);
+        // Local 1: record {heart_rate u8}, sent with a compressed timestamp header.
+        data.extend_from_slice(&[0x41, 0, 0, 20, 0, 1, 3, 1, 0x02]);
+        data.extend_from_slice(&[0x80 | 0x20 | ((t0 + 3) & 0x1F) as u8, 63]);
+        // Local 2: hrv {time [u16; 2]}, big-endian.
+        data.extend_from_slice(&[0x42, 0, 1, 0, 78, 1, 0, 4, 0x84]);
+        data.extend_from_slice(&[0x02, 0x03, 0x20, 0x03, 0x2A]);
+        // Local 3: sleep_level {timestamp, level}; deep then REM.
+        data.extend_from_slice(&[0x43, 0, 0, 0x13, 0x01, 2, 253, 4, 0x86, 0, 1, 0x00]);
+        for (dt, level) in [(60u32, 3u8), (120, 4)] {
+            data.push(0x03);
+            data.extend_from_slice(&(t0 + dt).to_le_bytes());
+            data.push(level);
+        }
+
+        let samples = parse(&file(&data)).unwrap();
+        let start = fit_time(t0).unwrap();
+        assert_eq!(samples[0], WearableSample::new(start, Measurement::HeartRateBpm(61.0)));
+        assert_eq!(samples[1], WearableSample::new(start + Duration::seconds(3), Measurement::HeartRateBpm(63.0)));
+        assert_eq!(samples[2].measurement, Measurement::RrIntervalMs(800.0));
+        assert_eq!(samples[3].measurement, Measurement::RrIntervalMs(810.0));
+        assert_eq!(
+            samples[4],
+            WearableSample::new(
+                start + Duration::seconds(60),
+                Measurement::Sleep { stage: SleepStage::Deep, end: start + Duration::seconds(120) }
+            )
+        );
+        assert_eq!(samples.len(), 6);
+        assert!(parse(b"not a fit file").is_err());
+    }
+}
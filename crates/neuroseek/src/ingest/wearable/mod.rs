@@ -0,0 +1,468 @@
+//! Importers of consumer wearable exports, for seeding a polytope map from history.
+//!
+//! Each importer turns one export into `WearableSample`s, a common representation of
+//! what wearables measure: heart rate, beat-to-beat intervals, HRV, skin temperature
+//! and sleep stages.
+//!
+//! - `apple_health`: the `export.xml` of Apple Health;
+//! - `fit`: Garmin/Fitbit FIT activity and monitoring files;
+//! - `oura`: Oura-style CSV.
+//!
+//! `to_states` maps samples onto the bioscale through a `WearableMapping`: samples
+//! are grouped into fixed bins, each bin's features are computed (means, RMSSD from
+//! beat intervals, skin temperature deviation from a rolling baseline, share of the
+//! bin in deep and REM sleep) and combined with a `features::Combination`. Slow
+//! signals such as nightly HRV are carried forward for a while; dimensions built on a
+//! carried-forward feature are flagged stale. The resulting states can be fed to the
+//! clusterer like any recorded session (`ReplaySource::from_events`).
+
+pub mod apple_health;
+pub mod fit;
+pub mod oura;
+
+use crate::features::temperature::TemperatureBaseline;
+use crate::features::{hrv, Combination, DimensionMapping, Feature, FeatureVector, Term};
+use crate::model::{QualityFlags, TaggedState};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use tracing::info;
+
+/// Errors raised while importing an export.
+#[derive(Debug, thiserror::Error)]
+pub enum WearableError {
+    #[error("Failed to read export: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Export is not valid XML: {0}")]
+    Xml(#[from] quick_xml::Error),
+    #[error("Export is not valid CSV: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("Invalid FIT file: {0}")]
+    Fit(String),
+    #[error("Unrecognized export format: {0}")]
+    UnknownFormat(String),
+    #[error("Mapping is not valid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid wearable mapping: {0}")]
+    InvalidMapping(String),
+}
+
+/// Sleep stage, as reported by the wearable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SleepStage {
+    Awake,
+    /// Light or core sleep (N1/N2).
+    Light,
+    Deep,
+    Rem,
+}
+
+/// One measurement of a wearable.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Measurement {
+    HeartRateBpm(f64),
+    /// A single beat-to-beat interval.
+    RrIntervalMs(f64),
+    RmssdMs(f64),
+    SdnnMs(f64),
+    /// Absolute skin temperature.
+    SkinTempC(f64),
+    /// Skin temperature deviation as computed by the wearable.
+    SkinTempDeviationC(f64),
+    /// A sleep stage lasting from the sample's timestamp to `end`.
+    Sleep { stage: SleepStage, end: DateTime<Utc> },
+}
+
+/// A timestamped measurement.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WearableSample {
+    pub timestamp: DateTime<Utc>,
+    pub measurement: Measurement,
+}
+
+impl WearableSample {
+    pub fn new(timestamp: DateTime<Utc>, measurement: Measurement) -> Self {
+        Self { timestamp, measurement }
+    }
+}
+
+/// Kind of export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WearableFormat {
+    AppleHealth,
+    Fit,
+    OuraCsv,
+}
+
+impl WearableFormat {
+    /// Guess the format from the file extension.
+    pub fn detect(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "xml" => Some(WearableFormat::AppleHealth),
+            "fit" => Some(WearableFormat::Fit),
+            "csv" => Some(WearableFormat::OuraCsv),
+            _ => None,
+        }
+    }
+}
+
+/// Import an export, detecting its format from the extension.
+pub fn import(path: &Path) -> Result<(WearableFormat, Vec<WearableSample>), WearableError> {
+    let format = WearableFormat::detect(path).ok_or_else(|| WearableError::UnknownFormat(path.display().to_string()))?;
+    let samples = match format {
+        WearableFormat::AppleHealth => apple_health::read(path)?,
+        WearableFormat::Fit => fit::read(path)?,
+        WearableFormat::OuraCsv => oura::read(path)?,
+    };
+    info!("Imported {} samples from {}", samples.len(), path.display());
+    Ok((format, samples))
+}
+
+/// Parse the timestamp formats found in exports: RFC 3339, `YYYY-MM-DD HH:MM:SS ±ZZZZ`
+/// (Apple Health), zone-less date-times (taken as UTC), and bare dates (midnight UTC).
+pub(crate) fn parse_timestamp(s: &str) -> Option<DateTime<Utc>> {
+    let s = s.trim();
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Some(t.with_timezone(&Utc));
+    }
+    if let Ok(t) = DateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S %z") {
+        return Some(t.with_timezone(&Utc));
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S"] {
+        if let Ok(t) = NaiveDateTime::parse_from_str(s, format) {
+            return Some(t.and_utc());
+        }
+    }
+    let date = NaiveDate::parse_from_str(s, "%Y-%m-%d").ok()?;
+    Some(date.and_hms_opt(0, 0, 0)?.and_utc())
+}
+
+/// Longest bin, carry-forward age or temperature baseline a mapping may ask for.
+const MAX_SPAN_SEC: i64 = 366 * 24 * 3600;
+
+/// How wearable samples become states.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WearableMapping {
+    pub combination: Combination,
+    /// Length of the bins samples are grouped into, in seconds. This and the spans below
+    /// must be positive and at most a year.
+    pub bin_sec: i64,
+    /// How long heart rate may be carried forward into bins without one, in seconds.
+    pub heart_rate_max_age_sec: i64,
+    /// How long HRV and skin temperature (often measured once a night) may be carried
+    /// forward, in seconds.
+    pub slow_max_age_sec: i64,
+    /// Length of the baseline absolute skin temperatures are compared to, in seconds.
+    pub temperature_baseline_sec: i64,
+}
+
+impl Default for WearableMapping {
+    fn default() -> Self {
+        Self {
+            combination: Combination {
+                e: DimensionMapping { offset: 0.0, terms: vec![Term::new(Feature::HeartRateBpm, 1.0, (40.0, 140.0))] },
+                m_prot: DimensionMapping::default(),
+                s_bio: DimensionMapping { offset: 0.6, terms: vec![Term::new(Feature::RmssdMs, -0.6, (10.0, 100.0))] },
+                theta: DimensionMapping {
+                    offset: 0.5,
+                    terms: vec![
+                        Term::new(Feature::SleepRemShare, 0.5, (0.0, 1.0)),
+                        Term::new(Feature::SleepDeepShare, -0.5, (0.0, 1.0)),
+                    ],
+                },
+                t: DimensionMapping {
+                    offset: 0.0,
+                    terms: vec![Term::new(Feature::SkinTempDeviationC, 1.0, (-2.0, 2.0))],
+                },
+            },
+            bin_sec: 300,
+            heart_rate_max_age_sec: 900,
+            slow_max_age_sec: 36 * 3600,
+            temperature_baseline_sec: 14 * 24 * 3600,
+        }
+    }
+}
+
+impl WearableMapping {
+    /// The default mapping for an export format. Apple Health reports SDNN rather than
+    /// RMSSD, so its stress dimension is built on SDNN.
+    pub fn for_format(format: WearableFormat) -> Self {
+        let mut mapping = Self::default();
+        if format == WearableFormat::AppleHealth {
+            mapping.combination.s_bio.terms = vec![Term::new(Feature::SdnnMs, -0.6, (20.0, 150.0))];
+        }
+        mapping
+    }
+
+    /// Load a mapping from JSON, rejecting spans that are not positive or exceed a year.
+    pub fn from_json(json: &str) -> Result<Self, WearableError> {
+        let mapping: Self = serde_json::from_str(json)?;
+        mapping.validate()?;
+        Ok(mapping)
+    }
+
+    fn validate(&self) -> Result<(), WearableError> {
+        let spans = [
+            ("bin_sec", self.bin_sec),
+            ("heart_rate_max_age_sec", self.heart_rate_max_age_sec),
+            ("slow_max_age_sec", self.slow_max_age_sec),
+            ("temperature_baseline_sec", self.temperature_baseline_sec),
+        ];
+        for (name, value) in spans {
+            if !(1..=MAX_SPAN_SEC).contains(&value) {
+                return Err(WearableError::InvalidMapping(format!(
+                    "{} must be between 1 and {} seconds, got {}",
+                    name, MAX_SPAN_SEC, value
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn set_feature(features: &mut FeatureVector, feature: Feature, value: f64) {
+    match feature {
+        Feature::HeartRateBpm => features.heart_rate_bpm = Some(value),
+        Feature::RmssdMs => features.rmssd_ms = Some(value),
+        Feature::SdnnMs => features.sdnn_ms = Some(value),
+        Feature::SkinTempDeviationC => features.skin_temp_deviation_c = Some(value),
+        _ => {}
+    }
+}
+
+fn seconds(value: i64) -> Result<Duration, WearableError> {
+    Duration::try_seconds(value)
+        .ok_or_else(|| WearableError::InvalidMapping(format!("{} seconds is out of range", value)))
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+}
+
+/// Seconds of `[start, end)` that overlap `[from, to)`.
+fn overlap(start: DateTime<Utc>, end: DateTime<Utc>, from: DateTime<Utc>, to: DateTime<Utc>) -> f64 {
+    let seconds = (end.min(to) - start.max(from)).num_milliseconds() as f64 / 1000.0;
+    seconds.max(0.0)
+}
+
+/// Time spent in deep and REM sleep per bin. Bins must be visited in order; intervals
+/// are sorted by start once and each is kept only while it can still overlap a bin.
+struct SleepSweep {
+    pending: std::vec::IntoIter<(DateTime<Utc>, DateTime<Utc>, SleepStage)>,
+    next: Option<(DateTime<Utc>, DateTime<Utc>, SleepStage)>,
+    active: Vec<(DateTime<Utc>, DateTime<Utc>, SleepStage)>,
+}
+
+impl SleepSweep {
+    fn new(mut intervals: Vec<(DateTime<Utc>, DateTime<Utc>, SleepStage)>) -> Self {
+        intervals.sort_by_key(|&(start, _, _)| start);
+        let mut pending = intervals.into_iter();
+        let next = pending.next();
+        Self { pending, next, active: Vec::new() }
+    }
+
+    /// Seconds of deep and REM sleep in `[from, to)`.
+    fn seconds(&mut self, from: DateTime<Utc>, to: DateTime<Utc>) -> (f64, f64) {
+        while let Some(interval) = self.next.filter(|&(start, _, _)| start < to) {
+            self.active.push(interval);
+            self.next = self.pending.next();
+        }
+        self.active.retain(|&(_, end, _)| end > from);
+        let mut deep = 0.0;
+        let mut rem = 0.0;
+        for &(start, end, stage) in &self.active {
+            match stage {
+                SleepStage::Deep => deep += overlap(start, end, from, to),
+                SleepStage::Rem => rem += overlap(start, end, from, to),
+                _ => {}
+            }
+        }
+        (deep, rem)
+    }
+}
+
+/// Measurements falling into one bin.
+#[derive(Default)]
+struct Bin {
+    heart_rate: Vec<f64>,
+    rr: Vec<f64>,
+    rmssd: Vec<f64>,
+    sdnn: Vec<f64>,
+    skin_temp: Vec<f64>,
+    skin_temp_deviation: Vec<f64>,
+}
+
+/// Map samples onto states, one per bin with any measurement whose features are all
+/// available. Bins outside any recorded sleep stage count as awake.
+pub fn to_states(samples: &[WearableSample], mapping: &WearableMapping) -> Result<Vec<TaggedState>, WearableError> {
+    mapping.validate()?;
+    let bin_sec = mapping.bin_sec;
+    let bin_length = seconds(bin_sec)?;
+    let heart_rate_max_age = seconds(mapping.heart_rate_max_age_sec)?;
+    let slow_max_age = seconds(mapping.slow_max_age_sec)?;
+    let mut bins: BTreeMap<i64, Bin> = BTreeMap::new();
+    let mut sleep = Vec::new();
+    for sample in samples {
+        if let Measurement::Sleep { stage, end } = sample.measurement {
+            sleep.push((sample.timestamp, end, stage));
+            continue;
+        }
+        let bin = bins.entry(sample.timestamp.timestamp().div_euclid(bin_sec)).or_default();
+        match sample.measurement {
+            Measurement::HeartRateBpm(v) => bin.heart_rate.push(v),
+            Measurement::RrIntervalMs(v) => bin.rr.push(v),
+            Measurement::RmssdMs(v) => bin.rmssd.push(v),
+            Measurement::SdnnMs(v) => bin.sdnn.push(v),
+            Measurement::SkinTempC(v) => bin.skin_temp.push(v),
+            Measurement::SkinTempDeviationC(v) => bin.skin_temp_deviation.push(v),
+            Measurement::Sleep { .. } => {}
+        }
+    }
+
+    let mut temperature = TemperatureBaseline::new(seconds(mapping.temperature_baseline_sec)?);
+    let mut sleep = SleepSweep::new(sleep);
+    let mut last: HashMap<Feature, (DateTime<Utc>, f64)> = HashMap::new();
+    let mut states = Vec::new();
+    let mut skipped = 0;
+    for (index, bin) in bins {
+        let from = index
+            .checked_mul(bin_sec)
+            .and_then(|start| Utc.timestamp_opt(start, 0).single())
+            .ok_or_else(|| WearableError::InvalidMapping(format!("bin {} of {} s is out of range", index, bin_sec)))?;
+        let to = from
+            .checked_add_signed(bin_length)
+            .ok_or_else(|| WearableError::InvalidMapping(format!("bin {} of {} s is out of range", index, bin_sec)))?;
+
+        // Measured features of the bin; sleep shares are never carried forward.
+        let fresh = [
+            (Feature::HeartRateBpm, mean(&bin.heart_rate).or_else(|| hrv::heart_rate_bpm(&bin.rr))),
+            (Feature::RmssdMs, mean(&bin.rmssd).or_else(|| hrv::rmssd(&bin.rr))),
+            (Feature::SdnnMs, mean(&bin.sdnn)),
+            (
+                Feature::SkinTempDeviationC,
+                mean(&bin.skin_temp_deviation).or_else(|| temperature.deviation(to, mean(&bin.skin_temp)?)),
+            ),
+        ];
+        let mut features = FeatureVector::default();
+        let mut carried = Vec::new();
+        for (feature, value) in fresh {
+            let max_age = match feature {
+                Feature::HeartRateBpm => heart_rate_max_age,
+                _ => slow_max_age,
+            };
+            match value {
+                Some(v) => {
+                    last.insert(feature, (to, v));
+                    set_feature(&mut features, feature, v);
+                }
+                None => {
+                    if let Some(&(at, v)) = last.get(&feature) {
+                        if to - at <= max_age {
+                            set_feature(&mut features, feature, v);
+                            carried.push(feature);
+                        }
+                    }
+                }
+            }
+        }
+        let (deep, rem) = sleep.seconds(from, to);
+        features.sleep_deep_share = Some((deep / bin_sec as f64).min(1.0));
+        features.sleep_rem_share = Some((rem / bin_sec as f64).min(1.0));
+
+        let combination = &mapping.combination;
+        match combination.combine(&features) {
+            Ok(state) => {
+                let mut quality = QualityFlags::default();
+                let dimensions = [&combination.e, &combination.m_prot, &combination.s_bio, &combination.theta, &combination.t];
+                for (i, dimension) in dimensions.iter().enumerate() {
+                    if dimension.terms.iter().any(|t| carried.contains(&t.feature)) {
+                        quality.insert(QualityFlags::stale(i));
+                    }
+                }
+                states.push(TaggedState::new(state, to, None).with_quality(quality));
+            }
+            Err(_) => skipped += 1,
+        }
+    }
+    if skipped > 0 {
+        info!("{} wearable bins lacked a feature needed by the mapping and were skipped", skipped);
+    }
+    Ok(states)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bins_carry_slow_signals_and_use_sleep_shares() {
+        let t0 = parse_timestamp("2024-05-01T23:00:00Z").unwrap();
+        let at = |minutes: i64| t0 + Duration::minutes(minutes);
+        let samples = vec![
+            WearableSample::new(at(0), Measurement::HeartRateBpm(60.0)),
+            WearableSample::new(at(1), Measurement::HeartRateBpm(70.0)),
+            WearableSample::new(at(2), Measurement::RmssdMs(55.0)),
+            WearableSample::new(at(3), Measurement::SkinTempDeviationC(0.4)),
+            WearableSample::new(at(5), Measurement::Sleep { stage: SleepStage::Deep, end: at(10) }),
+            WearableSample::new(at(6), Measurement::HeartRateBpm(50.0)),
+            // Too late for the heart rate to be carried forward.
+            WearableSample::new(at(60), Measurement::RmssdMs(40.0)),
+        ];
+        let states = to_states(&samples, &WearableMapping::default()).unwrap();
+        assert_eq!(states.len(), 2);
+
+        let awake = &states[0];
+        assert!((awake.state.e - 0.25).abs() < 1e-9);
+        assert!((awake.state.s_bio - 0.3).abs() < 1e-9);
+        assert!((awake.state.theta - 0.5).abs() < 1e-9);
+        assert!((awake.state.t - 0.6).abs() < 1e-9);
+        assert!(awake.quality.is_clean());
+
+        // The second bin is spent in deep sleep, with HRV and temperature carried over.
+        let asleep = &states[1];
+        assert_eq!(asleep.timestamp, at(10));
+        assert!((asleep.state.e - 0.1).abs() < 1e-9);
+        assert!((asleep.state.theta - 0.0).abs() < 1e-9);
+        assert_eq!(asleep.quality, QualityFlags::STALE_S_BIO | QualityFlags::STALE_T);
+
+        assert_eq!(parse_timestamp("2024-05-01 12:00:00 +0200").unwrap().to_rfc3339(), "2024-05-01T10:00:00+00:00");
+        assert_eq!(parse_timestamp("2024-05-01").unwrap().to_rfc3339(), "2024-05-01T00:00:00+00:00");
+    }
+    #[test]
+    fn test_sleep_shares_span_bins_and_mappings_are_validated() {
+        let t0 = parse_timestamp("2024-05-02T01:00:00Z").unwrap();
+        let at = |minutes: i64| t0 + Duration::minutes(minutes);
+        // Sleep intervals out of order, one spanning three bins.
+        let mut samples = vec![
+            WearableSample::new(at(12), Measurement::Sleep { stage: SleepStage::Rem, end: at(14) }),
+            WearableSample::new(at(2), Measurement::Sleep { stage: SleepStage::Deep, end: at(12) }),
+        ];
+        for minute in [1, 6, 11] {
+            samples.push(WearableSample::new(at(minute), Measurement::HeartRateBpm(60.0)));
+            samples.push(WearableSample::new(at(minute), Measurement::RmssdMs(50.0)));
+            samples.push(WearableSample::new(at(minute), Measurement::SkinTempDeviationC(0.0)));
+        }
+        let states = to_states(&samples, &WearableMapping::default()).unwrap();
+        let theta: Vec<f64> = states.iter().map(|s| s.state.theta).collect();
+        assert_eq!(theta.len(), 3);
+        assert!((theta[0] - 0.2).abs() < 1e-9);
+        assert!((theta[1] - 0.0).abs() < 1e-9);
+        assert!((theta[2] - 0.5).abs() < 1e-9);
+
+        for json in [
+            r#"{"bin_sec": 0}"#,
+            r#"{"bin_sec": -300}"#,
+            r#"{"heart_rate_max_age_sec": -1}"#,
+            r#"{"slow_max_age_sec": 9223372036854775807}"#,
+        ] {
+            assert!(matches!(WearableMapping::from_json(json), Err(WearableError::InvalidMapping(_))), "{}", json);
+        }
+        let mapping = WearableMapping { bin_sec: i64::MAX, ..WearableMapping::default() };
+        assert!(matches!(to_states(&samples, &mapping), Err(WearableError::InvalidMapping(_))));
+        assert!(WearableMapping::from_json(r#"{"bin_sec": 60}"#).is_ok());
+    }
+}
@@ -0,0 +1,96 @@
+//! Oura-style CSV exports.
+//!
+//! One row per timestamp with any of the columns `heart_rate` (bpm), `hrv` (RMSSD,
+//! ms), `temperature_deviation` (°C) and `sleep_stage`; empty cells and unknown
+//! columns are ignored. Sleep stages follow the 5-minute hypnogram, either by name
+//! (`awake`, `light`, `deep`, `rem`) or by Oura's digit code (1 deep, 2 light, 3 REM,
+//! 4 awake). Rows whose timestamp does not parse are skipped.
+
+use super::{parse_timestamp, Measurement, SleepStage, WearableError, WearableSample};
+use chrono::Duration;
+use serde::Deserialize;
+use std::io::Read;
+use std::path::Path;
+use tracing::warn;
+
+/// Length of one hypnogram entry.
+const STAGE_SEC: i64 = 300;
+
+#[derive(Debug, Deserialize)]
+struct Row {
+    timestamp: String,
+    heart_rate: Option<f64>,
+    hrv: Option<f64>,
+    temperature_deviation: Option<f64>,
+    sleep_stage: Option<String>,
+}
+
+fn sleep_stage(value: &str) -> Option<SleepStage> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "awake" | "4" => Some(SleepStage::Awake),
+        "light" | "2" => Some(SleepStage::Light),
+        "deep" | "1" => Some(SleepStage::Deep),
+        "rem" | "3" => Some(SleepStage::Rem),
+        _ => None,
+    }
+}
+
+/// Read an export file.
+pub fn read(path: &Path) -> Result<Vec<WearableSample>, WearableError> {
+    parse(std::fs::File::open(path)?)
+}
+
+/// Parse an export.
+pub fn parse(input: impl Read) -> Result<Vec<WearableSample>, WearableError> {
+    let mut reader = csv::Reader::from_reader(input);
+    let mut samples = Vec::new();
+    let mut unreadable = 0;
+    for row in reader.deserialize::<Row>() {
+        let row = row?;
+        let Some(at) = parse_timestamp(&row.timestamp) else {
+            unreadable += 1;
+            continue;
+        };
+        let measurements = [
+            row.heart_rate.map(Measurement::HeartRateBpm),
+            row.hrv.map(Measurement::RmssdMs),
+            row.temperature_deviation.map(Measurement::SkinTempDeviationC),
+            row.sleep_stage
+                .as_deref()
+                .and_then(sleep_stage)
+                .map(|stage| Measurement::Sleep { stage, end: at + Duration::seconds(STAGE_SEC) }),
+        ];
+        samples.extend(measurements.into_iter().flatten().map(|m| WearableSample::new(at, m)));
+    }
+    if unreadable > 0 {
+        warn!("Skipped {} rows with unreadable timestamps", unreadable);
+    }
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rows_with_sparse_columns() {
+        let csv = "timestamp,heart_rate,hrv,temperature_deviation,sleep_stage,steps\n\
+                   2024-05-01T23:00:00+00:00,58,,,1,0\n\
+                   2024-05-01T23:05:00+00:00,,42,,rem,\n\
+                   2024-05-02,,,-0.3,,\n\
+                   yesterday,60,,,,\n";
+        let samples = parse(csv.as_bytes()).unwrap();
+        let measurements: Vec<Measurement> = samples.iter().map(|s| s.measurement).collect();
+        let t0 = parse_timestamp("2024-05-01T23:00:00Z").unwrap();
+        assert_eq!(
+            measurements,
+            vec![
+                Measurement::HeartRateBpm(58.0),
+                Measurement::Sleep { stage: SleepStage::Deep, end: t0 + Duration::minutes(5) },
+                Measurement::RmssdMs(42.0),
+                Measurement::Sleep { stage: SleepStage::Rem, end: t0 + Duration::minutes(10) },
+                Measurement::SkinTempDeviationC(-0.3),
+            ]
+        );
+    }
+}
@@ -1,10 +1,13 @@
+pub mod bioload;
 pub mod biophysics;      // (we need to create this if not exists)
 pub mod clustering;
 pub mod compiler;
+pub mod features;
 pub mod governance;      // (we need to create this)
 pub mod ingest;
 pub mod model;
 pub mod neurorights;     // (we need to create this)
+pub mod profile;
 pub mod stimulus;
 
 // Re-export key types from other crates for convenience.
@@ -4,9 +4,9 @@
 //! All coordinates are mappable to the nicotine safety schema (HostBudget,
 //! BioCompatibilityEnvelope) and are used purely for observation and discovery.
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::time::{Duration, Instant};
 
 /// A point in the 5D bioscale space used for NeuroSeek clustering.
 ///
@@ -61,6 +61,25 @@ pub struct StimulusMetadata {
     pub audio_params: Option<AudioParams>,
 }
 
+/// Where a state falls relative to a stimulus session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    /// Within the configured window before the session started.
+    Pre,
+    /// Between the session's start and stop.
+    During,
+    /// Within the configured window after the session stopped.
+    Post,
+}
+
+/// The stimulus session a state was measured around, and in which phase of it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionPhase {
+    pub session_id: String,
+    pub phase: Phase,
+}
+
 /// Audio-specific parameters.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AudioParams {
@@ -69,12 +88,134 @@ pub struct AudioParams {
     pub amplitude: f64,
 }
 
+/// Data-quality flags of a tagged state.
+///
+/// The low five bits mark dimensions (in `as_array` order) whose value was carried
+/// forward from an earlier sample instead of being measured at the state's timestamp.
+/// The next five mark dimensions whose measured value was rejected as a spike and
+/// replaced by the local median (see `ingest::filter`), and `SMOOTHED` marks states
+/// that went through exponential smoothing. No flags set means every coordinate is
+/// fresh and unmodified.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct QualityFlags(pub u16);
+
+impl QualityFlags {
+    pub const STALE_E: Self = Self(1 << 0);
+    pub const STALE_M_PROT: Self = Self(1 << 1);
+    pub const STALE_S_BIO: Self = Self(1 << 2);
+    pub const STALE_THETA: Self = Self(1 << 3);
+    pub const STALE_T: Self = Self(1 << 4);
+    pub const SPIKE_E: Self = Self(1 << 5);
+    pub const SPIKE_M_PROT: Self = Self(1 << 6);
+    pub const SPIKE_S_BIO: Self = Self(1 << 7);
+    pub const SPIKE_THETA: Self = Self(1 << 8);
+    pub const SPIKE_T: Self = Self(1 << 9);
+    pub const SMOOTHED: Self = Self(1 << 10);
+
+    /// The stale flag of dimension `index` (0 = E, ..., 4 = T).
+    pub fn stale(index: usize) -> Self {
+        Self(1 << index)
+    }
+
+    /// The spike flag of dimension `index` (0 = E, ..., 4 = T).
+    pub fn spike(index: usize) -> Self {
+        Self(1 << (5 + index))
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    /// Whether no flag is set.
+    pub fn is_clean(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl std::ops::BitOr for QualityFlags {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
 /// A biophysical state with optional stimulus metadata.
-#[derive(Debug, Clone)]
+///
+/// `timestamp` is the time the state was measured, as reported by the source, not the
+/// time it reached NeuroSeek.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TaggedState {
     pub state: BiophysicalState,
-    pub timestamp: Instant,
+    pub timestamp: DateTime<Utc>,
     pub stimulus: Option<StimulusMetadata>,
+    #[serde(default)]
+    pub quality: QualityFlags,
+    /// Subject the state was measured on, for sources that serve several subjects.
+    #[serde(default)]
+    pub subject_id: Option<String>,
+    /// Device that measured it, if the source distinguishes devices.
+    #[serde(default)]
+    pub device_id: Option<String>,
+    /// Signal quality reported by the device (1 is clean, 0 unusable), e.g. from an
+    /// accelerometer-based motion detector.
+    #[serde(default)]
+    pub signal_quality: Option<f64>,
+    /// Stimulus session the state falls before, during or after, joined from the
+    /// stimulus event log.
+    #[serde(default)]
+    pub session_phase: Option<SessionPhase>,
+}
+
+impl TaggedState {
+    /// Create a tagged state measured at `timestamp`, with all coordinates fresh.
+    pub fn new(state: BiophysicalState, timestamp: DateTime<Utc>, stimulus: Option<StimulusMetadata>) -> Self {
+        Self {
+            state,
+            timestamp,
+            stimulus,
+            quality: QualityFlags::default(),
+            subject_id: None,
+            device_id: None,
+            signal_quality: None,
+            session_phase: None,
+        }
+    }
+
+    /// Set the subject and device.
+    pub fn with_subject(mut self, subject_id: Option<String>, device_id: Option<String>) -> Self {
+        self.subject_id = subject_id;
+        self.device_id = device_id;
+        self
+    }
+
+    /// Set the quality flags.
+    pub fn with_quality(mut self, quality: QualityFlags) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    /// Set the device-reported signal quality.
+    pub fn with_signal_quality(mut self, signal_quality: Option<f64>) -> Self {
+        self.signal_quality = signal_quality;
+        self
+    }
+
+    /// Set the stimulus session phase.
+    pub fn with_session_phase(mut self, session_phase: Option<SessionPhase>) -> Self {
+        self.session_phase = session_phase;
+        self
+    }
+}
+
+/// Seconds elapsed from `earlier` to `later`, or zero if `later` is not after `earlier`.
+pub fn seconds_between(earlier: DateTime<Utc>, later: DateTime<Utc>) -> f64 {
+    (later - earlier).to_std().map(|d| d.as_secs_f64()).unwrap_or(0.0)
 }
 
 /// A unique identifier for a micro-polytope.
@@ -94,14 +235,13 @@ pub struct MicroPolytope {
     /// Weighted sum of squares (for radius / spread).
     pub sq_sum: [f64; 5],
     /// Timestamp of last update (used for decay calculations).
-    pub last_update: Instant,
+    pub last_update: DateTime<Utc>,
     /// Craving statistics (optional).
     pub craving_sum: f64,
     pub craving_count: f64,
     /// Stimulus statistics: map from stimulus_id to count of points in this polytope
     /// that occurred under that stimulus.
     pub stimulus_counts: HashMap<String, u64>,
-    /// Also store total points per stimulus (maybe for correlation later).
 }
 
 impl MicroPolytope {
@@ -159,15 +299,15 @@ impl MicroPolytope {
 
     /// Incorporate a new tagged point into this polytope, applying time decay first.
     pub fn update(&mut self, point: &TaggedState, decay_rate: f64) {
-        let dt = point.timestamp.duration_since(self.last_update).as_secs_f64();
+        let dt = seconds_between(self.last_update, point.timestamp);
         if dt > 0.0 {
             self.decay(decay_rate.powf(dt));
         }
         let arr = point.state.as_array();
         self.weight += 1.0;
-        for i in 0..5 {
-            self.linear_sum[i] += arr[i];
-            self.sq_sum[i] += arr[i] * arr[i];
+        for (i, x) in arr.into_iter().enumerate() {
+            self.linear_sum[i] += x;
+            self.sq_sum[i] += x * x;
         }
         self.last_update = point.timestamp;
 
@@ -196,20 +336,30 @@ impl MicroPolytope {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::time::{Duration, Instant};
+    use chrono::Duration;
 
     #[test]
     fn test_centroid_and_radius() {
-        let now = Instant::now();
+        let now = Utc::now();
         let p1 = TaggedState {
             state: BiophysicalState::new(0.1, 0.2, 0.3, 0.4, 0.5),
             timestamp: now,
             stimulus: None,
+            quality: QualityFlags::default(),
+            subject_id: None,
+            device_id: None,
+            signal_quality: None,
+            session_phase: None,
         };
         let p2 = TaggedState {
             state: BiophysicalState::new(0.2, 0.3, 0.4, 0.5, 0.6),
-            timestamp: now + Duration::from_secs(1),
+            timestamp: now + Duration::seconds(1),
             stimulus: None,
+            quality: QualityFlags::default(),
+            subject_id: None,
+            device_id: None,
+            signal_quality: None,
+            session_phase: None,
         };
         let mut poly = MicroPolytope::from_point(&p1);
         poly.update(&p2, 0.99);
@@ -224,7 +374,7 @@ mod tests {
 
     #[test]
     fn test_stimulus_counts() {
-        let now = Instant::now();
+        let now = Utc::now();
         let stim1 = StimulusMetadata {
             stimulus_id: "abc".into(),
             stimulus_name: "test".into(),
@@ -234,6 +384,11 @@ mod tests {
             state: BiophysicalState::new(0.1, 0.2, 0.3, 0.4, 0.5),
             timestamp: now,
             stimulus: Some(stim1),
+            quality: QualityFlags::default(),
+            subject_id: None,
+            device_id: None,
+            signal_quality: None,
+            session_phase: None,
         };
         let poly = MicroPolytope::from_point(&p1);
         assert_eq!(poly.stimulus_counts.get("abc"), Some(&1));
@@ -1,6 +1,9 @@
 //! Neurorights constraints for the compiler.
 
-#[derive(Debug, Clone)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct NeurorightsConstraints {
     pub prohibit_thought_decoding: bool,
     pub require_consent_before_application: bool,
@@ -0,0 +1,334 @@
+//! Versioned compiler policy profiles.
+//!
+//! A profile is a JSON document that fully describes a `CompilerConfig`, so that
+//! hardware limits, the biophysical corridor, neurorights constraints and token
+//! validity live in reviewed files instead of binaries. Every profile has a
+//! content hash; the compiler stamps it on each approval and it is carried into
+//! session logs, so any session can be traced back to the policy that approved it.
+//!
+//! File format (schema version 1):
+//!
+//! ```json
+//! {
+//!   "schema_version": 1,
+//!   "name": "default-audio",
+//!   "version": "1.0.0",
+//!   "description": "Optional free text",
+//!   "compiler": {
+//!     "hardware": {
+//!       "max_amplitude": 1.0,
+//!       "min_frequency": 20.0,
+//!       "max_frequency": 20000.0,
+//!       "max_session_duration_sec": 3600.0,
+//!       "max_daily_duty": 0.3,
+//!       "biofield": {
+//!         "max_amplitude_ua": 100.0,
+//!         "min_frequency_hz": 0.5,
+//!         "max_frequency_hz": 100.0,
+//!         "max_duration_sec": 1800,
+//!         "waveforms": ["Sine", "Pulsed"]
+//!       }
+//!     },
+//!     "biophysical_corridor": {
+//!       "e": [0.0, 0.8], "m_prot": [0.0, 0.5], "s_bio": [0.0, 0.7],
+//!       "theta": [0.0, 1.0], "t": [-1.0, 1.0]
+//!     },
+//!     "neurorights": {
+//!       "prohibit_thought_decoding": true,
+//!       "require_consent_before_application": true,
+//!       "max_sessions_per_day": 4,
+//!       "max_cumulative_amplitude_per_session": 0.8
+//!     },
+//!     "token_validity_secs": 300,
+//!     "rules": {
+//!       "disabled": [],
+//!       "warn_only": ["beat_range"],
+//!       "beat_range_hz": [0.0, 30.0],
+//!       "plan": {
+//!         "max_amplitude_slew_per_sec": 0.05,
+//!         "max_carrier_slew_hz_per_sec": 20.0,
+//!         "max_beat_slew_hz_per_sec": 0.5,
+//!         "max_amplitude_seconds": 3600.0
+//!       },
+//!       "bioload": { "yellow_sd": 1.0, "red_sd": 2.0, "hysteresis_sd": 0.25 }
+//!     },
+//!     "suggestion": {
+//!       "enabled": true,
+//!       "amplitude": 0.1, "carrier_hz": 50.0, "beat_hz": 1.0,
+//!       "duty": 0.05, "session_duration_sec": 60.0
+//!     }
+//!   }
+//! }
+//! ```
+//!
+//! `hardware.biofield` is optional; without it biofield stimuli are always rejected.
+//! The `rules` section is optional; by default every registered rule runs and
+//! fails hard. Mandatory rules (hardware, corridor, neurorights, plan_dose, bioload)
+//! cannot be listed in `disabled` or `warn_only`. `rules.plan` holds the transition slew
+//! limits and the amplitude-time ceiling for session plans. The optional `suggestion` section sets the scales
+//! of the distance used to pick the nearest safe alternative on rejection.
+//!
+//! Unknown fields are rejected at parse time. After parsing, semantic checks
+//! verify that every range is ordered (`min < max`), every corridor interval is
+//! non-empty and all limits are finite and positive.
+
+use crate::compiler::rules::MANDATORY_RULES;
+use crate::compiler::CompilerConfig;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+/// The profile schema version understood by this build.
+pub const SUPPORTED_SCHEMA_VERSION: u32 = 1;
+
+/// Errors raised while loading or validating a profile.
+#[derive(Debug, thiserror::Error)]
+pub enum ProfileError {
+    #[error("Failed to read profile: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Profile does not match schema: {0}")]
+    Schema(#[from] serde_json::Error),
+    #[error("Unsupported profile schema version {found} (supported: {supported})")]
+    UnsupportedVersion { found: u32, supported: u32 },
+    #[error("Profile failed semantic checks: {}", .0.join("; "))]
+    Invalid(Vec<String>),
+}
+
+/// A versioned compiler policy profile as stored on disk.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CompilerProfile {
+    /// Version of the file format (see `SUPPORTED_SCHEMA_VERSION`).
+    pub schema_version: u32,
+    /// Short identifier of the policy (e.g., "default-audio").
+    pub name: String,
+    /// Policy revision chosen by the maintainer (e.g., "1.2.0").
+    pub version: String,
+    /// Optional free-text description.
+    #[serde(default)]
+    pub description: Option<String>,
+    /// The compiler configuration this profile defines.
+    pub compiler: CompilerConfig,
+}
+
+impl CompilerProfile {
+    /// Load, parse and validate a profile from a JSON file.
+    pub fn load(path: &Path) -> Result<Self, ProfileError> {
+        let contents = std::fs::read_to_string(path)?;
+        Self::from_json(&contents)
+    }
+
+    /// Parse and validate a profile from a JSON string.
+    pub fn from_json(json: &str) -> Result<Self, ProfileError> {
+        let profile: Self = serde_json::from_str(json)?;
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    /// Run the schema version check and all semantic checks.
+    pub fn validate(&self) -> Result<(), ProfileError> {
+        if self.schema_version != SUPPORTED_SCHEMA_VERSION {
+            return Err(ProfileError::UnsupportedVersion {
+                found: self.schema_version,
+                supported: SUPPORTED_SCHEMA_VERSION,
+            });
+        }
+        let mut problems = Vec::new();
+        if self.name.trim().is_empty() {
+            problems.push("name must not be empty".to_string());
+        }
+        if self.version.trim().is_empty() {
+            problems.push("version must not be empty".to_string());
+        }
+        problems.extend(validate_config(&self.compiler));
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ProfileError::Invalid(problems))
+        }
+    }
+
+    /// Content hash of the policy (see `policy_hash`).
+    pub fn hash(&self) -> String {
+        policy_hash(&self.compiler)
+    }
+}
+
+/// Compute the content hash of a compiler configuration.
+///
+/// The configuration is serialized to canonical JSON (object keys sorted, no
+/// whitespace) and hashed with SHA-256, so formatting and key order in the
+/// source file do not change the hash, while any change to a limit does.
+/// Profile metadata (name, version, description) is not part of the hash.
+pub fn policy_hash(config: &CompilerConfig) -> String {
+    canonical_hash(config)
+}
+
+/// SHA-256 of the canonical JSON serialization of a value, hex-encoded.
+pub fn canonical_hash<T: Serialize + ?Sized>(value: &T) -> String {
+    // `serde_json::Value` keeps object keys in sorted order, which makes the
+    // serialization canonical.
+    let canonical = serde_json::to_value(value)
+        .and_then(|v| serde_json::to_string(&v))
+        .expect("value is always serializable");
+    let mut hasher = Sha256::new();
+    hasher.update(canonical.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// Semantic checks on a compiler configuration. Returns one message per problem.
+pub fn validate_config(config: &CompilerConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let hw = &config.hardware;
+    check_positive(&mut problems, "hardware.max_amplitude", hw.max_amplitude);
+    check_positive(&mut problems, "hardware.min_frequency", hw.min_frequency);
+    check_positive(&mut problems, "hardware.max_frequency", hw.max_frequency);
+    check_range(&mut problems, "hardware frequency range", (hw.min_frequency, hw.max_frequency));
+    check_positive(&mut problems, "hardware.max_session_duration_sec", hw.max_session_duration_sec);
+    check_positive(&mut problems, "hardware.max_daily_duty", hw.max_daily_duty);
+    if hw.max_daily_duty > 1.0 {
+        problems.push(format!("hardware.max_daily_duty {} exceeds 1.0", hw.max_daily_duty));
+    }
+    if let Some(bf) = &hw.biofield {
+        check_positive(&mut problems, "hardware.biofield.max_amplitude_ua", bf.max_amplitude_ua);
+        check_positive(&mut problems, "hardware.biofield.min_frequency_hz", bf.min_frequency_hz);
+        check_range(&mut problems, "hardware.biofield frequency range", (bf.min_frequency_hz, bf.max_frequency_hz));
+        if bf.max_duration_sec == 0 {
+            problems.push("hardware.biofield.max_duration_sec must be positive".to_string());
+        }
+        if bf.waveforms.is_empty() {
+            problems.push("hardware.biofield.waveforms must not be empty".to_string());
+        }
+    }
+
+    let c = &config.biophysical_corridor;
+    for (name, range) in [
+        ("biophysical_corridor.e", c.e),
+        ("biophysical_corridor.m_prot", c.m_prot),
+        ("biophysical_corridor.s_bio", c.s_bio),
+        ("biophysical_corridor.theta", c.theta),
+        ("biophysical_corridor.t", c.t),
+    ] {
+        check_range(&mut problems, name, range);
+    }
+
+    let nr = &config.neurorights;
+    if !nr.prohibit_thought_decoding {
+        problems.push("neurorights.prohibit_thought_decoding must be true".to_string());
+    }
+    if nr.max_sessions_per_day == 0 {
+        problems.push("neurorights.max_sessions_per_day must be at least 1".to_string());
+    }
+    check_positive(
+        &mut problems,
+        "neurorights.max_cumulative_amplitude_per_session",
+        nr.max_cumulative_amplitude_per_session,
+    );
+
+    if config.token_validity_duration.is_zero() {
+        problems.push("token_validity_secs must be positive".to_string());
+    }
+
+    let rules = &config.rules;
+    for id in rules.disabled.iter().chain(&rules.warn_only) {
+        if MANDATORY_RULES.contains(&id.as_str()) {
+            problems.push(format!("rule {} is mandatory and cannot be disabled or downgraded", id));
+        }
+    }
+    check_range(&mut problems, "rules.beat_range_hz", rules.beat_range_hz);
+    check_positive(&mut problems, "rules.plan.max_amplitude_slew_per_sec", rules.plan.max_amplitude_slew_per_sec);
+    check_positive(&mut problems, "rules.plan.max_carrier_slew_hz_per_sec", rules.plan.max_carrier_slew_hz_per_sec);
+    check_positive(&mut problems, "rules.plan.max_beat_slew_hz_per_sec", rules.plan.max_beat_slew_hz_per_sec);
+    check_positive(&mut problems, "rules.plan.max_amplitude_seconds", rules.plan.max_amplitude_seconds);
+    check_positive(&mut problems, "rules.bioload.yellow_sd", rules.bioload.yellow_sd);
+    check_range(&mut problems, "rules.bioload band thresholds", (rules.bioload.yellow_sd, rules.bioload.red_sd));
+    if !rules.bioload.hysteresis_sd.is_finite() || rules.bioload.hysteresis_sd < 0.0 {
+        problems.push(format!("rules.bioload.hysteresis_sd must not be negative (got {})", rules.bioload.hysteresis_sd));
+    }
+
+    let s = &config.suggestion;
+    check_positive(&mut problems, "suggestion.amplitude", s.amplitude);
+    check_positive(&mut problems, "suggestion.carrier_hz", s.carrier_hz);
+    check_positive(&mut problems, "suggestion.beat_hz", s.beat_hz);
+    check_positive(&mut problems, "suggestion.duty", s.duty);
+    check_positive(&mut problems, "suggestion.session_duration_sec", s.session_duration_sec);
+
+    problems
+}
+
+fn check_positive(problems: &mut Vec<String>, name: &str, value: f64) {
+    if !value.is_finite() || value <= 0.0 {
+        problems.push(format!("{} must be a finite positive number (got {})", name, value));
+    }
+}
+
+fn check_range(problems: &mut Vec<String>, name: &str, (min, max): (f64, f64)) {
+    if !min.is_finite() || !max.is_finite() {
+        problems.push(format!("{} bounds must be finite (got [{}, {}])", name, min, max));
+    } else if min >= max {
+        problems.push(format!("{} is empty: min {} must be below max {}", name, min, max));
+    }
+}
+
+/// Serde helper storing a `Duration` as whole seconds.
+pub(crate) mod duration_secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u64(d.as_secs())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        u64::deserialize(d).map(Duration::from_secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFAULT_PROFILE: &str = include_str!("../profiles/default.json");
+
+    #[test]
+    fn test_default_profile_loads() {
+        let profile = CompilerProfile::from_json(DEFAULT_PROFILE).unwrap();
+        assert_eq!(profile.schema_version, SUPPORTED_SCHEMA_VERSION);
+        assert_eq!(profile.hash().len(), 64);
+    }
+
+    #[test]
+    fn test_hash_ignores_formatting_and_metadata() {
+        let a = CompilerProfile::from_json(DEFAULT_PROFILE).unwrap();
+        let mut value: serde_json::Value = serde_json::from_str(DEFAULT_PROFILE).unwrap();
+        value["name"] = "renamed".into();
+        let b = CompilerProfile::from_json(&serde_json::to_string(&value).unwrap()).unwrap();
+        assert_eq!(a.hash(), b.hash());
+
+        value["compiler"]["hardware"]["max_amplitude"] = 0.9.into();
+        let c = CompilerProfile::from_json(&serde_json::to_string(&value).unwrap()).unwrap();
+        assert_ne!(a.hash(), c.hash());
+    }
+
+    #[test]
+    fn test_rejects_unknown_fields_and_empty_corridor() {
+        let mut value: serde_json::Value = serde_json::from_str(DEFAULT_PROFILE).unwrap();
+        value["compiler"]["hardware"]["max_amplitud"] = 1.0.into();
+        assert!(matches!(
+            CompilerProfile::from_json(&value.to_string()),
+            Err(ProfileError::Schema(_))
+        ));
+
+        let mut value: serde_json::Value = serde_json::from_str(DEFAULT_PROFILE).unwrap();
+        value["compiler"]["biophysical_corridor"]["e"] = serde_json::json!([0.5, 0.5]);
+        value["compiler"]["rules"]["disabled"] = serde_json::json!(["corridor"]);
+        match CompilerProfile::from_json(&value.to_string()) {
+            Err(ProfileError::Invalid(problems)) => {
+                assert!(problems.iter().any(|p| p.contains("biophysical_corridor.e")));
+                assert!(problems.iter().any(|p| p.contains("rule corridor is mandatory")));
+            }
+            other => panic!("expected semantic failure, got {:?}", other),
+        }
+    }
+}
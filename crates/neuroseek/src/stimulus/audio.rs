@@ -1,11 +1,28 @@
 //! Audio stimulus execution: generates sound and logs session data.
+//!
+//! Audio is synthesised block by block (`neuroseek_audio::stream`) into the WAV file
+//! and, when playing, into a `player` command at the same time, so playback is never a
+//! separate process outliving the session. While a session runs,
+//! `AudioStimulusExecutor::active_session` gives a handle to stop it with a short
+//! fade-out or to steer it within its polytope.
+//!
+//! With a `SessionEventLog` attached, playback is bracketed by start and stop events
+//! carrying the session id, so ingest can attribute states to the session by time.
+//! The handle is taken before the start is logged and released after the stop is, so
+//! a session stopped through it is always logged as stopped. Sessions only generated
+//! (without `play_after_generate`) deliver nothing to the subject and are not logged;
+//! their outcome is the returned `SessionLog` alone.
+//! The `active_stimulus` gauge is set and cleared at the same points, labelled with
+//! the same stimulus id (the stimulus name).
 
+use super::event_log::{SessionEventKind, SessionEventLog};
 use crate::compiler::AudioStimulus;
 use crate::governance::{NanoswarmTelemetry, SessionLog};
+use crate::model::{AudioParams, StimulusMetadata};
 use neuroseek_audio::audio_nanopolytope::AudioState;
-use neuroseek_audio::generator::{generate_wav_from_fixed, generate_wav_from_polytope};
-use neuroseek_audio::governance::SessionLog as AudioSessionLog;
-use std::fs;
+use neuroseek_audio::governance::{clear_active_stimulus, set_active_stimulus, SessionLog as AudioSessionLog};
+use neuroseek_audio::sink::{AudioSink, PcmSink, WavSink};
+use neuroseek_audio::stream::{render, StreamConfig, StreamControl, SynthStream};
 use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -16,8 +33,21 @@ use tracing::{info, error};
 pub struct AudioOutputConfig {
     pub sample_rate: u32,
     pub output_dir: PathBuf,
-    /// If true, also play the generated file using the system audio.
+    /// If true, also play the session through `player` as it is synthesised, so that
+    /// it can be stopped midway.
     pub play_after_generate: bool,
+    /// Command that plays raw signed 16-bit little-endian stereo PCM from its stdin,
+    /// e.g. `aplay -q -f S16_LE -c 2 -r 44100`. Defaults to `aplay` at `sample_rate`.
+    pub player: Option<String>,
+}
+
+impl AudioOutputConfig {
+    /// The player command, or `aplay` at `sample_rate` if none is set.
+    pub fn player_command(&self) -> String {
+        self.player
+            .clone()
+            .unwrap_or_else(|| format!("aplay -q -f S16_LE -c 2 -r {}", self.sample_rate))
+    }
 }
 
 /// Executes an audio stimulus.
@@ -25,6 +55,10 @@ pub struct AudioStimulusExecutor {
     config: AudioOutputConfig,
     /// Optional: handle to a telemetry source to get nanoswarm data before/after.
     telemetry_source: Option<Arc<Mutex<dyn TelemetryProvider + Send>>>,
+    /// Optional: log of session start and stop events.
+    event_log: Option<Mutex<SessionEventLog>>,
+    /// Control of the session being synthesised, if any.
+    active: std::sync::Mutex<Option<StreamControl>>,
 }
 
 impl AudioStimulusExecutor {
@@ -32,6 +66,8 @@ impl AudioStimulusExecutor {
         Self {
             config,
             telemetry_source: None,
+            event_log: None,
+            active: std::sync::Mutex::new(None),
         }
     }
 
@@ -41,8 +77,60 @@ impl AudioStimulusExecutor {
         self
     }
 
+    /// Record the start and stop of every played session in `log`; sessions that are
+    /// only generated are not recorded.
+    pub fn with_event_log(mut self, log: SessionEventLog) -> Self {
+        self.event_log = Some(Mutex::new(log));
+        self
+    }
+
+    /// Control of the session currently being synthesised, to stop or steer it.
+    pub fn active_session(&self) -> Option<StreamControl> {
+        self.active.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Stop the running session with a short fade-out. Returns false if none is running.
+    pub fn stop(&self) -> bool {
+        match self.active_session() {
+            Some(control) => {
+                control.stop();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn set_active(&self, control: Option<StreamControl>) {
+        *self.active.lock().unwrap_or_else(|e| e.into_inner()) = control;
+    }
+
+    /// Log a session event and set or clear the gauge of the delivered state.
+    async fn record_event(
+        &self,
+        kind: SessionEventKind,
+        session_id: &str,
+        stimulus: &StimulusMetadata,
+        delivered: Option<&AudioState>,
+    ) {
+        match (kind, delivered) {
+            (SessionEventKind::Start, Some(state)) => {
+                set_active_stimulus(session_id, &stimulus.stimulus_id, &stimulus.stimulus_name, state)
+            }
+            (SessionEventKind::Start, None) => {}
+            (SessionEventKind::Stop, _) => clear_active_stimulus(session_id),
+        }
+        if let Some(log) = &self.event_log {
+            if let Err(e) = log.lock().await.record(kind, session_id, stimulus) {
+                error!("Failed to log {:?} of session {}: {}", kind, session_id, e);
+            }
+        }
+    }
+
     /// Execute an approved audio stimulus. Returns the path to the generated file and a session log.
-    pub async fn execute(&self, stimulus: &AudioStimulus) -> anyhow::Result<(PathBuf, SessionLog)> {
+    /// `profile_hash` is the hash carried by the approval and is recorded in the log.
+    pub async fn execute(&self, stimulus: &AudioStimulus, profile_hash: &str) -> anyhow::Result<(PathBuf, SessionLog)> {
+        let session_id = uuid::Uuid::new_v4().to_string();
+
         // Get telemetry before (if available)
         let telemetry_before = if let Some(ref provider) = self.telemetry_source {
             Some(provider.lock().await.get_telemetry().await?)
@@ -54,28 +142,60 @@ impl AudioStimulusExecutor {
         let timestamp = chrono::Utc::now().format("%Y%m%d_%H%M%S");
         let filename = self.config.output_dir.join(format!("{}_{}.wav", stimulus.name, timestamp));
 
-        // Generate audio based on protocol type
-        match &stimulus.protocol {
-            neuroseek_audio::config::Protocol::Fixed(state) => {
-                generate_wav_from_fixed(state, self.config.sample_rate, filename.to_str().unwrap())?;
-            }
+        // Set up the stream based on protocol type
+        let stream_config = StreamConfig { sample_rate: self.config.sample_rate, ..Default::default() };
+        let mut stream = match &stimulus.protocol {
+            neuroseek_audio::config::Protocol::Fixed(state) => SynthStream::fixed(state, stream_config),
             neuroseek_audio::config::Protocol::Polytope(poly) => {
                 // For polytope, we need to choose a trajectory. Here we just pick two points (start/end)
                 // In a real implementation, you'd have a controller that picks vertices based on craving state.
                 // For now, we pick the center as start and a slightly different point as end.
                 let center = poly.center().ok_or_else(|| anyhow::anyhow!("Polytope has no center"))?;
-                let mut end = center.clone();
+                let mut end = center;
                 end.amplitude = (end.amplitude + 0.1).min(1.0);
                 end.beat_hz = (end.beat_hz + 2.0).min(30.0);
-                generate_wav_from_polytope(poly, &center, &end, self.config.sample_rate, filename.to_str().unwrap())?;
+                SynthStream::trajectory(poly, &center, &end, stream_config)
+                    .map_err(|_| anyhow::anyhow!("Start or end point not inside the safe polytope"))?
             }
+        };
+        let mut sinks: Vec<Box<dyn AudioSink>> = vec![Box::new(WavSink::create(&filename, self.config.sample_rate)?)];
+        if self.config.play_after_generate {
+            sinks.push(Box::new(PcmSink::spawn(&self.config.player_command())?));
         }
 
-        // Play if requested
-        if self.config.play_after_generate {
-            if let Err(e) = self.play_audio(&filename).await {
-                error!("Failed to play audio: {}", e);
+        let delivered = match &stimulus.protocol {
+            neuroseek_audio::config::Protocol::Fixed(state) => Some(*state),
+            neuroseek_audio::config::Protocol::Polytope(poly) => poly.center(),
+        };
+        let metadata = self.config.play_after_generate.then(|| {
+            StimulusMetadata {
+                stimulus_id: stimulus.name.clone(),
+                stimulus_name: stimulus.name.clone(),
+                audio_params: delivered.map(|s| AudioParams {
+                    carrier_hz: s.carrier_hz,
+                    beat_hz: s.beat_hz,
+                    amplitude: s.amplitude,
+                }),
             }
+        });
+
+        // Synthesise, playing along if requested
+        self.set_active(Some(stream.control()));
+        if let Some(metadata) = &metadata {
+            self.record_event(SessionEventKind::Start, &session_id, metadata, delivered.as_ref()).await;
+        }
+        let rendered = tokio::task::spawn_blocking(move || {
+            let frames = render(&mut stream, &mut sinks)?;
+            Ok::<_, anyhow::Error>((frames, stream.stopped()))
+        })
+        .await;
+        if let Some(metadata) = &metadata {
+            self.record_event(SessionEventKind::Stop, &session_id, metadata, None).await;
+        }
+        self.set_active(None);
+        let (frames, stopped) = rendered??;
+        if stopped {
+            info!("Session {} stopped after {:.1} s", session_id, frames as f64 / self.config.sample_rate as f64);
         }
 
         // Get telemetry after
@@ -86,7 +206,7 @@ impl AudioStimulusExecutor {
         };
 
         // Create session log
-        let audio_log = match &stimulus.protocol {
+        let mut audio_log = match &stimulus.protocol {
             neuroseek_audio::config::Protocol::Fixed(state) => {
                 // We don't have a polytope reference here, so we need to construct a minimal log.
                 // For now, we create a dummy polytope with default K/D/DW.
@@ -113,22 +233,19 @@ impl AudioStimulusExecutor {
             }
         };
 
+        audio_log.session_id = session_id.clone();
         let session_log = SessionLog {
+            session_id,
+            timestamp: audio_log.timestamp,
+            stimulus_name: stimulus.name.clone(),
+            profile_hash: profile_hash.to_string(),
             audio_log,
+            telemetry_before,
             telemetry_after,
-            stimulus_name: stimulus.name.clone(),
-            // ... other fields
         };
 
         Ok((filename, session_log))
     }
-
-    async fn play_audio(&self, path: &PathBuf) -> anyhow::Result<()> {
-        // Use rodio or similar to play the file.
-        // For now, we just log.
-        info!("Would play audio from {}", path.display());
-        Ok(())
-    }
 }
 
 /// Trait for telemetry provider (e.g., from Prometheus or nanoswarm).
@@ -153,3 +270,72 @@ impl TelemetryProvider for DummyTelemetryProvider {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stimulus::event_log::SessionEvent;
+    use std::fs;
+
+    #[tokio::test]
+    async fn test_only_played_sessions_are_logged() {
+        let dir = std::env::temp_dir().join(format!("neuroseek_play_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let log_path = dir.join("events.ndjson");
+        let executor = Arc::new(
+            AudioStimulusExecutor::new(AudioOutputConfig {
+                sample_rate: 8000,
+                output_dir: dir.clone(),
+                play_after_generate: true,
+                player: Some("dd of=/dev/null status=none".into()),
+            })
+            .with_event_log(SessionEventLog::open(&log_path).unwrap()),
+        );
+        let state =
+            AudioState { amplitude: 0.3, carrier_hz: 250.0, beat_hz: 10.0, duty: 0.1, session_duration_sec: 600.0 };
+        let stimulus = AudioStimulus {
+            protocol: neuroseek_audio::config::Protocol::Fixed(state),
+            name: "calm".into(),
+            description: None,
+        };
+        let session = {
+            let executor = executor.clone();
+            tokio::spawn(async move { executor.execute(&stimulus, "hash").await })
+        };
+        while !executor.stop() {
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        }
+        let (wav, _) = session.await.unwrap().unwrap();
+
+        // Ten minutes were cut short after the fade, and the stop was logged.
+        assert!(fs::metadata(&wav).unwrap().len() < 8000 * 4 * 10);
+        let kinds: Vec<SessionEventKind> = fs::read_to_string(&log_path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<SessionEvent>(line).unwrap().kind)
+            .collect();
+        assert_eq!(kinds, [SessionEventKind::Start, SessionEventKind::Stop]);
+        assert!(!executor.stop());
+
+        // A session generated without playing leaves the log untouched.
+        let generated_log = dir.join("generated.ndjson");
+        let executor = AudioStimulusExecutor::new(AudioOutputConfig {
+            sample_rate: 8000,
+            output_dir: dir.clone(),
+            play_after_generate: false,
+            player: None,
+        })
+        .with_event_log(SessionEventLog::open(&generated_log).unwrap());
+        let short = AudioState { session_duration_sec: 1.0, ..state };
+        let stimulus = AudioStimulus {
+            protocol: neuroseek_audio::config::Protocol::Fixed(short),
+            name: "calm".into(),
+            description: None,
+        };
+        let (wav, log) = executor.execute(&stimulus, "hash").await.unwrap();
+        assert!(fs::metadata(&wav).unwrap().len() > 8000 * 4 / 2);
+        assert_eq!(log.profile_hash, "hash");
+        assert_eq!(fs::read_to_string(&generated_log).unwrap(), "");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
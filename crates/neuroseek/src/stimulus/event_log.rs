@@ -0,0 +1,79 @@
+//! Local log of stimulus sessions starting and stopping.
+//!
+//! The executor appends one NDJSON record per event,
+//!
+//! ```text
+//! {"timestamp":"2024-05-01T12:00:00Z","session_id":"…","kind":"start","stimulus":{...},"subject_id":"s1"}
+//! {"timestamp":"2024-05-01T12:10:00Z","session_id":"…","kind":"stop","stimulus":{...},"subject_id":"s1"}
+//! ```
+//!
+//! so ingest can attribute states to sessions by timestamp (see `ingest::phase`)
+//! instead of relying on the `active_stimulus` gauge being set when it polls. Each
+//! record, newline included, is built in one buffer and written with one `write_all`
+//! on a file opened with `O_APPEND`, so it lands at the end of the file even when
+//! several writers share the log. A record this size normally takes a single write;
+//! should the OS accept only part of it, the rest follows before this writer's next
+//! record, and a reader catching the log in between sees a line without its newline.
+
+use crate::model::StimulusMetadata;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Whether a session started or stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionEventKind {
+    Start,
+    Stop,
+}
+
+/// One record of the log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionEvent {
+    pub timestamp: DateTime<Utc>,
+    pub session_id: String,
+    pub kind: SessionEventKind,
+    pub stimulus: StimulusMetadata,
+    /// Subject the session was delivered to; None applies to every subject.
+    #[serde(default)]
+    pub subject_id: Option<String>,
+}
+
+/// Append-only writer of the log.
+#[derive(Debug)]
+pub struct SessionEventLog {
+    file: File,
+    subject_id: Option<String>,
+}
+
+impl SessionEventLog {
+    /// Open the log for appending, creating it if needed.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file, subject_id: None })
+    }
+
+    /// Record sessions as delivered to `subject_id`.
+    pub fn with_subject(mut self, subject_id: Option<String>) -> Self {
+        self.subject_id = subject_id;
+        self
+    }
+
+    /// Append an event stamped now.
+    pub fn record(&mut self, kind: SessionEventKind, session_id: &str, stimulus: &StimulusMetadata) -> io::Result<()> {
+        let event = SessionEvent {
+            timestamp: Utc::now(),
+            session_id: session_id.to_string(),
+            kind,
+            stimulus: stimulus.clone(),
+            subject_id: self.subject_id.clone(),
+        };
+        let mut line = serde_json::to_string(&event)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.flush()
+    }
+}
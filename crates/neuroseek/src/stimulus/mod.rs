@@ -1,6 +1,8 @@
 //! Stimulus execution: audio, and in the future other modalities.
 
 pub mod audio;
+pub mod event_log;
 
 // Re-export key types.
 pub use audio::{AudioOutputConfig, AudioStimulusExecutor};
+pub use event_log::{SessionEvent, SessionEventKind, SessionEventLog};
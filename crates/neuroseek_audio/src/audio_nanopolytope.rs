@@ -1,9 +1,17 @@
 //! Defines the 5D audio parameter space and safe polytope with linear constraints.
 
+use crate::transfer::{LinearTransfer, TransferUncertainty};
 use nalgebra::{DMatrix, DVector};
 use serde::{Deserialize, Serialize};
 use std::f64;
 
+/// Tolerance used for constraint activity and vertex de-duplication.
+const VERTEX_TOLERANCE: f64 = 1e-7;
+
+/// Half-width of the auxiliary box used to detect unbounded polytopes during vertex
+/// enumeration. Larger than any meaningful audio coordinate (Hz, seconds).
+const BOUNDING_BOX_LIMIT: f64 = 1e7;
+
 /// 5D audio state vector.
 /// Dimensions: (amplitude, carrier_hz, beat_hz, duty, session_duration_sec)
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
@@ -56,6 +64,9 @@ pub struct AudioNanopolytope {
     /// For simplicity, we store a linear approximation: z = M * x + offset.
     pub transfer_matrix: Option<[[f64; 5]; 5]>, // 5x5 matrix mapping audio to z
     pub transfer_offset: Option<[f64; 5]>,
+    /// Optional: per-coefficient uncertainty of the fitted transfer.
+    #[serde(default)]
+    pub transfer_uncertainty: Option<TransferUncertainty>,
 }
 
 impl AudioNanopolytope {
@@ -70,6 +81,7 @@ impl AudioNanopolytope {
             dw,
             transfer_matrix: None,
             transfer_offset: None,
+            transfer_uncertainty: None,
         }
     }
 
@@ -114,18 +126,105 @@ impl AudioNanopolytope {
         self
     }
 
+    /// Attach per-coefficient uncertainty intervals to the transfer function.
+    pub fn with_transfer_uncertainty(mut self, uncertainty: TransferUncertainty) -> Self {
+        self.transfer_uncertainty = Some(uncertainty);
+        self
+    }
+
+    /// The transfer function of this polytope, if one is set.
+    pub fn transfer(&self) -> Option<LinearTransfer> {
+        match (&self.transfer_matrix, &self.transfer_offset) {
+            (Some(m), Some(off)) => Some(LinearTransfer {
+                matrix: *m,
+                offset: *off,
+                uncertainty: self.transfer_uncertainty,
+            }),
+            _ => None,
+        }
+    }
+
     /// Map an audio state to the predicted biophysical state z = (E, Mprot, Sbio, theta, T).
     pub fn map_to_biophysical(&self, audio: &AudioState) -> Option<[f64; 5]> {
-        match (&self.transfer_matrix, &self.transfer_offset) {
-            (Some(m), Some(off)) => {
-                let x = audio.to_vector();
-                let mut z = [0.0; 5];
-                for i in 0..5 {
-                    z[i] = off[i] + (0..5).map(|j| m[i][j] * x[j]).sum::<f64>();
-                }
-                Some(z)
+        self.transfer().map(|t| t.nominal(audio))
+    }
+
+    /// Map an audio state to the interval of biophysical states allowed by the
+    /// transfer uncertainty (nominal prediction if no uncertainty is set).
+    pub fn map_to_biophysical_interval(&self, audio: &AudioState) -> Option<[(f64, f64); 5]> {
+        self.transfer().map(|t| t.interval(audio))
+    }
+
+    /// Enumerate the vertices of the polytope.
+    ///
+    /// Every choice of 5 constraints is made active and the resulting linear system
+    /// solved; solutions satisfying all constraints are vertices. An auxiliary box of
+    /// half-width `BOUNDING_BOX_LIMIT` is added so that unbounded polytopes show up as
+    /// vertices on that box. Returns `None` if the polytope is empty or unbounded.
+    pub fn vertices(&self) -> Option<Vec<AudioState>> {
+        let mut rows: Vec<[f64; 5]> = self.a.clone();
+        let mut rhs: Vec<f64> = self.b.clone();
+        let user_constraints = rows.len();
+        for i in 0..5 {
+            let mut row = [0.0; 5];
+            row[i] = 1.0;
+            rows.push(row);
+            rhs.push(BOUNDING_BOX_LIMIT);
+            row[i] = -1.0;
+            rows.push(row);
+            rhs.push(BOUNDING_BOX_LIMIT);
+        }
+
+        let mut vertices: Vec<DVector<f64>> = Vec::new();
+        let mut unbounded = false;
+        for_each_combination(rows.len(), 5, &mut |combo| {
+            let m = DMatrix::from_fn(5, 5, |r, c| rows[combo[r]][c]);
+            let rhs_vec = DVector::from_fn(5, |r, _| rhs[combo[r]]);
+            let Some(x) = m.lu().solve(&rhs_vec) else { return };
+            let feasible = rows.iter().zip(&rhs).all(|(row, &bound)| {
+                let lhs: f64 = row.iter().zip(x.iter()).map(|(a, x)| a * x).sum();
+                lhs <= bound + VERTEX_TOLERANCE * bound.abs().max(1.0)
+            });
+            if !feasible {
+                return;
             }
-            _ => None,
+            if combo.iter().any(|&k| k >= user_constraints) {
+                // A feasible point on the auxiliary box means the polytope extends that far.
+                unbounded = true;
+                return;
+            }
+            if !vertices.iter().any(|v| (v - &x).amax() <= VERTEX_TOLERANCE * x.amax().max(1.0)) {
+                vertices.push(x);
+            }
+        });
+
+        if unbounded || vertices.is_empty() {
+            None
+        } else {
+            Some(vertices.into_iter().map(AudioState::from_vector).collect())
+        }
+    }
+}
+
+/// Call `f` with every k-element combination of `0..n` (in lexicographic order).
+fn for_each_combination(n: usize, k: usize, f: &mut dyn FnMut(&[usize])) {
+    if k > n {
+        return;
+    }
+    let mut combo: Vec<usize> = (0..k).collect();
+    loop {
+        f(&combo);
+        // Find the rightmost index that can still be incremented.
+        let mut i = k;
+        while i > 0 && combo[i - 1] == n - k + i - 1 {
+            i -= 1;
+        }
+        if i == 0 {
+            return;
+        }
+        combo[i - 1] += 1;
+        for j in i..k {
+            combo[j] = combo[j - 1] + 1;
         }
     }
 }
@@ -147,6 +246,19 @@ pub enum ConstraintError {
     OutsidePolytope,
 }
 
+impl AudioNanopolytope {
+    /// Compute a central point as the average of the vertices.
+    /// By convexity it lies inside the polytope. Returns None if the polytope is empty or unbounded.
+    pub fn center(&self) -> Option<AudioState> {
+        let vertices = self.vertices()?;
+        let n = vertices.len() as f64;
+        let sum = vertices
+            .iter()
+            .fold(DVector::zeros(5), |acc: DVector<f64>, v| acc + v.to_vector());
+        Some(AudioState::from_vector(sum / n))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -188,14 +300,61 @@ mod tests {
         };
         assert!(!poly.contains(&outside));
     }
-}
 
-impl AudioNanopolytope {
-    /// Compute the center if the polytope is axis-aligned (i.e., constraints are simple bounds).
-    /// Returns None if not axis-aligned.
-    pub fn center(&self) -> Option<AudioState> {
-        // This is a placeholder. In reality, you'd need to parse constraints to find bounds.
-        // For now, we return None.
-        None
+    fn box_polytope() -> AudioNanopolytope {
+        let mut a = Vec::new();
+        let mut b = Vec::new();
+        let bounds = [(0.1, 0.5), (100.0, 400.0), (4.0, 20.0), (0.05, 0.3), (60.0, 600.0)];
+        for (i, (lo, hi)) in bounds.iter().enumerate() {
+            let mut row = [0.0; 5];
+            row[i] = 1.0;
+            a.push(row);
+            b.push(*hi);
+            row[i] = -1.0;
+            a.push(row);
+            b.push(-lo);
+        }
+        AudioNanopolytope::new("box".into(), a, b, 0.9, 0.2, 0.1)
+    }
+
+    #[test]
+    fn test_vertices_and_center_of_box() {
+        let poly = box_polytope();
+        let vertices = poly.vertices().unwrap();
+        assert_eq!(vertices.len(), 32);
+        assert!(vertices.iter().all(|v| poly.contains(v)));
+
+        let center = poly.center().unwrap();
+        assert!((center.amplitude - 0.3).abs() < 1e-9);
+        assert!((center.carrier_hz - 250.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_unbounded_polytope_has_no_vertices() {
+        // Only an upper bound on amplitude: unbounded below and in all other dimensions.
+        let poly = AudioNanopolytope::new("open".into(), vec![[1.0, 0.0, 0.0, 0.0, 0.0]], vec![0.5], 0.0, 0.0, 0.0);
+        assert!(poly.vertices().is_none());
+        assert!(poly.center().is_none());
+    }
+
+    #[test]
+    fn test_transfer_interval_widens_with_uncertainty() {
+        let mut matrix = [[0.0; 5]; 5];
+        matrix[0][0] = 0.2;
+        let mut delta = [[0.0; 5]; 5];
+        delta[0][0] = 0.05;
+        let poly = box_polytope()
+            .with_transfer(matrix, [0.1, 0.0, 0.0, 0.0, 0.0])
+            .with_transfer_uncertainty(TransferUncertainty { matrix: delta, offset: [0.01, 0.0, 0.0, 0.0, 0.0] });
+
+        let state = poly.center().unwrap();
+        let nominal = poly.map_to_biophysical(&state).unwrap();
+        let interval = poly.map_to_biophysical_interval(&state).unwrap();
+        assert!((nominal[0] - (0.1 + 0.2 * 0.3)).abs() < 1e-9);
+        assert!((interval[0].1 - nominal[0] - (0.05 * 0.3 + 0.01)).abs() < 1e-9);
+
+        // Worst case over the polytope is reached at the amplitude = 0.5 vertices.
+        let worst = poly.transfer().unwrap().worst_case(&poly.vertices().unwrap()).unwrap();
+        assert!((worst[0].1 - (0.1 + 0.25 * 0.5 + 0.01)).abs() < 1e-9);
     }
 }
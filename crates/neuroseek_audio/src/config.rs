@@ -10,7 +10,7 @@ pub enum Protocol {
     /// A single fixed audio state.
     Fixed(AudioState),
     /// A polytope of safe audio states.
-    Polytope(AudioNanopolytope),
+    Polytope(Box<AudioNanopolytope>),
 }
 
 impl Protocol {
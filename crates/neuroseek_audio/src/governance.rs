@@ -2,6 +2,8 @@
 
 use crate::audio_nanopolytope::{AudioNanopolytope, AudioState};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
 use std::time::SystemTime;
 use prometheus::*; // hypothetical; in practice you'd use a Prometheus client crate.
 
@@ -60,31 +62,86 @@ impl SessionLog {
     }
 }
 
-/// Prometheus metrics for active stimulus.
+// Prometheus metrics for active stimulus.
 lazy_static::lazy_static! {
     static ref ACTIVE_STIMULUS: GaugeVec = register_gauge_vec!(
         "active_stimulus",
         "Indicates an active stimulus with metadata as labels",
         &["id", "name", "carrier", "beat", "amplitude"]
     ).unwrap();
+    /// Label values of each active session, needed to remove exactly its series.
+    static ref ACTIVE_LABELS: Mutex<HashMap<String, [String; 5]>> = Mutex::new(HashMap::new());
 }
 
 /// Set Prometheus metrics when an audio session starts.
-pub fn set_active_stimulus(session_id: &str, name: &str, state: &AudioState) {
-    ACTIVE_STIMULUS
-        .with_label_values(&[
-            session_id,
-            name,
-            &state.carrier_hz.to_string(),
-            &state.beat_hz.to_string(),
-            &state.amplitude.to_string(),
-        ])
-        .set(1.0);
+///
+/// The `id` label carries `stimulus_id`, the same id as the session event log and
+/// the tags of ingested states; sessions are tracked by `session_id`, so that ending
+/// one session leaves the series of an identical session still running.
+pub fn set_active_stimulus(session_id: &str, stimulus_id: &str, name: &str, state: &AudioState) {
+    let labels = [
+        stimulus_id.to_string(),
+        name.to_string(),
+        state.carrier_hz.to_string(),
+        state.beat_hz.to_string(),
+        state.amplitude.to_string(),
+    ];
+    ACTIVE_STIMULUS.with_label_values(&labels.each_ref().map(String::as_str)).set(1.0);
+    let mut active = ACTIVE_LABELS.lock().unwrap_or_else(|e| e.into_inner());
+    // A session restarted with other parameters replaces its previous series.
+    if let Some(previous) = active.insert(session_id.to_string(), labels) {
+        remove_unused(&active, &previous);
+    }
 }
 
 /// Clear Prometheus metrics when session ends.
 pub fn clear_active_stimulus(session_id: &str) {
-    ACTIVE_STIMULUS
-        .remove_label_values(&[session_id, "", "", "", ""]) // careful: need exact labels to remove
-        .unwrap_or(());
+    let mut active = ACTIVE_LABELS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(labels) = active.remove(session_id) {
+        remove_unused(&active, &labels);
+    }
+}
+
+/// Remove the series of `labels` unless another active session still has it.
+fn remove_unused(active: &HashMap<String, [String; 5]>, labels: &[String; 5]) {
+    if !active.values().any(|other| other == labels) {
+        let _ = ACTIVE_STIMULUS.remove_label_values(&labels.each_ref().map(String::as_str));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn active_stimuli() -> Vec<String> {
+        prometheus::gather()
+            .iter()
+            .filter(|family| family.get_name() == "active_stimulus")
+            .flat_map(|family| family.get_metric())
+            .flat_map(|metric| metric.get_label())
+            .filter(|label| label.get_name() == "id")
+            .map(|label| label.get_value().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_clear_removes_only_its_session() {
+        let state = AudioState { amplitude: 0.4, carrier_hz: 220.0, beat_hz: 6.0, duty: 1.0, session_duration_sec: 600.0 };
+        let is_active = |id: &str| active_stimuli().iter().any(|active| active == id);
+        set_active_stimulus("session-a", "governance-test-calm", "Calm", &state);
+        set_active_stimulus("session-b", "governance-test-focus", "Focus", &AudioState { beat_hz: 12.0, ..state });
+        clear_active_stimulus("session-a");
+        assert!(!is_active("governance-test-calm"));
+        assert!(is_active("governance-test-focus"));
+        clear_active_stimulus("session-b");
+        assert!(!is_active("governance-test-focus"));
+
+        // Two sessions of the same stimulus share its series until both have ended.
+        set_active_stimulus("session-c", "governance-test-calm", "Calm", &state);
+        set_active_stimulus("session-d", "governance-test-calm", "Calm", &state);
+        clear_active_stimulus("session-c");
+        assert!(is_active("governance-test-calm"));
+        clear_active_stimulus("session-d");
+        assert!(!is_active("governance-test-calm"));
+    }
 }
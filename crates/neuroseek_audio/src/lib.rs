@@ -4,15 +4,21 @@
 //! - `AudioNanopolytope`: a safe region in 5D audio parameter space.
 //! - `AudioState`: a point in that space.
 //! - Generator that produces stereo WAV files or real‑time streams from a polytope trajectory.
+//! - `SynthStream`: block-wise synthesis into pluggable sinks, stoppable and steerable live.
 //! - Governance logging with ResponseMetric and nanoswarm telemetry.
 
 pub mod audio_nanopolytope;
 pub mod config;
 pub mod generator;
 pub mod governance;
+pub mod sink;
+pub mod stream;
 pub mod transfer;
 
 pub use audio_nanopolytope::{AudioNanopolytope, AudioState, ConstraintError};
 pub use config::Protocol;
 pub use generator::{generate_wav_from_polytope, generate_wav_from_fixed};
 pub use governance::SessionLog;
+pub use sink::{AudioSink, NullSink, PcmSink, WavSink};
+pub use stream::{render, StreamConfig, StreamControl, SynthStream};
+pub use transfer::{LinearTransfer, TransferUncertainty};
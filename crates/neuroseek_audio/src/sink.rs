@@ -0,0 +1,181 @@
+//! Destinations for streamed audio: a WAV file, a raw PCM pipe, or nowhere.
+//!
+//! Sinks receive blocks of interleaved stereo 16-bit samples from `stream::render`.
+//! `PcmSink::spawn` pipes signed 16-bit little-endian PCM into a player's stdin, e.g.
+//! `aplay -q -f S16_LE -c 2 -r 44100`; the player's own buffering paces synthesis, so a
+//! stop takes effect within that buffer. `NullSink` only counts frames, for headless use.
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+/// Receives interleaved stereo samples.
+pub trait AudioSink: Send {
+    /// Write one block of interleaved left/right samples.
+    fn write_block(&mut self, samples: &[i16]) -> anyhow::Result<()>;
+
+    /// Flush and close the sink; called once, after the last block.
+    fn finish(&mut self) -> anyhow::Result<()>;
+}
+
+/// Writes a stereo 16-bit WAV file.
+pub struct WavSink {
+    writer: Option<WavWriter<BufWriter<File>>>,
+}
+
+impl WavSink {
+    pub fn create(path: impl AsRef<Path>, sample_rate: u32) -> anyhow::Result<Self> {
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        Ok(Self { writer: Some(WavWriter::create(path, spec)?) })
+    }
+}
+
+impl AudioSink for WavSink {
+    fn write_block(&mut self, samples: &[i16]) -> anyhow::Result<()> {
+        let writer = self.writer.as_mut().ok_or_else(|| anyhow::anyhow!("WAV sink already finished"))?;
+        for &sample in samples {
+            writer.write_sample(sample)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        if let Some(writer) = self.writer.take() {
+            writer.finalize()?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes raw signed 16-bit little-endian PCM, optionally into a spawned player.
+pub struct PcmSink<W: Write + Send> {
+    writer: Option<W>,
+    child: Option<Child>,
+}
+
+impl<W: Write + Send> PcmSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer: Some(writer), child: None }
+    }
+
+    /// The underlying writer, until the sink is finished.
+    pub fn get_ref(&self) -> Option<&W> {
+        self.writer.as_ref()
+    }
+}
+
+impl PcmSink<ChildStdin> {
+    /// Spawn `command` (program and whitespace-separated arguments) and pipe into its stdin.
+    pub fn spawn(command: &str) -> anyhow::Result<Self> {
+        let mut parts = command.split_whitespace();
+        let program = parts.next().ok_or_else(|| anyhow::anyhow!("Empty player command"))?;
+        let mut child = Command::new(program).args(parts).stdin(Stdio::piped()).spawn()?;
+        let stdin = child.stdin.take().ok_or_else(|| anyhow::anyhow!("Player has no stdin"))?;
+        Ok(Self { writer: Some(stdin), child: Some(child) })
+    }
+}
+
+impl<W: Write + Send> AudioSink for PcmSink<W> {
+    fn write_block(&mut self, samples: &[i16]) -> anyhow::Result<()> {
+        let writer = self.writer.as_mut().ok_or_else(|| anyhow::anyhow!("PCM sink already finished"))?;
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        writer.write_all(&bytes)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush()?;
+            // Dropping the writer closes a player's stdin, so that it can finish.
+        }
+        if let Some(mut child) = self.child.take() {
+            let status = child.wait()?;
+            if !status.success() {
+                anyhow::bail!("Player exited with {}", status);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Discards samples, counting the frames.
+#[derive(Debug, Default)]
+pub struct NullSink {
+    frames: u64,
+}
+
+impl NullSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stereo frames received so far.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+}
+
+impl AudioSink for NullSink {
+    fn write_block(&mut self, samples: &[i16]) -> anyhow::Result<()> {
+        self.frames += samples.len() as u64 / 2;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Several sinks fed the same blocks, e.g. a file and a player.
+impl AudioSink for Vec<Box<dyn AudioSink>> {
+    fn write_block(&mut self, samples: &[i16]) -> anyhow::Result<()> {
+        for sink in self.iter_mut() {
+            sink.write_block(samples)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        // Finish every sink even if one fails, then report the first failure.
+        let mut result = Ok(());
+        for sink in self.iter_mut() {
+            let finished = sink.finish();
+            if result.is_ok() {
+                result = finished;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sinks_receive_the_same_samples() {
+        let path = std::env::temp_dir().join(format!("neuroseek_sink_{}.wav", std::process::id()));
+        let samples = [0i16, 1, -2, 300, i16::MIN, i16::MAX];
+        let mut sinks: Vec<Box<dyn AudioSink>> = vec![Box::new(WavSink::create(&path, 8000).unwrap()), Box::new(NullSink::new())];
+        sinks.write_block(&samples).unwrap();
+        sinks.finish().unwrap();
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().channels, 2);
+        let read: Vec<i16> = reader.samples::<i16>().map(Result::unwrap).collect();
+        assert_eq!(read, samples);
+        let _ = std::fs::remove_file(&path);
+
+        let mut pcm = PcmSink::new(Vec::new());
+        pcm.write_block(&samples[..2]).unwrap();
+        assert_eq!(pcm.get_ref().unwrap(), &[0, 0, 1, 0]);
+        pcm.finish().unwrap();
+        assert!(pcm.write_block(&samples).is_err());
+    }
+}
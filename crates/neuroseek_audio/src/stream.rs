@@ -0,0 +1,365 @@
+//! Block-wise binaural synthesis with live control.
+//!
+//! A `SynthStream` yields blocks of interleaved stereo samples on demand instead of
+//! rendering a whole session up front, and `render` hands them to an `AudioSink` as
+//! they are produced. Tone parameters move linearly from a start to an end state over
+//! the session, as in `generator`; the oscillators accumulate phase, so frequency
+//! changes stay click-free.
+//!
+//! A `StreamControl`, cloneable across threads, acts on a running stream at the next
+//! block:
+//!
+//! - `stop` fades out over `StreamConfig::stop_fade_sec` and ends the stream;
+//! - `update` glides to a new tone over `StreamConfig::glide_sec` and holds it for the
+//!   rest of the session, provided the state delivered lies inside the stream's
+//!   polytope.
+//!
+//! The session length and duty cycle are fixed when the stream is created; updates do
+//! not change them, so an update is checked with the stream's own duty and duration.
+
+use crate::audio_nanopolytope::{AudioNanopolytope, AudioState, ConstraintError};
+use crate::sink::AudioSink;
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Tuning of the stream.
+#[derive(Debug, Clone, Copy)]
+pub struct StreamConfig {
+    pub sample_rate: u32,
+    /// Stereo frames per block; controls are applied at block boundaries.
+    pub block_frames: usize,
+    /// Length of the fade-out after `StreamControl::stop`, in seconds.
+    pub stop_fade_sec: f64,
+    /// Time taken to reach the state of `StreamControl::update`, in seconds.
+    pub glide_sec: f64,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: 44100,
+            block_frames: 1024,
+            stop_fade_sec: 0.05,
+            glide_sec: 0.5,
+        }
+    }
+}
+
+/// The audible part of an `AudioState`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Tone {
+    amplitude: f64,
+    carrier_hz: f64,
+    beat_hz: f64,
+}
+
+impl Tone {
+    fn of(state: &AudioState) -> Self {
+        Self {
+            amplitude: state.amplitude,
+            carrier_hz: state.carrier_hz,
+            beat_hz: state.beat_hz,
+        }
+    }
+
+    fn lerp(&self, other: &Tone, alpha: f64) -> Tone {
+        Tone {
+            amplitude: self.amplitude + alpha * (other.amplitude - self.amplitude),
+            carrier_hz: self.carrier_hz + alpha * (other.carrier_hz - self.carrier_hz),
+            beat_hz: self.beat_hz + alpha * (other.beat_hz - self.beat_hz),
+        }
+    }
+}
+
+/// Linear move from one tone to another between two frames, held afterwards.
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    from: Tone,
+    to: Tone,
+    start: u64,
+    end: u64,
+}
+
+impl Segment {
+    fn at(&self, frame: u64) -> Tone {
+        if frame >= self.end {
+            return self.to;
+        }
+        let alpha = frame.saturating_sub(self.start) as f64 / (self.end - self.start) as f64;
+        self.from.lerp(&self.to, alpha)
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    stop: AtomicBool,
+    update: Mutex<Option<AudioState>>,
+    polytope: Option<AudioNanopolytope>,
+    /// State whose duty cycle and session duration the stream delivers.
+    base: AudioState,
+}
+
+/// Handle to stop or steer a running `SynthStream`.
+#[derive(Debug, Clone)]
+pub struct StreamControl {
+    shared: Arc<Shared>,
+}
+
+impl StreamControl {
+    /// Fade out and end the stream.
+    pub fn stop(&self) {
+        self.shared.stop.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether `stop` was called.
+    pub fn is_stopped(&self) -> bool {
+        self.shared.stop.load(Ordering::SeqCst)
+    }
+
+    /// Glide to the tone of `state`. Its duty cycle and duration are those of the
+    /// stream, and the tone with them must lie inside the stream's polytope; a stream
+    /// of a fixed state has none and refuses every update.
+    pub fn update(&self, state: AudioState) -> Result<(), ConstraintError> {
+        let state = AudioState {
+            amplitude: state.amplitude,
+            carrier_hz: state.carrier_hz,
+            beat_hz: state.beat_hz,
+            ..self.shared.base
+        };
+        match &self.shared.polytope {
+            Some(polytope) if polytope.contains(&state) => {
+                *self.shared.update.lock().unwrap_or_else(|e| e.into_inner()) = Some(state);
+                Ok(())
+            }
+            _ => Err(ConstraintError::OutsidePolytope),
+        }
+    }
+}
+
+/// Pull-based stereo binaural generator.
+#[derive(Debug)]
+pub struct SynthStream {
+    config: StreamConfig,
+    /// Session length in frames.
+    frames: u64,
+    duration: f64,
+    position: u64,
+    segment: Segment,
+    phase_left: f64,
+    phase_right: f64,
+    /// Frame at which the stop fade began.
+    stop_at: Option<u64>,
+    shared: Arc<Shared>,
+}
+
+impl SynthStream {
+    /// Constant tones of a fixed state, for its session duration.
+    pub fn fixed(state: &AudioState, config: StreamConfig) -> Self {
+        Self::new(state, state, None, config)
+    }
+
+    /// Move from `start` to `end` over `end`'s session duration, inside `polytope`.
+    pub fn trajectory(
+        polytope: &AudioNanopolytope,
+        start: &AudioState,
+        end: &AudioState,
+        config: StreamConfig,
+    ) -> Result<Self, ConstraintError> {
+        if !polytope.contains(start) || !polytope.contains(end) {
+            return Err(ConstraintError::OutsidePolytope);
+        }
+        Ok(Self::new(start, end, Some(polytope.clone()), config))
+    }
+
+    fn new(start: &AudioState, end: &AudioState, polytope: Option<AudioNanopolytope>, config: StreamConfig) -> Self {
+        let config = StreamConfig { block_frames: config.block_frames.max(1), ..config };
+        let duration = end.session_duration_sec.max(0.0);
+        let frames = (config.sample_rate as f64 * duration) as u64;
+        Self {
+            config,
+            frames,
+            duration,
+            position: 0,
+            segment: Segment { from: Tone::of(start), to: Tone::of(end), start: 0, end: frames },
+            phase_left: 0.0,
+            phase_right: 0.0,
+            stop_at: None,
+            shared: Arc::new(Shared {
+                stop: AtomicBool::new(false),
+                update: Mutex::new(None),
+                polytope,
+                base: *end,
+            }),
+        }
+    }
+
+    /// A handle acting on this stream.
+    pub fn control(&self) -> StreamControl {
+        StreamControl { shared: self.shared.clone() }
+    }
+
+    /// Frames produced so far.
+    pub fn elapsed_frames(&self) -> u64 {
+        self.position
+    }
+
+    /// Whether the stream ended, or is ending, because it was stopped.
+    pub fn stopped(&self) -> bool {
+        self.stop_at.is_some()
+    }
+
+    fn sec(&self, frames: f64) -> f64 {
+        frames / self.config.sample_rate as f64
+    }
+
+    /// Apply control requests made since the previous block.
+    fn apply_controls(&mut self) {
+        if self.stop_at.is_none() && self.shared.stop.load(Ordering::SeqCst) {
+            self.stop_at = Some(self.position);
+        }
+        let update = self.shared.update.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(state) = update {
+            let glide = (self.config.glide_sec * self.config.sample_rate as f64) as u64;
+            self.segment = Segment {
+                from: self.segment.at(self.position),
+                to: Tone::of(&state),
+                start: self.position,
+                end: self.position + glide.max(1),
+            };
+        }
+    }
+
+    /// Fade-in and fade-out (one second each) of sessions longer than two seconds.
+    fn edge_fade(&self, t: f64) -> f64 {
+        if self.duration > 2.0 {
+            if t < 1.0 {
+                t
+            } else if t > self.duration - 1.0 {
+                self.duration - t
+            } else {
+                1.0
+            }
+        } else {
+            1.0
+        }
+    }
+
+    /// End of the stream in frames: the session end, or the end of the stop fade.
+    fn end(&self) -> u64 {
+        match self.stop_at {
+            Some(at) => self.frames.min(at + (self.config.stop_fade_sec * self.config.sample_rate as f64) as u64),
+            None => self.frames,
+        }
+    }
+}
+
+impl Iterator for SynthStream {
+    /// Interleaved left/right samples.
+    type Item = Vec<i16>;
+
+    fn next(&mut self) -> Option<Vec<i16>> {
+        self.apply_controls();
+        let end = self.end();
+        if self.position >= end {
+            return None;
+        }
+        let count = (end - self.position).min(self.config.block_frames as u64);
+        let step = 2.0 * PI / self.config.sample_rate as f64;
+        let stop_frames = (self.config.stop_fade_sec * self.config.sample_rate as f64).max(1.0);
+        let mut block = Vec::with_capacity(2 * count as usize);
+        for frame in self.position..self.position + count {
+            let tone = self.segment.at(frame);
+            let stop_gain = match self.stop_at {
+                Some(at) => (1.0 - (frame - at) as f64 / stop_frames).max(0.0),
+                None => 1.0,
+            };
+            let amp = tone.amplitude.clamp(0.0, 1.0) * self.edge_fade(self.sec(frame as f64)) * stop_gain;
+
+            let left = (amp * self.phase_left.sin()) as f32;
+            let right = (amp * self.phase_right.sin()) as f32;
+            block.push((left * i16::MAX as f32) as i16);
+            block.push((right * i16::MAX as f32) as i16);
+
+            self.phase_left = (self.phase_left + step * tone.carrier_hz).rem_euclid(2.0 * PI);
+            self.phase_right = (self.phase_right + step * (tone.carrier_hz + tone.beat_hz)).rem_euclid(2.0 * PI);
+        }
+        self.position += count;
+        Some(block)
+    }
+}
+
+/// Feed every block of `stream` to `sink`, then finish the sink. Returns the number of
+/// frames written.
+pub fn render(stream: &mut SynthStream, sink: &mut dyn AudioSink) -> anyhow::Result<u64> {
+    let start = stream.elapsed_frames();
+    for block in stream.by_ref() {
+        if let Err(e) = sink.write_block(&block) {
+            let _ = sink.finish();
+            return Err(e);
+        }
+    }
+    sink.finish()?;
+    Ok(stream.elapsed_frames() - start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::NullSink;
+
+    fn state(amplitude: f64, carrier_hz: f64, beat_hz: f64) -> AudioState {
+        AudioState { amplitude, carrier_hz, beat_hz, duty: 0.1, session_duration_sec: 10.0 }
+    }
+
+    #[test]
+    fn test_stop_and_update_while_streaming() {
+        let config = StreamConfig { sample_rate: 8000, block_frames: 800, stop_fade_sec: 0.05, glide_sec: 0.2 };
+        let mut sink = NullSink::new();
+        assert_eq!(render(&mut SynthStream::fixed(&state(0.3, 250.0, 10.0), config), &mut sink).unwrap(), 80_000);
+        assert_eq!(sink.frames(), 80_000);
+
+        // Box: amplitude <= 0.5, beat <= 20.
+        let polytope = AudioNanopolytope::new(
+            "box".into(),
+            vec![[1.0, 0.0, 0.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0, 0.0]],
+            vec![0.5, 20.0],
+            0.9,
+            0.2,
+            0.1,
+        );
+        let start = state(0.3, 250.0, 10.0);
+        assert!(SynthStream::trajectory(&polytope, &start, &state(0.8, 250.0, 10.0), config).is_err());
+        let mut stream = SynthStream::trajectory(&polytope, &start, &start, config).unwrap();
+        let control = stream.control();
+        let peak = |block: &[i16]| block.iter().map(|s| s.unsigned_abs()).max().unwrap();
+
+        // Past the fade-in, at full amplitude.
+        let blocks: Vec<Vec<i16>> = stream.by_ref().take(15).collect();
+        assert!((peak(&blocks[14]) as f64 / i16::MAX as f64 - 0.3).abs() < 0.01);
+
+        assert!(matches!(control.update(state(0.9, 250.0, 10.0)), Err(ConstraintError::OutsidePolytope)));
+        control.update(state(0.5, 250.0, 10.0)).unwrap();
+        let blocks: Vec<Vec<i16>> = stream.by_ref().take(3).collect();
+        assert!((peak(&blocks[2]) as f64 / i16::MAX as f64 - 0.5).abs() < 0.01);
+
+        // A stop fades out within 50 ms instead of running the remaining 8 s.
+        control.stop();
+        let rest: Vec<i16> = stream.by_ref().flatten().collect();
+        assert_eq!(rest.len(), 2 * 400);
+        assert!(peak(&rest[rest.len() - 80..]) < peak(&rest[..80]) / 5);
+        assert!(stream.stopped());
+        assert!(SynthStream::fixed(&start, config).control().update(start).is_err());
+
+        // amplitude + duration / 1000 <= 0.9: a 600 s session allows amplitude 0.3.
+        let coupled =
+            AudioNanopolytope::new("coupled".into(), vec![[1.0, 0.0, 0.0, 0.0, 0.001]], vec![0.9], 0.9, 0.2, 0.1);
+        let long = AudioState { session_duration_sec: 600.0, ..state(0.2, 250.0, 10.0) };
+        let control = SynthStream::trajectory(&coupled, &long, &long, config).unwrap().control();
+        // Allowed for a 100 s session, but this session lasts 600 s.
+        let loud = AudioState { session_duration_sec: 100.0, ..state(0.5, 250.0, 10.0) };
+        assert!(coupled.contains(&loud));
+        assert!(matches!(control.update(loud), Err(ConstraintError::OutsidePolytope)));
+        assert!(control.update(AudioState { amplitude: 0.3, ..loud }).is_ok());
+    }
+}
@@ -2,6 +2,7 @@
 //! These are placeholder linear models; in practice they'd be calibrated from your data.
 
 use crate::audio_nanopolytope::AudioState;
+use serde::{Deserialize, Serialize};
 
 /// A simple linear transfer: maps audio amplitude to Energy, carrier to theta proxy, etc.
 pub fn default_transfer(audio: &AudioState) -> [f64; 5] {
@@ -13,3 +14,70 @@ pub fn default_transfer(audio: &AudioState) -> [f64; 5] {
     let t = 0.0; // no temperature change
     [e, m_prot, s_bio, theta, t]
 }
+
+/// Uncertainty of a fitted linear transfer, as symmetric per-coefficient intervals.
+///
+/// Each entry is a half-width: the true coefficient `m[i][j]` is assumed to lie in
+/// `[m[i][j] - matrix[i][j], m[i][j] + matrix[i][j]]`, and likewise for the offset.
+/// Intervals from a fit's standard errors are typically `k * sigma` with k ≈ 2–3.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TransferUncertainty {
+    pub matrix: [[f64; 5]; 5],
+    pub offset: [f64; 5],
+}
+
+/// Linear transfer z = M * x + offset, optionally with coefficient uncertainty.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinearTransfer {
+    pub matrix: [[f64; 5]; 5],
+    pub offset: [f64; 5],
+    pub uncertainty: Option<TransferUncertainty>,
+}
+
+impl LinearTransfer {
+    /// Nominal prediction using the fitted coefficients.
+    pub fn nominal(&self, audio: &AudioState) -> [f64; 5] {
+        let x = audio.to_vector();
+        let mut z = [0.0; 5];
+        for (i, zi) in z.iter_mut().enumerate() {
+            *zi = self.offset[i] + (0..5).map(|j| self.matrix[i][j] * x[j]).sum::<f64>();
+        }
+        z
+    }
+
+    /// Interval of possible predictions over the uncertainty set (interval arithmetic).
+    ///
+    /// For fixed x, each output is linear in the coefficients, so its extremes are
+    /// nominal ± (Σ_j |Δm_ij · x_j| + Δoffset_i).
+    pub fn interval(&self, audio: &AudioState) -> [(f64, f64); 5] {
+        let nominal = self.nominal(audio);
+        let x = audio.to_vector();
+        let mut out = [(0.0, 0.0); 5];
+        for i in 0..5 {
+            let spread = match &self.uncertainty {
+                Some(u) => u.offset[i].abs() + (0..5).map(|j| (u.matrix[i][j] * x[j]).abs()).sum::<f64>(),
+                None => 0.0,
+            };
+            out[i] = (nominal[i] - spread, nominal[i] + spread);
+        }
+        out
+    }
+
+    /// Worst-case prediction interval over a set of audio states and the uncertainty set.
+    ///
+    /// The upper bound of each output is convex in x and the lower bound concave, so for
+    /// a convex polytope the extremes are attained at its vertices: passing the vertex
+    /// set yields the exact worst case over the whole polytope.
+    /// Returns `None` for an empty set.
+    pub fn worst_case(&self, points: &[AudioState]) -> Option<[(f64, f64); 5]> {
+        let mut iter = points.iter().map(|p| self.interval(p));
+        let mut acc = iter.next()?;
+        for interval in iter {
+            for i in 0..5 {
+                acc[i].0 = acc[i].0.min(interval[i].0);
+                acc[i].1 = acc[i].1.max(interval[i].1);
+            }
+        }
+        Some(acc)
+    }
+}